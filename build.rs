@@ -0,0 +1,53 @@
+//! Record the compiler, target, and features that the library is built with so
+//! that plugins built differently can be rejected, see `src/plugins.rs`, and
+//! extract the doc comment of each module so that its description can be
+//! embedded without the rest of its source, see `Module::describe`
+
+use std::{path::Path, process::Command};
+
+/// Write the `/*! ... */` doc comment of each source file under the given
+/// directory to the same relative path under the output directory
+fn extract_docs(src: &Path, out: &Path) {
+    for entry in std::fs::read_dir(src).unwrap() {
+        let path = entry.unwrap().path();
+        let out = out.join(path.file_name().unwrap());
+        if path.is_dir() {
+            std::fs::create_dir_all(&out).unwrap();
+            extract_docs(&path, &out);
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            let src = std::fs::read_to_string(&path).unwrap();
+            let doc = src.split_once("/*!")
+                .and_then(|(_, doc)| doc.split_once("*/"))
+                .map_or("", |(doc, _)| doc);
+            std::fs::write(out, format!("/*!{doc}*/\n")).unwrap();
+        }
+    }
+}
+
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map_or_else(|| "unknown rustc".to_string(), |v| v.trim().to_string());
+    println!("cargo:rustc-env=VINCE_RUSTC_VERSION={version}");
+
+    println!("cargo:rustc-env=VINCE_TARGET={}", std::env::var("TARGET").unwrap());
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(k, _)| k.strip_prefix("CARGO_FEATURE_").map(str::to_lowercase))
+        .filter(|f| f != "default")
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=VINCE_FEATURES={}", features.join(","));
+
+    let out = Path::new(&std::env::var("OUT_DIR").unwrap()).join("module_docs");
+    std::fs::create_dir_all(&out).unwrap();
+    extract_docs(Path::new("src/modules"), &out);
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/modules");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+}
@@ -0,0 +1,31 @@
+/*!
+Convert a VCV Rack patch to a Vince rack, see [`vince_core::vcv`] for details.
+
+```
+$ cargo run --release --features vcv --bin vcv2vince -- patch.vcv > assets/racks/patch.toml
+```
+
+*/
+
+fn main() {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("Usage: vcv2vince <patch.vcv>");
+        std::process::exit(1);
+    };
+
+    let import = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {path}: {e}"))
+        .and_then(|json| vince_core::vcv::import(&json));
+    match import {
+        Ok(import) => {
+            for w in &import.warnings {
+                eprintln!("Warning: {w}");
+            }
+            print!("{}", import.to_toml());
+        },
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        },
+    }
+}
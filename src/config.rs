@@ -0,0 +1,259 @@
+/*!
+App-level settings which are loaded from a `vince.toml` file at startup, as
+opposed to the per-rack settings in each rack's file.
+
+The file is read from the current directory, or from the path given by the
+`--config` flag. Every setting is optional:
+
+```toml
+rack = "racks/"         # The rack file or directory of racks to load
+asset_dir = "assets"    # The directory which racks are loaded from
+plugin_dir = "plugins"  # The directory which plugins are loaded from
+frame_rate = 60         # How many times per second to step and render
+layout = "stage.toml"   # The window layout to apply when racks are loaded
+
+[window]                # The main window, like a module's `window` table
+monitor = 0
+resolution = [1920, 1080]
+is_fullscreen = true
+
+[audio]                 # The default audio devices for racks which don't
+host = "JACK"           # set their own in their `[audio]` section
+output = { device = "Scarlett 2i2 USB" }
+
+[program_change]        # Switch racks with MIDI program changes
+port = "FCB1010"
+channel = 16
+```
+
+In the browser, only the rack can be set with the page's URL, see
+[`crate::web`] for details.
+
+Each setting can be overridden on the command line, where the rack is given as
+the first positional argument:
+
+```text
+$ cargo run --release -- --frame-rate 30 --fullscreen --audio-device "Scarlett 2i2 USB" racks/
+```
+
+ * `--config <path>` - load the settings from the given file
+ * `--asset-dir <dir>` - load racks relative to the given directory
+ * `--plugin-dir <dir>` - load plugins from the given directory
+ * `--frame-rate <fps>` - step and render at the given frame rate
+ * `--layout <path>` - place the module windows with the given layout file, see
+   [`crate::layout`]
+ * `--resolution <width>x<height>` - the size of the main window
+ * `--monitor <index>` - open the main window on the given monitor
+ * `--fullscreen` and `--windowed` - whether the main window is fullscreen
+ * `--audio-host <name>` - the default audio host
+ * `--audio-device <name>` - the default audio output device
+ * `--audio-input-device <name>` - the default audio input device
+ * `--program-change-channel <channel>` - switch racks with MIDI program
+   changes on the given channel
+
+*/
+
+use std::sync::OnceLock;
+
+use bevy::prelude::*;
+
+use serde::Deserialize;
+
+use crate::{rack::AudioConfig, modules::WindowOptions};
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+const CONFIG_PATH: &str = "vince.toml";
+
+fn default_rack() -> String {
+    // Directories can't be listed over HTTP so load a single rack in the
+    // browser
+    if cfg!(target_arch = "wasm32") {
+        "racks/rack1.toml".to_string()
+    } else {
+        "racks/".to_string()
+    }
+}
+fn default_asset_dir() -> String {
+    "assets".to_string()
+}
+fn default_plugin_dir() -> String {
+    "plugins".to_string()
+}
+fn default_frame_rate() -> u16 {
+    60
+}
+fn default_program_change_channel() -> u8 {
+    1
+}
+
+/// The MIDI input which switches racks on program changes, see
+/// [`crate::program_change`]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProgramChangeConfig {
+    /// Only connect to the ports whose names contain this, ignoring case
+    #[serde(default)]
+    pub port: Option<String>,
+    /// The channel to listen on in the range [1, 16]
+    #[serde(default = "default_program_change_channel")]
+    pub channel: u8,
+}
+impl Default for ProgramChangeConfig {
+    fn default() -> Self {
+        Self {
+            port: None,
+            channel: default_program_change_channel(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The rack file or directory of racks to load, relative to the asset
+    /// directory
+    #[serde(default = "default_rack")]
+    pub rack: String,
+    /// The directory which racks are loaded from, relative to either the
+    /// `CARGO_MANIFEST_DIR` or the built executable
+    #[serde(default = "default_asset_dir")]
+    pub asset_dir: String,
+    /// The directory which plugins are loaded from with the `plugins` feature
+    #[serde(default = "default_plugin_dir")]
+    pub plugin_dir: String,
+    #[serde(default = "default_frame_rate")]
+    pub frame_rate: u16,
+    /// The window layout file, see [`crate::layout`]
+    #[serde(default)]
+    pub layout: Option<String>,
+
+    /// The options for the main window
+    #[serde(default)]
+    pub window: WindowOptions,
+    /// The default audio host and devices
+    #[serde(default)]
+    pub audio: AudioConfig,
+    /// Switch racks with MIDI program changes when set, with the `midi`
+    /// feature
+    #[serde(default)]
+    pub program_change: Option<ProgramChangeConfig>,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            rack: default_rack(),
+            asset_dir: default_asset_dir(),
+            plugin_dir: default_plugin_dir(),
+            frame_rate: default_frame_rate(),
+            layout: None,
+
+            window: WindowOptions::default(),
+            audio: AudioConfig::default(),
+            program_change: None,
+        }
+    }
+}
+impl Config {
+    /// Load the config file and apply the command line flags on top of it
+    fn load() -> Result<Self, String> {
+        let mut args = std::env::args().skip(1);
+        let mut flags = vec![];
+        let mut config_path = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => config_path = Some(args.next().ok_or("Missing path for --config")?),
+                "--fullscreen" | "--windowed" => flags.push((arg, String::new())),
+                a if a.starts_with("--") => {
+                    let value = args.next().ok_or(format!("Missing value for {a}"))?;
+                    flags.push((arg, value));
+                },
+                _ => flags.push(("--rack".to_string(), arg)),
+            }
+        }
+
+        let mut config = match &config_path {
+            Some(path) => {
+                let config = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read config {path}: {e}"))?;
+                toml::from_str(&config)
+                    .map_err(|e| format!("Invalid config {path}: {e}"))?
+            },
+            None => match std::fs::read_to_string(CONFIG_PATH) {
+                Ok(config) => toml::from_str(&config)
+                    .map_err(|e| format!("Invalid config {CONFIG_PATH}: {e}"))?,
+                Err(_) => Config::default(),
+            },
+        };
+
+        for (flag, value) in flags {
+            match flag.as_str() {
+                "--rack" => config.rack = value,
+                "--asset-dir" => config.asset_dir = value,
+                "--plugin-dir" => config.plugin_dir = value,
+                "--frame-rate" => {
+                    config.frame_rate = value.parse()
+                        .map_err(|e| format!("Invalid frame rate {value}: {e}"))?;
+                },
+                "--layout" => config.layout = Some(value),
+                "--resolution" => {
+                    let resolution = value.split_once('x')
+                        .and_then(|(w, h)| Some([w.parse().ok()?, h.parse().ok()?]));
+                    config.window.resolution = Some(resolution.ok_or(format!("Invalid resolution {value}, expected <width>x<height>"))?);
+                },
+                "--monitor" => {
+                    config.window.monitor = Some(
+                        value.parse()
+                            .map_err(|e| format!("Invalid monitor {value}: {e}"))?
+                    );
+                },
+                "--fullscreen" => config.window.is_fullscreen = true,
+                "--windowed" => config.window.is_fullscreen = false,
+                "--audio-host" => config.audio.host = Some(value),
+                "--audio-device" => config.audio.output.device = Some(value),
+                "--audio-input-device" => config.audio.input.device = Some(value),
+                "--program-change-channel" => {
+                    config.program_change.get_or_insert_with(ProgramChangeConfig::default).channel = value.parse()
+                        .map_err(|e| format!("Invalid program change channel {value}: {e}"))?;
+                },
+                f => return Err(format!("Unknown flag {f}")),
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        if let Some(rack) = crate::web::rack_path() {
+            config.rack = rack;
+        }
+
+        if config.frame_rate == 0 {
+            return Err("Invalid frame rate 0, must be at least 1".to_string());
+        }
+        if let Some(pc) = &config.program_change {
+            if !(1..=16).contains(&pc.channel) {
+                return Err(format!("Invalid program change channel {}, must be in the range [1, 16]", pc.channel));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Load the config, exiting with an error if it's invalid
+pub fn init() {
+    match Config::load() {
+        Ok(config) => {
+            CONFIG.set(config).expect("Config was already initialized");
+        },
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        },
+    }
+}
+/// Get the loaded config, or the defaults if it hasn't been loaded
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| {
+        warn!("Config accessed before it was loaded, using the defaults");
+        Config::default()
+    })
+}
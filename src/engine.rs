@@ -0,0 +1,242 @@
+/*!
+A headless engine which loads a rack and steps it without running the Bevy app,
+opening any windows, or opening any audio devices, so that the synth can be
+embedded in other front-ends or used in tests.
+
+```no_run
+use vince_core::engine::Engine;
+
+let mut engine = Engine::from_path("assets/racks/rack1.toml").unwrap();
+
+// Render one second of the audio sent to the rack's AudioOut modules
+let audio = engine.render_audio(44100);
+
+// Or render the RGB signals patched into the ComponentVideoOut module 3
+let video = engine.render_video(3, 80 * 60);
+```
+
+Each step advances the rack by one sample at 44100 Hz, in the same way as the
+rack's default `Audio` mode. Modules only have their state set up with
+[`Module::init_state`](crate::modules::Module::init_state) so their UI isn't
+spawned, they don't connect to external devices, and they can't receive
+keyboard or mouse input, but every other module steps as it does in the app.
+Modules with invalid options panic in the same way as when the app inits them.
+
+##### Note
+Building the engine without Bevy isn't supported. The [`Module`](crate::modules::Module)
+trait spawns and renders each module's UI with Bevy's types, so the library
+still depends on Bevy's render and UI stack even though the engine never runs
+the app, and splitting the UI out of the modules is left for a later change.
+Only the app's windows and cameras are moved to the `vince` binary.
+
+*/
+
+use std::path::Path;
+
+use crate::{StepType, patch_lang, rack::Rack, modules::{ModuleKey, ModuleIOK}};
+
+/// The sample rate that the engine steps at
+pub const SAMPLE_RATE: u32 = 44100;
+/// How many steps there are in each frame, the first of which is a key step
+const FRAME_STEPS: u64 = 735;
+
+pub struct Engine {
+    rack: Rack,
+    steps: u64,
+}
+impl Engine {
+    fn from_rack(mut rack: Rack) -> Self {
+        rack.keep_audio = true;
+        rack.init_seed();
+        for (k, m) in &mut rack.modules {
+            m.init_state(k.id);
+        }
+        rack.init_knob_ranges();
+        rack.init_calibrations();
+
+        Self {
+            rack,
+            steps: 0,
+        }
+    }
+    /// Load a rack from its TOML definition
+    pub fn from_toml(toml: &str) -> Result<Self, String> {
+        let rack: Rack = toml::from_str(toml)
+            .map_err(|e| format!("Invalid rack: {e}"))?;
+        Ok(Self::from_rack(rack))
+    }
+    /// Load a rack from its definition in the text format of [`patch_lang`]
+    pub fn from_patch_lang(src: &str) -> Result<Self, String> {
+        let rack = patch_lang::parse(src)
+            .map_err(|e| format!("Invalid rack: {e}"))?;
+        Ok(Self::from_rack(rack))
+    }
+    /// Load a rack from the file at the given path, either a TOML file or a
+    /// `.vince` file in the text format
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let src = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read rack {}: {e}", path.display()))?;
+        if path.extension().is_some_and(|ext| ext == "vince") {
+            Self::from_patch_lang(&src)
+        } else {
+            Self::from_toml(&src)
+        }
+    }
+
+    pub fn rack(&self) -> &Rack {
+        &self.rack
+    }
+    /// Get the rack mutably, e.g. to set the knobs of its modules
+    pub fn rack_mut(&mut self) -> &mut Rack {
+        &mut self.rack
+    }
+    /// The current time of the rack in seconds
+    pub fn time(&self) -> f64 {
+        self.steps as f64 / f64::from(SAMPLE_RATE)
+    }
+
+    /// Step every module in the rack once
+    pub fn step(&mut self) {
+        let st = if self.steps % FRAME_STEPS == 0 {
+            StepType::Key
+        } else {
+            StepType::Audio
+        };
+        self.rack.step(self.time(), st);
+        self.steps += 1;
+    }
+    /// Get the value of the given output of the given module from the last
+    /// step, if the module has that output
+    pub fn output(&self, module: usize, output: usize) -> Option<f32> {
+        self.rack.signal(&ModuleKey {
+            id: module,
+            iok: ModuleIOK::Output(output),
+        })
+    }
+    /// Get the value which was patched into the given input of the given
+    /// module during the last step, if the input is patched
+    pub fn input(&self, module: usize, input: usize) -> Option<f32> {
+        self.rack.patches.iter()
+            .find(|p| p.1.id == module && p.1.iok == ModuleIOK::Input(input))
+            .and_then(|p| self.rack.signal(p.0))
+    }
+
+    /// Step the rack the given number of times and collect the stereo audio
+    /// sent to its audio outputs
+    pub fn render_audio(&mut self, steps: usize) -> Vec<[f32; 2]> {
+        let mut audio = Vec::with_capacity(steps);
+        for _ in 0..steps {
+            self.step();
+            audio.extend(
+                self.rack.drain_audio(2)
+                    .into_iter()
+                    .map(|frame| [frame[0], frame[1]])
+            );
+        }
+        audio
+    }
+    /// Step the rack the given number of times and collect the RGB signals
+    /// which were patched into the first 3 inputs of the given video output
+    /// module, where unpatched channels are [f32::NAN]
+    pub fn render_video(&mut self, module: usize, steps: usize) -> Vec<[f32; 3]> {
+        (0..steps).map(|_| {
+            self.step();
+            [0, 1, 2].map(|i| self.input(module, i).unwrap_or(f32::NAN))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limited_sine() {
+        let mut engine = Engine::from_toml(r#"
+            [modules]
+            0M = { type = "AudioOut", knobs = [1.0] }
+            1M = { type = "Oscillator", func = "Sine", knobs = [0.0, 440.0, 1.0, 0.0] }
+            2M = { type = "Limiter", knobs = [-0.5, 0.5, 0.0, 0.0, 0.0] }
+
+            [patches]
+            1M0O = ["2M0I"]
+            2M0O = ["0M0I"]
+        "#).unwrap();
+        let audio = engine.render_audio(SAMPLE_RATE as usize / 10);
+
+        assert_eq!(audio.len(), SAMPLE_RATE as usize / 10);
+        let peak = audio.iter()
+            .flatten()
+            .fold(0.0f32, |a, s| a.max(s.abs()));
+        assert!(peak > 0.45 && peak <= 0.5, "expected the sine to be limited to 0.5 but its peak was {peak}");
+        assert!(audio.iter().all(|[l, r]| l == r), "expected the mono output on both channels");
+    }
+
+    #[test]
+    fn patch_routing() {
+        let mut engine = Engine::from_toml(r#"
+            [modules]
+            0M = { type = "AudioOut", knobs = [1.0] }
+            1M = { type = "Oscillator", func = "Sine", knobs = [0.8, 0.0, 0.0, 0.0] }
+            2M = { type = "Scaler", knobs = [0.5] }
+            3M = { type = "Limiter", knobs = [-0.5, 0.5] }
+
+            [patches]
+            1M0O = ["2M0I", "3M0I"]
+            2M0O = ["0M0I"]
+            3M0O = ["0M1I"]
+        "#).unwrap();
+        let audio = engine.render_audio(FRAME_STEPS as usize);
+
+        // A single output fans out to every input it's patched to
+        assert_eq!(engine.output(1, 0), Some(0.8));
+        assert_eq!(engine.input(2, 0), Some(0.8));
+        assert_eq!(engine.input(3, 0), Some(0.8));
+        assert_eq!(engine.input(0, 0), engine.output(2, 0));
+        assert_eq!(engine.input(0, 1), engine.output(3, 0));
+
+        // Unpatched outputs are still stepped, but unpatched inputs aren't
+        assert_eq!(engine.output(3, 1), Some(1.0 - 0.5 / 0.8));
+        assert_eq!(engine.input(1, 0), None);
+
+        assert_eq!(audio.last(), Some(&[0.4, 0.5]));
+    }
+
+    #[test]
+    fn invalid_song() {
+        let result = Engine::from_toml(r#"
+            [modules]
+            1M = { type = "MultiSequencer", song = [[0, 1.0], [1, 1.0]], sequencers = [
+                [{ type = "Sequencer", notes = [[440.0, 1.0, 1.0]], knobs = [120.0] }, 1.0],
+            ] }
+        "#);
+
+        let Err(e) = result else {
+            panic!("expected the rack with an invalid song to fail to load");
+        };
+        assert!(e.contains("position 1 references sequencer 1"), "unexpected error: {e}");
+    }
+
+    #[test]
+    fn bundled_racks() {
+        let mut paths: Vec<_> = std::fs::read_dir("assets/racks")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml" || ext == "vince"))
+            // Needs a user-supplied greenscreen video
+            .filter(|path| !path.ends_with("rack25.toml"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty(), "expected the bundled racks in assets/racks");
+
+        for path in paths {
+            let mut engine = Engine::from_path(&path)
+                .unwrap_or_else(|e| panic!("Failed to load {}: {e}", path.display()));
+            for _ in 0..FRAME_STEPS {
+                engine.step();
+            }
+            assert_eq!(engine.rack().bypassed().count(), 0, "expected no modules of {} to be bypassed", path.display());
+        }
+    }
+}
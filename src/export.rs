@@ -0,0 +1,65 @@
+/*!
+Helpers which allow modules to export their current data to files in the
+`exports` directory so that it can be documented or analysed offline.
+*/
+
+use std::{path::PathBuf, time::{SystemTime, UNIX_EPOCH}, fs, io::Write};
+
+use bevy::prelude::*;
+
+const EXPORT_DIR: &str = "exports";
+
+fn export_path(name: &str, ext: &str) -> std::io::Result<PathBuf> {
+    fs::create_dir_all(EXPORT_DIR)?;
+
+    let name = name.trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        }).collect::<String>();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+
+    Ok(PathBuf::from(EXPORT_DIR).join(format!("{name}-{timestamp}.{ext}")))
+}
+
+/// Write the given rows to a CSV file and return its path
+pub fn export_csv(name: &str, header: &[&str], rows: impl Iterator<Item = Vec<f64>>) -> std::io::Result<PathBuf> {
+    let path = export_path(name, "csv")?;
+    let mut file = std::io::BufWriter::new(fs::File::create(&path)?);
+
+    writeln!(file, "{}", header.join(","))?;
+    for row in rows {
+        writeln!(
+            file,
+            "{}",
+            row.iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+        )?;
+    }
+    file.flush()?;
+
+    info!("Exported {}", path.display());
+    Ok(path)
+}
+
+/// Write the given RGBA data to a PNG file and return its path
+#[cfg(feature = "export")]
+pub fn export_png(name: &str, width: usize, height: usize, rgba: &[u8]) -> std::io::Result<PathBuf> {
+    let path = export_path(name, "png")?;
+
+    let image = image::RgbaImage::from_raw(width as u32, height as u32, rgba.to_vec())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "RGBA data doesn't match the image size"))?;
+    image.save(&path)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    info!("Exported {}", path.display());
+    Ok(path)
+}
@@ -0,0 +1,205 @@
+/*!
+An offline stepping harness for testing modules without running the Bevy app.
+
+Modules are constructed from TOML snippets in the same format as a rack's
+module definitions and are stepped directly with synthetic inputs. Their
+outputs are compared against golden files under `tests/golden/` so that DSP
+regressions can be caught by running `cargo test`.
+
+Golden files are either CSV files with one line of comma-separated outputs per
+step, or, with the `files` feature, WAV files with one 32-bit float channel per
+output. If the `VINCE_BLESS` environment variable is set, the golden file is
+written from the current outputs instead, otherwise a missing golden file fails
+the test.
+
+*/
+
+use std::{f32::consts::PI, path::{Path, PathBuf}};
+
+use crate::{StepType, modules::Module};
+
+/// The sample rate assumed by the modules
+const SR: f32 = 44100.0;
+/// The largest difference allowed between an output and its golden value
+const TOLERANCE: f32 = 1e-4;
+
+pub(crate) struct Harness {
+    module: Box<dyn Module>,
+    time: f64,
+}
+impl Harness {
+    /// Construct a module from a TOML snippet, e.g.
+    /// `type = "Delay"\nknobs = [0.1, 0.5, 0.5]`
+    pub(crate) fn new(toml: &str) -> Self {
+        let mut module: Box<dyn Module> = toml::from_str(toml)
+            .unwrap_or_else(|e| panic!("Failed to construct module from TOML: {e}"));
+        module.init_state(0);
+        Self {
+            module,
+            time: 0.0,
+        }
+    }
+    /// Step the module the given number of times with the inputs returned for
+    /// each step and collect its outputs
+    pub(crate) fn run(&mut self, steps: usize, input: impl Fn(usize) -> Vec<f32>) -> Vec<Vec<f32>> {
+        let inputs = self.module.inputs();
+        (0..steps).map(|i| {
+            let mut ins = input(i);
+            ins.resize(inputs, f32::NAN);
+
+            let outs = self.module.step(self.time, StepType::Audio, &ins);
+            self.time += 1.0 / f64::from(SR);
+            outs
+        }).collect()
+    }
+
+    fn golden_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(name)
+    }
+    fn should_bless(path: &Path) -> bool {
+        if std::env::var_os("VINCE_BLESS").is_some() {
+            return true;
+        }
+        assert!(path.exists(), "Missing golden file {}, run with VINCE_BLESS=1 to write it", path.display());
+        false
+    }
+    fn compare(name: &str, outputs: &[Vec<f32>], golden: &[Vec<f32>]) {
+        assert_eq!(outputs.len(), golden.len(), "{name}: expected {} steps but got {}", golden.len(), outputs.len());
+        for (i, (o, g)) in outputs.iter().zip(golden).enumerate() {
+            assert_eq!(o.len(), g.len(), "{name}: step {i} expected {} outputs but got {}", g.len(), o.len());
+            for (c, (o, g)) in o.iter().zip(g).enumerate() {
+                let is_match = (o.is_nan() && g.is_nan()) || (o - g).abs() <= TOLERANCE;
+                assert!(is_match, "{name}: step {i} output {c} expected {g} but got {o}");
+            }
+        }
+    }
+
+    /// Compare the outputs against the golden CSV file with the given name
+    pub(crate) fn assert_golden_csv(name: &str, outputs: &[Vec<f32>]) {
+        let path = Self::golden_path(name);
+        if Self::should_bless(&path) {
+            let csv: String = outputs.iter()
+                .map(|o| {
+                    let line: Vec<String> = o.iter()
+                        .map(f32::to_string)
+                        .collect();
+                    line.join(",") + "\n"
+                }).collect();
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, csv)
+                .unwrap_or_else(|e| panic!("Failed to write golden file {}: {e}", path.display()));
+            eprintln!("Wrote golden file {}", path.display());
+            return;
+        }
+
+        let golden: Vec<Vec<f32>> = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Failed to read golden file {}: {e}", path.display()))
+            .lines()
+            .map(|l| {
+                l.split(',')
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.parse().unwrap_or_else(|e| panic!("Invalid value {v} in golden file {}: {e}", path.display())))
+                    .collect()
+            }).collect();
+        Self::compare(name, outputs, &golden);
+    }
+    /// Compare the outputs against the golden WAV file with the given name
+    #[cfg(feature = "files")]
+    pub(crate) fn assert_golden_wav(name: &str, outputs: &[Vec<f32>]) {
+        let path = Self::golden_path(name);
+        let channels = outputs.first().map_or(1, Vec::len);
+        if Self::should_bless(&path) {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let spec = hound::WavSpec {
+                channels: channels as u16,
+                sample_rate: SR as u32,
+                bits_per_sample: 32,
+                sample_format: hound::SampleFormat::Float,
+            };
+            let mut writer = hound::WavWriter::create(&path, spec)
+                .unwrap_or_else(|e| panic!("Failed to write golden file {}: {e}", path.display()));
+            for s in outputs.iter().flatten() {
+                writer.write_sample(*s).unwrap();
+            }
+            writer.finalize().unwrap();
+            eprintln!("Wrote golden file {}", path.display());
+            return;
+        }
+
+        let mut reader = hound::WavReader::open(&path)
+            .unwrap_or_else(|e| panic!("Failed to read golden file {}: {e}", path.display()));
+        let golden_channels = usize::from(reader.spec().channels);
+        let samples: Vec<f32> = reader.samples::<f32>()
+            .map(Result::unwrap)
+            .collect();
+        let golden: Vec<Vec<f32>> = samples.chunks(golden_channels)
+            .map(<[f32]>::to_vec)
+            .collect();
+        Self::compare(name, outputs, &golden);
+    }
+}
+
+/// A sine wave with the given frequency
+pub(crate) fn sine(freq: f32) -> impl Fn(usize) -> Vec<f32> {
+    move |i| vec![(2.0 * PI * freq * i as f32 / SR).sin()]
+}
+/// A single impulse on the first step
+pub(crate) fn impulse(i: usize) -> Vec<f32> {
+    vec![if i == 0 {
+        1.0
+    } else {
+        0.0
+    }]
+}
+/// Deterministic white noise from a linear congruential generator
+pub(crate) fn noise(i: usize) -> Vec<f32> {
+    let x = (i as u32).wrapping_mul(1_103_515_245).wrapping_add(12345);
+    vec![(x >> 8) as f32 / (1u32 << 23) as f32 - 1.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_impulse() {
+        let mut h = Harness::new(r#"
+            type = "Delay"
+            knobs = [0.01, 0.5, 0.5]
+        "#);
+        let outputs = h.run(2048, impulse);
+        Harness::assert_golden_csv("delay_impulse.csv", &outputs);
+    }
+    #[test]
+    fn equalizer_lpf_noise() {
+        let mut h = Harness::new(r#"
+            type = "Equalizer"
+            func = "LPF"
+            knobs = [1000.0, 0.707, 1.0]
+        "#);
+        let outputs = h.run(4096, noise);
+        Harness::assert_golden_csv("equalizer_lpf_noise.csv", &outputs);
+    }
+    #[test]
+    fn equalizer_bpf_sine() {
+        let mut h = Harness::new(r#"
+            type = "Equalizer"
+            func = "BPF"
+            knobs = [440.0, 2.0, 1.0]
+        "#);
+        let outputs = h.run(4096, sine(440.0));
+        Harness::assert_golden_csv("equalizer_bpf_sine.csv", &outputs);
+    }
+    #[cfg(all(feature = "pitch_shifter", feature = "files"))]
+    #[test]
+    fn pitch_shifter_sine() {
+        let mut h = Harness::new(r#"
+            type = "PitchShifter"
+            knobs = [1.5]
+        "#);
+        let outputs = h.run(8192, sine(440.0));
+        Harness::assert_golden_wav("pitch_shifter_sine.wav", &outputs);
+    }
+}
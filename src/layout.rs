@@ -0,0 +1,81 @@
+/*!
+A window layout for performances which places the windows of modules with
+`is_own_window` enabled whenever a rack is loaded, so that they don't need to
+be arranged by hand before each show.
+
+The layout file is given by `layout` in `vince.toml` or the `--layout` flag,
+and is read from the current directory:
+
+```toml
+is_rack_hidden = true   # Hide the modules and patches in the main window
+
+[windows]               # Each module window, like a module's `window` table
+3 = { monitor = 1, is_fullscreen = true }
+"Preview" = { monitor = 0, resolution = [640, 480], is_always_on_top = true }
+```
+
+Windows are given by either the index of their module or its `name`, where the
+index takes precedence, and replace the module's own `window` table. Since a
+layout applies to every rack, modules can be given the same name in each rack
+so that their windows are placed in the same spot.
+
+*/
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use bevy::prelude::*;
+
+use serde::Deserialize;
+
+use crate::{config, modules::WindowOptions};
+
+static LAYOUT: OnceLock<Layout> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Layout {
+    /// Whether to hide the modules and patches in the main window, e.g. when
+    /// only the module windows are shown to the audience
+    #[serde(default)]
+    pub is_rack_hidden: bool,
+    /// The options for each module window by module index or name
+    #[serde(default)]
+    pub windows: HashMap<String, WindowOptions>,
+}
+impl Layout {
+    fn load(path: &str) -> Result<Self, String> {
+        let layout = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read layout {path}: {e}"))?;
+        toml::from_str(&layout)
+            .map_err(|e| format!("Invalid layout {path}: {e}"))
+    }
+    /// Get the window options for the module with the given index and name,
+    /// if the layout places it
+    pub fn window(&self, id: usize, name: Option<&str>) -> Option<&WindowOptions> {
+        self.windows.get(&id.to_string())
+            .or_else(|| name.and_then(|name| self.windows.get(name)))
+    }
+}
+
+/// Load the layout file if one is given in the config, exiting with an error
+/// if it's invalid
+pub fn init() {
+    let layout = match &config::get().layout {
+        Some(path) => match Layout::load(path) {
+            Ok(layout) => {
+                info!("Using window layout {path}");
+                layout
+            },
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            },
+        },
+        None => Layout::default(),
+    };
+    LAYOUT.set(layout).expect("Layout was already initialized");
+}
+/// Get the loaded layout, or an empty layout if it hasn't been loaded
+pub fn get() -> &'static Layout {
+    LAYOUT.get_or_init(Layout::default)
+}
@@ -0,0 +1,83 @@
+/*!
+The core of Vince as a library, i.e. the racks, the modules, and the stepping
+of them, so that the synth can be embedded in other front-ends or tests.
+
+The [`engine`] module loads a rack and steps it headlessly, while the `vince`
+binary runs racks in a Bevy app along with their UI, windows, and audio
+devices.
+
+```no_run
+use vince_core::engine::Engine;
+
+let mut engine = Engine::from_toml(r#"
+    [modules]
+    0M = { type = "AudioOut", knobs = [1.0] }
+    1M = { type = "Oscillator", func = "Sine", knobs = [0.0, 440.0, 1.0, 0.0] }
+
+    [patches]
+    1M0O = ["0M0I"]
+"#).unwrap();
+let audio = engine.render_audio(44100);
+```
+
+*/
+
+#![feature(type_alias_impl_trait)]
+#![feature(impl_trait_in_assoc_type)]
+#![feature(iter_array_chunks)]
+#![feature(extract_if)]
+#![feature(path_file_prefix)]
+
+#![deny(rust_2018_idioms)]
+#![allow(elided_lifetimes_in_paths)]
+
+#![allow(clippy::too_many_lines)]
+#![allow(clippy::too_many_arguments)]
+#![allow(clippy::type_complexity)]
+#![allow(clippy::needless_pass_by_value)]
+
+#[cfg(all(target_arch = "wasm32", any(feature = "video_in", feature = "serial", feature = "stream", feature = "plugins")))]
+compile_error!("The video_in, pose, serial, stream, and plugins features aren't supported on wasm32");
+
+pub mod config;
+
+pub mod layout;
+
+pub mod engine;
+
+pub mod rack;
+
+pub mod patch;
+
+pub mod patch_lang;
+
+#[cfg(feature = "vcv")]
+pub mod vcv;
+
+pub mod oversampling;
+
+pub mod session;
+
+pub mod replay;
+
+#[cfg(feature = "midi")]
+pub mod program_change;
+
+pub mod modules;
+
+pub mod plugins;
+
+#[cfg(target_arch = "wasm32")]
+pub mod web;
+
+pub mod export;
+
+#[cfg(test)]
+mod harness;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StepType {
+    Key,
+    Audio,
+    Video,
+}
@@ -16,13 +16,153 @@ It is recommended to run in release mode but it's no longer required.
 $ cargo run --release racks/
 ```
 
+### Configuration
+
+App-level settings such as the main window, frame rate, default audio
+devices, and default rack can be set in a `vince.toml` file in the current
+directory, and each of them can be overridden with a command line flag. See
+the [`config`][vince_core::config] module for details.
+
+### Library
+
+The racks, modules, and stepping are also built as the `vince_core` library
+so that the synth can be embedded in other front-ends or tests. Its headless
+engine loads a rack and renders its audio and video signals without running
+the app, see [`vince_core::engine`] for details.
+
+Third-party modules can be registered by front-ends which embed the library,
+or built as plugins which are loaded from the `plugins` directory at startup
+when built with the `plugins` feature, see [`vince_core::plugins`] for details.
+
+### Web
+
+Racks can also run in the browser, where the main window is drawn into a
+canvas and audio is played with WebAudio. Build for `wasm32-unknown-unknown`
+without the features that need native devices, then serve the `web` directory
+along with a copy of the assets:
+
+```
+$ cargo build --release --target wasm32-unknown-unknown --no-default-features --features midi,pitch_shifter
+$ wasm-bindgen --out-dir web/pkg --target web target/wasm32-unknown-unknown/release/vince.wasm
+$ cp -r assets web/
+```
+
+Patches can be shared as links with either a rack path, e.g.
+`index.html?rack=racks/rack1.toml`, or a whole URL-encoded rack, e.g.
+`index.html#rack=%5Bmodules%5D...`, see [`vince_core::web`] for details.
+
+### Controls
+
+ * `Left` and `Right` - switch to the previous or next rack in the directory
+ * `Tab` - open the rack select menu which lists every rack in the directory
+   along with the `[info]` of the selected one, then use `Up` and `Down` or
+   hover to select a rack and `Enter` or click to load it, or `Tab` or
+   `Escape` to close the menu
+ * `F1` - hold while hovering over a module to show its inputs, outputs, and
+   knobs along with their ranges
+ * `Ctrl+D` - duplicate the module under the cursor along with its current
+   knob values, which lasts until the rack is reloaded
+ * `Ctrl+P` - open the command palette to search the modules by name or
+   index, then use `Up` and `Down` to select one and `Enter` to center it on
+   screen or focus its own window, or `Escape` to close the palette
+ * `Ctrl+Tab` and `Ctrl+Shift+Tab` - move the keyboard focus to the next or
+   previous module which takes keyboard input, e.g. a `KeyboardIn`, or click on
+   one to focus it. Only the focused module, which is outlined in white,
+   receives keystrokes
+ * Hover over a patch cable to show the current value of its signal along with
+   its range over the last second. Cables brighten with the amplitude of their
+   signal and turn grey while it's NaN, e.g. when an upstream module is
+   bypassed or unpatched, and dashes flow along them faster for louder and
+   busier signals
+ * `F11` - toggle fullscreen for the focused window
+ * `Escape` - quit
+
+### JACK
+
+On Linux, Vince can be built with the `jack` feature in order to run as a JACK
+client instead of using the default audio device. The JACK host must then be
+selected in the rack's `[audio]` section:
+
+```toml
+[audio]
+host = "JACK"
+```
+
+The audio ports are registered by the `cpal_client_out` and `cpal_client_in`
+clients, and the MIDI ports are registered by the `Vince MidiIn` client as
+`vince-midi-in`, so that they can be routed to other JACK clients.
+
+### Session Log
+
+A performance can be recorded to a JSONL file by setting the
+`VINCE_SESSION_LOG` environment variable to its path. Each line records a rack
+being loaded, a knob changing, a MIDI message, or an error along with the time
+since the session started, so that the performance can be reconstructed or
+debugged afterwards:
+
+```
+$ VINCE_SESSION_LOG=session.jsonl cargo run --release racks/
+```
+
+### Input Recording
+
+The keyboard, mouse, MIDI, and rack audio input can be recorded to a file by
+setting the `VINCE_RECORD_INPUTS` environment variable to its path. Setting
+`VINCE_REPLAY_INPUTS` to the path of a recording replays it into the rack
+instead of the live MIDI and audio input, along with the rack's seed, so that
+bugs can be reproduced or a take can be re-performed while tweaking the patch.
+Other inputs aren't recorded, i.e. touches and the `SerialIn`, `NetReceive`,
+`MotionIn`, `PoseIn`, and `VideoIn` modules along with `AudioIn` modules with
+their own `device`, so a warning is logged for each of these modules while
+replaying:
+
+```
+$ VINCE_RECORD_INPUTS=take.toml cargo run --release racks/rack1.toml
+$ VINCE_REPLAY_INPUTS=take.toml cargo run --release racks/rack1.toml
+```
+
+### Audio Devices
+
+If the audio device disappears, e.g. when a USB interface is unplugged, or the
+system's default output device changes, the audio streams are rebuilt on the
+new device automatically. The rack keeps running silently while no device is
+available and continues from silence once one is.
+
 # Racks
 
 Racks consist of modules and the patches between them. They are defined as TOML
 files under the `assets/racks` directory which is relative to either the
 `CARGO_MANIFEST_DIR` or the built executable. See the provided racks for
 details on how to make your own. When a rack file is modified, it will be
-hot-reloaded without needing to restart the program.
+hot-reloaded without needing to restart the program, and an overlay briefly
+lists the modules, knobs, and patches which were added, removed, or changed.
+
+When a directory of racks is loaded, they're played in the order of their file
+paths. A rack can instead be placed in a setlist with `order` in its `[info]`
+section, where the racks with an order are played first from lowest to
+highest, so that the set can be arranged without renaming the files:
+
+```toml
+[info]
+name = "Opening"
+order = 1
+```
+
+Setting `duration` in a rack's `[info]` section to a number of seconds
+automatically advances to the next rack once it has played for that long,
+which allows a directory of racks to play unattended, e.g. for an
+installation. The `Info` module counts down the time left.
+
+Racks can also be switched hands-free with MIDI Program Change messages, e.g.
+from a foot controller, by setting a `[program_change]` channel in
+`vince.toml`. Each program selects the rack with the same `program` in its
+`[info]` section, or otherwise the rack at that index in the order, see [`vince_core::program_change`]
+for details.
+
+When the control screen is projected as part of the show, setting
+`theme = "Reactive"` in a rack's `[info]` section makes the module backgrounds
+and patch cables pulse with the level of the audio output, see
+[`vince_core::modules::info`] for details.
 
 Basic example rack:
 
@@ -47,12 +187,83 @@ Optionally, each module can be named. This name will appear on screen in place
 of the module type. The remaining parameters are module-specific so be sure to
 read each module's documentation to understand what each one does.
 
+Modules can also be organized with a `group` name and a `color`. Modules in the
+same group are shown together under a header after the ungrouped modules, and
+the color tints the module's background:
+
+```toml
+5 = { type = "Oscillator", func = "Sine", group = "voices", color = "#aa3355", knobs = [0.0, 220.0, 1.0, 0.0] }
+```
+
+A module can instead be placed at an explicit `pos = [x, y]` in pixels from the
+top left of the main window, which takes it out of the automatic layout and its
+group:
+
+```toml
+6 = { type = "Oscilloscope", pos = [800, 40] }
+```
+
 A patch consists of a key defining the output index and an array that lists the
 input indices that the given output should be copied to. Each IO index is
 specific to a certain module, so it also contains the module index. Patches can
 also be created between outputs and knobs. See `racks/rack1.toml` for an
 example.
 
+Racks can also be written in a terser text format in `.vince` files, where
+modules are referred to by name instead of by index, see [`vince_core::patch_lang`] for details:
+
+```text
+out: AudioOut 0.1
+osc1: Oscillator Sine 0 440 1 0 -> out.0
+lfo: Oscillator Triangle 0 1 1 0 -> osc1.k1
+```
+
+With the `vcv` feature, VCV Rack patches in `.vcv` files are also loaded as
+racks by mapping their common modules onto Vince's, or they can be converted to
+TOML with the `vcv2vince` binary, see [`vince_core::vcv`] for details.
+
+Knobs can be held to a range in the `[knob_ranges]` table, where any `min` or
+`max` that isn't given defaults to the range in the module's documentation,
+which is otherwise only advisory. The value can also be quantized to a `step`,
+and patched values in the range [0.0, 1.0] can be scaled onto the knob's range
+with `scaling` set to `Linear` or `Exp`, e.g. for sweeping a filter's
+frequency. Knobs which are changed in other ways, e.g. by MIDI or the mouse,
+are clamped to the range once per frame:
+
+```toml
+[knob_ranges]
+4M0K = { min = 20.0, max = 20000.0, scaling = "Exp" }
+```
+
+Inputs and outputs also follow the signal standards given by the ranges in
+their module's documentation, either audio in the range [-1.0, 1.0], unipolar
+control or video in the range [0.0, 1.0], or a frequency in Hz. A warning is
+logged when a patch connects two different standards, e.g. a `MidiIn`
+frequency to a `ComponentVideoOut` channel. Setting `calibrate = "Auto"` in the
+rack's `[info]` section converts these patches between the standards instead,
+where frequencies are mapped exponentially from 20 Hz to 20 kHz, and
+`calibrate = "Off"` disables the check.
+
+If a module panics while stepping or a patch refers to an input or knob that
+the module doesn't have, the module is bypassed instead of crashing the rack.
+Its outputs become unpatched and the error is shown on its panel until the rack
+is reloaded.
+
+Modules which display video or waveforms, such as `ComponentVideoOut`, can be
+opened in their own window by setting `is_own_window = true`. That window can
+be configured with a `window` table that sets the `monitor` index, the
+`resolution` as `[width, height]`, `is_always_on_top`, and `is_fullscreen` to
+make it borderless fullscreen on its monitor. For example, to send the video to
+a projector while the rack stays on the main screen:
+
+```toml
+3 = { type = "ComponentVideoOut", is_own_window = true, window = { monitor = 1, is_fullscreen = true } }
+```
+
+For performances, the module windows of every rack can instead be placed by a
+layout file given by `layout` in `vince.toml`, which can also hide the rack in
+the main window, see [`vince_core::layout`] for details.
+
 */
 
 #![feature(type_alias_impl_trait)]
@@ -69,7 +280,7 @@ example.
 #![allow(clippy::type_complexity)]
 #![allow(clippy::needless_pass_by_value)]
 
-#[cfg(not(target_env = "msvc"))]
+#[cfg(not(any(target_env = "msvc", target_arch = "wasm32")))]
 #[global_allocator]
 static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
@@ -77,48 +288,79 @@ use std::ops::DerefMut;
 use std::path::{Path, PathBuf};
 use std::sync::{Mutex, atomic::{self, AtomicUsize}};
 use std::{time::Duration, cmp};
-use std::env;
 
-use bevy::{prelude::*, app::AppExit, asset::{LoadState, ChangeWatcher}, sprite::{MaterialMesh2dBundle, Mesh2dHandle}, window::{PrimaryWindow, WindowResolution, PresentMode, WindowRef, WindowMode, WindowResized}, render::{render_resource::PrimitiveTopology, camera::{RenderTarget, ScalingMode}}};
+use bevy::{prelude::*, ecs::system::EntityCommands, utils::HashMap, app::AppExit, input::InputSystem, asset::{LoadState, ChangeWatcher}, sprite::{MaterialMesh2dBundle, Mesh2dHandle}, window::{PrimaryWindow, WindowResolution, WindowPosition, WindowLevel, MonitorSelection, PresentMode, WindowRef, WindowMode, WindowResized, RawHandleWrapper}, render::{render_resource::PrimitiveTopology, camera::{RenderTarget, ScalingMode}}};
 
 use bevy_common_assets::toml::TomlAssetPlugin;
 
-pub mod rack;
-use rack::{Rack, RackHandles};
-
-pub mod patch;
-use patch::PatchComponent;
+use vince_core::{StepType, config, layout, session, replay, modules};
+use vince_core::rack::{Rack, RackLoaderPlugin, RackHandles, AudioStreams, ModuleLayout, RackSummary};
+use vince_core::patch::{PatchComponent, PatchCableComponent};
+use vince_core::modules::{Module, WindowOptions, TopModuleComponent, ModuleComponent, ModuleTextComponent, ModuleMeshComponent, ModuleImageComponent, ModuleImageWindowComponent, ModuleExtraWindowComponent, ModuleKey, ModuleIOK};
 
-pub mod modules;
-use modules::{Module, TopModuleComponent, ModuleComponent, ModuleTextComponent, ModuleMeshComponent, ModuleImageComponent, ModuleImageWindowComponent, ModuleKey, ModuleIOK};
-
-const FRAME_RATE: u16 = 60;
+/// How long to show the summary of changes after a rack is hot-reloaded
+const RELOAD_DIFF_DURATION: Duration = Duration::from_secs(5);
+/// The sample rate to step at while there's no audio output device
+const FALLBACK_SAMPLE_RATE: u64 = 44100;
 
 static RACK_DIR_IDX: AtomicUsize = AtomicUsize::new(0);
 
 static CONTINUOUS_TIME: Mutex<Option<f64>> = Mutex::new(None);
 
 fn main() {
+    config::init();
+    let config = config::get();
+    layout::init();
+    #[cfg(feature = "plugins")]
+    vince_core::plugins::load_dir(Path::new(&config.plugin_dir));
+
+    let mut window = Window {
+        title: "Vince Audio-Video Synth".to_string(),
+        present_mode: PresentMode::AutoNoVsync,
+        ..default()
+    };
+    if cfg!(target_arch = "wasm32") {
+        window.canvas = Some("#vince".to_string());
+        window.fit_canvas_to_parent = true;
+    }
+    if let Some([w, h]) = config.window.resolution {
+        window.resolution = WindowResolution::new(w, h);
+    }
+    if let Some(monitor) = config.window.monitor {
+        window.position = WindowPosition::Centered(MonitorSelection::Index(monitor));
+    }
+    if config.window.is_always_on_top {
+        window.window_level = WindowLevel::AlwaysOnTop;
+    }
+    if config.window.is_fullscreen {
+        window.mode = WindowMode::BorderlessFullscreen;
+    }
+
     App::new()
         .add_plugins(DefaultPlugins.set(AssetPlugin {
+            asset_folder: config.asset_dir.clone(),
             watch_for_changes: ChangeWatcher::with_delay(Duration::from_millis(200)),
-            ..default()
         }).set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "Vince Audio-Video Synth".to_string(),
-                present_mode: PresentMode::AutoNoVsync,
-                ..default()
-            }),
+            primary_window: Some(window),
             ..default()
         })).add_plugins(TomlAssetPlugin::<Rack>::new(&["toml"]))
+        .add_plugins(RackLoaderPlugin)
         .add_plugins(bevy_framepace::FramepacePlugin)
         .add_state::<AppState>()
-        .insert_resource(FixedTime::new_from_secs(1.0 / f32::from(FRAME_RATE)))
+        .init_non_send_resource::<AudioStreams>()
+        .init_resource::<CommandPalette>()
+        .init_resource::<RackSelect>()
+        .init_resource::<ReloadDiff>()
+        .add_event::<RackSwitch>()
+        .insert_resource(FixedTime::new_from_secs(1.0 / f32::from(config.frame_rate)))
         .add_systems(Startup, load_rack)
+        .add_systems(PreUpdate, replay::replay_inputs.after(InputSystem))
+        .add_systems(Last, replay::record_inputs)
         .add_systems(Update, setup.run_if(in_state(AppState::Loading)))
+        .add_systems(Update, module_extra_windows)
         .add_systems(Update, setup_patches.run_if(in_state(AppState::Loaded)))
-        .add_systems(Update, (rack_reloader, keyboard_input, mouse_input, window_resize).run_if(in_state(AppState::Ready)))
-        .add_systems(FixedUpdate, (rack_stepper, rack_render).run_if(in_state(AppState::Ready)))
+        .add_systems(Update, (rack_reloader, keyboard_input, command_palette.after(keyboard_input), rack_select.after(keyboard_input), rack_autoplay, rack_program_change, rack_switcher.after(rack_reloader).after(keyboard_input).after(rack_select).after(rack_autoplay).after(rack_program_change), mouse_input, help_overlay, signal_tooltip, cable_render, theme_render, focus_render, reload_diff_overlay, module_errors, duplicate_module, window_resize, window_fullscreen).run_if(in_state(AppState::Ready)))
+        .add_systems(FixedUpdate, (rack_stepper, rack_render, session_knobs.after(rack_stepper)).run_if(in_state(AppState::Ready)))
         .run();
 }
 
@@ -129,22 +371,95 @@ enum AppState {
     Loaded,
     Ready,
 }
+/// A request to tear down the current rack and load another, which is handled
+/// by `rack_switcher` so that every way of switching racks behaves the same
+#[derive(Event, Debug, Clone, Copy)]
+enum RackSwitch {
+    /// Exit the current rack and load the rack with the given index
+    Load(usize),
+    /// Load the current rack again after its file was modified
+    Reload,
+}
 
-#[derive(Component)]
-pub struct CameraComponent;
 #[derive(Component)]
 pub struct MainCameraComponent;
+/// A window which will be made fullscreen once it's been created
+#[derive(Component)]
+pub struct PendingFullscreenComponent;
+#[derive(Component)]
+pub struct HelpOverlayComponent;
+#[derive(Component)]
+pub struct CommandPaletteComponent;
+#[derive(Component)]
+pub struct SignalTooltipComponent;
+/// A module's panel text showing why the module with the given index was
+/// bypassed
+#[derive(Component)]
+pub struct ModuleErrorComponent(usize);
+/// An overlay showing the changes of a hot-reload until its timer finishes
+#[derive(Component)]
+pub struct ReloadDiffComponent(Timer);
+/// The root UI node of the main window's module layout
+#[derive(Component)]
+pub struct RackLayoutComponent;
+#[derive(Component)]
+pub struct RackSelectComponent;
+/// An entry of the rack select menu for the rack with the given index
+#[derive(Component)]
+pub struct RackSelectItemComponent(usize);
+/// The rack select menu's preview of the selected rack's info
+#[derive(Component)]
+pub struct RackSelectInfoComponent;
+/// A window which shows the module with the given index
+#[derive(Component)]
+pub struct ModuleWindowComponent(usize);
+/// The background color of a module before it's pulsed by the theme
+#[derive(Component)]
+pub struct ThemeBaseColor(Color);
+
+/// The state of the `Ctrl+P` command palette
+#[derive(Resource, Default)]
+struct CommandPalette {
+    is_open: bool,
+    query: String,
+    selected: usize,
+}
+/// The state of the `Tab` rack select menu
+#[derive(Resource, Default)]
+struct RackSelect {
+    is_open: bool,
+    selected: usize,
+}
+/// The summary of the last loaded rack, used to show what changed when it's
+/// hot-reloaded
+#[derive(Resource, Default)]
+struct ReloadDiff {
+    previous: Option<RackSummary>,
+    is_reload: bool,
+}
 
-fn load_rack(mut commands: Commands, asset_server: Res<AssetServer>, mut settings_fp: ResMut<bevy_framepace::FramepaceSettings>, mut q_window: Query<&mut Window, With<PrimaryWindow>>) {
-    settings_fp.limiter = bevy_framepace::Limiter::from_framerate(f64::from(FRAME_RATE));
+fn load_rack(mut commands: Commands, asset_server: Res<AssetServer>, mut racks: ResMut<Assets<Rack>>, mut settings_fp: ResMut<bevy_framepace::FramepaceSettings>, mut q_window: Query<&mut Window, With<PrimaryWindow>>) {
+    settings_fp.limiter = bevy_framepace::Limiter::from_framerate(f64::from(config::get().frame_rate));
+    session::init();
+    replay::init();
+    #[cfg(feature = "midi")]
+    vince_core::program_change::init();
+
+    // Load rack from config, or from the page's URL in the browser
+    let rack_path = config::get().rack.clone();
+    #[cfg(target_arch = "wasm32")]
+    let shared_rack = vince_core::web::shared_rack()
+        .and_then(|toml| {
+            toml::from_str::<Rack>(&toml)
+                .map_err(|e| error!("Invalid shared rack: {e}"))
+                .ok()
+        });
+    #[cfg(not(target_arch = "wasm32"))]
+    let shared_rack: Option<Rack> = None;
 
-    // Load rack from config
-    let rack_path = if let Some(rack_path) = env::args().nth(1) {
-        rack_path
-    } else {
-        "racks/".to_string()
-    };
-    let h_racks = if Path::new(&rack_path).is_dir() {
+    let h_racks = if let Some(rack) = shared_rack {
+        RackHandles(vec![racks.add(rack)])
+    } else if Path::new(&rack_path).is_dir() {
         RackHandles(
             asset_server.load_folder(rack_path.clone())
                 .unwrap_or_else(|e| panic!("Failed to load rack folder {}: {e}", rack_path))
@@ -161,15 +476,11 @@ fn load_rack(mut commands: Commands, asset_server: Res<AssetServer>, mut setting
         window.title = format!("Vince Audio-Video Synth - {rack_path}");
     }
 }
-fn setup(mut commands: Commands, mut h_racks: ResMut<RackHandles>, mut racks: ResMut<Assets<Rack>>, mut images: ResMut<Assets<Image>>, mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<ColorMaterial>>, asset_server: Res<AssetServer>, mut state: ResMut<NextState<AppState>>, mut q_window: Query<&mut Window, With<PrimaryWindow>>, mut exit: EventWriter<AppExit>) {
+fn setup(mut commands: Commands, mut h_racks: ResMut<RackHandles>, mut racks: ResMut<Assets<Rack>>, mut images: ResMut<Assets<Image>>, mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<ColorMaterial>>, asset_server: Res<AssetServer>, mut state: ResMut<NextState<AppState>>, mut q_window: Query<&mut Window, With<PrimaryWindow>>, mut streams: NonSendMut<AudioStreams>, mut reload_diff: ResMut<ReloadDiff>, mut is_sorted: Local<bool>, mut exit: EventWriter<AppExit>) {
     for rh in &h_racks.0 {
         if racks.get(rh).is_none() {
             if asset_server.get_load_state(rh) == LoadState::Failed {
-                let rack_path = if let Some(rack_path) = env::args().nth(1) {
-                    rack_path
-                } else {
-                    "racks/".to_string()
-                };
+                let rack_path = config::get().rack.clone();
                 error!("Invalid file path: {}", rack_path);
                 error!("Check whether you need to enable a feature");
                 exit.send(AppExit);
@@ -178,11 +489,29 @@ fn setup(mut commands: Commands, mut h_racks: ResMut<RackHandles>, mut racks: Re
         }
     }
 
-    h_racks.0.sort_by_key(|rh| {
-        match asset_server.get_handle_path(rh) {
+    // Order the racks by their `order` info followed by their paths, keeping
+    // the current rack selected if the order changes on a hot-reload
+    let current = is_sorted.then(|| {
+        h_racks.0[RACK_DIR_IDX.load(atomic::Ordering::Acquire)].clone()
+    });
+    h_racks.0.sort_by_cached_key(|rh| {
+        let order = racks.get(rh)
+            .and_then(|rack| rack.info.get("order"))
+            .and_then(|order| order.parse::<i64>().ok());
+        let path = match asset_server.get_handle_path(rh) {
             Some(path) => path.path().to_owned(),
             None => PathBuf::new(),
-        }
+        };
+        (order.is_none(), order, path)
+    });
+    if let Some(idx) = current.and_then(|current| h_racks.0.iter().position(|rh| *rh == current)) {
+        RACK_DIR_IDX.store(idx, atomic::Ordering::Release);
+    }
+    *is_sorted = true;
+
+    let next_rack = (h_racks.0.len() > 1).then(|| {
+        let next_idx = (RACK_DIR_IDX.load(atomic::Ordering::Acquire) + 1) % h_racks.0.len();
+        rack_name(&racks, &asset_server, &h_racks.0[next_idx])
     });
 
     if let Some(rack) = racks.get_mut(
@@ -191,11 +520,36 @@ fn setup(mut commands: Commands, mut h_racks: ResMut<RackHandles>, mut racks: Re
         ]
     ) {
         // Init rack info
-        let rack_path = if let Some(rack_path) = env::args().nth(1) {
-            rack_path
-        } else {
-            "racks/".to_string()
+        rack.init_seed();
+        rack.init_knob_ranges();
+        rack.init_calibrations();
+        replay::warn_unreplayable(rack);
+
+        if let Some(path) = asset_server.get_handle_path(
+            &h_racks.0[
+                RACK_DIR_IDX.load(atomic::Ordering::Acquire)
+            ]
+        ) {
+            session::record("rack", &[
+                ("path", session::Field::Str(&path.path().to_string_lossy())),
+                ("reload", session::Field::Bool(reload_diff.is_reload)),
+            ]);
+        }
+
+        // Show what changed if the rack was hot-reloaded
+        let summary = rack.summary();
+        if reload_diff.is_reload {
+            let changes = reload_diff.previous.as_ref()
+                .map(|previous| previous.diff(&summary))
+                .unwrap_or_default();
+            spawn_reload_diff(&mut commands, &changes);
+        }
+        *reload_diff = ReloadDiff {
+            previous: Some(summary),
+            is_reload: false,
         };
+
+        let rack_path = config::get().rack.clone();
         let mut window_title = format!("Vince Audio-Video Synth - {rack_path}");
         if let Ok(mut window) = q_window.get_single_mut() {
             if let Some(name) = rack.info.get("name") {
@@ -215,33 +569,34 @@ fn setup(mut commands: Commands, mut h_racks: ResMut<RackHandles>, mut racks: Re
                     id: usize::MAX,
                     iok: ModuleIOK::None,
                 },
-                Box::new(modules::info::Info::new(rack.info.clone())),
+                Box::new(modules::info::Info::new(rack.info.clone(), next_rack)),
             );
         }
 
         // Main camera
         commands.spawn((
             Camera2dBundle::default(),
-            CameraComponent,
             MainCameraComponent,
         ));
 
         // Module rects
-        let ts = TextStyle {
-            font_size: 16.0,
-            color: Color::WHITE,
-            ..default()
-        };
-        let mut component = commands.spawn(
+        let ts = module_text_style();
+        let mut component = commands.spawn((
             NodeBundle {
                 style: Style {
                     flex_wrap: FlexWrap::Wrap,
                     align_content: AlignContent::FlexStart,
                     ..default()
                 },
+                visibility: if layout::get().is_rack_hidden {
+                    Visibility::Hidden
+                } else {
+                    Visibility::Inherited
+                },
                 ..default()
             },
-        );
+            RackLayoutComponent,
+        ));
         let mut sorted_modules = rack.modules.iter_mut().collect::<Vec<(&ModuleKey, &mut Box<dyn Module>)>>();
         sorted_modules.sort_by(|a, b| {
             if a.0.id == usize::MAX {
@@ -252,47 +607,75 @@ fn setup(mut commands: Commands, mut h_racks: ResMut<RackHandles>, mut racks: Re
                 a.0.cmp(b.0)
             }
         });
-        component.with_children(|parent| {
-            for m in &mut sorted_modules {
-                if m.1.is_own_window() {
-                    continue;
-                }
 
-                m.1.init(
-                    m.0.id,
-                    parent.spawn((
-                        NodeBundle {
-                            style: Style {
-                                width: if m.1.is_large() {
-                                    Val::Px(660.0)
-                                } else {
-                                    Val::Px(170.0)
-                                },
-                                height: if m.1.is_large() {
-                                    Val::Px(550.0)
-                                } else {
-                                    Val::Px(200.0)
+        // Place ungrouped modules first, followed by each group in the order
+        // of their first module, and finally the modules with an explicit
+        // position
+        let layout = &rack.layout;
+        let no_layout = ModuleLayout::default();
+        let layout_of = |id: usize| layout.get(&id).unwrap_or(&no_layout);
+        let group_of = |id: usize| layout_of(id).group.as_deref();
+        let mut groups: Vec<Option<&str>> = vec![None];
+        for m in &sorted_modules {
+            if layout_of(m.0.id).pos.is_none() && !groups.contains(&group_of(m.0.id)) {
+                groups.push(group_of(m.0.id));
+            }
+        }
+        component.with_children(|parent| {
+            for group in groups {
+                let modules = sorted_modules.iter_mut()
+                    .filter(|m| !m.1.is_own_window() && layout_of(m.0.id).pos.is_none() && group_of(m.0.id) == group);
+                match group {
+                    None => {
+                        for m in modules {
+                            init_module(parent, m.0.id, m.1, layout_of(m.0.id), &mut images, &mut meshes, &mut materials, &ts);
+                        }
+                    },
+                    Some(name) => {
+                        parent.spawn((
+                            NodeBundle {
+                                style: Style {
+                                    width: Val::Percent(100.0),
+                                    flex_wrap: FlexWrap::Wrap,
+                                    align_content: AlignContent::FlexStart,
+                                    margin: UiRect::vertical(Val::Px(5.0)),
+                                    ..default()
                                 },
-                                margin: UiRect::all(Val::Px(5.0)),
-                                padding: UiRect::all(Val::Px(10.0)),
-                                overflow: Overflow::clip(),
+                                background_color: Color::rgba(1.0, 1.0, 1.0, 0.05).into(),
                                 ..default()
                             },
-                            background_color: Color::DARK_GRAY.into(),
-                            ..default()
-                        },
-                        TopModuleComponent,
-                    )),
-                    &mut images,
-                    &mut meshes,
-                    &mut materials,
-                    ts.clone(),
-                );
+                            TopModuleComponent,
+                        )).with_children(|parent| {
+                            parent.spawn(
+                                TextBundle::from_section(name, ts.clone())
+                                    .with_style(Style {
+                                        width: Val::Percent(100.0),
+                                        margin: UiRect::all(Val::Px(5.0)),
+                                        ..default()
+                                    })
+                            );
+                            for m in modules {
+                                init_module(parent, m.0.id, m.1, layout_of(m.0.id), &mut images, &mut meshes, &mut materials, &ts);
+                            }
+                        });
+                    },
+                }
+            }
+
+            for m in sorted_modules.iter_mut()
+                .filter(|m| !m.1.is_own_window() && layout_of(m.0.id).pos.is_some())
+            {
+                init_module(parent, m.0.id, m.1, layout_of(m.0.id), &mut images, &mut meshes, &mut materials, &ts);
             }
         });
 
-        // Init modules which have their own window
+        // Init modules which have their own window, placing them according to
+        // the layout
         for m in &mut sorted_modules {
+            let layout_window = layout::get().window(m.0.id, m.1.name().as_deref());
+            if layout_window.is_some() && !m.1.is_own_window() {
+                warn!("Ignoring layout window for M{}: the module doesn't have is_own_window enabled", m.0.id);
+            }
             if m.1.is_own_window() {
                 let mname = m.1.name()
                     .unwrap_or_else(|| {
@@ -302,18 +685,17 @@ fn setup(mut commands: Commands, mut h_racks: ResMut<RackHandles>, mut racks: Re
                             .unwrap()
                             .to_string()
                     });
-                let child_window = commands.spawn(
-                    Window {
-                        title: format!("{} - {}", window_title, mname),
-                        resolution: if m.1.is_large() {
-                            WindowResolution::new(640.0, 480.0)
-                        } else {
-                            WindowResolution::new(150.0, 100.0)
-                        },
-                        present_mode: PresentMode::AutoNoVsync,
-                        ..default()
-                    }
-                ).id();
+                let child_window = spawn_module_window(
+                    &mut commands,
+                    format!("{} - {}", window_title, mname),
+                    if m.1.is_large() {
+                        [640.0, 480.0]
+                    } else {
+                        [150.0, 100.0]
+                    },
+                    layout_window.unwrap_or(&m.1.window_options()),
+                );
+                commands.entity(child_window).insert(ModuleWindowComponent(m.0.id));
                 let _child_camera = commands.spawn((
                     Camera2dBundle {
                         camera: Camera {
@@ -340,7 +722,6 @@ fn setup(mut commands: Commands, mut h_racks: ResMut<RackHandles>, mut racks: Re
                     UiCameraConfig {
                         show_ui: false,
                     },
-                    CameraComponent,
                 ));
 
                 m.1.init(
@@ -377,7 +758,7 @@ fn setup(mut commands: Commands, mut h_racks: ResMut<RackHandles>, mut racks: Re
         }
 
         // Setup audio
-        rack.init_audio();
+        rack.init_audio(&mut streams);
 
         state.set(AppState::Loaded);
     }
@@ -389,6 +770,7 @@ fn setup_patches(mut commands: Commands, racks: Res<Assets<Rack>>, h_racks: ResM
         ]
     ) {
         // Patch cables
+        const CABLE_SUBDIVISIONS: usize = 8;
         let colors = [
             Color::RED,
             Color::ORANGE,
@@ -410,33 +792,56 @@ fn setup_patches(mut commands: Commands, racks: Res<Assets<Rack>>, h_racks: ResM
             };
             if let Some(mout) = rack.modules.get(&mout_id) {
                 if let Some(min) = rack.modules.get(&min_id) {
-                    let startpos = mout.get_world_pos(&q_child, &q_transform, &q_main_camera) + Vec3::new(50.0, 0.0, 0.0);
-                    let endpos = min.get_world_pos(&q_child, &q_transform, &q_main_camera) + Vec3::new(-50.0, 0.0, 0.0);
+                    let startpos = mout.get_world_pos(&q_child, &q_transform, q_main_camera.get_single().ok()) + Vec3::new(50.0, 0.0, 0.0);
+                    let endpos = min.get_world_pos(&q_child, &q_transform, q_main_camera.get_single().ok()) + Vec3::new(-50.0, 0.0, 0.0);
                     let mut bottom = (startpos.y+endpos.y)/2.0;
                     bottom = bottom.min(startpos.y).min(endpos.y);
                     let midpos = Vec3::new((startpos.x+endpos.x)/2.0, bottom - 50.0 - 5.0 * i as f32, 0.0);
 
-                    let points: Vec<Vec3> = [
+                    let cable = [
                         startpos,
                         startpos.lerp(midpos, 0.5) - Vec3::Y * 10.0,
                         midpos,
                         midpos.lerp(endpos, 0.5) - Vec3::Y * 10.0,
                         endpos,
-                    ].iter()
+                    ];
+                    // Subdivide the cable so that its dashes can flow
+                    // smoothly along it
+                    let cable: Vec<Vec3> = cable.windows(2)
+                        .flat_map(|seg| {
+                            (0..CABLE_SUBDIVISIONS).map(|j| seg[0].lerp(seg[1], j as f32 / CABLE_SUBDIVISIONS as f32))
+                        }).chain([endpos])
+                        .collect();
+                    let points: Vec<Vec3> = cable.iter()
                         .map(|p| *p - startpos)
                         .collect();
 
                     let mut mesh = Mesh::new(PrimitiveTopology::LineStrip);
+                    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, vec![[1.0; 4]; points.len()]);
                     mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, points);
 
+                    let color = colors[i % colors.len()];
                     let _component = commands.spawn((
                         MaterialMesh2dBundle {
                             mesh: meshes.add(mesh).into(),
-                            material: materials.add(colors[i % colors.len()].into()),
+                            material: materials.add(color.into()),
                             transform: Transform::from_translation(startpos),
+                            visibility: if layout::get().is_rack_hidden {
+                                Visibility::Hidden
+                            } else {
+                                Visibility::Inherited
+                            },
                             ..default()
                         },
                         PatchComponent,
+                        PatchCableComponent {
+                            output: *patch.0,
+                            points: cable.iter()
+                                .map(|p| p.truncate())
+                                .collect(),
+                            color,
+                            flow: 0.0,
+                        },
                     ));
                 }
             }
@@ -446,48 +851,182 @@ fn setup_patches(mut commands: Commands, racks: Res<Assets<Rack>>, h_racks: ResM
     }
 }
 
-fn rack_reloader(mut commands: Commands, mut ev_asset: EventReader<AssetEvent<Rack>>, mut racks: ResMut<Assets<Rack>>, h_racks: ResMut<RackHandles>, mut state: ResMut<NextState<AppState>>, q_any: Query<Entity, Or::<(With<CameraComponent>, With<TopModuleComponent>, With<ModuleMeshComponent>, With<ModuleImageWindowComponent>, With<PatchComponent>)>>, q_windows: Query<Entity, (With<Window>, Without<PrimaryWindow>)>) {
+/// The name of the given rack from its `[info]` section, or its file name
+fn rack_name(racks: &Assets<Rack>, asset_server: &AssetServer, rh: &Handle<Rack>) -> String {
+    racks.get(rh)
+        .and_then(|rack| rack.info.get("name").cloned())
+        .or_else(|| {
+            asset_server.get_handle_path(rh)
+                .and_then(|path| path.path().file_name().map(|f| f.to_string_lossy().to_string()))
+        }).unwrap_or_default()
+}
+/// Spawn the UI node for a module and init the module in it
+fn init_module(parent: &mut ChildBuilder<'_, '_, '_>, id: usize, m: &mut Box<dyn Module>, layout: &ModuleLayout, images: &mut ResMut<Assets<Image>>, meshes: &mut ResMut<Assets<Mesh>>, materials: &mut ResMut<Assets<ColorMaterial>>, ts: &TextStyle) {
+    m.init(
+        id,
+        parent.spawn((
+            module_node(m.as_ref(), layout),
+            TopModuleComponent,
+        )),
+        images,
+        meshes,
+        materials,
+        ts.clone(),
+    );
+}
+/// The UI node which contains a module, tinted and positioned according to
+/// its layout
+fn module_node(m: &dyn Module, layout: &ModuleLayout) -> NodeBundle {
+    let (position_type, left, top) = match layout.pos {
+        Some(pos) => (PositionType::Absolute, Val::Px(pos.x), Val::Px(pos.y)),
+        None => (PositionType::Relative, Val::Auto, Val::Auto),
+    };
+    NodeBundle {
+        style: Style {
+            position_type,
+            left,
+            top,
+            width: if m.is_large() {
+                Val::Px(660.0)
+            } else {
+                Val::Px(170.0)
+            },
+            height: if m.is_large() {
+                Val::Px(550.0)
+            } else {
+                Val::Px(200.0)
+            },
+            margin: UiRect::all(Val::Px(5.0)),
+            padding: UiRect::all(Val::Px(8.0)),
+            border: UiRect::all(Val::Px(2.0)),
+            overflow: Overflow::clip(),
+            ..default()
+        },
+        background_color: layout.color.unwrap_or(Color::DARK_GRAY).into(),
+        border_color: Color::NONE.into(),
+        ..default()
+    }
+}
+fn module_text_style() -> TextStyle {
+    TextStyle {
+        font_size: 16.0,
+        color: Color::WHITE,
+        ..default()
+    }
+}
+fn rack_reloader(mut ev_asset: EventReader<AssetEvent<Rack>>, racks: Res<Assets<Rack>>, h_racks: ResMut<RackHandles>, mut ev_switch: EventWriter<RackSwitch>) {
     for ev in ev_asset.iter() {
         if let AssetEvent::Modified { handle } = ev {
             if handle == &h_racks.0[
                 RACK_DIR_IDX.load(atomic::Ordering::Acquire)
             ] {
-                if let Some(rack) = racks.get_mut(handle) {
+                if let Some(rack) = racks.get(handle) {
                     if rack.modules.iter()
                         .any(|m| {
                             !m.1.is_init()
                         })
                     {
-                        if let Some(AppState::Loading) = &state.0 {
-                            return;
-                        }
-
-                        for ent in &q_any {
-                            if let Some(ent) = commands.get_entity(ent) {
-                                ent.despawn_recursive();
-                            }
-                        }
-
-                        for window in &q_windows {
-                            if let Some(window) = commands.get_entity(window) {
-                                window.despawn_recursive();
-                            }
-                        }
-
                         info!("Reloading rack...");
-
-                        state.set(AppState::Loading);
+                        ev_switch.send(RackSwitch::Reload);
                     }
                 }
             }
         }
     }
 }
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum StepType {
-    Key,
-    Audio,
-    Video,
+/// Tear down the current rack and load the requested one, despawning the
+/// entities and windows of the current rack. Only the last request of each
+/// frame is handled
+fn rack_switcher(mut commands: Commands, mut ev_switch: EventReader<RackSwitch>, mut racks: ResMut<Assets<Rack>>, h_racks: Res<RackHandles>, q_any: Query<Entity, Or::<(With<Camera>, With<TopModuleComponent>, With<ModuleMeshComponent>, With<ModuleImageWindowComponent>, With<PatchComponent>, With<HelpOverlayComponent>, With<CommandPaletteComponent>, With<RackLayoutComponent>, With<SignalTooltipComponent>, With<ReloadDiffComponent>, With<RackSelectComponent>)>>, q_child_windows: Query<Entity, (With<Window>, Without<PrimaryWindow>)>, mut state: ResMut<NextState<AppState>>, mut streams: NonSendMut<AudioStreams>, mut reload_diff: ResMut<ReloadDiff>) {
+    let Some(switch) = ev_switch.iter().last().copied() else {
+        return;
+    };
+    if let Some(AppState::Loading) = &state.0 {
+        return;
+    }
+
+    match switch {
+        RackSwitch::Load(idx) => {
+            let current = RACK_DIR_IDX.load(atomic::Ordering::Acquire);
+            if let Some(rack) = racks.get_mut(&h_racks.0[current]) {
+                rack.exit(&mut streams);
+            }
+            RACK_DIR_IDX.store(idx % h_racks.0.len(), atomic::Ordering::Release);
+        },
+        RackSwitch::Reload => {
+            // The modified rack has already replaced the current one
+            reload_diff.is_reload = true;
+        },
+    }
+
+    for ent in &q_any {
+        if let Some(ent) = commands.get_entity(ent) {
+            ent.despawn_recursive();
+        }
+    }
+
+    for window in &q_child_windows {
+        if let Some(window) = commands.get_entity(window) {
+            window.despawn_recursive();
+        }
+    }
+
+    state.set(AppState::Loading);
+}
+/// Spawn an overlay listing the changes of a hot-reload, colored by whether
+/// they're additions, removals, or modifications
+fn spawn_reload_diff(commands: &mut Commands, changes: &[String]) {
+    const MAX_CHANGES: usize = 20;
+
+    for change in changes {
+        info!("Rack changed: {change}");
+    }
+
+    let ts = module_text_style();
+    let mut sections: Vec<TextSection> = changes.iter()
+        .take(MAX_CHANGES)
+        .map(|change| {
+            let color = match change.chars().next() {
+                Some('+') => Color::GREEN,
+                Some('-') => Color::RED,
+                _ => Color::YELLOW,
+            };
+            TextSection::new(format!("{change}\n"), TextStyle {
+                color,
+                ..ts.clone()
+            })
+        }).collect();
+    if changes.is_empty() {
+        sections.push(TextSection::new("No changes\n", ts.clone()));
+    } else if changes.len() > MAX_CHANGES {
+        sections.push(TextSection::new(format!("...and {} more\n", changes.len() - MAX_CHANGES), ts.clone()));
+    }
+    sections.insert(0, TextSection::new("Rack reloaded\n", ts));
+
+    commands.spawn((
+        TextBundle {
+            z_index: ZIndex::Global(10),
+            ..TextBundle::from_sections(sections)
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    right: Val::Px(20.0),
+                    top: Val::Px(20.0),
+                    padding: UiRect::all(Val::Px(10.0)),
+                    ..default()
+                })
+                .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.9))
+        },
+        ReloadDiffComponent(Timer::new(RELOAD_DIFF_DURATION, TimerMode::Once)),
+    ));
+}
+fn reload_diff_overlay(mut commands: Commands, time: Res<Time>, mut q_overlay: Query<(Entity, &mut ReloadDiffComponent)>) {
+    for (ent, mut overlay) in &mut q_overlay {
+        if overlay.0.tick(time.delta()).finished() {
+            if let Some(ent) = commands.get_entity(ent) {
+                ent.despawn_recursive();
+            }
+        }
+    }
 }
 fn continuous_step(time: &Res<Time>, dt: f64, rack: &mut Rack, st: StepType) {
     let t: f64 = match CONTINUOUS_TIME.lock().unwrap().deref_mut() {
@@ -496,57 +1035,59 @@ fn continuous_step(time: &Res<Time>, dt: f64, rack: &mut Rack, st: StepType) {
             *t
         },
         ct @ None => {
-            *ct = Some(time.elapsed_seconds_wrapped_f64());
+            *ct = Some(replay::sync_time(time.elapsed_seconds_wrapped_f64()));
             ct.unwrap()
         },
     };
 
     rack.step(t, st);
 }
-fn rack_stepper(time: Res<Time>, mut racks: ResMut<Assets<Rack>>, h_racks: ResMut<RackHandles>) {
+fn rack_stepper(time: Res<Time>, mut racks: ResMut<Assets<Rack>>, h_racks: ResMut<RackHandles>, mut streams: NonSendMut<AudioStreams>) {
     if let Some(rack) = racks.get_mut(
         &h_racks.0[
             RACK_DIR_IDX.load(atomic::Ordering::Acquire)
         ]
     ) {
-        if let Some(audio_context) = &rack.audio_context {
-            let sr = audio_context.output.config.sample_rate.0 as u64;
-            let audio_steps = sr / u64::from(FRAME_RATE);
+        rack.monitor_audio(&mut streams);
 
-            match rack.info.get("mode").map(|m| m.as_str()) {
-                Some("Key") => {
-                    let kdt = Duration::from_micros(1000 * 1000 / u64::from(FRAME_RATE)).as_secs_f64();
+        // Keep stepping without audio so the rack continues from silence once
+        // an audio device is available again
+        let sr = rack.sample_rate()
+            .map_or(FALLBACK_SAMPLE_RATE, u64::from);
+        let frame_rate = u64::from(config::get().frame_rate);
+        let audio_steps = sr / frame_rate;
 
-                    continuous_step(&time, kdt, rack, StepType::Key);
-                },
-                Some("Audio") | None => {
-                    let adt = Duration::from_micros(1000 * 1000 / sr).as_secs_f64();
+        match rack.info.get("mode").map(|m| m.as_str()) {
+            Some("Key") => {
+                let kdt = Duration::from_micros(1000 * 1000 / frame_rate).as_secs_f64();
 
-                    continuous_step(&time, adt, rack, StepType::Key);
-                    for _ in 1..audio_steps {
-                        continuous_step(&time, adt, rack, StepType::Audio);
+                continuous_step(&time, kdt, rack, StepType::Key);
+            },
+            Some("Audio") | None => {
+                let adt = Duration::from_micros(1000 * 1000 / sr).as_secs_f64();
+
+                continuous_step(&time, adt, rack, StepType::Key);
+                for _ in 1..audio_steps {
+                    continuous_step(&time, adt, rack, StepType::Audio);
+                }
+            },
+            Some("Video") => {
+                let video_steps = 4;
+                let vdt = Duration::from_nanos(1000 * 1000 * 1000 / sr / video_steps).as_secs_f64();
+
+                let mut start_step = 2;
+                continuous_step(&time, vdt, rack, StepType::Key);
+                for _ in 1..audio_steps {
+                    continuous_step(&time, vdt, rack, StepType::Audio);
+                    for _ in start_step..video_steps {
+                        continuous_step(&time, vdt, rack, StepType::Video);
                     }
-                },
-                Some("Video") => {
-                    let video_steps = 4;
-                    let vdt = Duration::from_nanos(1000 * 1000 * 1000 / sr / video_steps).as_secs_f64();
-
-                    let mut start_step = 2;
-                    continuous_step(&time, vdt, rack, StepType::Key);
-                    for _ in 1..audio_steps {
-                        continuous_step(&time, vdt, rack, StepType::Audio);
-                        for _ in start_step..video_steps {
-                            continuous_step(&time, vdt, rack, StepType::Video);
-                        }
-                        if start_step == 2 {
-                            start_step = 1;
-                        }
+                    if start_step == 2 {
+                        start_step = 1;
                     }
-                },
-                Some(m) => panic!("Unknown rack mode: {m}"),
-            }
-        } else {
-            rack.init_audio();
+                }
+            },
+            Some(m) => panic!("Unknown rack mode: {m}"),
         }
     }
 }
@@ -559,71 +1100,192 @@ fn rack_render(mut racks: ResMut<Assets<Rack>>, mut images: ResMut<Assets<Image>
         rack.render(&mut images, &mut meshes, &mut q_text, &mut q_image, &mut q_mesh);
     }
 }
-fn keyboard_input(mut commands: Commands, keys: Res<Input<KeyCode>>, mut racks: ResMut<Assets<Rack>>, h_racks: ResMut<RackHandles>, mut q_windows: Query<&mut Window>, q_child_windows: Query<Entity, (With<Window>, Without<PrimaryWindow>)>, q_any: Query<Entity, Or::<(With<CameraComponent>, With<TopModuleComponent>, With<ModuleMeshComponent>, With<ModuleImageWindowComponent>, With<PatchComponent>)>>, mut state: ResMut<NextState<AppState>>, mut exit: EventWriter<AppExit>) {
-    if let Some(rack) = racks.get_mut(
+/// How strongly the UI pulses with the rack's output level in the range
+/// [0.0, 1.0], or [None] if the rack's `theme` isn't `Reactive`
+fn theme_pulse(rack: &Rack) -> Option<f32> {
+    if rack.info.get("theme").map(|t| t.as_str()) != Some("Reactive") {
+        return None;
+    }
+    let depth = rack.info.get("theme_depth")
+        .and_then(|d| d.parse::<f32>().ok())
+        .unwrap_or(0.25);
+    Some(depth.clamp(0.0, 1.0) * rack.output_level().min(1.0))
+}
+/// Brighten the given color towards white by the given pulse
+fn pulse_color(color: Color, pulse: f32) -> Color {
+    let [r, g, b, a] = color.as_rgba_f32();
+    Color::rgba(r + (1.0 - r) * pulse, g + (1.0 - g) * pulse, b + (1.0 - b) * pulse, a)
+}
+/// Pulse each module's background with the rack's output level if the rack
+/// has a reactive theme
+fn theme_render(mut commands: Commands, racks: Res<Assets<Rack>>, h_racks: Res<RackHandles>, mut q_modules: Query<(Entity, &mut BackgroundColor, Option<&ThemeBaseColor>), With<TopModuleComponent>>) {
+    let Some(pulse) = racks.get(
         &h_racks.0[
             RACK_DIR_IDX.load(atomic::Ordering::Acquire)
         ]
-    ) {
-        rack.keyboard_input(&keys);
-
-        if keys.just_released(KeyCode::Right) {
-            rack.exit();
+    ).and_then(theme_pulse) else {
+        return;
+    };
 
-            if let Some(AppState::Loading) = &state.0 {
-                return;
-            }
+    for (ent, mut bg, base) in &mut q_modules {
+        let base = match base {
+            Some(base) => base.0,
+            None => {
+                commands.entity(ent).insert(ThemeBaseColor(bg.0));
+                bg.0
+            },
+        };
+        bg.0 = pulse_color(base, pulse);
+    }
+}
+/// Outline the module which receives keyboard input
+fn focus_render(racks: Res<Assets<Rack>>, h_racks: Res<RackHandles>, q_parent: Query<&Parent>, mut q_modules: Query<(Entity, &mut BorderColor), With<TopModuleComponent>>) {
+    let Some(rack) = racks.get(
+        &h_racks.0[
+            RACK_DIR_IDX.load(atomic::Ordering::Acquire)
+        ]
+    ) else {
+        return;
+    };
 
-            for ent in &q_any {
-                if let Some(ent) = commands.get_entity(ent) {
-                    ent.despawn_recursive();
-                }
-            }
+    let focused = rack.focused_module()
+        .and_then(|id| rack.modules.get(&ModuleKey {
+            id,
+            iok: ModuleIOK::None,
+        }))
+        .and_then(|m| m.component())
+        .and_then(|c| q_parent.get(c).ok())
+        .map(Parent::get);
+    for (ent, mut border) in &mut q_modules {
+        let color = if Some(ent) == focused {
+            Color::WHITE
+        } else {
+            Color::NONE
+        };
+        if border.0 != color {
+            border.0 = color;
+        }
+    }
+}
+/// Color and animate each patch cable by the signal it carries
+fn cable_render(time: Res<Time>, racks: Res<Assets<Rack>>, h_racks: Res<RackHandles>, mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<ColorMaterial>>, mut q_cables: Query<(&mut PatchCableComponent, &Mesh2dHandle, &Handle<ColorMaterial>)>) {
+    /// The length of cable that each dash and the gap after it covers
+    const DASH_LENGTH: f32 = 40.0;
 
-            for window in &q_child_windows {
-                if let Some(window) = commands.get_entity(window) {
-                    window.despawn_recursive();
+    if let Some(rack) = racks.get(
+        &h_racks.0[
+            RACK_DIR_IDX.load(atomic::Ordering::Acquire)
+        ]
+    ) {
+        let pulse = theme_pulse(rack);
+        for (mut cable, mesh, material) in &mut q_cables {
+            // The amplitude and peak-to-peak range of the signal over the last
+            // frame
+            let signal = rack.signal(&cable.output)
+                .map(|v| {
+                    rack.signal_history(&cable.output)
+                        .and_then(|h| h.frames.back())
+                        .map_or((v.abs(), 0.0), |(lo, hi)| (lo.abs().max(hi.abs()), hi - lo))
+                });
+
+            // Grey out NaN signals and otherwise brighten with the amplitude
+            if let Some(material) = materials.get_mut(material) {
+                material.color = match signal {
+                    Some((amp, _)) => {
+                        let brightness = 0.3 + 0.7 * amp.clamp(0.0, 1.0);
+                        let [r, g, b, a] = cable.color.as_rgba_f32();
+                        Color::rgba(r * brightness, g * brightness, b * brightness, a)
+                    },
+                    None => Color::GRAY,
+                };
+                if let Some(pulse) = pulse {
+                    material.color = pulse_color(material.color, pulse);
                 }
             }
 
-            info!("Loading next rack...");
-
-            RACK_DIR_IDX.fetch_add(1, atomic::Ordering::AcqRel);
-            RACK_DIR_IDX.fetch_update(
-                atomic::Ordering::Release,
-                atomic::Ordering::Acquire,
-                |mut idx| {
-                    idx %= h_racks.0.len();
-                    Some(idx)
-                }
-            ).unwrap();
-
-            state.set(AppState::Loading);
-        } else if keys.just_released(KeyCode::Left) {
-            rack.exit();
+            // Flow the dashes along the cable faster for louder and busier
+            // signals
+            let (amp, span) = signal.unwrap_or_default();
+            let speed = amp.min(1.0) + span.min(2.0);
+            cable.flow = (cable.flow + speed * time.delta_seconds()).fract();
+
+            if let Some(mesh) = meshes.get_mut(&mesh.0) {
+                let mut dist = 0.0;
+                let colors: Vec<[f32; 4]> = cable.points.iter()
+                    .enumerate()
+                    .map(|(i, p)| {
+                        if i > 0 {
+                            dist += p.distance(cable.points[i - 1]);
+                        }
+                        if signal.is_none() {
+                            return [1.0; 4];
+                        }
 
-            if let Some(AppState::Loading) = &state.0 {
-                return;
+                        let wave = 0.5 + 0.5 * (std::f32::consts::TAU * (dist / DASH_LENGTH - cable.flow)).cos();
+                        let v = 1.0 - 0.6 * wave;
+                        [v, v, v, 1.0]
+                    }).collect();
+                mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
             }
+        }
+    }
+}
+/// Record each knob change in the session log, at most once per frame
+fn session_knobs(racks: Res<Assets<Rack>>, h_racks: Res<RackHandles>, mut knobs: Local<(usize, HashMap<usize, Vec<f32>>)>) {
+    if !session::is_enabled() {
+        return;
+    }
 
-            for ent in &q_any {
-                if let Some(ent) = commands.get_entity(ent) {
-                    ent.despawn_recursive();
-                }
-            }
+    let rack_idx = RACK_DIR_IDX.load(atomic::Ordering::Acquire);
+    if let Some(rack) = racks.get(&h_racks.0[rack_idx]) {
+        // Don't compare the knobs of different racks
+        let (last_idx, last_knobs) = &mut *knobs;
+        if *last_idx != rack_idx {
+            *last_idx = rack_idx;
+            last_knobs.clear();
+        }
 
-            for window in &q_child_windows {
-                if let Some(window) = commands.get_entity(window) {
-                    window.despawn_recursive();
+        for (k, m) in &rack.modules {
+            let current = m.get_knobs();
+            if let Some(last) = last_knobs.get(&k.id) {
+                for (i, (v, lv)) in current.iter().zip(last).enumerate() {
+                    if v != lv && !(v.is_nan() && lv.is_nan()) {
+                        session::record("knob", &[
+                            ("module", session::Field::Num(k.id as f64)),
+                            ("knob", session::Field::Num(i as f64)),
+                            ("value", session::Field::Num(f64::from(*v))),
+                        ]);
+                    }
                 }
             }
+            last_knobs.insert(k.id, current);
+        }
+    }
+}
+fn keyboard_input(keys: Res<Input<KeyCode>>, mut racks: ResMut<Assets<Rack>>, h_racks: ResMut<RackHandles>, mut q_windows: Query<&mut Window>, mut streams: NonSendMut<AudioStreams>, palette: Res<CommandPalette>, rack_select: Res<RackSelect>, mut ev_switch: EventWriter<RackSwitch>, mut exit: EventWriter<AppExit>) {
+    // Keys typed into the command palette or rack select menu shouldn't also
+    // control the rack
+    if palette.is_open || rack_select.is_open {
+        return;
+    }
 
-            info!("Loading previous rack...");
-
-            RACK_DIR_IDX.fetch_sub(1, atomic::Ordering::AcqRel);
-            RACK_DIR_IDX.fetch_min(h_racks.0.len()-1, atomic::Ordering::AcqRel);
+    if let Some(rack) = racks.get_mut(
+        &h_racks.0[
+            RACK_DIR_IDX.load(atomic::Ordering::Acquire)
+        ]
+    ) {
+        if keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) && keys.just_pressed(KeyCode::Tab) {
+            rack.focus_next(keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]));
+        }
+        rack.keyboard_input(&keys);
 
-            state.set(AppState::Loading);
+        let current = RACK_DIR_IDX.load(atomic::Ordering::Acquire);
+        if keys.just_released(KeyCode::Right) {
+            info!("Loading next rack...");
+            ev_switch.send(RackSwitch::Load(current + 1));
+        } else if keys.just_released(KeyCode::Left) {
+            info!("Loading previous rack...");
+            ev_switch.send(RackSwitch::Load(current + h_racks.0.len() - 1));
         } else if keys.just_released(KeyCode::F11) {
             for mut window in &mut q_windows {
                 if window.focused {
@@ -639,18 +1301,699 @@ fn keyboard_input(mut commands: Commands, keys: Res<Input<KeyCode>>, mut racks:
                 }
             }
         } else if keys.just_released(KeyCode::Escape) {
-            rack.exit();
+            rack.exit(&mut streams);
             exit.send(AppExit);
         }
     }
 }
-fn mouse_input(mouse_buttons: Res<Input<MouseButton>>, q_windows: Query<&Window, With<PrimaryWindow>>, mut racks: ResMut<Assets<Rack>>, h_racks: ResMut<RackHandles>, q_child: Query<&Parent, With<ModuleComponent>>, q_transform: Query<&GlobalTransform>) {
+/// Advance to the next rack once the current rack has played for its
+/// `duration`
+fn rack_autoplay(racks: Res<Assets<Rack>>, h_racks: Res<RackHandles>, mut ev_switch: EventWriter<RackSwitch>) {
+    if h_racks.0.len() < 2 {
+        return;
+    }
+
+    if let Some(rack) = racks.get(
+        &h_racks.0[
+            RACK_DIR_IDX.load(atomic::Ordering::Acquire)
+        ]
+    ) {
+        let Some(duration) = rack.duration() else {
+            return;
+        };
+        if rack.elapsed() < duration {
+            return;
+        }
+
+        info!("Rack finished after {duration} seconds, loading next rack...");
+        ev_switch.send(RackSwitch::Load(RACK_DIR_IDX.load(atomic::Ordering::Acquire) + 1));
+    }
+}
+/// Switch to the rack selected by the last MIDI program change, or reload the
+/// current rack from its file if it's selected again
+fn rack_program_change(racks: Res<Assets<Rack>>, h_racks: Res<RackHandles>, asset_server: Res<AssetServer>, mut ev_switch: EventWriter<RackSwitch>) {
+    #[cfg(feature = "midi")]
+    let program = vince_core::program_change::take();
+    #[cfg(not(feature = "midi"))]
+    let program: Option<u32> = None;
+
+    let Some(program) = program else {
+        return;
+    };
+
+    let idx = h_racks.0.iter()
+        .position(|rh| {
+            racks.get(rh)
+                .and_then(|rack| rack.info.get("program"))
+                .and_then(|p| p.parse::<u32>().ok())
+                == Some(program)
+        }).or_else(|| {
+            let idx = program as usize;
+            (idx < h_racks.0.len()).then_some(idx)
+        });
+    let Some(idx) = idx else {
+        warn!("No rack for program {program}");
+        return;
+    };
+
+    // The rack reloader takes over once the file is reloaded
+    let current = RACK_DIR_IDX.load(atomic::Ordering::Acquire);
+    if idx == current {
+        info!("Program {program} selected, reloading rack...");
+        if let Some(path) = asset_server.get_handle_path(&h_racks.0[current]) {
+            asset_server.reload_asset(path);
+        }
+        return;
+    }
+
+    info!("Program {program} selected, loading rack {idx}...");
+    ev_switch.send(RackSwitch::Load(idx));
+}
+fn mouse_input(mouse_buttons: Res<Input<MouseButton>>, touches: Res<Touches>, q_windows: Query<&Window, With<PrimaryWindow>>, mut racks: ResMut<Assets<Rack>>, h_racks: ResMut<RackHandles>, q_child: Query<&Parent, With<ModuleComponent>>, q_transform: Query<&GlobalTransform>, rack_select: Res<RackSelect>) {
+    // Clicks on the rack select menu shouldn't also turn the knobs beneath it
+    if rack_select.is_open {
+        return;
+    }
+
     if let Some(rack) = racks.get_mut(
         &h_racks.0[
             RACK_DIR_IDX.load(atomic::Ordering::Acquire)
         ]
     ) {
         rack.mouse_input(&mouse_buttons, q_windows.single(), &q_child, &q_transform);
+        rack.touch_input(&touches);
+    }
+}
+fn help_overlay(mut commands: Commands, keys: Res<Input<KeyCode>>, q_windows: Query<&Window, With<PrimaryWindow>>, racks: Res<Assets<Rack>>, h_racks: Res<RackHandles>, q_child: Query<&Parent, With<ModuleComponent>>, q_transform: Query<&GlobalTransform>, q_overlay: Query<Entity, With<HelpOverlayComponent>>) {
+    if keys.just_released(KeyCode::F1) {
+        for ent in &q_overlay {
+            if let Some(ent) = commands.get_entity(ent) {
+                ent.despawn_recursive();
+            }
+        }
+    } else if keys.just_pressed(KeyCode::F1) {
+        let Ok(window) = q_windows.get_single() else {
+            return;
+        };
+        let Some(mpos) = window.cursor_position() else {
+            return;
+        };
+
+        if let Some(rack) = racks.get(
+            &h_racks.0[
+                RACK_DIR_IDX.load(atomic::Ordering::Acquire)
+            ]
+        ) {
+            if let Some(help) = rack.help_text(mpos, &q_child, &q_transform) {
+                let width = 400.0;
+                commands.spawn((
+                    NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            left: Val::Px(mpos.x.min(window.width() - width).max(0.0)),
+                            top: Val::Px(mpos.y),
+                            width: Val::Px(width),
+                            padding: UiRect::all(Val::Px(10.0)),
+                            ..default()
+                        },
+                        background_color: Color::rgba(0.0, 0.0, 0.0, 0.9).into(),
+                        z_index: ZIndex::Global(10),
+                        ..default()
+                    },
+                    HelpOverlayComponent,
+                )).with_children(|parent| {
+                    parent.spawn(TextBundle::from_section(
+                        help,
+                        TextStyle {
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    ));
+                });
+            }
+        }
+    }
+}
+fn command_palette(mut commands: Commands, keys: Res<Input<KeyCode>>, mut ev_chars: EventReader<ReceivedCharacter>, mut palette: ResMut<CommandPalette>, racks: Res<Assets<Rack>>, h_racks: Res<RackHandles>, mut q_windows: Query<(&mut Window, Option<&PrimaryWindow>, Option<&ModuleWindowComponent>)>, q_parent: Query<&Parent>, q_transform: Query<&GlobalTransform>, mut q_layout: Query<&mut Style, With<RackLayoutComponent>>, q_patches: Query<Entity, With<PatchComponent>>, mut q_palette: Query<(Entity, &mut Text), With<CommandPaletteComponent>>, mut state: ResMut<NextState<AppState>>) {
+    const MAX_RESULTS: usize = 10;
+
+    if !palette.is_open {
+        if keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) && keys.just_pressed(KeyCode::P) {
+            *palette = CommandPalette {
+                is_open: true,
+                ..default()
+            };
+            ev_chars.clear();
+
+            commands.spawn((
+                TextBundle {
+                    z_index: ZIndex::Global(10),
+                    ..TextBundle::from_section("", module_text_style())
+                        .with_style(Style {
+                            position_type: PositionType::Absolute,
+                            left: Val::Percent(25.0),
+                            top: Val::Px(20.0),
+                            width: Val::Percent(50.0),
+                            padding: UiRect::all(Val::Px(10.0)),
+                            ..default()
+                        })
+                        .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.9))
+                },
+                CommandPaletteComponent,
+            ));
+        }
+        return;
+    }
+
+    // Close the palette if it was despawned by a rack reload
+    let Ok((palette_ent, mut text)) = q_palette.get_single_mut() else {
+        *palette = CommandPalette::default();
+        return;
+    };
+    let close = |commands: &mut Commands, palette: &mut CommandPalette| {
+        *palette = CommandPalette::default();
+        commands.entity(palette_ent).despawn_recursive();
+    };
+    if keys.just_released(KeyCode::Escape) {
+        close(&mut commands, &mut palette);
+        return;
+    }
+
+    for ev in ev_chars.iter() {
+        if !ev.char.is_control() {
+            palette.query.push(ev.char);
+            palette.selected = 0;
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        palette.query.pop();
+        palette.selected = 0;
+    }
+
+    let Some(rack) = racks.get(
+        &h_racks.0[
+            RACK_DIR_IDX.load(atomic::Ordering::Acquire)
+        ]
+    ) else {
+        return;
+    };
+    let query = palette.query.to_lowercase();
+    let matches: Vec<(usize, String)> = rack.module_labels()
+        .into_iter()
+        .filter(|(_, label)| label.to_lowercase().contains(&query))
+        .take(MAX_RESULTS)
+        .collect();
+
+    if keys.just_pressed(KeyCode::Up) {
+        palette.selected = palette.selected.saturating_sub(1);
+    } else if keys.just_pressed(KeyCode::Down) {
+        palette.selected += 1;
+    }
+    palette.selected = palette.selected.min(matches.len().saturating_sub(1));
+
+    if keys.just_pressed(KeyCode::Return) {
+        let m = matches.get(palette.selected)
+            .and_then(|(id, _)| {
+                rack.modules.get(&ModuleKey {
+                    id: *id,
+                    iok: ModuleIOK::None,
+                }).map(|m| (*id, m))
+            });
+        if let Some((id, m)) = m {
+            if m.is_own_window() {
+                for (mut window, _, mw) in &mut q_windows {
+                    if mw.is_some_and(|mw| mw.0 == id) {
+                        window.focused = true;
+                    }
+                }
+            } else {
+                // Shift the layout so that the module is centered in the
+                // main window
+                let center = q_windows.iter()
+                    .find(|(_, primary, _)| primary.is_some())
+                    .map(|(window, _, _)| Vec2::new(window.width(), window.height()) / 2.0);
+                let pos = m.component()
+                    .and_then(|c| q_parent.get(c).ok())
+                    .and_then(|top| q_transform.get(top.get()).ok())
+                    .map(|t| t.translation().truncate());
+                if let (Some(center), Some(pos), Ok(mut style)) = (center, pos, q_layout.get_single_mut()) {
+                    let px = |v: Val| match v {
+                        Val::Px(v) => v,
+                        _ => 0.0,
+                    };
+                    let offset = center - pos;
+                    style.left = Val::Px(px(style.left) + offset.x);
+                    style.top = Val::Px(px(style.top) + offset.y);
+
+                    // Redraw the patch cables at the new positions
+                    for patch in &q_patches {
+                        if let Some(patch) = commands.get_entity(patch) {
+                            patch.despawn_recursive();
+                        }
+                    }
+                    state.set(AppState::Loaded);
+                }
+            }
+        }
+
+        close(&mut commands, &mut palette);
+        return;
+    }
+
+    let mut value = format!("> {}\n", palette.query);
+    for (i, (_, label)) in matches.iter().enumerate() {
+        let cursor = if i == palette.selected {
+            ">"
+        } else {
+            " "
+        };
+        value += &format!("{cursor} {label}\n");
+    }
+    if matches.is_empty() {
+        value += "No matching modules\n";
+    }
+    text.sections[0].value = value;
+}
+/// Spawn the rack select menu with an entry for each rack in the directory
+fn spawn_rack_select(commands: &mut Commands, racks: &Assets<Rack>, asset_server: &AssetServer, h_racks: &RackHandles) {
+    let ts = module_text_style();
+    let current = RACK_DIR_IDX.load(atomic::Ordering::Acquire);
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Percent(20.0),
+                top: Val::Px(20.0),
+                width: Val::Percent(60.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.9).into(),
+            z_index: ZIndex::Global(10),
+            ..default()
+        },
+        RackSelectComponent,
+    )).with_children(|parent| {
+        parent.spawn(NodeBundle {
+            style: Style {
+                width: Val::Percent(50.0),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            ..default()
+        }).with_children(|parent| {
+            parent.spawn(TextBundle::from_section("Racks\n", ts.clone()));
+            for (i, rh) in h_racks.0.iter().enumerate() {
+                let playing = if i == current {
+                    " (playing)"
+                } else {
+                    ""
+                };
+                parent.spawn((
+                    TextBundle::from_section(format!("{i}. {}{playing}", rack_name(racks, asset_server, rh)), ts.clone())
+                        .with_style(Style {
+                            padding: UiRect::horizontal(Val::Px(5.0)),
+                            ..default()
+                        }),
+                    Interaction::default(),
+                    RackSelectItemComponent(i),
+                ));
+            }
+        });
+        parent.spawn((
+            TextBundle::from_section("", ts)
+                .with_style(Style {
+                    width: Val::Percent(50.0),
+                    flex_wrap: FlexWrap::Wrap,
+                    ..default()
+                }),
+            RackSelectInfoComponent,
+        ));
+    });
+}
+fn rack_select(mut commands: Commands, keys: Res<Input<KeyCode>>, mut select: ResMut<RackSelect>, palette: Res<CommandPalette>, racks: Res<Assets<Rack>>, h_racks: Res<RackHandles>, asset_server: Res<AssetServer>, q_select: Query<Entity, With<RackSelectComponent>>, mut q_items: Query<(&Interaction, &RackSelectItemComponent, &mut Style, &mut BackgroundColor)>, mut q_info: Query<&mut Text, With<RackSelectInfoComponent>>, mut ev_switch: EventWriter<RackSwitch>) {
+    /// The most racks to list at once around the selected one
+    const MAX_ITEMS: usize = 20;
+
+    if !select.is_open {
+        // Ctrl+Tab cycles the focused module instead
+        if keys.just_pressed(KeyCode::Tab) && !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) && !palette.is_open {
+            *select = RackSelect {
+                is_open: true,
+                selected: RACK_DIR_IDX.load(atomic::Ordering::Acquire),
+            };
+            spawn_rack_select(&mut commands, &racks, &asset_server, &h_racks);
+        }
+        return;
+    }
+
+    // Close the menu if it was despawned by a rack reload
+    let Ok(select_ent) = q_select.get_single() else {
+        *select = RackSelect::default();
+        return;
+    };
+    if keys.just_pressed(KeyCode::Tab) || keys.just_released(KeyCode::Escape) {
+        *select = RackSelect::default();
+        commands.entity(select_ent).despawn_recursive();
+        return;
+    }
+
+    let len = h_racks.0.len();
+    if keys.just_pressed(KeyCode::Up) {
+        select.selected = select.selected.saturating_sub(1);
+    } else if keys.just_pressed(KeyCode::Down) {
+        select.selected = (select.selected + 1).min(len - 1);
+    }
+    let mut is_chosen = keys.just_pressed(KeyCode::Return);
+    for (interaction, item, _, _) in &q_items {
+        match interaction {
+            Interaction::Pressed => {
+                select.selected = item.0;
+                is_chosen = true;
+            },
+            Interaction::Hovered => select.selected = item.0,
+            Interaction::None => {},
+        }
+    }
+
+    if is_chosen {
+        let current = RACK_DIR_IDX.load(atomic::Ordering::Acquire);
+        if select.selected == current {
+            *select = RackSelect::default();
+            commands.entity(select_ent).despawn_recursive();
+            return;
+        }
+
+        info!("Loading rack {}...", select.selected);
+        ev_switch.send(RackSwitch::Load(select.selected));
+        *select = RackSelect::default();
+        return;
+    }
+
+    // Highlight the selected rack and only list the racks around it
+    let first = select.selected.saturating_sub(MAX_ITEMS / 2).min(len.saturating_sub(MAX_ITEMS));
+    for (_, item, mut style, mut bg) in &mut q_items {
+        style.display = if (first..first + MAX_ITEMS).contains(&item.0) {
+            Display::Flex
+        } else {
+            Display::None
+        };
+        *bg = if item.0 == select.selected {
+            Color::rgba(1.0, 1.0, 1.0, 0.2).into()
+        } else {
+            Color::NONE.into()
+        };
+    }
+
+    // Preview the selected rack's info
+    if let Ok(mut text) = q_info.get_single_mut() {
+        let rh = &h_racks.0[select.selected];
+        let mut value = format!("{}\n", rack_name(&racks, &asset_server, rh));
+        if let Some(rack) = racks.get(rh) {
+            let mut info: Vec<(&String, &String)> = rack.info.iter()
+                .filter(|(k, _)| k.as_str() != "name")
+                .collect();
+            info.sort();
+            for (k, v) in info {
+                value += &format!("{k}: {v}\n");
+            }
+            let modules = rack.modules.keys()
+                .filter(|k| k.id != usize::MAX)
+                .count();
+            value += &format!("{modules} modules\n");
+        }
+        text.sections[0].value = value;
+    }
+}
+fn signal_tooltip(mut commands: Commands, q_windows: Query<&Window, With<PrimaryWindow>>, racks: Res<Assets<Rack>>, h_racks: Res<RackHandles>, q_cables: Query<&PatchCableComponent>, q_main_camera: Query<(&Camera, &GlobalTransform), With<MainCameraComponent>>, q_tooltip: Query<Entity, With<SignalTooltipComponent>>) {
+    /// The furthest the cursor can be from a cable while hovering it
+    const HOVER_DISTANCE: f32 = 5.0;
+    const SPARKLINE_HEIGHT: f32 = 40.0;
+    const BAR_WIDTH: f32 = 2.0;
+
+    for ent in &q_tooltip {
+        if let Some(ent) = commands.get_entity(ent) {
+            ent.despawn_recursive();
+        }
+    }
+
+    let Some(mpos) = q_windows.get_single().ok().and_then(Window::cursor_position) else {
+        return;
+    };
+    let Some(wpos) = q_main_camera.get_single()
+        .ok()
+        .and_then(|(camera, transform)| camera.viewport_to_world_2d(transform, mpos))
+    else {
+        return;
+    };
+
+    // Find the nearest cable under the cursor
+    let Some(output) = q_cables.iter()
+        .map(|cable| (cable.output, cable.distance(wpos)))
+        .filter(|(_, d)| *d <= HOVER_DISTANCE)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(output, _)| output)
+    else {
+        return;
+    };
+    let ModuleIOK::Output(o) = output.iok else {
+        return;
+    };
+
+    if let Some(rack) = racks.get(
+        &h_racks.0[
+            RACK_DIR_IDX.load(atomic::Ordering::Acquire)
+        ]
+    ) {
+        let value = match rack.signal(&output) {
+            Some(v) => format!("{v:.4}"),
+            None => "NaN".to_string(),
+        };
+        let frames: Vec<(f32, f32)> = rack.signal_history(&output)
+            .map(|h| h.frames.iter().copied().collect())
+            .unwrap_or_default();
+
+        // Scale the sparkline to the range of the last second
+        let lo = frames.iter().map(|f| f.0).fold(f32::INFINITY, f32::min);
+        let hi = frames.iter().map(|f| f.1).fold(f32::NEG_INFINITY, f32::max);
+        let span = if hi > lo {
+            hi - lo
+        } else {
+            1.0
+        };
+
+        commands.spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Px(mpos.x + 15.0),
+                    top: Val::Px(mpos.y + 15.0),
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(Val::Px(5.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.9).into(),
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            SignalTooltipComponent,
+        )).with_children(|parent| {
+            let range = if frames.is_empty() {
+                String::new()
+            } else {
+                format!("\n[{lo:.4}, {hi:.4}]")
+            };
+            parent.spawn(TextBundle::from_section(
+                format!("{}M{o}O: {value}{range}", output.id),
+                module_text_style(),
+            ));
+
+            parent.spawn(NodeBundle {
+                style: Style {
+                    width: Val::Px(BAR_WIDTH * frames.len() as f32),
+                    height: Val::Px(SPARKLINE_HEIGHT),
+                    margin: UiRect::top(Val::Px(5.0)),
+                    ..default()
+                },
+                ..default()
+            }).with_children(|parent| {
+                for (flo, fhi) in &frames {
+                    parent.spawn(NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Relative,
+                            width: Val::Px(BAR_WIDTH),
+                            height: Val::Px(((fhi - flo) / span * SPARKLINE_HEIGHT).max(1.0)),
+                            top: Val::Px((hi - fhi) / span * SPARKLINE_HEIGHT),
+                            ..default()
+                        },
+                        background_color: Color::GREEN.into(),
+                        ..default()
+                    });
+                }
+            });
+        });
+    }
+}
+fn module_errors(mut commands: Commands, racks: Res<Assets<Rack>>, h_racks: Res<RackHandles>, q_child: Query<&Parent, With<ModuleComponent>>, q_errors: Query<&ModuleErrorComponent>) {
+    if let Some(rack) = racks.get(
+        &h_racks.0[
+            RACK_DIR_IDX.load(atomic::Ordering::Acquire)
+        ]
+    ) {
+        for (id, reason) in rack.bypassed() {
+            if q_errors.iter().any(|e| e.0 == id) {
+                continue;
+            }
+
+            // Show the error over the bottom of the module's panel
+            let top = rack.modules.get(&ModuleKey {
+                id,
+                iok: ModuleIOK::None,
+            }).and_then(|m| m.component())
+                .and_then(|c| q_child.get(c).ok())
+                .map(Parent::get);
+            if let Some(mut top) = top.and_then(|top| commands.get_entity(top)) {
+                top.with_children(|parent| {
+                    parent.spawn((
+                        TextBundle {
+                            z_index: ZIndex::Local(1),
+                            ..TextBundle::from_section(
+                                format!("Bypassed: {reason}"),
+                                TextStyle {
+                                    color: Color::RED,
+                                    ..module_text_style()
+                                },
+                            ).with_style(Style {
+                                position_type: PositionType::Absolute,
+                                left: Val::Px(0.0),
+                                right: Val::Px(0.0),
+                                bottom: Val::Px(0.0),
+                                padding: UiRect::all(Val::Px(5.0)),
+                                ..default()
+                            }).with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.9))
+                        },
+                        ModuleErrorComponent(id),
+                    ));
+                });
+            }
+        }
+    }
+}
+fn duplicate_module(mut commands: Commands, keys: Res<Input<KeyCode>>, q_windows: Query<&Window, With<PrimaryWindow>>, mut racks: ResMut<Assets<Rack>>, h_racks: ResMut<RackHandles>, mut images: ResMut<Assets<Image>>, mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<ColorMaterial>>, q_child: Query<&Parent, With<ModuleComponent>>, q_transform: Query<&GlobalTransform>, q_parent: Query<&Parent>, q_children: Query<&Children>) {
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) || !keys.just_pressed(KeyCode::D) {
+        return;
+    }
+    let Some(mpos) = q_windows.get_single().ok().and_then(Window::cursor_position) else {
+        return;
+    };
+
+    if let Some(rack) = racks.get_mut(
+        &h_racks.0[
+            RACK_DIR_IDX.load(atomic::Ordering::Acquire)
+        ]
+    ) {
+        let Some((id, mut m)) = rack.modules.iter()
+            .find(|(k, m)| k.id != usize::MAX && m.is_init() && m.is_hovered(mpos, &q_child, &q_transform))
+            .map(|(k, m)| (k.id, m.clone()))
+        else {
+            return;
+        };
+
+        // Find the original module's place in the layout
+        let Some(top) = m.component()
+            .and_then(|c| q_parent.get(c).ok())
+            .map(Parent::get)
+        else {
+            return;
+        };
+        let Some(container) = q_parent.get(top).ok().map(Parent::get) else {
+            return;
+        };
+        let index = q_children.get(container)
+            .ok()
+            .and_then(|children| children.iter().position(|c| *c == top))
+            .map_or(0, |i| i + 1);
+
+        // Init the copy next to the original, letting it flow with the
+        // automatic layout even if the original has an explicit position
+        m.exit();
+        let new_id = rack.next_module_id();
+        let layout = ModuleLayout {
+            pos: None,
+            ..rack.layout.get(&id)
+                .cloned()
+                .unwrap_or_default()
+        };
+        let node = commands.spawn((
+            module_node(m.as_ref(), &layout),
+            TopModuleComponent,
+        )).id();
+        m.init(
+            new_id,
+            commands.entity(node),
+            &mut images,
+            &mut meshes,
+            &mut materials,
+            module_text_style(),
+        );
+        commands.entity(container).insert_children(index, &[node]);
+
+        info!("Duplicated M{id} as M{new_id} with knobs {:?}", m.get_knobs());
+        rack.layout.insert(new_id, layout);
+        rack.modules.insert(
+            ModuleKey {
+                id: new_id,
+                iok: ModuleIOK::None,
+            },
+            m,
+        );
+        rack.init_knob_ranges();
+    }
+}
+/// Make the given entity a window for a module with the given options
+fn insert_module_window(window: &mut EntityCommands<'_, '_, '_>, title: String, resolution: [f32; 2], options: &WindowOptions) {
+    let [w, h] = options.resolution.unwrap_or(resolution);
+    window.insert(
+        Window {
+            title,
+            resolution: WindowResolution::new(w, h),
+            position: match options.monitor {
+                Some(monitor) => WindowPosition::Centered(MonitorSelection::Index(monitor)),
+                None => WindowPosition::Automatic,
+            },
+            window_level: if options.is_always_on_top {
+                WindowLevel::AlwaysOnTop
+            } else {
+                WindowLevel::Normal
+            },
+            present_mode: PresentMode::AutoNoVsync,
+            ..default()
+        }
+    );
+    if options.is_fullscreen {
+        window.insert(PendingFullscreenComponent);
+    }
+}
+/// Spawn a window for a module with the given options
+fn spawn_module_window(commands: &mut Commands, title: String, resolution: [f32; 2], options: &WindowOptions) -> Entity {
+    let mut window = commands.spawn_empty();
+    insert_module_window(&mut window, title, resolution, options);
+    window.id()
+}
+/// Open the extra windows that modules have asked for, e.g. the tiles of a
+/// video wall
+fn module_extra_windows(mut commands: Commands, q_windows: Query<(Entity, &ModuleExtraWindowComponent), Without<Window>>) {
+    for (entity, mw) in &q_windows {
+        insert_module_window(&mut commands.entity(entity), mw.title.clone(), mw.resolution, &mw.options);
+    }
+}
+fn window_fullscreen(mut commands: Commands, mut q_windows: Query<(Entity, &mut Window), (With<PendingFullscreenComponent>, With<RawHandleWrapper>)>) {
+    // Wait until the window has been created on its monitor before making it
+    // fullscreen so that it stays on that monitor
+    for (entity, mut window) in &mut q_windows {
+        window.mode = WindowMode::BorderlessFullscreen;
+        commands.entity(entity).remove::<PendingFullscreenComponent>();
     }
 }
 fn window_resize(mut commands: Commands, mut ev_resize: EventReader<WindowResized>, q_windows: Query<&PrimaryWindow>, q_patches: Query<Entity, With<PatchComponent>>, mut state: ResMut<NextState<AppState>>) {
@@ -0,0 +1,156 @@
+/*!
+The `Attenuverter` module takes up to 4 inputs and separately scales, inverts,
+and offsets each of them.
+
+Each unpatched input is treated as 0.0, so that its channel outputs a constant
+signal of its offset.
+
+## Inputs
+0. First signal
+1. Second signal
+2. Third signal
+3. Fourth signal
+
+## Outputs
+0. First scaled signal
+1. Second scaled signal
+2. Third scaled signal
+3. Fourth scaled signal
+
+## Knobs
+0. Scale for Input 0 in the range [-1.0, 1.0] where negative values invert it
+1. Scale for Input 1 in the range [-1.0, 1.0]
+2. Scale for Input 2 in the range [-1.0, 1.0]
+3. Scale for Input 3 in the range [-1.0, 1.0]
+4. Offset for Input 0 in the range (-inf, inf)
+5. Offset for Input 1 in the range (-inf, inf)
+6. Offset for Input 2 in the range (-inf, inf)
+7. Offset for Input 3 in the range (-inf, inf)
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Attenuverter {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    knobs: [f32; 8],
+}
+impl Attenuverter {
+    const CHANNELS: usize = 4;
+}
+#[typetag::deserialize]
+impl Module for Attenuverter {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Attenuverter\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("C0\n", ts.clone()),
+                            TextSection::new("C1\n", ts.clone()),
+                            TextSection::new("C2\n", ts.clone()),
+                            TextSection::new("C3\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        Self::CHANNELS
+    }
+    fn outputs(&self) -> usize {
+        Self::CHANNELS
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("attenuverter.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        ins.iter()
+            .enumerate()
+            .map(|(c, inp)| {
+                let inp = if inp.is_nan() {
+                    0.0
+                } else {
+                    *inp
+                };
+                inp * self.knobs[c].clamp(-1.0, 1.0) + self.knobs[c + Self::CHANNELS]
+            }).collect()
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                for c in 0..Self::CHANNELS {
+                    text.sections[c + 1].value = format!(
+                        "K{c} Scale {}: {}, K{} Offset {}: {}\n",
+                        c + 1,
+                        self.knobs[c],
+                        c + Self::CHANNELS,
+                        c + 1,
+                        self.knobs[c + Self::CHANNELS],
+                    );
+                }
+            }
+        }
+    }
+}
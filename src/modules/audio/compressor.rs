@@ -1,17 +1,37 @@
 /*!
 The `Compressor` module takes an input and compresses it the given amount.
 
+## Oversampling
+Setting `oversampling = 2` or `oversampling = 4` runs the module at that
+multiple of the sample rate to reduce aliasing at high gains, at the cost of
+16 samples of latency.
+
+## Detector
+The level which is compared to the threshold is detected from a copy of the
+input which can be high-passed so that the low end, e.g. a kick drum, doesn't
+pump the rest of the signal. The detected level follows peaks instantly and
+releases with two stages, so short transients release at the release time
+while sustained compression releases up to 5 times slower.
+
+Setting `auto_makeup = true` additionally applies the makeup gain which brings
+a full-scale signal back to full-scale after compression.
+
 ## Inputs
 0. The signal to compress
 
 ## Outputs
 0. The compressed signal
 1. The amount of gain reduction applied to the signal
+2. The amount of gain reduction applied to the signal in dB
 
 ## Knobs
 0. Compression ratio in the range (0.0, inf)
 1. Threshold in the range [0.0, inf)
 2. Makeup gain in the range [0.0, inf)
+3. Detector high-pass cutoff in the range [0.0, 22050.0) in Hz, where 0.0
+   disables the filter
+4. Release time in the range [0.0, inf) in seconds, where 0.0 releases
+   instantly
 
 */
 
@@ -19,7 +39,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, audio::equalizer::{EqualizerFunc, Biquad}}};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Compressor {
@@ -33,12 +53,42 @@ pub struct Compressor {
     #[serde(skip)]
     children: Vec<Entity>,
 
-    knobs: [f32; 3],
+    #[serde(default = "default_oversampling")]
+    oversampling: usize,
+    #[serde(default)]
+    auto_makeup: bool,
+
+    #[serde(skip)]
+    detector: Biquad,
+    /// The fast and slow stages of the detected level
+    #[serde(skip)]
+    levels: [f32; 2],
+
+    knobs: [f32; 5],
+}
+fn default_oversampling() -> usize {
+    1
+}
+impl Compressor {
+    /// How much slower the slow release stage is than the fast stage
+    const SLOW_RELEASE: f32 = 5.0;
+
+    /// Get the compressed level for the given detected level
+    fn compress(&self, level: f32) -> f32 {
+        let ratio = self.knobs[0];
+        let threshold = self.knobs[1];
+        if ratio == 0.0 || level <= threshold {
+            level
+        } else {
+            threshold + (level - threshold) / ratio
+        }
+    }
 }
 #[typetag::deserialize]
 impl Module for Compressor {
     fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
         self.id = Some(id);
+        self.init_state(id);
         ec.with_children(|parent| {
             let mut component = parent.spawn((
                 NodeBundle {
@@ -62,7 +112,9 @@ impl Module for Compressor {
                             TextSection::new(name, ts.clone()),
                             TextSection::new("K0\n", ts.clone()),
                             TextSection::new("K1\n", ts.clone()),
-                            TextSection::new("K2\n", ts),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts.clone()),
+                            TextSection::new("K4\n", ts),
                         ]),
                         ModuleTextComponent,
                     )).id()
@@ -71,10 +123,22 @@ impl Module for Compressor {
             self.component = Some(component.id());
         });
     }
+    fn init_state(&mut self, _id: usize) {
+        if ![1, 2, 4].contains(&self.oversampling) {
+            error!("Unsupported oversampling factor {} for Compressor, must be 1, 2, or 4", self.oversampling);
+            self.oversampling = 1;
+        }
+    }
     fn exit(&mut self) {
         self.id = None;
         self.component = None;
         self.children = vec![];
+
+        self.detector = Biquad::default();
+        self.levels = [0.0; 2];
+    }
+    fn oversampling(&self) -> usize {
+        self.oversampling
     }
 
     fn id(&self) -> Option<usize> {
@@ -91,50 +155,87 @@ impl Module for Compressor {
         1
     }
     fn outputs(&self) -> usize {
-        2
+        3
     }
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/compressor.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
     }
     fn set_knob(&mut self, i: usize, val: f32) {
         self.knobs[i] = val;
+
+        if i == 3 {
+            self.detector.reset_feedback();
+        }
     }
 
     fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
-        let ratio = self.knobs[0];
-        let threshold = self.knobs[1];
-        let makeup = self.knobs[2];
-
-        if ratio == 0.0 {
-            return vec![
-                ins[0] * (1.0 + makeup),
-                0.0,
-            ];
+        let sr = 44100.0 * self.oversampling as f32;
+        let x = ins[0];
+        if x.is_nan() {
+            return vec![f32::NAN; self.outputs()];
         }
+        let cutoff = self.knobs[3];
+        let release = self.knobs[4].max(0.0);
 
-        if ins[0].abs() > threshold {
-            let excess = ins[0].abs() - threshold;
-            vec![
-                ins[0].signum() * (threshold + excess / ratio) * (1.0 + makeup),
-                excess - excess / ratio,
-            ]
+        // Detect the level of the optionally high-passed input
+        let d = if cutoff > 0.0 {
+            let (a, b) = EqualizerFunc::HPF.coefficients(cutoff.min(sr / 2.0 - 1.0) / self.oversampling as f32, 0.707, 1.0);
+            self.detector.process(&a, &b, x)
         } else {
-            vec![
-                ins[0] * (1.0 + makeup),
-                0.0,
-            ]
-        }
+            x
+        };
+        let level = if release > 0.0 {
+            let fast = (-1.0 / (release * sr)).exp();
+            let slow = (-1.0 / (release * Self::SLOW_RELEASE * sr)).exp();
+            self.levels[0] = if d.abs() > self.levels[0] {
+                d.abs()
+            } else {
+                self.levels[0] * fast
+            };
+            self.levels[1] = if d.abs() > self.levels[1] {
+                self.levels[1] + (d.abs() - self.levels[1]) * (1.0 - fast)
+            } else {
+                self.levels[1] * slow
+            };
+            self.levels[0].max(self.levels[1])
+        } else {
+            self.levels = [0.0; 2];
+            d.abs()
+        };
+
+        let compressed = self.compress(level);
+        let gain = if level > 0.0 {
+            compressed / level
+        } else {
+            1.0
+        };
+        let makeup = (1.0 + self.knobs[2]) * if self.auto_makeup {
+            1.0 / self.compress(1.0).max(f32::EPSILON)
+        } else {
+            1.0
+        };
+
+        vec![
+            x * gain * makeup,
+            level - compressed,
+            -20.0 * gain.log10(),
+        ]
     }
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Ratio: {}\n", self.knobs[0]);
-                text.sections[2].value = format!("K1 Threshold: {}\n", self.knobs[1]);
-                text.sections[3].value = format!("K2 Makeup Gain: {}\n", self.knobs[2]);
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(4, self.knobs[4]));
             }
         }
     }
@@ -0,0 +1,171 @@
+/*!
+The `Crossover` module takes an input and splits it into low, mid, and high
+frequency bands, e.g. to build multiband effects by processing each band
+separately and mixing them back together.
+
+The bands are split with 4th-order Linkwitz-Riley filters and the low band is
+passed through a matching all-pass filter, so that the bands stay in phase with
+each other and sum back to a flat response.
+
+## Inputs
+0. The signal to split
+
+## Outputs
+0. The low band below K0
+1. The mid band between K0 and K1
+2. The high band above K1
+
+## Knobs
+0. Low/mid split frequency in the range (0.0, 22050.0) in Hz
+1. Mid/high split frequency in the range (0.0, 22050.0) in Hz
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, audio::equalizer::{EqualizerFunc, Biquad}}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Crossover {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    /// The low-pass, high-pass, and all-pass filters of each band
+    #[serde(skip)]
+    biquads: [Biquad; 7],
+
+    knobs: [f32; 2],
+}
+impl Crossover {
+    /// The Q of the Butterworth filters which make up each Linkwitz-Riley
+    /// filter
+    const Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    /// Filter the given signal through two cascaded biquads
+    fn cascade(biquads: &mut [Biquad], func: &EqualizerFunc, f_0: f32, x: f32) -> f32 {
+        let (a, b) = func.coefficients(f_0, Self::Q, 1.0);
+        biquads.iter_mut()
+            .fold(x, |x, bq| bq.process(&a, &b, x))
+    }
+}
+#[typetag::deserialize]
+impl Module for Crossover {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Crossover\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.biquads = Default::default();
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        3
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/crossover.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+
+        for bq in &mut self.biquads {
+            bq.reset_feedback();
+        }
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let x = ins[0];
+        if x.is_nan() {
+            return vec![f32::NAN; self.outputs()];
+        }
+
+        let f_low = self.knobs[0].min(self.knobs[1]).clamp(1.0, 22049.0);
+        let f_high = self.knobs[0].max(self.knobs[1]).clamp(1.0, 22049.0);
+
+        let (lp_low, rest) = self.biquads.split_at_mut(2);
+        let (hp_low, rest) = rest.split_at_mut(2);
+        let (lp_high, rest) = rest.split_at_mut(2);
+        let (hp_high, ap_high) = rest.split_at_mut(2);
+
+        let low = Self::cascade(lp_low, &EqualizerFunc::LPF, f_low, x);
+        let above = Self::cascade(hp_low, &EqualizerFunc::HPF, f_low, x);
+        let mid = Self::cascade(lp_high, &EqualizerFunc::LPF, f_high, above);
+        let high = Self::cascade(hp_high, &EqualizerFunc::HPF, f_high, above);
+
+        // Shift the low band's phase to match the split between the mid and
+        // high bands
+        let (a, b) = EqualizerFunc::APF.coefficients(f_high, Self::Q, 1.0);
+        let low = ap_high[0].process(&a, &b, low);
+
+        vec![low, mid, high]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+            }
+        }
+    }
+}
@@ -20,7 +20,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, rack::flush_state, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Delay {
@@ -102,6 +102,9 @@ impl Module for Delay {
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/delay.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -135,7 +138,7 @@ impl Module for Delay {
         let delayed = if buflen > 0 {
             self.delay_idx %= buflen;
             let delayed = self.buffer[self.delay_idx];
-            self.buffer[self.delay_idx] = feedback * (x + delayed);
+            self.buffer[self.delay_idx] = flush_state(feedback * (x + delayed));
             delayed
         } else {
             0.0
@@ -151,9 +154,9 @@ impl Module for Delay {
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Delay: {}\n", self.knobs[0]);
-                text.sections[2].value = format!("K1 Feedback: {}\n", self.knobs[1]);
-                text.sections[3].value = format!("K2 Dry/Wet: {}\n", self.knobs[2]);
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
             }
         }
     }
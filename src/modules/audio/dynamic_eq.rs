@@ -0,0 +1,182 @@
+/*!
+The `DynamicEq` module takes an input and cuts a single frequency band by an
+amount which depends on that band's level, e.g. as a de-esser on vocals or to
+tame resonances in feedback patches.
+
+The band is band-passed from the input and its level is detected like a
+`Compressor` which follows peaks instantly and releases over
+[DynamicEq::RELEASE] seconds. Whenever the level is above the threshold, the
+band is subtracted from the input so that its level is compressed by the
+ratio, while the rest of the spectrum is left untouched.
+
+## Inputs
+0. The signal to equalize
+
+## Outputs
+0. The equalized signal
+1. The amount of gain reduction applied to the band in dB
+2. The detected band, e.g. for tuning the frequency and Q
+
+## Knobs
+0. Frequency of the band in the range (0.0, 22050.0) in Hz, e.g. around
+   6000.0 for de-essing
+1. Q in the range (0.0, inf)
+2. Threshold in the range [0.0, inf)
+3. Compression ratio in the range [1.0, inf)
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, audio::equalizer::{EqualizerFunc, Biquad}}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DynamicEq {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    biquad: Biquad,
+    #[serde(skip)]
+    level: f32,
+
+    knobs: [f32; 4],
+}
+impl DynamicEq {
+    const RELEASE: f32 = 0.05;
+}
+#[typetag::deserialize]
+impl Module for DynamicEq {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} DynamicEq\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.biquad = Biquad::default();
+        self.level = 0.0;
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        3
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/dynamic_eq.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+
+        if i < 2 {
+            self.biquad.reset_feedback();
+        }
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let sr = 44100.0;
+        let x = ins[0];
+        if x.is_nan() {
+            return vec![f32::NAN; self.outputs()];
+        }
+
+        let f_0 = self.knobs[0].clamp(1.0, sr / 2.0 - 1.0);
+        let q = self.knobs[1].max(f32::EPSILON);
+        let threshold = self.knobs[2].max(0.0);
+        let ratio = self.knobs[3].max(1.0);
+
+        // Normalize the band-pass to unity gain at the center frequency
+        let (a, b) = EqualizerFunc::BPF.coefficients(f_0, q, 1.0 / q);
+        let band = self.biquad.process(&a, &b, x);
+
+        let release = (-1.0 / (Self::RELEASE * sr)).exp();
+        self.level = if band.abs() > self.level {
+            band.abs()
+        } else {
+            self.level * release
+        };
+
+        let gain = if self.level > threshold {
+            (threshold + (self.level - threshold) / ratio) / self.level
+        } else {
+            1.0
+        };
+
+        vec![
+            x - band * (1.0 - gain),
+            -20.0 * gain.log10(),
+            band,
+        ]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+            }
+        }
+    }
+}
@@ -30,7 +30,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, rack::flush_state, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
 
 #[derive(Default, Deserialize, Debug, Clone)]
 pub enum EqualizerFunc {
@@ -43,6 +43,138 @@ pub enum EqualizerFunc {
     LowShelf,
     HighShelf,
 }
+impl EqualizerFunc {
+    /// Calculate the biquad coefficients `(a, b)` for the given frequency, Q,
+    /// and gain
+    pub fn coefficients(&self, f_0: f32, q: f32, g: f32) -> ([f32; 3], [f32; 3]) {
+        let f_s = 44100.0;
+
+        let w_0 = 2.0 * PI * f_0 / f_s;
+        let alpha = w_0.sin() / 2.0 / q;
+
+        match self {
+            Self::LPF => (
+                [
+                    1.0 + alpha,
+                    -2.0 * w_0.cos(),
+                    1.0 - alpha,
+                ],
+                [
+                    g * (1.0 - w_0.cos()) / 2.0,
+                    g * (1.0 - w_0.cos()),
+                    g * (1.0 - w_0.cos()) / 2.0,
+                ]
+            ),
+            Self::HPF => (
+                [
+                    1.0 + alpha,
+                    -2.0 * w_0.cos(),
+                    1.0 - alpha,
+                ],
+                [
+                    g * (1.0 + w_0.cos()) / 2.0,
+                    g * (-1.0 - w_0.cos()),
+                    g * (1.0 + w_0.cos()) / 2.0,
+                ]
+            ),
+            Self::BPF => (
+                [
+                    1.0 + alpha,
+                    -2.0 * w_0.cos(),
+                    1.0 - alpha,
+                ],
+                [
+                    g * w_0.sin() / 2.0,
+                    0.0,
+                    g * -w_0.sin() / 2.0,
+                ]
+            ),
+            Self::Notch => (
+                [
+                    1.0 + alpha,
+                    -2.0 * w_0.cos(),
+                    1.0 - alpha,
+                ],
+                [
+                    g,
+                    g * -2.0 * w_0.cos(),
+                    g,
+                ]
+            ),
+            Self::APF => (
+                [
+                    1.0 + alpha,
+                    -2.0 * w_0.cos(),
+                    1.0 - alpha,
+                ],
+                [
+                    g * (1.0 - alpha),
+                    g * -2.0 * w_0.cos(),
+                    g * (1.0 + alpha),
+                ]
+            ),
+            Self::LowShelf => {
+                let dbgain = 10.0 * g.log10();
+                let big_a = 10.0f32.powf(dbgain / 40.0);
+                (
+                    [
+                        big_a + 1.0 + (big_a - 1.0) * w_0.cos() + 2.0 * big_a.sqrt() * alpha,
+                        -2.0 * (big_a - 1.0 + (big_a + 1.0) * w_0.cos()),
+                        big_a + 1.0 + (big_a - 1.0) * w_0.cos() - 2.0 * big_a.sqrt() * alpha,
+                    ],
+                    [
+                        big_a * (big_a + 1.0 - (big_a  - 1.0) * w_0.cos() + 2.0 * big_a.sqrt() * alpha),
+                        2.0 * big_a * (big_a - 1.0 - (big_a + 1.0) * w_0.cos()),
+                        big_a * (big_a + 1.0 - (big_a - 1.0) * w_0.cos() - 2.0 * big_a.sqrt() * alpha),
+                    ]
+                )
+            },
+            Self::HighShelf => {
+                let dbgain = 10.0 * g.log10();
+                let big_a = 10.0f32.powf(dbgain / 40.0);
+                (
+                    [
+                        big_a + 1.0 - (big_a - 1.0) * w_0.cos() + 2.0 * big_a.sqrt() * alpha,
+                        2.0 * (big_a - 1.0 - (big_a + 1.0) * w_0.cos()),
+                        big_a + 1.0 - (big_a - 1.0) * w_0.cos() - 2.0 * big_a.sqrt() * alpha,
+                    ],
+                    [
+                        big_a * (big_a + 1.0 + (big_a  - 1.0) * w_0.cos() + 2.0 * big_a.sqrt() * alpha),
+                        -2.0 * big_a * (big_a - 1.0 + (big_a + 1.0) * w_0.cos()),
+                        big_a * (big_a + 1.0 + (big_a - 1.0) * w_0.cos() - 2.0 * big_a.sqrt() * alpha),
+                    ]
+                )
+            },
+        }
+    }
+}
+
+/// The state of a single biquad filter
+#[derive(Default, Debug, Clone)]
+pub struct Biquad {
+    xs: [f32; 2],
+    ys: [f32; 2],
+}
+impl Biquad {
+    pub fn process(&mut self, a: &[f32; 3], b: &[f32; 3], x: f32) -> f32 {
+        let y = b[0] / a[0] * x
+            + b[1] / a[0] * self.xs[0]
+            + b[2] / a[0] * self.xs[1]
+            - a[1] / a[0] * self.ys[0]
+            - a[2] / a[0] * self.ys[1];
+
+        self.ys[1] = self.ys[0];
+        self.ys[0] = flush_state(y);
+        self.xs[1] = self.xs[0];
+        self.xs[0] = flush_state(x);
+
+        y
+    }
+    /// Reset the Y's to prevent runaway feedback
+    pub fn reset_feedback(&mut self) {
+        self.ys = [0.0, 0.0];
+    }
+}
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Equalizer {
@@ -60,9 +192,7 @@ pub struct Equalizer {
     func: EqualizerFunc,
 
     #[serde(skip)]
-    xs: [f32; 2],
-    #[serde(skip)]
-    ys: [f32; 2],
+    biquad: Biquad,
 
     knobs: [f32; 3],
 }
@@ -128,6 +258,9 @@ impl Module for Equalizer {
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/equalizer.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -135,8 +268,7 @@ impl Module for Equalizer {
     fn set_knob(&mut self, i: usize, val: f32) {
         self.knobs[i] = val;
         if i == 0 {
-            // Reset Y's to prevent runaway feedback
-            self.ys = [0.0, 0.0];
+            self.biquad.reset_feedback();
         }
     }
 
@@ -150,116 +282,8 @@ impl Module for Equalizer {
         let q = self.knobs[1];
         let g = self.knobs[2];
 
-        let f_s = 44100.0;
-
-        let w_0 = 2.0 * PI * f_0 / f_s;
-        let alpha = w_0.sin() / 2.0 / q;
-
-        let (a, b) = match self.func {
-            EqualizerFunc::LPF => (
-                [
-                    1.0 + alpha,
-                    -2.0 * w_0.cos(),
-                    1.0 - alpha,
-                ],
-                [
-                    g * (1.0 - w_0.cos()) / 2.0,
-                    g * (1.0 - w_0.cos()),
-                    g * (1.0 - w_0.cos()) / 2.0,
-                ]
-            ),
-            EqualizerFunc::HPF => (
-                [
-                    1.0 + alpha,
-                    -2.0 * w_0.cos(),
-                    1.0 - alpha,
-                ],
-                [
-                    g * (1.0 + w_0.cos()) / 2.0,
-                    g * (-1.0 - w_0.cos()),
-                    g * (1.0 + w_0.cos()) / 2.0,
-                ]
-            ),
-            EqualizerFunc::BPF => (
-                [
-                    1.0 + alpha,
-                    -2.0 * w_0.cos(),
-                    1.0 - alpha,
-                ],
-                [
-                    g * w_0.sin() / 2.0,
-                    0.0,
-                    g * -w_0.sin() / 2.0,
-                ]
-            ),
-            EqualizerFunc::Notch => (
-                [
-                    1.0 + alpha,
-                    -2.0 * w_0.cos(),
-                    1.0 - alpha,
-                ],
-                [
-                    g,
-                    g * -2.0 * w_0.cos(),
-                    g,
-                ]
-            ),
-            EqualizerFunc::APF => (
-                [
-                    1.0 + alpha,
-                    -2.0 * w_0.cos(),
-                    1.0 - alpha,
-                ],
-                [
-                    g * (1.0 - alpha),
-                    g * -2.0 * w_0.cos(),
-                    g * (1.0 + alpha),
-                ]
-            ),
-            EqualizerFunc::LowShelf => {
-                let dbgain = 10.0 * g.log10();
-                let big_a = 10.0f32.powf(dbgain / 40.0);
-                (
-                    [
-                        big_a + 1.0 + (big_a - 1.0) * w_0.cos() + 2.0 * big_a.sqrt() * alpha,
-                        -2.0 * (big_a - 1.0 + (big_a + 1.0) * w_0.cos()),
-                        big_a + 1.0 + (big_a - 1.0) * w_0.cos() - 2.0 * big_a.sqrt() * alpha,
-                    ],
-                    [
-                        big_a * (big_a + 1.0 - (big_a  - 1.0) * w_0.cos() + 2.0 * big_a.sqrt() * alpha),
-                        2.0 * big_a * (big_a - 1.0 - (big_a + 1.0) * w_0.cos()),
-                        big_a * (big_a + 1.0 - (big_a - 1.0) * w_0.cos() - 2.0 * big_a.sqrt() * alpha),
-                    ]
-                )
-            },
-            EqualizerFunc::HighShelf => {
-                let dbgain = 10.0 * g.log10();
-                let big_a = 10.0f32.powf(dbgain / 40.0);
-                (
-                    [
-                        big_a + 1.0 - (big_a - 1.0) * w_0.cos() + 2.0 * big_a.sqrt() * alpha,
-                        2.0 * (big_a - 1.0 - (big_a + 1.0) * w_0.cos()),
-                        big_a + 1.0 - (big_a - 1.0) * w_0.cos() - 2.0 * big_a.sqrt() * alpha,
-                    ],
-                    [
-                        big_a * (big_a + 1.0 + (big_a  - 1.0) * w_0.cos() + 2.0 * big_a.sqrt() * alpha),
-                        -2.0 * big_a * (big_a - 1.0 + (big_a + 1.0) * w_0.cos()),
-                        big_a * (big_a + 1.0 + (big_a - 1.0) * w_0.cos() - 2.0 * big_a.sqrt() * alpha),
-                    ]
-                )
-            },
-        };
-
-        let y = b[0] / a[0] * x
-            + b[1] / a[0] * self.xs[0]
-            + b[2] / a[0] * self.xs[1]
-            - a[1] / a[0] * self.ys[0]
-            - a[2] / a[0] * self.ys[1];
-
-        self.ys[1] = self.ys[0];
-        self.ys[0] = y;
-        self.xs[1] = self.xs[0];
-        self.xs[0] = x;
+        let (a, b) = self.func.coefficients(f_0, q, g);
+        let y = self.biquad.process(&a, &b, x);
 
         vec![y]
     }
@@ -267,9 +291,9 @@ impl Module for Equalizer {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
                 text.sections[1].value = format!("Func: {:?}\n", self.func);
-                text.sections[2].value = format!("K0 Frequency: {}\n", self.knobs[0]);
-                text.sections[3].value = format!("K1 Q: {}\n", self.knobs[1]);
-                text.sections[4].value = format!("K2 Gain: {}\n", self.knobs[2]);
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
             }
         }
     }
@@ -0,0 +1,207 @@
+/*!
+The `FreqShift` module takes an input and shifts all of its frequencies by a
+constant amount, unlike the `PitchShifter` module which scales them. This
+produces inharmonic, bell-like tones for small shifts and barber pole phasing
+for very small ones.
+
+The input is split into a quadrature pair by a Hilbert transformer made of two
+allpass filter chains, which is then single-sideband modulated by an internal
+quadrature oscillator.
+
+## Inputs
+0. The signal to shift
+
+## Outputs
+0. The signal shifted up by K0
+1. The signal shifted down by K0
+
+## Knobs
+0. Shift amount in the range (-inf, inf) in Hz
+1. Dry/Wet mix in the range [0.0, 1.0]
+
+*/
+
+use std::f64::consts::PI;
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+/// A chain of 2nd-order allpass filters with the given squared coefficients
+#[derive(Default, Debug, Clone)]
+struct AllpassChain {
+    coeffs: [f32; 4],
+    xs: [[f32; 2]; 4],
+    ys: [[f32; 2]; 4],
+}
+impl AllpassChain {
+    fn new(coeffs: [f32; 4]) -> Self {
+        Self {
+            coeffs,
+            ..default()
+        }
+    }
+    fn process(&mut self, mut x: f32) -> f32 {
+        for ((a, xs), ys) in self.coeffs.iter().zip(&mut self.xs).zip(&mut self.ys) {
+            let y = a * (x + ys[1]) - xs[1];
+            *xs = [x, xs[0]];
+            *ys = [y, ys[0]];
+            x = y;
+        }
+        x
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FreqShift {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    real: AllpassChain,
+    #[serde(skip)]
+    imag: AllpassChain,
+    #[serde(skip)]
+    last_real: f32,
+    #[serde(skip)]
+    phase: f64,
+    #[serde(skip)]
+    last_outs: Vec<f32>,
+
+    knobs: [f32; 2],
+}
+impl FreqShift {
+    /// The squares of Olli Niemitalo's allpass coefficients which give a 90
+    /// degree phase difference between the two chains over most of the
+    /// audible range
+    const REAL_COEFFS: [f32; 4] = [0.479_401, 0.876_218_5, 0.976_597_6, 0.997_499_2];
+    const IMAG_COEFFS: [f32; 4] = [0.161_758_5, 0.733_028_9, 0.945_349_8, 0.990_599_2];
+}
+#[typetag::deserialize]
+impl Module for FreqShift {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} FreqShift\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, _id: usize) {
+        self.real = AllpassChain::new(Self::REAL_COEFFS);
+        self.imag = AllpassChain::new(Self::IMAG_COEFFS);
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.last_real = 0.0;
+        self.phase = 0.0;
+        self.last_outs.clear();
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        2
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/freq_shift.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        let sr = 44100.0;
+        let shift = f64::from(self.knobs[0]);
+        let dwmix = self.knobs[1].clamp(0.0, 1.0);
+
+        let x = ins[0];
+        if x.is_nan() {
+            return vec![f32::NAN; self.outputs()];
+        }
+        if st == StepType::Video && !self.last_outs.is_empty() {
+            return self.last_outs.clone();
+        }
+
+        // The real chain is delayed by one sample to align it with the
+        // imaginary chain
+        let re = self.last_real;
+        self.last_real = self.real.process(x);
+        let im = self.imag.process(x);
+
+        self.phase = (self.phase + 2.0 * PI * shift / sr).rem_euclid(2.0 * PI);
+        let (sin, cos) = (self.phase.sin() as f32, self.phase.cos() as f32);
+
+        let up = re * cos - im * sin;
+        let down = re * cos + im * sin;
+        self.last_outs = vec![
+            x * (1.0 - dwmix) + up * dwmix,
+            x * (1.0 - dwmix) + down * dwmix,
+        ];
+        self.last_outs.clone()
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+            }
+        }
+    }
+}
@@ -1,6 +1,11 @@
 /*!
 The `Fuzz` module takes an input and applies a fuzz to it.
 
+## Oversampling
+Setting `oversampling = 2` or `oversampling = 4` runs the module at that
+multiple of the sample rate to reduce aliasing at high gains, at the cost of
+16 samples of latency.
+
 ## Inputs
 0. The signal to distort
 
@@ -20,7 +25,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Fuzz {
@@ -34,12 +39,19 @@ pub struct Fuzz {
     #[serde(skip)]
     children: Vec<Entity>,
 
+    #[serde(default = "default_oversampling")]
+    oversampling: usize,
+
     knobs: [f32; 3],
 }
+fn default_oversampling() -> usize {
+    1
+}
 #[typetag::deserialize]
 impl Module for Fuzz {
     fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
         self.id = Some(id);
+        self.init_state(id);
         ec.with_children(|parent| {
             let mut component = parent.spawn((
                 NodeBundle {
@@ -72,11 +84,20 @@ impl Module for Fuzz {
             self.component = Some(component.id());
         });
     }
+    fn init_state(&mut self, _id: usize) {
+        if ![1, 2, 4].contains(&self.oversampling) {
+            error!("Unsupported oversampling factor {} for Fuzz, must be 1, 2, or 4", self.oversampling);
+            self.oversampling = 1;
+        }
+    }
     fn exit(&mut self) {
         self.id = None;
         self.component = None;
         self.children = vec![];
     }
+    fn oversampling(&self) -> usize {
+        self.oversampling
+    }
 
     fn id(&self) -> Option<usize> {
         self.id
@@ -97,6 +118,9 @@ impl Module for Fuzz {
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/fuzz.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -133,9 +157,9 @@ impl Module for Fuzz {
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Distortion: {}\n", self.knobs[0]);
-                text.sections[2].value = format!("K1 Volume: {}\n", self.knobs[1]);
-                text.sections[3].value = format!("K2 Dry/Wet: {}\n", self.knobs[2]);
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
             }
         }
     }
@@ -2,15 +2,30 @@
 The `Gate` module takes an input and outputs silence if the amplitude is below
 a given threshold.
 
+To stop noisy inputs from chattering, the gate only closes once the input falls
+below the threshold minus the hysteresis, and then only after it has stayed
+there for the hold time.
+
+## Gate Modes
+ * `Threshold` - Open while the amplitude is above K0, the default
+ * `Range` - Open while the value is within the range [K0, K4], e.g. to pass a
+   control signal only within a window of values
+
 ## Inputs
 0. The signal to gate
 
 ## Outputs
 0. The gated signal
+1. Whether the gate is open as 1.0, otherwise 0.0
 
 ## Knobs
-0. Threshold in the range [0.0, inf)
+0. Threshold in the range [0.0, inf), or the lower bound of the range in the
+   `Range` mode
 1. Damped Level in the range [0.0, 1.0]
+2. Hysteresis in the range [0.0, inf), how far past the threshold or range the
+   input must go to close the gate
+3. Hold time in the range [0.0, inf) in seconds
+4. Upper bound of the range in the range (-inf, inf) in the `Range` mode
 
 */
 
@@ -18,7 +33,14 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Default, Deserialize, Debug, Clone)]
+enum GateMode {
+    #[default]
+    Threshold,
+    Range,
+}
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Gate {
@@ -32,7 +54,15 @@ pub struct Gate {
     #[serde(skip)]
     children: Vec<Entity>,
 
-    knobs: [f32; 2],
+    #[serde(default)]
+    mode: GateMode,
+    #[serde(skip)]
+    is_open: bool,
+    /// The last time that the input was above the close threshold
+    #[serde(skip)]
+    last_open_time: f64,
+
+    knobs: [f32; 5],
 }
 #[typetag::deserialize]
 impl Module for Gate {
@@ -59,8 +89,12 @@ impl Module for Gate {
                     parent.spawn((
                         TextBundle::from_sections([
                             TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("Mode: {:?}\n", self.mode), ts.clone()),
                             TextSection::new("K0\n", ts.clone()),
-                            TextSection::new("K1\n", ts),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts.clone()),
+                            TextSection::new("K4\n", ts),
                         ]),
                         ModuleTextComponent,
                     )).id()
@@ -73,6 +107,9 @@ impl Module for Gate {
         self.id = None;
         self.component = None;
         self.children = vec![];
+
+        self.is_open = false;
+        self.last_open_time = 0.0;
     }
 
     fn id(&self) -> Option<usize> {
@@ -89,11 +126,14 @@ impl Module for Gate {
         1
     }
     fn outputs(&self) -> usize {
-        1
+        2
     }
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/gate.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -102,21 +142,48 @@ impl Module for Gate {
         self.knobs[i] = val;
     }
 
-    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+    fn step(&mut self, time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let x = ins[0];
         let threshold = self.knobs[0];
         let level = self.knobs[1];
+        let hysteresis = self.knobs[2].max(0.0);
+        let hold = f64::from(self.knobs[3].max(0.0));
+
+        let (should_open, should_stay) = match self.mode {
+            GateMode::Threshold => (
+                x.abs() >= threshold,
+                x.abs() >= threshold - hysteresis,
+            ),
+            GateMode::Range => {
+                let upper = self.knobs[4];
+                (
+                    x >= threshold && x <= upper,
+                    x >= threshold - hysteresis && x <= upper + hysteresis,
+                )
+            },
+        };
+
+        if should_open || (self.is_open && should_stay) {
+            self.is_open = true;
+            self.last_open_time = time;
+        } else if self.is_open && (time - self.last_open_time >= hold || time < self.last_open_time) {
+            self.is_open = false;
+        }
 
-        if ins[0].abs() < threshold {
-            vec![ins[0] * level]
+        if self.is_open {
+            vec![x, 1.0]
         } else {
-            vec![ins[0]]
+            vec![x * level, 0.0]
         }
     }
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Threshold: {}\n", self.knobs[0]);
-                text.sections[2].value = format!("K1 Damped Level: {}\n", self.knobs[1]);
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+                text.sections[6].value = format!("{}\n", self.describe().knob_row(4, self.knobs[4]));
             }
         }
     }
@@ -0,0 +1,217 @@
+/*!
+The `HatSynth` module synthesizes a hi-hat or cymbal whenever it's triggered,
+with six detuned square waves for the metallic partials mixed with noise and
+high-passed.
+
+Short decay times sound like a closed hi-hat while long decay times sound like
+an open hi-hat or a cymbal.
+
+## Inputs
+0. The trigger which starts a hit whenever this becomes positive, e.g. the
+   press/sustain/release output of a `Sequencer`
+
+## Outputs
+0. The hat signal
+
+## Knobs
+0. Tuning in the range (0.0, inf) as a multiple of the partials' frequencies,
+   usually around 1.0
+1. Decay time in the range (0.0, inf) in seconds
+2. Noise mix in the range [0.0, 1.0] where 0.0 is only the partials and 1.0 is
+   only the noise
+3. Brightness in the range (0.0, 22050.0) in Hz, the cutoff of the high-pass
+   filter, usually around 7000.0
+4. Level in the range [0.0, inf)
+
+*/
+
+use rand::prelude::*;
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, rack::module_rng, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, audio::equalizer::{EqualizerFunc, Biquad}}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct HatSynth {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    rng: Option<StdRng>,
+    #[serde(skip)]
+    biquad: Biquad,
+    #[serde(skip)]
+    last_trigger: f32,
+    /// The number of samples since the last hit, if it's still sounding
+    #[serde(skip)]
+    elapsed: Option<usize>,
+    #[serde(skip)]
+    phases: [f64; 6],
+    #[serde(skip)]
+    last_outs: Vec<f32>,
+
+    knobs: [f32; 5],
+}
+impl HatSynth {
+    /// The frequencies of the metallic partials in Hz at a tuning of 1.0
+    const PARTIALS: [f32; 6] = [205.3, 304.4, 369.6, 522.7, 540.0, 800.0];
+    /// The Q of the high-pass filter
+    const FILTER_Q: f32 = 0.707;
+    /// The envelope level below which a hit is finished
+    const SILENCE: f32 = 0.0001;
+}
+#[typetag::deserialize]
+impl Module for HatSynth {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} HatSynth\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts.clone()),
+                            TextSection::new("K4\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, id: usize) {
+        self.rng = Some(module_rng(id));
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.rng = None;
+        self.biquad = Biquad::default();
+        self.last_trigger = 0.0;
+        self.elapsed = None;
+        self.phases = [0.0; 6];
+        self.last_outs.clear();
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        1
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/hat_synth.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+
+        if i == 3 {
+            self.biquad.reset_feedback();
+        }
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        let sr = 44100.0;
+        if st == StepType::Video && !self.last_outs.is_empty() {
+            return self.last_outs.clone();
+        }
+
+        let trigger = ins[0];
+        if trigger > 0.0 && (self.last_trigger.is_nan() || self.last_trigger <= 0.0) {
+            self.elapsed = Some(0);
+        }
+        self.last_trigger = trigger;
+
+        let y = match self.elapsed {
+            Some(n) => {
+                let t = n as f32 / sr;
+                let env = (-t / self.knobs[1].max(f32::EPSILON)).exp();
+                self.elapsed = if env < Self::SILENCE {
+                    None
+                } else {
+                    Some(n + 1)
+                };
+
+                // Sum the square wave partials
+                let tune = self.knobs[0].max(0.0);
+                let mut partials = 0.0;
+                for (phase, freq) in self.phases.iter_mut().zip(Self::PARTIALS) {
+                    partials += if *phase < 0.5 { 1.0 } else { -1.0 };
+                    *phase = (*phase + f64::from(freq * tune / sr)).fract();
+                }
+                partials /= Self::PARTIALS.len() as f32;
+
+                let rng = self.rng.get_or_insert_with(StdRng::from_entropy);
+                let noise: f32 = rng.gen_range(-1.0..=1.0);
+
+                let mix = self.knobs[2].clamp(0.0, 1.0);
+                let x = partials * (1.0 - mix) + noise * mix;
+                let (a, b) = EqualizerFunc::HPF.coefficients(self.knobs[3].clamp(1.0, sr / 2.0 - 1.0), Self::FILTER_Q, 1.0);
+                self.biquad.process(&a, &b, x) * env * self.knobs[4]
+            },
+            None => 0.0,
+        };
+
+        self.last_outs = vec![y];
+        self.last_outs.clone()
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(4, self.knobs[4]));
+            }
+        }
+    }
+}
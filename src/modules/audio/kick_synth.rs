@@ -0,0 +1,202 @@
+/*!
+The `KickSynth` module synthesizes a kick drum whenever it's triggered, with a
+tuned sine body whose pitch sweeps down from above the tuning and a short noise
+click for the beater.
+
+## Inputs
+0. The trigger which starts a hit whenever this becomes positive, e.g. the
+   press/sustain/release output of a `Sequencer`
+
+## Outputs
+0. The kick signal
+
+## Knobs
+0. Tuning of the body in the range (0.0, inf) in Hz, usually around 50.0
+1. Pitch sweep in the range [0.0, inf) in octaves above the tuning
+2. Decay time in the range (0.0, inf) in seconds
+3. Click level in the range [0.0, 1.0]
+4. Level in the range [0.0, inf)
+
+*/
+
+use std::f64::consts::PI;
+
+use rand::prelude::*;
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, rack::module_rng, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct KickSynth {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    rng: Option<StdRng>,
+    #[serde(skip)]
+    last_trigger: f32,
+    /// The number of samples since the last hit, if it's still sounding
+    #[serde(skip)]
+    elapsed: Option<usize>,
+    #[serde(skip)]
+    phase: f64,
+    #[serde(skip)]
+    last_outs: Vec<f32>,
+
+    knobs: [f32; 5],
+}
+impl KickSynth {
+    /// The time constant of the pitch sweep in seconds
+    const SWEEP_TIME: f32 = 0.03;
+    /// The time constant of the click in seconds
+    const CLICK_TIME: f32 = 0.002;
+    /// The envelope level below which a hit is finished
+    const SILENCE: f32 = 0.0001;
+}
+#[typetag::deserialize]
+impl Module for KickSynth {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} KickSynth\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts.clone()),
+                            TextSection::new("K4\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, id: usize) {
+        self.rng = Some(module_rng(id));
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.rng = None;
+        self.last_trigger = 0.0;
+        self.elapsed = None;
+        self.phase = 0.0;
+        self.last_outs.clear();
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        1
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/kick_synth.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        let sr = 44100.0;
+        if st == StepType::Video && !self.last_outs.is_empty() {
+            return self.last_outs.clone();
+        }
+
+        let trigger = ins[0];
+        if trigger > 0.0 && (self.last_trigger.is_nan() || self.last_trigger <= 0.0) {
+            self.elapsed = Some(0);
+            self.phase = 0.0;
+        }
+        self.last_trigger = trigger;
+
+        let y = match self.elapsed {
+            Some(n) => {
+                let t = n as f32 / sr;
+                let env = (-t / self.knobs[2].max(f32::EPSILON)).exp();
+                self.elapsed = if env < Self::SILENCE {
+                    None
+                } else {
+                    Some(n + 1)
+                };
+
+                let freq = self.knobs[0].max(0.0)
+                    * 2.0f32.powf(self.knobs[1].max(0.0) * (-t / Self::SWEEP_TIME).exp());
+                let body = (2.0 * PI * self.phase).sin() as f32;
+                self.phase = (self.phase + f64::from(freq / sr)).fract();
+
+                let rng = self.rng.get_or_insert_with(StdRng::from_entropy);
+                let click = rng.gen_range(-1.0..=1.0)
+                    * (-t / Self::CLICK_TIME).exp()
+                    * self.knobs[3].clamp(0.0, 1.0);
+
+                (body * env + click) * self.knobs[4]
+            },
+            None => 0.0,
+        };
+
+        self.last_outs = vec![y];
+        self.last_outs.clone()
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(4, self.knobs[4]));
+            }
+        }
+    }
+}
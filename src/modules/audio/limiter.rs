@@ -1,23 +1,40 @@
 /*!
 The `Limiter` module takes an input and restricts it to the given bound.
 
+When the lower limit is less than the upper limit, the signal is limited by
+reducing its gain around the center of the bound. A lookahead delay can be used
+so that the gain is reduced before a peak arrives, and a soft knee can be used
+to begin reducing the gain before the limit is reached. Any remaining overs are
+clipped to the bound.
+
+Optionally, `true_peak` can be set in order to detect inter-sample peaks by
+oversampling the input 4 times.
+
 ## Inputs
 0. The signal to limit
 
 ## Outputs
 0. The limited signal
+1. The amount of gain reduction applied to the signal in the range [0.0, 1.0]
 
 ## Knobs
 0. Lower limit in the range (-inf, inf)
 1. Upper limit in the range (-inf, inf)
+2. Lookahead in the range [0.0, inf) in milliseconds
+3. Knee width in the range [0.0, inf)
+4. Release in the range [0.0, inf) in seconds
+
+The lookahead, knee width, and release are 0.0 when only the bound is given.
 
 */
 
+use std::collections::VecDeque;
+
 use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, modules::{self, Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Limiter {
@@ -31,12 +48,68 @@ pub struct Limiter {
     #[serde(skip)]
     children: Vec<Entity>,
 
-    knobs: [f32; 2],
+    #[serde(default)]
+    true_peak: bool,
+    #[serde(skip)]
+    history: [f32; 3],
+    #[serde(skip)]
+    lookahead: VecDeque<(f32, f32)>,
+    #[serde(skip)]
+    gain: f32,
+
+    #[serde(deserialize_with = "deserialize_knobs")]
+    knobs: [f32; 5],
+}
+/// Racks from before the lookahead, knee, and release knobs were added only
+/// give the bound, which is hard clipped without them
+fn deserialize_knobs<'de, D>(deserializer: D) -> Result<[f32; 5], D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    modules::deserialize_knobs(deserializer, 2, [0.0; 5])
+}
+impl Limiter {
+    const OVERSAMPLING: usize = 4;
+
+    /// Estimate the peak up to the current sample, including the peaks
+    /// between the last few samples with Catmull-Rom interpolation
+    fn true_peak(&mut self, x: f32) -> f32 {
+        let [p0, p1, p2] = self.history;
+        let p3 = x;
+        self.history = [p1, p2, p3];
+
+        (0..Self::OVERSAMPLING)
+            .map(|i| {
+                let t = i as f32 / Self::OVERSAMPLING as f32;
+                0.5 * (
+                    2.0 * p1
+                    + (p2 - p0) * t
+                    + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+                    + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t * t * t
+                )
+            }).fold(p2.abs().max(p3.abs()), |a, v| a.max(v.abs()))
+    }
+    fn target_gain(peak: f32, threshold: f32, knee: f32) -> f32 {
+        if peak <= 0.0 {
+            return 1.0;
+        }
+
+        let out = if knee > 0.0 && peak > threshold - knee / 2.0 && peak < threshold + knee / 2.0 {
+            let over = peak - threshold + knee / 2.0;
+            peak - over * over / (2.0 * knee)
+        } else if peak >= threshold {
+            threshold
+        } else {
+            peak
+        };
+        (out / peak).clamp(0.0, 1.0)
+    }
 }
 #[typetag::deserialize]
 impl Module for Limiter {
     fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
         self.id = Some(id);
+        self.init_state(id);
         ec.with_children(|parent| {
             let mut component = parent.spawn((
                 NodeBundle {
@@ -59,7 +132,10 @@ impl Module for Limiter {
                         TextBundle::from_sections([
                             TextSection::new(name, ts.clone()),
                             TextSection::new("K0\n", ts.clone()),
-                            TextSection::new("K1\n", ts),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts.clone()),
+                            TextSection::new("K4\n", ts),
                         ]),
                         ModuleTextComponent,
                     )).id()
@@ -68,6 +144,9 @@ impl Module for Limiter {
             self.component = Some(component.id());
         });
     }
+    fn init_state(&mut self, _id: usize) {
+        self.gain = 1.0;
+    }
     fn exit(&mut self) {
         self.id = None;
         self.component = None;
@@ -88,11 +167,14 @@ impl Module for Limiter {
         1
     }
     fn outputs(&self) -> usize {
-        1
+        2
     }
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/limiter.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -105,29 +187,71 @@ impl Module for Limiter {
         let lower = self.knobs[0];
         let upper = self.knobs[1];
 
+        if ins[0].is_nan() {
+            return vec![f32::NAN, f32::NAN];
+        }
+
         if lower < upper {
-            if ins[0] > upper {
-                vec![upper]
-            } else if ins[0] < lower {
-                vec![lower]
+            let sr = 44100.0;
+            let (center, threshold) = if lower.is_finite() && upper.is_finite() {
+                ((lower + upper) / 2.0, (upper - lower) / 2.0)
             } else {
-                vec![ins[0]]
+                // Only clip when the bound is open-ended
+                (0.0, f32::INFINITY)
+            };
+
+            let y = ins[0] - center;
+            let peak = if self.true_peak {
+                self.true_peak(y)
+            } else {
+                y.abs()
+            };
+            let target = Self::target_gain(peak, threshold, self.knobs[3].max(0.0));
+
+            // Delay the signal so that the gain is reduced before peaks arrive
+            let lookahead_len = (self.knobs[2].max(0.0) / 1000.0 * sr) as usize + 1;
+            self.lookahead.push_back((y, target));
+            while self.lookahead.len() > lookahead_len {
+                self.lookahead.pop_front();
             }
+            let (y, _) = self.lookahead[0];
+            let target = self.lookahead.iter()
+                .fold(1.0f32, |a, (_, g)| a.min(*g));
+
+            if target <= self.gain {
+                self.gain = target;
+            } else {
+                let release = self.knobs[4].max(0.0);
+                self.gain = if release > 0.0 {
+                    (self.gain + 1.0 / (release * sr)).min(target)
+                } else {
+                    target
+                };
+            }
+
+            let limited = (center + y * self.gain).clamp(lower, upper);
+            vec![
+                limited,
+                1.0 - self.gain,
+            ]
         } else if ins[0] < lower && ins[0] > upper {
             if lower - ins[0] <= ins[0] - upper {
-                vec![lower]
+                vec![lower, 0.0]
             } else {
-                vec![upper]
+                vec![upper, 0.0]
             }
         } else {
-            vec![ins[0]]
+            vec![ins[0], 0.0]
         }
     }
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Lower: {}\n", self.knobs[0]);
-                text.sections[2].value = format!("K0 Upper: {}\n", self.knobs[1]);
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(4, self.knobs[4]));
             }
         }
     }
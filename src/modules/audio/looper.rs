@@ -19,7 +19,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, rack::flush_state, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Looper {
@@ -44,6 +44,7 @@ pub struct Looper {
 impl Module for Looper {
     fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
         self.id = Some(id);
+        self.init_state(id);
         ec.with_children(|parent| {
             let mut component = parent.spawn((
                 NodeBundle {
@@ -74,7 +75,8 @@ impl Module for Looper {
             });
             self.component = Some(component.id());
         });
-
+    }
+    fn init_state(&mut self, _id: usize) {
         let duration = self.knobs[0];
         let d = (duration * 44100.0) as usize;
         self.buffer = Vec::with_capacity(d);
@@ -104,6 +106,9 @@ impl Module for Looper {
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/looper.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -126,7 +131,7 @@ impl Module for Looper {
                 }
             },
             Ordering::Less => {
-                self.buffer.push(ins[0]);
+                self.buffer.push(flush_state(ins[0]));
             },
             Ordering::Equal => {},
         }
@@ -140,8 +145,8 @@ impl Module for Looper {
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Duration: {}\n", self.knobs[0]);
-                text.sections[2].value = format!("K1 Volume: {}\n", self.knobs[1]);
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
             }
         }
     }
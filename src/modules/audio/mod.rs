@@ -1,7 +1,9 @@
 /*!
 The following audio modules are defined here: `Sampler`, `MultiSampler`,
-`Envelope`, `Gate`, `Compressor`, `Limiter`, `Equalizer`, `Delay`, `Panner`,
-`Fuzz`, `Looper`, `PitchShifter`
+`Envelope`, `Gate`, `Compressor`, `Limiter`, `Equalizer`, `Crossover`,
+`DynamicEq`, `Delay`, `StereoDelay`, `Panner`, `SpatialPanner`, `Fuzz`,
+`RingMod`, `FreqShift`, `Looper`, `PitchShifter`, `TimeStretch`, `Shimmer`,
+`TrackerSynth`, `KickSynth`, `SnareSynth`, `HatSynth`
 */
 
 pub mod sampler;
@@ -13,11 +15,25 @@ pub mod gate;
 pub mod compressor;
 pub mod limiter;
 pub mod equalizer;
+pub mod crossover;
+pub mod dynamic_eq;
 pub mod delay;
+pub mod stereo_delay;
 pub mod panner;
+pub mod spatial_panner;
 
 pub mod fuzz;
+pub mod ring_mod;
+pub mod freq_shift;
 
 pub mod looper;
 
 pub mod pitch_shifter;
+#[cfg(feature = "pitch_shifter")]
+pub mod time_stretch;
+#[cfg(feature = "pitch_shifter")]
+pub mod shimmer;
+pub mod tracker_synth;
+pub mod kick_synth;
+pub mod snare_synth;
+pub mod hat_synth;
@@ -32,7 +32,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, audio::sampler::Sampler}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, audio::sampler::Sampler}};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct MultiSampler {
@@ -58,6 +58,7 @@ pub struct MultiSampler {
 impl Module for MultiSampler {
     fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
         self.id = Some(id);
+        self.init_state(id);
         ec.with_children(|parent| {
             let mut component = parent.spawn((
                 NodeBundle {
@@ -101,7 +102,8 @@ impl Module for MultiSampler {
             });
             self.component = Some(component.id());
         });
-
+    }
+    fn init_state(&mut self, _id: usize) {
         for samp in &mut self.samplers {
             samp.0.init_readers();
         }
@@ -134,6 +136,9 @@ impl Module for MultiSampler {
     fn knobs(&self) -> usize {
         0
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/multi_sampler.rs")
+    }
 
     fn step(&mut self, time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
         let lengths: Vec<f32> = self.samplers.iter()
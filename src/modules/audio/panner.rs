@@ -1,8 +1,19 @@
 /*!
 The `Panner` module takes an input and applies a panning to it.
 
+The panning can be swept back and forth by a built-in autopan LFO whose period
+is given in beats, so that it can be synced to the tempo of a `Sequencer` by
+setting K1 to the same tempo. When a stereo signal is patched into both inputs,
+its width is adjusted before it's panned.
+
+## Pan Laws
+ * `Linear` - The gains sum to 1.0, i.e. -6 dB at the center, the default
+ * `EqualPower` - The powers sum to 1.0, i.e. -3 dB at the center
+ * `Compromise` - Halfway between the other laws, i.e. -4.5 dB at the center
+
 ## Inputs
-0. The signal to pan
+0. The signal to pan, or the left channel of a stereo signal
+1. The optional right channel of a stereo signal
 
 ## Outputs
 0. The left channel
@@ -11,14 +22,28 @@ The `Panner` module takes an input and applies a panning to it.
 ## Knobs
 0. Panning in the range [-1.0, 1.0] where -1.0 is full left and 1.0 is full
 right
+1. Autopan tempo in the range [0.0, inf) in BPM, where 0.0 disables the LFO
+2. Autopan period in the range (0.0, inf) in beats
+3. Autopan depth in the range [0.0, 1.0]
+4. Stereo width in the range [0.0, inf) where 0.0 is mono and 1.0 is unchanged
 
 */
 
+use std::f64::consts::PI;
+
 use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Default, Deserialize, Debug, Clone)]
+enum PannerLaw {
+    #[default]
+    Linear,
+    EqualPower,
+    Compromise,
+}
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Panner {
@@ -32,7 +57,27 @@ pub struct Panner {
     #[serde(skip)]
     children: Vec<Entity>,
 
-    knobs: [f32; 1],
+    #[serde(default)]
+    law: PannerLaw,
+    #[serde(skip)]
+    pan: f32,
+
+    knobs: [f32; 5],
+}
+impl Panner {
+    /// Get the left and right gains for the given panning
+    fn gains(&self, pan: f32) -> (f32, f32) {
+        let right = (1.0 + pan) / 2.0;
+        let theta = right * std::f32::consts::FRAC_PI_2;
+        match self.law {
+            PannerLaw::Linear => (1.0 - right, right),
+            PannerLaw::EqualPower => (theta.cos(), theta.sin()),
+            PannerLaw::Compromise => (
+                ((1.0 - right) * theta.cos()).sqrt(),
+                (right * theta.sin()).sqrt(),
+            ),
+        }
+    }
 }
 #[typetag::deserialize]
 impl Module for Panner {
@@ -59,7 +104,12 @@ impl Module for Panner {
                     parent.spawn((
                         TextBundle::from_sections([
                             TextSection::new(name, ts.clone()),
-                            TextSection::new("K0\n", ts),
+                            TextSection::new(format!("Law: {:?}\n", self.law), ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts.clone()),
+                            TextSection::new("K4\n", ts),
                         ]),
                         ModuleTextComponent,
                     )).id()
@@ -72,6 +122,8 @@ impl Module for Panner {
         self.id = None;
         self.component = None;
         self.children = vec![];
+
+        self.pan = 0.0;
     }
 
     fn id(&self) -> Option<usize> {
@@ -85,7 +137,7 @@ impl Module for Panner {
     }
 
     fn inputs(&self) -> usize {
-        1
+        2
     }
     fn outputs(&self) -> usize {
         2
@@ -93,6 +145,9 @@ impl Module for Panner {
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/panner.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -101,19 +156,43 @@ impl Module for Panner {
         self.knobs[i] = val;
     }
 
-    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
-        let right = (1.0 + self.knobs[0]) / 2.0;
-        let left = 1.0 - right;
+    fn step(&mut self, time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let tempo = f64::from(self.knobs[1]);
+        let period = f64::from(self.knobs[2]);
+        let depth = self.knobs[3].clamp(0.0, 1.0);
+
+        // Sweep the panning with the autopan LFO
+        let lfo = if tempo > 0.0 && period > 0.0 {
+            (2.0 * PI * time * tempo / 60.0 / period).sin() as f32
+        } else {
+            0.0
+        };
+        self.pan = (self.knobs[0] + lfo * depth).clamp(-1.0, 1.0);
+        let (left, right) = self.gains(self.pan);
+
+        if ins[1].is_nan() {
+            return vec![
+                ins[0] * left,
+                ins[0] * right,
+            ];
+        }
 
+        // Adjust the stereo width with mid/side processing
+        let mid = (ins[0] + ins[1]) / 2.0;
+        let side = (ins[0] - ins[1]) / 2.0 * self.knobs[4].max(0.0);
         vec![
-            ins[0] * left,
-            ins[0] * right,
+            (mid + side) * left,
+            (mid - side) * right,
         ]
     }
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Panning: {}\n", self.knobs[0]);
+                text.sections[2].value = format!("{} ({:.2})\n", self.describe().knob_row(0, self.knobs[0]), self.pan);
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+                text.sections[6].value = format!("{}\n", self.describe().knob_row(4, self.knobs[4]));
             }
         }
     }
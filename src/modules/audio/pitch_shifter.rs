@@ -33,7 +33,7 @@ use serde::Deserialize;
 
 use rustfft::{FftPlanner, num_complex::Complex};
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
 
 #[derive(Default, Deserialize, Debug, Clone)]
 enum PitchShifterFunc {
@@ -70,11 +70,40 @@ pub struct PitchShifter {
 }
 impl PitchShifter {
     const BUFSIZE: usize = 4096;
+
+    /// Create a pitch shifter which isn't part of the rack, e.g. for shifting
+    /// the signal within another module by stepping it directly
+    pub(crate) fn new(shift: f32) -> Self {
+        Self {
+            id: None,
+            name: None,
+
+            component: None,
+            children: vec![],
+
+            in_buffer: Vec::with_capacity(Self::BUFSIZE),
+            out_buffer: vec![],
+            window: Self::gaussian_window(),
+            bin_energies: vec![],
+
+            func: PitchShifterFunc::default(),
+
+            knobs: [shift],
+        }
+    }
+    fn gaussian_window() -> Vec<f32> {
+        const SIGMA: f32 = 0.4;
+        (0..Self::BUFSIZE)
+            .map(|n| {
+                (-0.5 * ((n as f32 - Self::BUFSIZE as f32 / 2.0) / (SIGMA * Self::BUFSIZE as f32 / 2.0)).powi(2)).exp()
+            }).collect()
+    }
 }
 #[typetag::deserialize]
 impl Module for PitchShifter {
     fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
         self.id = Some(id);
+        self.init_state(id);
         ec.with_children(|parent| {
             let mut component = parent.spawn((
                 NodeBundle {
@@ -105,15 +134,11 @@ impl Module for PitchShifter {
             });
             self.component = Some(component.id());
         });
-
+    }
+    fn init_state(&mut self, _id: usize) {
         self.in_buffer = Vec::with_capacity(PitchShifter::BUFSIZE);
 
-        // Generate Gaussian window
-        const SIGMA: f32 = 0.4;
-        self.window = (0..PitchShifter::BUFSIZE)
-            .map(|n| {
-                (-0.5 * ((n as f32 - PitchShifter::BUFSIZE as f32 / 2.0) / (SIGMA * PitchShifter::BUFSIZE as f32 / 2.0)).powi(2)).exp()
-            }).collect();
+        self.window = Self::gaussian_window();
     }
     fn exit(&mut self) {
         self.id = None;
@@ -140,6 +165,9 @@ impl Module for PitchShifter {
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/pitch_shifter.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -248,7 +276,7 @@ impl Module for PitchShifter {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
                 text.sections[1].value = format!("Func: {:?}\n", self.func);
-                text.sections[2].value = format!("K0 Shift: {}\n", self.knobs[0]);
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
             }
         }
     }
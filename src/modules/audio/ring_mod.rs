@@ -0,0 +1,185 @@
+/*!
+The `RingMod` module takes an input and multiplies it by a carrier wave,
+producing the sum and difference of their frequencies. The carrier is generated
+by an internal oscillator unless an external carrier is patched in.
+
+## Carrier Functions
+ * `Sine` - a smooth wave, the default
+ * `Triangle` - a linear sine-like wave
+ * `Square` - a binary wave
+ * `Saw` - a linear ramp-like wave
+
+## Inputs
+0. The signal to modulate
+1. An optional external carrier which replaces the internal oscillator
+
+## Outputs
+0. The modulated signal
+1. The carrier signal
+
+## Knobs
+0. Carrier frequency in the range [0.0, inf) in Hz
+1. Dry/Wet mix in the range [0.0, 1.0]
+
+*/
+
+use std::f64::consts::PI;
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Default, Deserialize, Debug, Clone)]
+enum RingModFunc {
+    #[default]
+    Sine,
+    Triangle,
+    Square,
+    Saw,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RingMod {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default)]
+    func: RingModFunc,
+    #[serde(skip)]
+    phase: f64,
+    #[serde(skip)]
+    last_outs: Vec<f32>,
+
+    knobs: [f32; 2],
+}
+impl RingMod {
+    fn carrier(&self) -> f32 {
+        let p = self.phase;
+        (match self.func {
+            RingModFunc::Sine => (2.0 * PI * p).sin(),
+            RingModFunc::Triangle => 1.0 - 4.0 * (p - 0.25 - (p + 0.25).floor()).abs(),
+            RingModFunc::Square => if p < 0.5 { 1.0 } else { -1.0 },
+            RingModFunc::Saw => 2.0 * p - 1.0,
+        }) as f32
+    }
+}
+#[typetag::deserialize]
+impl Module for RingMod {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} RingMod\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("Func\n", ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.phase = 0.0;
+        self.last_outs.clear();
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        2
+    }
+    fn outputs(&self) -> usize {
+        2
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/ring_mod.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        let sr = 44100.0;
+        let freq = f64::from(self.knobs[0].max(0.0));
+        let dwmix = self.knobs[1].clamp(0.0, 1.0);
+
+        if st == StepType::Video && !self.last_outs.is_empty() {
+            return self.last_outs.clone();
+        }
+
+        let carrier = if ins[1].is_nan() {
+            let carrier = self.carrier();
+            self.phase = (self.phase + freq / sr).fract();
+            carrier
+        } else {
+            ins[1]
+        };
+
+        let x = ins[0];
+        let y = if x.is_nan() {
+            f32::NAN
+        } else {
+            x * (1.0 - dwmix) + x * carrier * dwmix
+        };
+        self.last_outs = vec![y, carrier];
+        self.last_outs.clone()
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("Func: {:?}\n", self.func);
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+            }
+        }
+    }
+}
@@ -30,7 +30,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle, utils:
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, io::file_decoder::{FileReader, WavReader}}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, io::file_decoder::{FileReader, WavReader}}};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Sampler {
@@ -135,6 +135,9 @@ impl Module for Sampler {
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/sampler.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -177,7 +180,7 @@ impl Module for Sampler {
             }
             match self.active_samples.get(&i) {
                 Some(v) => {
-                    match reader.read_sample(false) {
+                    match reader.read_sample(time, false) {
                         Some(sample) => {
                             outs.push((sample[0] + sample[1]) / 2.0 * v);
                         },
@@ -196,8 +199,8 @@ impl Module for Sampler {
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Tempo: {}\n", self.knobs[0]);
-                text.sections[2].value = format!("K1 Length: {}\n", self.knobs[1]);
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
 
                 let tempo = self.knobs[0];
                 let beat = self.time * tempo as f64 / 60.0;
@@ -0,0 +1,238 @@
+/*!
+The `Shimmer` module takes an input and applies a diffuse reverb-like delay
+network to it, whose feedback is pitch shifted up an octave and a fifth so that
+each repeat rises above the last, for ambient pads.
+
+The input is smeared by two all-pass diffusers and then fed into four delay
+lines which are mixed into each other's feedback. Part of the feedback is
+pitch shifted by two [PitchShifter](crate::modules::audio::pitch_shifter)s,
+which adds 2048 samples of latency to the shimmer.
+
+## Inputs
+0. The signal to shimmer
+
+## Outputs
+0. The left channel
+1. The right channel
+
+## Knobs
+0. Size in the range [0.01, 2.0] in seconds, the length of the shortest delay
+   line
+1. Feedback in the range [0.0, 0.98]
+2. Shimmer amount in the range [0.0, 1.0], how much of the feedback is pitch
+   shifted
+3. Fifth mix in the range [0.0, 1.0] where 0.0 only shifts up an octave and 1.0
+   only shifts up a fifth
+4. Dry/Wet mix in the range [0.0, 1.0]
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, rack::flush_state, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, audio::pitch_shifter::PitchShifter}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Shimmer {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    diffusers: [(Vec<f32>, usize); 2],
+    #[serde(skip)]
+    lines: [(Vec<f32>, usize); 4],
+    /// The octave and fifth pitch shifters
+    #[serde(skip)]
+    shifters: Vec<PitchShifter>,
+    /// The mono output of the delay lines from the last step
+    #[serde(skip)]
+    last_wet: f32,
+
+    knobs: [f32; 5],
+}
+impl Shimmer {
+    /// The lengths of the diffusers in samples
+    const DIFFUSER_LENS: [usize; 2] = [142, 379];
+    const DIFFUSER_GAIN: f32 = 0.6;
+    /// The lengths of the delay lines relative to the size
+    const LINE_RATIOS: [f32; 4] = [1.0, 1.179, 1.371, 1.591];
+}
+#[typetag::deserialize]
+impl Module for Shimmer {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Shimmer\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts.clone()),
+                            TextSection::new("K4\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, _id: usize) {
+        for ((buffer, _), len) in self.diffusers.iter_mut().zip(Self::DIFFUSER_LENS) {
+            *buffer = vec![0.0; len];
+        }
+        self.shifters = vec![
+            PitchShifter::new(12.0),
+            PitchShifter::new(7.0),
+        ];
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.diffusers = Default::default();
+        self.lines = Default::default();
+        self.shifters.clear();
+        self.last_wet = 0.0;
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        2
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/shimmer.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        let x = ins[0];
+        if x.is_nan() {
+            return vec![f32::NAN; self.outputs()];
+        }
+
+        let sr = 44100.0;
+        let size = self.knobs[0].clamp(0.01, 2.0);
+        let feedback = self.knobs[1].clamp(0.0, 0.98);
+        let shimmer = self.knobs[2].clamp(0.0, 1.0);
+        let fifth = self.knobs[3].clamp(0.0, 1.0);
+        let dwmix = self.knobs[4];
+
+        // Pitch shift the last output, which is silent until the shifters'
+        // buffers have filled
+        let mut shifted = [0.0; 2];
+        for (s, shifter) in shifted.iter_mut().zip(&mut self.shifters) {
+            let y = shifter.step(time, st, &[self.last_wet])[0];
+            if !y.is_nan() {
+                *s = y;
+            }
+        }
+        let shifted = shifted[0] * (1.0 - fifth) + shifted[1] * fifth;
+
+        // Smear the input with the all-pass diffusers
+        let mut d = x;
+        for (buffer, idx) in &mut self.diffusers {
+            if buffer.is_empty() {
+                continue;
+            }
+            *idx %= buffer.len();
+            let delayed = buffer[*idx];
+            let y = delayed - Self::DIFFUSER_GAIN * d;
+            buffer[*idx] = flush_state(d + Self::DIFFUSER_GAIN * y);
+            *idx += 1;
+            d = y;
+        }
+
+        // Read the delay lines and mix them into each other with a Hadamard
+        // matrix which preserves their energy
+        let mut outs = [0.0; 4];
+        for ((buffer, idx), ratio) in self.lines.iter_mut().zip(Self::LINE_RATIOS) {
+            let len = ((size * ratio * sr) as usize).max(1);
+            buffer.resize(len, 0.0);
+            *idx %= len;
+        }
+        for (o, (buffer, idx)) in outs.iter_mut().zip(&self.lines) {
+            *o = buffer[*idx];
+        }
+        let mixed = [
+            (outs[0] + outs[1] + outs[2] + outs[3]) / 2.0,
+            (outs[0] - outs[1] + outs[2] - outs[3]) / 2.0,
+            (outs[0] + outs[1] - outs[2] - outs[3]) / 2.0,
+            (outs[0] - outs[1] - outs[2] + outs[3]) / 2.0,
+        ];
+        for ((buffer, idx), m) in self.lines.iter_mut().zip(mixed) {
+            let fb = m * (1.0 - shimmer) + shifted * shimmer / 2.0;
+            buffer[*idx] = flush_state(d / 2.0 + fb * feedback);
+            *idx += 1;
+        }
+
+        let left = (outs[0] + outs[2]) / 2.0;
+        let right = (outs[1] + outs[3]) / 2.0;
+        self.last_wet = mixed[0];
+
+        vec![
+            x * (1.0 - dwmix) + left * dwmix,
+            x * (1.0 - dwmix) + right * dwmix,
+        ]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(4, self.knobs[4]));
+            }
+        }
+    }
+}
@@ -0,0 +1,219 @@
+/*!
+The `SnareSynth` module synthesizes a snare drum whenever it's triggered, with
+a tuned body made of two sine modes and band-passed noise for the snares.
+
+## Inputs
+0. The trigger which starts a hit whenever this becomes positive, e.g. the
+   press/sustain/release output of a `Sequencer`
+
+## Outputs
+0. The snare signal
+
+## Knobs
+0. Tuning of the body in the range (0.0, inf) in Hz, usually around 180.0
+1. Body decay time in the range (0.0, inf) in seconds
+2. Noise decay time in the range (0.0, inf) in seconds
+3. Noise tone in the range (0.0, 22050.0) in Hz, the center of the noise's
+   band-pass filter
+4. Noise mix in the range [0.0, 1.0] where 0.0 is only the body and 1.0 is only
+   the noise
+5. Level in the range [0.0, inf)
+
+*/
+
+use std::f64::consts::PI;
+
+use rand::prelude::*;
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, rack::module_rng, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, audio::equalizer::{EqualizerFunc, Biquad}}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SnareSynth {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    rng: Option<StdRng>,
+    #[serde(skip)]
+    biquad: Biquad,
+    #[serde(skip)]
+    last_trigger: f32,
+    /// The number of samples since the last hit, if it's still sounding
+    #[serde(skip)]
+    elapsed: Option<usize>,
+    #[serde(skip)]
+    phases: [f64; 2],
+    #[serde(skip)]
+    last_outs: Vec<f32>,
+
+    knobs: [f32; 6],
+}
+impl SnareSynth {
+    /// The frequency ratio of the second body mode to the first
+    const OVERTONE: f32 = 1.6;
+    /// The octaves that the body's pitch drops by at the start of a hit
+    const PITCH_DROP: f32 = 0.5;
+    /// The time constant of the body's pitch drop in seconds
+    const DROP_TIME: f32 = 0.01;
+    /// The Q of the noise's band-pass filter
+    const NOISE_Q: f32 = 0.7;
+    /// The envelope level below which a hit is finished
+    const SILENCE: f32 = 0.0001;
+}
+#[typetag::deserialize]
+impl Module for SnareSynth {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} SnareSynth\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts.clone()),
+                            TextSection::new("K4\n", ts.clone()),
+                            TextSection::new("K5\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, id: usize) {
+        self.rng = Some(module_rng(id));
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.rng = None;
+        self.biquad = Biquad::default();
+        self.last_trigger = 0.0;
+        self.elapsed = None;
+        self.phases = [0.0; 2];
+        self.last_outs.clear();
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        1
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/snare_synth.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+
+        if i == 3 {
+            self.biquad.reset_feedback();
+        }
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        let sr = 44100.0;
+        if st == StepType::Video && !self.last_outs.is_empty() {
+            return self.last_outs.clone();
+        }
+
+        let trigger = ins[0];
+        if trigger > 0.0 && (self.last_trigger.is_nan() || self.last_trigger <= 0.0) {
+            self.elapsed = Some(0);
+            self.phases = [0.0; 2];
+        }
+        self.last_trigger = trigger;
+
+        let y = match self.elapsed {
+            Some(n) => {
+                let t = n as f32 / sr;
+                let body_env = (-t / self.knobs[1].max(f32::EPSILON)).exp();
+                let noise_env = (-t / self.knobs[2].max(f32::EPSILON)).exp();
+                self.elapsed = if body_env.max(noise_env) < Self::SILENCE {
+                    None
+                } else {
+                    Some(n + 1)
+                };
+
+                let freq = self.knobs[0].max(0.0)
+                    * 2.0f32.powf(Self::PITCH_DROP * (-t / Self::DROP_TIME).exp());
+                let body = ((2.0 * PI * self.phases[0]).sin() + 0.5 * (2.0 * PI * self.phases[1]).sin()) as f32 / 1.5;
+                self.phases[0] = (self.phases[0] + f64::from(freq / sr)).fract();
+                self.phases[1] = (self.phases[1] + f64::from(freq * Self::OVERTONE / sr)).fract();
+
+                let rng = self.rng.get_or_insert_with(StdRng::from_entropy);
+                let (a, b) = EqualizerFunc::BPF.coefficients(self.knobs[3].clamp(1.0, sr / 2.0 - 1.0), Self::NOISE_Q, 1.0);
+                let noise = self.biquad.process(&a, &b, rng.gen_range(-1.0..=1.0));
+
+                let mix = self.knobs[4].clamp(0.0, 1.0);
+                (body * body_env * (1.0 - mix) + noise * noise_env * mix) * self.knobs[5]
+            },
+            None => 0.0,
+        };
+
+        self.last_outs = vec![y];
+        self.last_outs.clone()
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(4, self.knobs[4]));
+                text.sections[6].value = format!("{}\n", self.describe().knob_row(5, self.knobs[5]));
+            }
+        }
+    }
+}
@@ -0,0 +1,291 @@
+/*!
+The `SpatialPanner` module takes a mono input and positions it in space around
+the listener, outputting one signal per speaker in the given layout. The
+outputs can then be patched into an `AudioOutMulti` module.
+
+## Layouts
+ * `Stereo` - 2 speakers at -30 and 30 degrees, the default
+ * `Quad` - 4 speakers at -45, 45, 135, and -135 degrees
+ * `Hexagon` - 6 speakers starting at -30 degrees
+ * `Octagon` - 8 speakers starting at -22.5 degrees
+ * `Ambisonic` - first-order ambisonic B-format (W, X, Y, Z)
+ * `Binaural` - stereo for headphones using a simple spherical head model
+
+For the ring layouts, the speakers are ordered clockwise starting from the
+front left and the input is panned between the two nearest speakers using an
+equal-power law. Signals with a high elevation are spread across all speakers.
+
+## Inputs
+0. The signal to position
+
+## Outputs
+0. The first speaker or channel
+1. The second speaker or channel
+2. etc. depending on the layout
+
+## Knobs
+0. Azimuth in the range [-180.0, 180.0] degrees where 0.0 is in front and 90.0
+   is to the right
+1. Elevation in the range [-90.0, 90.0] degrees where 90.0 is directly above
+2. Distance in the range [0.0, inf) where the gain is halved at 1.0
+
+*/
+
+use std::f32::consts::PI;
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Default, Deserialize, Debug, Clone, Copy, PartialEq)]
+enum SpatialLayout {
+    #[default]
+    Stereo,
+    Quad,
+    Hexagon,
+    Octagon,
+    Ambisonic,
+    Binaural,
+}
+impl SpatialLayout {
+    fn channels(&self) -> usize {
+        match self {
+            Self::Stereo | Self::Binaural => 2,
+            Self::Quad | Self::Ambisonic => 4,
+            Self::Hexagon => 6,
+            Self::Octagon => 8,
+        }
+    }
+    fn speakers(&self) -> Vec<f32> {
+        match self {
+            Self::Stereo => vec![-30.0, 30.0],
+            Self::Quad => vec![-45.0, 45.0, 135.0, 225.0],
+            Self::Hexagon | Self::Octagon => {
+                let n = self.channels();
+                let spacing = 360.0 / n as f32;
+                (0..n).map(|i| -spacing / 2.0 + spacing * i as f32)
+                    .collect()
+            },
+            Self::Ambisonic | Self::Binaural => vec![],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SpatialPanner {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default)]
+    layout: SpatialLayout,
+
+    #[serde(skip)]
+    delay_idx: usize,
+    #[serde(skip)]
+    buffer: Vec<f32>,
+    #[serde(skip)]
+    shadow: [f32; 2],
+
+    knobs: [f32; 3],
+}
+impl SpatialPanner {
+    const HEAD_RADIUS: f32 = 0.0875;
+    const SPEED_OF_SOUND: f32 = 343.0;
+
+    fn ring_gains(&self, azimuth: f32, elevation: f32) -> Vec<f32> {
+        let speakers = self.layout.speakers();
+        let n = speakers.len();
+        let mut gains = vec![0.0; n];
+
+        let azimuth = azimuth.rem_euclid(360.0);
+        for i in 0..n {
+            let a = speakers[i].rem_euclid(360.0);
+            let b = speakers[(i+1) % n].rem_euclid(360.0);
+            let span = (b - a).rem_euclid(360.0);
+            let offset = (azimuth - a).rem_euclid(360.0);
+            if offset <= span {
+                let frac = if span > 0.0 {
+                    offset / span
+                } else {
+                    0.0
+                };
+                gains[i] = (frac * PI / 2.0).cos();
+                gains[(i+1) % n] = (frac * PI / 2.0).sin();
+                break;
+            }
+        }
+
+        let horizontal = elevation.cos();
+        let spread = elevation.sin().abs() / (n as f32).sqrt();
+        gains.iter()
+            .map(|g| g * horizontal + spread)
+            .collect()
+    }
+    fn binaural(&mut self, x: f32, azimuth: f32, elevation: f32) -> Vec<f32> {
+        let sr = 44100.0;
+
+        // Woodworth's formula for the interaural time difference
+        let lateral = (azimuth.sin() * elevation.cos()).asin();
+        let itd = Self::HEAD_RADIUS / Self::SPEED_OF_SOUND * (lateral.abs() + lateral.abs().sin());
+        let itd_samples = itd * sr;
+
+        let buflen = (Self::HEAD_RADIUS / Self::SPEED_OF_SOUND * (PI / 2.0 + 1.0) * sr) as usize + 2;
+        if self.buffer.len() != buflen {
+            self.buffer = vec![0.0; buflen];
+            self.delay_idx = 0;
+        }
+        self.delay_idx %= buflen;
+        self.buffer[self.delay_idx] = x;
+
+        let delay = itd_samples.floor() as usize;
+        let frac = itd_samples.fract();
+        let d0 = self.buffer[(self.delay_idx + buflen - delay) % buflen];
+        let d1 = self.buffer[(self.delay_idx + buflen - delay - 1) % buflen];
+        let delayed = d0 * (1.0 - frac) + d1 * frac;
+        self.delay_idx += 1;
+
+        // Shadow the far ear with a lowpass filter, and both ears when behind
+        let behind = (-azimuth.cos()).max(0.0) * 0.3;
+        let near_coeff = 1.0 - behind;
+        let far_coeff = (1.0 - lateral.abs() / (PI / 2.0) * 0.8) * near_coeff;
+        let (left, right) = if lateral >= 0.0 {
+            (delayed, x)
+        } else {
+            (x, delayed)
+        };
+        let (left_coeff, right_coeff) = if lateral >= 0.0 {
+            (far_coeff, near_coeff)
+        } else {
+            (near_coeff, far_coeff)
+        };
+        self.shadow[0] += left_coeff * (left - self.shadow[0]);
+        self.shadow[1] += right_coeff * (right - self.shadow[1]);
+
+        let far_gain = 1.0 - lateral.abs() / (PI / 2.0) * 0.5;
+        if lateral >= 0.0 {
+            vec![self.shadow[0] * far_gain, self.shadow[1]]
+        } else {
+            vec![self.shadow[0], self.shadow[1] * far_gain]
+        }
+    }
+}
+#[typetag::deserialize]
+impl Module for SpatialPanner {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Spatial Panner\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("Layout: {:?}\n", self.layout), ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        self.layout.channels()
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/spatial_panner.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let x = ins[0];
+        if x.is_nan() {
+            return vec![f32::NAN; self.outputs()];
+        }
+
+        let azimuth = self.knobs[0].to_radians();
+        let elevation = self.knobs[1].clamp(-90.0, 90.0).to_radians();
+        let x = x / (1.0 + self.knobs[2].max(0.0));
+
+        match self.layout {
+            SpatialLayout::Ambisonic => {
+                vec![
+                    x * std::f32::consts::FRAC_1_SQRT_2,
+                    x * azimuth.cos() * elevation.cos(),
+                    x * -azimuth.sin() * elevation.cos(),
+                    x * elevation.sin(),
+                ]
+            },
+            SpatialLayout::Binaural => self.binaural(x, azimuth, elevation),
+            _ => {
+                self.ring_gains(self.knobs[0], elevation)
+                    .into_iter()
+                    .map(|g| x * g)
+                    .collect()
+            },
+        }
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+            }
+        }
+    }
+}
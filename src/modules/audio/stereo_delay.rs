@@ -0,0 +1,238 @@
+/*!
+The `StereoDelay` module takes a stereo input and applies a delay to each
+channel, with the feedback either kept within each channel or bounced between
+them for the ping-pong effect.
+
+The delay time can be synced to the tempo of a `Sequencer` by setting K0 to the
+same tempo, in which case K1 is the delay time in beats, e.g. 0.75 for a dotted
+eighth note. The feedback passes through a low-pass filter so that each repeat
+is darker than the last.
+
+## Delay Modes
+ * `PingPong` - The input is summed to mono and fed into the left channel, whose
+   repeats feed into the right channel and back again, the default
+ * `Stereo` - Each channel feeds back into itself
+
+## Inputs
+0. The left channel, or a mono signal
+1. The optional right channel
+
+## Outputs
+0. The left channel
+1. The right channel
+
+## Knobs
+0. Tempo in the range [0.0, inf) in BPM, where 0.0 disables the tempo sync
+1. Delay in the range (0.0, inf) in beats, or in seconds if K0 is 0.0
+2. Feedback in the range [0.0, 1.0]
+3. Dry/Wet mix in the range [0.0, 1.0]
+4. High-cut frequency of the feedback in the range (0.0, 22050.0) in Hz
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, rack::flush_state, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, audio::equalizer::{EqualizerFunc, Biquad}}};
+
+#[derive(Default, Deserialize, Debug, Clone)]
+enum StereoDelayMode {
+    #[default]
+    PingPong,
+    Stereo,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StereoDelay {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default)]
+    mode: StereoDelayMode,
+
+    #[serde(skip)]
+    delay_idx: usize,
+    #[serde(skip)]
+    buffers: [Vec<f32>; 2],
+    #[serde(skip)]
+    biquads: [Biquad; 2],
+
+    knobs: [f32; 5],
+}
+impl StereoDelay {
+    const MAX_DELAY: f32 = 10.0;
+
+    /// Get the delay time in seconds
+    fn delay(&self) -> f32 {
+        let tempo = self.knobs[0];
+        let delay = if tempo > 0.0 {
+            self.knobs[1] * 60.0 / tempo
+        } else {
+            self.knobs[1]
+        };
+        delay.clamp(0.0, Self::MAX_DELAY)
+    }
+}
+#[typetag::deserialize]
+impl Module for StereoDelay {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} StereoDelay\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("Mode: {:?}\n", self.mode), ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts.clone()),
+                            TextSection::new("K4\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.delay_idx = 0;
+        self.buffers = Default::default();
+        self.biquads = Default::default();
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        2
+    }
+    fn outputs(&self) -> usize {
+        2
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/stereo_delay.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+
+        if i == 4 {
+            for bq in &mut self.biquads {
+                bq.reset_feedback();
+            }
+        }
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let left = ins[0];
+        if left.is_nan() {
+            return vec![f32::NAN; self.outputs()];
+        }
+        let right = if ins[1].is_nan() {
+            left
+        } else {
+            ins[1]
+        };
+
+        let feedback = self.knobs[2].clamp(0.0, 1.0);
+        let dwmix = self.knobs[3];
+        let high_cut = self.knobs[4].clamp(1.0, 22049.0);
+
+        let sr = 44100.0;
+        let buflen = (self.delay() * sr) as usize;
+        for buffer in &mut self.buffers {
+            buffer.resize(buflen, 0.0);
+        }
+
+        let delayed = if buflen > 0 {
+            self.delay_idx %= buflen;
+            let delayed = [
+                self.buffers[0][self.delay_idx],
+                self.buffers[1][self.delay_idx],
+            ];
+
+            let (a, b) = EqualizerFunc::LPF.coefficients(high_cut, 0.707, 1.0);
+            let repeats = [
+                self.biquads[0].process(&a, &b, delayed[0]) * feedback,
+                self.biquads[1].process(&a, &b, delayed[1]) * feedback,
+            ];
+            let writes = match self.mode {
+                StereoDelayMode::PingPong => [
+                    (left + right) / 2.0 + repeats[1],
+                    repeats[0],
+                ],
+                StereoDelayMode::Stereo => [
+                    left + repeats[0],
+                    right + repeats[1],
+                ],
+            };
+            self.buffers[0][self.delay_idx] = flush_state(writes[0]);
+            self.buffers[1][self.delay_idx] = flush_state(writes[1]);
+
+            delayed
+        } else {
+            [0.0; 2]
+        };
+
+        self.delay_idx += 1;
+
+        vec![
+            left * (1.0 - dwmix) + delayed[0] * dwmix,
+            right * (1.0 - dwmix) + delayed[1] * dwmix,
+        ]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[3].value = format!("{} ({:.3}s)\n", self.describe().knob_row(1, self.knobs[1]), self.delay());
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+                text.sections[6].value = format!("{}\n", self.describe().knob_row(4, self.knobs[4]));
+            }
+        }
+    }
+}
@@ -0,0 +1,277 @@
+/*!
+The `TimeStretch` module takes an input and outputs a time stretched version of
+it without changing its pitch, the complement of the `PitchShifter` module.
+
+The input is recorded into a history buffer of up to [TimeStretch::MAX_HISTORY]
+samples which is read back at the stretched speed with a phase vocoder. When
+slowing down, the playback falls further behind the input until it reaches the
+end of the history and jumps forward to the oldest remaining audio. When
+speeding up, the playback catches up to the input and then jumps back by one
+window so that it continues from the most recent audio.
+
+## Inputs
+0. The signal to time stretch
+
+## Outputs
+0. The time stretched signal
+1. How far the playback is behind the input in seconds
+
+##### Note
+The outputs will be [f32::NAN] while the input is unpatched, and the time
+stretched signal has a latency of 1.25 windows.
+
+## Knobs
+0. Stretch ratio in the range (0.0, inf) where 2.0 plays at half speed
+1. Window length in the range [0.01, 0.5] in seconds
+
+*/
+
+use std::{collections::VecDeque, f32::consts::PI};
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use rustfft::{FftPlanner, num_complex::Complex};
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TimeStretch {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    history: VecDeque<f32>,
+    /// The total number of samples written to the history
+    #[serde(skip)]
+    written: usize,
+    /// The playback position as a total sample index
+    #[serde(skip)]
+    pos: f64,
+
+    #[serde(skip)]
+    window: Vec<f32>,
+    #[serde(skip)]
+    out_buffer: VecDeque<f32>,
+    #[serde(skip)]
+    hop_count: usize,
+    #[serde(skip)]
+    phases: Vec<f32>,
+    #[serde(skip)]
+    is_reset: bool,
+    #[serde(skip)]
+    last_outs: Vec<f32>,
+
+    knobs: [f32; 2],
+}
+impl TimeStretch {
+    const MAX_HISTORY: usize = 44100 * 10;
+    const MIN_WINDOW: usize = 256;
+    const MAX_WINDOW: usize = 16384;
+
+    fn window_len(&self) -> usize {
+        ((self.knobs[1].clamp(0.01, 0.5) * 44100.0) as usize)
+            .clamp(Self::MIN_WINDOW, Self::MAX_WINDOW)
+    }
+    /// Get the windowed frame of the history which starts at the given total
+    /// sample index
+    fn frame(&self, start: usize) -> Vec<Complex<f32>> {
+        let offset = start - (self.written - self.history.len());
+        self.history.range(offset..offset + self.window.len())
+            .zip(&self.window)
+            .map(|(x, w)| Complex {
+                re: x * w,
+                im: 0.0,
+            }).collect()
+    }
+    /// Synthesize the next frame from the current playback position and add it
+    /// to the output buffer
+    fn synthesize(&mut self, hop: usize) {
+        let n = self.window.len();
+
+        // Keep the playback position within the history
+        let oldest = self.written - self.history.len();
+        if self.pos < oldest as f64 {
+            self.pos = oldest as f64;
+            self.is_reset = true;
+        }
+        if self.pos as usize + hop + n > self.written {
+            if self.written < hop + n {
+                return;
+            }
+            self.pos = (self.written - hop - n).max(oldest) as f64;
+            self.is_reset = true;
+        }
+
+        // Measure the phase advance of each bin over one hop
+        let start = self.pos as usize;
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(n);
+        let mut x0 = self.frame(start);
+        let mut x1 = self.frame(start + hop);
+        fft.process(&mut x0);
+        fft.process(&mut x1);
+
+        if self.is_reset || self.phases.len() != n {
+            self.phases = x0.iter()
+                .map(|c| c.arg())
+                .collect();
+            self.is_reset = false;
+        }
+        let mut y: Vec<Complex<f32>> = x0.iter()
+            .zip(&x1)
+            .zip(&mut self.phases)
+            .map(|((c0, c1), phase)| {
+                *phase = (*phase + c1.arg() - c0.arg()).rem_euclid(2.0 * PI);
+                Complex::from_polar(c1.norm(), *phase)
+            }).collect();
+
+        let ifft = planner.plan_fft_inverse(n);
+        ifft.process(&mut y);
+
+        // Overlap-add with a synthesis window, normalized for the 75% overlap
+        // of the squared Hann windows
+        for ((o, c), w) in self.out_buffer.iter_mut().zip(&y).zip(&self.window) {
+            *o += c.re / n as f32 * w / 1.5;
+        }
+
+        self.pos += hop as f64 / f64::from(self.knobs[0].max(f32::EPSILON));
+    }
+}
+#[typetag::deserialize]
+impl Module for TimeStretch {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} TimeStretch\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.history.clear();
+        self.written = 0;
+        self.pos = 0.0;
+        self.window.clear();
+        self.out_buffer.clear();
+        self.hop_count = 0;
+        self.phases.clear();
+        self.last_outs.clear();
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        2
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/time_stretch.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        let x = ins[0];
+        if x.is_nan() {
+            return vec![f32::NAN; self.outputs()];
+        }
+        if st == StepType::Video && !self.last_outs.is_empty() {
+            return self.last_outs.clone();
+        }
+
+        // Generate a periodic Hann window whenever the window length changes
+        let n = self.window_len();
+        if self.window.len() != n {
+            self.window = (0..n)
+                .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / n as f32).cos())
+                .collect();
+            self.out_buffer = VecDeque::from(vec![0.0; n]);
+            self.hop_count = 0;
+            self.is_reset = true;
+        }
+
+        self.history.push_back(x);
+        self.written += 1;
+        if self.history.len() > Self::MAX_HISTORY {
+            self.history.pop_front();
+        }
+
+        let hop = n / 4;
+        self.hop_count += 1;
+        if self.hop_count >= hop {
+            self.hop_count = 0;
+            self.synthesize(hop);
+        }
+
+        let y = self.out_buffer.pop_front().unwrap_or_default();
+        self.out_buffer.push_back(0.0);
+
+        let lag = (self.written as f64 - self.pos) as f32 / 44100.0;
+        self.last_outs = vec![y, lag];
+        self.last_outs.clone()
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+            }
+        }
+    }
+}
@@ -0,0 +1,294 @@
+/*!
+The `TrackerSynth` module tracks the pitch and loudness of its input and
+resynthesizes them with an internal oscillator, e.g. for guitar-to-synth
+patches.
+
+The pitch is detected with the YIN algorithm every [TrackerSynth::HOP] samples
+between [TrackerSynth::MIN_FREQ] and [TrackerSynth::MAX_FREQ] Hz. While no
+pitch is detected, the last detected pitch is held and the oscillator follows
+the input's loudness down to silence.
+
+## Wave Functions
+ * `Sine` - a smooth wave, the default
+ * `Triangle` - a linear sine-like wave
+ * `Square` - a binary wave
+ * `Saw` - a linear ramp-like wave
+
+## Inputs
+0. The signal to track
+
+## Outputs
+0. The resynthesized signal
+1. The detected frequency in Hz after gliding and transposing, or [f32::NAN]
+   if no pitch has been detected yet
+2. The loudness of the input as an RMS amplitude
+
+## Knobs
+0. Glide time in the range [0.0, inf) in seconds
+1. Transpose amount in the range (-inf, inf) in semitones
+2. Depth in the range [0.0, inf), equivalent to the gain
+3. Detection threshold in the range (0.0, 1.0) where lower values only accept
+   clearer pitches, usually around 0.15
+
+*/
+
+use std::{collections::VecDeque, f64::consts::PI};
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Default, Deserialize, Debug, Clone)]
+enum TrackerSynthFunc {
+    #[default]
+    Sine,
+    Triangle,
+    Square,
+    Saw,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TrackerSynth {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default)]
+    func: TrackerSynthFunc,
+
+    #[serde(skip)]
+    buffer: VecDeque<f32>,
+    #[serde(skip)]
+    hop_count: usize,
+    #[serde(skip)]
+    target_freq: Option<f32>,
+    #[serde(skip)]
+    freq: Option<f32>,
+    #[serde(skip)]
+    rms: f32,
+    #[serde(skip)]
+    phase: f64,
+    #[serde(skip)]
+    last_outs: Vec<f32>,
+
+    knobs: [f32; 4],
+}
+impl TrackerSynth {
+    const HOP: usize = 512;
+    const WINDOW: usize = 512;
+    const MIN_FREQ: f32 = 60.0;
+    const MAX_FREQ: f32 = 1500.0;
+    /// The RMS amplitude below which the input is considered silent
+    const SILENCE: f32 = 0.01;
+
+    /// Detect the frequency of the buffered input with the YIN algorithm
+    fn detect(&self, sr: f32) -> Option<f32> {
+        let threshold = self.knobs[3].clamp(f32::EPSILON, 1.0);
+        let tau_min = (sr / Self::MAX_FREQ) as usize;
+        let tau_max = (sr / Self::MIN_FREQ) as usize;
+        let x: Vec<f32> = self.buffer.iter().copied().collect();
+        if x.len() < Self::WINDOW + tau_max {
+            return None;
+        }
+
+        // Cumulative mean normalized difference function
+        let mut cmnd = vec![1.0; tau_max + 1];
+        let mut sum = 0.0;
+        for tau in 1..=tau_max {
+            let d: f32 = (0..Self::WINDOW)
+                .map(|j| (x[j] - x[j + tau]).powi(2))
+                .sum();
+            sum += d;
+            cmnd[tau] = if sum > 0.0 {
+                d * tau as f32 / sum
+            } else {
+                1.0
+            };
+        }
+
+        // Find the first dip below the threshold and then its local minimum
+        let mut tau = (tau_min..tau_max).find(|tau| cmnd[*tau] < threshold)?;
+        while tau + 1 < tau_max && cmnd[tau + 1] < cmnd[tau] {
+            tau += 1;
+        }
+
+        // Refine the period with parabolic interpolation
+        let (a, b, c) = (cmnd[tau - 1], cmnd[tau], cmnd[tau + 1]);
+        let denom = a - 2.0 * b + c;
+        let offset = if denom.abs() > f32::EPSILON {
+            0.5 * (a - c) / denom
+        } else {
+            0.0
+        };
+        Some(sr / (tau as f32 + offset))
+    }
+    fn wave(&self) -> f32 {
+        let p = self.phase;
+        (match self.func {
+            TrackerSynthFunc::Sine => (2.0 * PI * p).sin(),
+            TrackerSynthFunc::Triangle => 1.0 - 4.0 * (p - 0.25 - (p + 0.25).floor()).abs(),
+            TrackerSynthFunc::Square => if p < 0.5 { 1.0 } else { -1.0 },
+            TrackerSynthFunc::Saw => 2.0 * p - 1.0,
+        }) as f32
+    }
+}
+#[typetag::deserialize]
+impl Module for TrackerSynth {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} TrackerSynth\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("Func\n", ts.clone()),
+                            TextSection::new("Pitch\n", ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.buffer.clear();
+        self.hop_count = 0;
+        self.target_freq = None;
+        self.freq = None;
+        self.rms = 0.0;
+        self.phase = 0.0;
+        self.last_outs.clear();
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        3
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("audio/tracker_synth.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        let sr = 44100.0;
+        let glide = self.knobs[0].max(0.0);
+        let transpose = 2.0f32.powf(self.knobs[1] / 12.0);
+        let depth = self.knobs[2].max(0.0);
+
+        let x = ins[0];
+        if x.is_nan() {
+            return vec![f32::NAN; self.outputs()];
+        }
+        if st == StepType::Video && !self.last_outs.is_empty() {
+            return self.last_outs.clone();
+        }
+
+        let capacity = Self::WINDOW + (sr / Self::MIN_FREQ) as usize + 1;
+        self.buffer.push_back(x);
+        if self.buffer.len() > capacity {
+            self.buffer.pop_front();
+        }
+
+        self.hop_count += 1;
+        if self.hop_count >= Self::HOP {
+            self.hop_count = 0;
+
+            let n = self.buffer.len().min(Self::HOP);
+            self.rms = (self.buffer.iter().rev().take(n).map(|x| x.powi(2)).sum::<f32>() / n as f32).sqrt();
+            if self.rms >= Self::SILENCE {
+                if let Some(freq) = self.detect(sr) {
+                    self.target_freq = Some(freq);
+                }
+            }
+        }
+
+        // Glide towards the detected pitch
+        if let Some(target) = self.target_freq {
+            self.freq = Some(match self.freq {
+                Some(freq) if glide > 0.0 => freq + (target - freq) * (1.0 - (-1.0 / (glide * sr)).exp()),
+                _ => target,
+            });
+        }
+
+        let (y, freq) = match self.freq {
+            Some(freq) => {
+                let freq = freq * transpose;
+                let y = self.wave() * self.rms * depth;
+                self.phase = (self.phase + f64::from(freq / sr)).fract();
+                (y, freq)
+            },
+            None => (0.0, f32::NAN),
+        };
+        self.last_outs = vec![y, freq, self.rms];
+        self.last_outs.clone()
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("Func: {:?}\n", self.func);
+                text.sections[2].value = match self.freq {
+                    Some(freq) => format!("Pitch: {freq:.1} Hz\n"),
+                    None => "Pitch: Not detected\n".to_string(),
+                };
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[6].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+            }
+        }
+    }
+}
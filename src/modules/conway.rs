@@ -2,6 +2,18 @@
 The `Conway` module outputs a signal based on a Conway's Game of Life
 simulation.
 
+## Rules
+Other Life-like cellular automata can be simulated by setting `rule` to a
+rulestring in B/S notation, e.g. `"B36/S23"` for HighLife. The default is
+`"B3/S23"`.
+
+The grid is initially filled with live cells based on the given `seed` and
+`density`. If no `seed` is given, the rack's seed is used instead.
+
+## Mouse
+ * Left click to seed a random patch of cells at the clicked position
+ * Right click to clear a patch of cells at the clicked position
+
 ## Inputs
 0. Whether to reset the simulation, any non-zero value for yes
 
@@ -13,6 +25,8 @@ simulation.
 1. The signal to output for newly alive pixels
 2. The signal to output for alive pixels
 3. The signal to output for newly dead pixels
+4. Speed divider in the range [1.0, inf), i.e. the number of frames per
+   generation
 
 */
 
@@ -21,11 +35,14 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 use rand::Rng;
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut}};
+use crate::{StepType, rack::module_rng, modules::{Module, ModuleDescription, MouseClick, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut}};
 
 fn default_half() -> f64 {
     0.5
 }
+fn default_rule() -> String {
+    "B3/S23".to_string()
+}
 
 #[derive(Default, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 enum Cell {
@@ -52,22 +69,67 @@ pub struct Conway {
     seed: String,
     #[serde(default = "default_half")]
     density: f64,
+    #[serde(default = "default_rule")]
+    rule: String,
+    #[serde(skip)]
+    birth: [bool; 9],
+    #[serde(skip)]
+    survival: [bool; 9],
     #[serde(skip)]
     rng: Option<rand::rngs::StdRng>,
     #[serde(skip)]
     grid: Option<[[Cell; ComponentVideoOut::WIDTH]; ComponentVideoOut::HEIGHT]>,
     #[serde(skip)]
     scan: usize,
+    #[serde(skip)]
+    frame_count: usize,
 
-    knobs: [f32; 4],
+    knobs: [f32; 5],
 }
 impl Conway {
+    const PATCH_SIZE: isize = 5;
+
+    fn parse_rule(&mut self) {
+        let mut birth = [false; 9];
+        let mut survival = [false; 9];
+
+        let mut is_valid = true;
+        for part in self.rule.to_uppercase().split('/') {
+            let (counts, digits) = match part.get(..1) {
+                Some("B") => (&mut birth, &part[1..]),
+                Some("S") => (&mut survival, &part[1..]),
+                _ => {
+                    is_valid = false;
+                    break;
+                },
+            };
+            for d in digits.chars() {
+                match d.to_digit(10) {
+                    Some(n) if n <= 8 => counts[n as usize] = true,
+                    _ => is_valid = false,
+                }
+            }
+        }
+
+        if is_valid {
+            self.birth = birth;
+            self.survival = survival;
+        } else {
+            error!("Invalid Conway rulestring {}, using B3/S23", self.rule);
+            self.birth = [false, false, false, true, false, false, false, false, false];
+            self.survival = [false, false, true, true, false, false, false, false, false];
+        }
+    }
     fn init_grid(&mut self) {
-        let seed = self.seed.chars()
-            .map(|c| c as u8)
-            .chain([0u8; 32])
-            .collect::<Vec<u8>>();
-        let mut rng: rand::rngs::StdRng = rand::SeedableRng::from_seed(seed[..32].try_into().unwrap());
+        let mut rng: rand::rngs::StdRng = if self.seed.is_empty() {
+            module_rng(self.id.unwrap_or_default())
+        } else {
+            let seed = self.seed.chars()
+                .map(|c| c as u8)
+                .chain([0u8; 32])
+                .collect::<Vec<u8>>();
+            rand::SeedableRng::from_seed(seed[..32].try_into().unwrap())
+        };
         let mut grid = [[Cell::Dead; ComponentVideoOut::WIDTH]; ComponentVideoOut::HEIGHT];
         for row in &mut grid {
             for col in row {
@@ -84,6 +146,7 @@ impl Conway {
 impl Module for Conway {
     fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
         self.id = Some(id);
+        self.init_state(id);
         ec.with_children(|parent| {
             let mut component = parent.spawn((
                 NodeBundle {
@@ -107,10 +170,12 @@ impl Module for Conway {
                             TextSection::new(name, ts.clone()),
                             TextSection::new(format!("Seed: {}\n", self.seed), ts.clone()),
                             TextSection::new(format!("Density: {}\n", self.density), ts.clone()),
+                            TextSection::new(format!("Rule: {}\n", self.rule), ts.clone()),
                             TextSection::new("K0\n", ts.clone()),
                             TextSection::new("K1\n", ts.clone()),
                             TextSection::new("K2\n", ts.clone()),
-                            TextSection::new("K3\n", ts),
+                            TextSection::new("K3\n", ts.clone()),
+                            TextSection::new("K4\n", ts),
                         ]),
                         ModuleTextComponent,
                     )).id()
@@ -118,7 +183,9 @@ impl Module for Conway {
             });
             self.component = Some(component.id());
         });
-
+    }
+    fn init_state(&mut self, _id: usize) {
+        self.parse_rule();
         if self.grid.is_none() {
             self.init_grid();
         }
@@ -131,6 +198,7 @@ impl Module for Conway {
         self.rng = None;
         self.grid = None;
         self.scan = 0;
+        self.frame_count = 0;
     }
 
     fn id(&self) -> Option<usize> {
@@ -152,6 +220,9 @@ impl Module for Conway {
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("conway.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -160,12 +231,38 @@ impl Module for Conway {
         self.knobs[i] = val;
     }
 
+    fn mouse_click(&mut self, mouse_click: MouseClick) {
+        let cx = (mouse_click.rel_pos.x * ComponentVideoOut::WIDTH as f32) as isize;
+        let cy = (mouse_click.rel_pos.y * ComponentVideoOut::HEIGHT as f32) as isize;
+
+        if self.grid.is_none() {
+            self.init_grid();
+        }
+        if let (Some(grid), Some(rng)) = (&mut self.grid, &mut self.rng) {
+            for dy in -Self::PATCH_SIZE/2..=Self::PATCH_SIZE/2 {
+                for dx in -Self::PATCH_SIZE/2..=Self::PATCH_SIZE/2 {
+                    let x = (cx + dx).rem_euclid(ComponentVideoOut::WIDTH as isize) as usize;
+                    let y = (cy + dy).rem_euclid(ComponentVideoOut::HEIGHT as isize) as usize;
+                    match mouse_click.button {
+                        MouseButton::Left if rng.gen_bool(self.density) => {
+                            grid[y][x] = Cell::NewlyAlive;
+                        },
+                        MouseButton::Right if matches!(grid[y][x], Cell::NewlyAlive | Cell::Alive) => {
+                            grid[y][x] = Cell::NewlyDead;
+                        },
+                        _ => {},
+                    }
+                }
+            }
+        }
+    }
     fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
         let reset = ins[0];
         if !reset.is_nan() && reset != 0.0 {
             self.rng = None;
             self.grid = None;
             self.scan = 0;
+            self.frame_count = 0;
         }
 
         if self.grid.is_none() {
@@ -186,6 +283,12 @@ impl Module for Conway {
         self.scan %= ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT;
 
         if self.scan == 0 {
+            self.frame_count += 1;
+        }
+        let divider = (self.knobs[4].round() as usize).max(1);
+        if self.scan == 0 && self.frame_count >= divider {
+            self.frame_count = 0;
+
             let mut old_grid = self.grid.take().unwrap();
             let mut grid = [[Cell::Dead; ComponentVideoOut::WIDTH]; ComponentVideoOut::HEIGHT];
             for j in 0..ComponentVideoOut::HEIGHT {
@@ -196,11 +299,12 @@ impl Module for Conway {
                         old_grid[j][i] = Cell::Dead;
                     }
 
-                    match get_neighbors(&old_grid, i, j) {
-                        2 | 3 if old_grid[j][i] == Cell::Alive => {
+                    let neighbors = get_neighbors(&old_grid, i, j);
+                    match neighbors {
+                        n if self.survival[n] && old_grid[j][i] == Cell::Alive => {
                             grid[j][i] = Cell::Alive;
                         },
-                        3 if old_grid[j][i] == Cell::Dead => {
+                        n if self.birth[n] && old_grid[j][i] == Cell::Dead => {
                             grid[j][i] = Cell::NewlyAlive;
                         },
                         _ if old_grid[j][i] == Cell::Alive => {
@@ -220,10 +324,11 @@ impl Module for Conway {
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[3].value = format!("K0 Dead: {}\n", self.knobs[0]);
-                text.sections[4].value = format!("K1 Newly Alive: {}\n", self.knobs[1]);
-                text.sections[5].value = format!("K2 Alive: {}\n", self.knobs[2]);
-                text.sections[6].value = format!("K3 Newly Dead: {}\n", self.knobs[3]);
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[6].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[7].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+                text.sections[8].value = format!("{}\n", self.describe().knob_row(4, self.knobs[4]));
             }
         }
     }
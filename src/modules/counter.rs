@@ -0,0 +1,153 @@
+/*!
+The `Counter` module counts the triggers received on its input, wrapping back
+to 0 at a given maximum, e.g. to address the steps of a switch or to build
+generative structures.
+
+## Inputs
+0. The trigger which increments the count whenever this becomes positive
+1. The reset which sets the count back to 0 whenever this becomes positive
+
+## Outputs
+0. The count in the range [0, K0)
+1. The count normalized into a ramp in the range [0.0, 1.0)
+2. The wrap trigger which is 1.0 on the step where the count wraps back to 0,
+   otherwise 0.0
+
+## Knobs
+0. Maximum in the range [1.0, inf), rounded to the nearest integer
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Counter {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    count: usize,
+    #[serde(skip)]
+    last_ins: [f32; 2],
+
+    knobs: [f32; 1],
+}
+#[typetag::deserialize]
+impl Module for Counter {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Counter\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("Count: 0\n", ts.clone()),
+                            TextSection::new("K0\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.count = 0;
+        self.last_ins = [0.0; 2];
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        2
+    }
+    fn outputs(&self) -> usize {
+        3
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("counter.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let max = (self.knobs[0].round() as usize).max(1);
+
+        let is_rising = |i: usize| ins[i] > 0.0 && (self.last_ins[i].is_nan() || self.last_ins[i] <= 0.0);
+        let (is_trigger, is_reset) = (is_rising(0), is_rising(1));
+        self.last_ins = [ins[0], ins[1]];
+
+        let mut wrap = 0.0;
+        if is_reset {
+            self.count = 0;
+        } else if is_trigger {
+            self.count += 1;
+            if self.count >= max {
+                self.count = 0;
+                wrap = 1.0;
+            }
+        }
+        self.count %= max;
+
+        vec![
+            self.count as f32,
+            self.count as f32 / max as f32,
+            wrap,
+        ]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("Count: {}\n", self.count);
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+/*!
+The `Crossfade` module takes 2 inputs and blends between them, e.g. to fade
+between two synth voices or two control sources.
+
+Each unpatched signal input is treated as 0.0.
+
+## Crossfade Curves
+ * `Linear` - The gains sum to 1.0, which keeps control signals in range, the
+   default
+ * `EqualPower` - The powers sum to 1.0, which keeps the loudness of
+   uncorrelated audio signals constant
+
+## Inputs
+0. The signal at position 0.0
+1. The signal at position 1.0
+2. Position modulation which is added to K0
+
+## Outputs
+0. The blended signal
+
+## Knobs
+0. Position in the range [0.0, 1.0]
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Default, Deserialize, Debug, Clone)]
+enum CrossfadeCurve {
+    #[default]
+    Linear,
+    EqualPower,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Crossfade {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default)]
+    curve: CrossfadeCurve,
+
+    knobs: [f32; 1],
+}
+#[typetag::deserialize]
+impl Module for Crossfade {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Crossfade\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("Curve: {:?}\n", self.curve), ts.clone()),
+                            TextSection::new("K0\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        3
+    }
+    fn outputs(&self) -> usize {
+        1
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("crossfade.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let [a, b, pm] = [ins[0], ins[1], ins[2]]
+            .map(|inp| if inp.is_nan() { 0.0 } else { inp });
+        let pos = (self.knobs[0] + pm).clamp(0.0, 1.0);
+
+        let (ga, gb) = match self.curve {
+            CrossfadeCurve::Linear => (1.0 - pos, pos),
+            CrossfadeCurve::EqualPower => {
+                let theta = pos * std::f32::consts::FRAC_PI_2;
+                (theta.cos(), theta.sin())
+            },
+        };
+
+        vec![a * ga + b * gb]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+            }
+        }
+    }
+}
@@ -2,15 +2,40 @@
 The `EnvelopeGenerator` module outputs an ADSR envelope based on the given
 parameters.
 
+The shape of the attack, decay, and release stages can be set with `curves`
+as an array of 3 curves, e.g. `curves = ["Logarithmic", "Exponential",
+"Exponential"]`, where each stage is linear by default. When the envelope is
+retriggered it restarts from 0.0 by default, or from its current level if
+`retrigger = "Current"` which avoids clicks when retriggering long envelopes.
+
+## Envelope Curves
+ * `Linear` - Moves at a constant rate, the default
+ * `Exponential` - Moves slowly toward the start level and quickly toward the
+   end level when rising, or quickly away from the start level when falling,
+   like an analog decay
+ * `Logarithmic` - The opposite of `Exponential`, like an analog attack
+
+## Retrigger Modes
+ * `Zero` - Restart the attack from 0.0, the default
+ * `Current` - Restart the attack from the current level
+
 ## Inputs
 0. The envelope's max level
 1. The envelope's attack/sustain/release behavior according to the below table:
    * If just triggered this frame: 1.0
    * If just released this frame: -1.0
    * Otherwise: 0.0
+2. The velocity which scales the peak and sustain levels, sampled whenever the
+   envelope is triggered and treated as 1.0 when unpatched
 
 ## Outputs
 0. The envelope's level
+1. The end of attack trigger which is 1.0 on the step where the attack stage
+   finishes, otherwise 0.0
+2. The end of decay trigger which is 1.0 on the step where the decay stage
+   finishes, otherwise 0.0
+3. The end of release trigger which is 1.0 on the step where the release stage
+   finishes, otherwise 0.0
 
 ## Knobs
 0. Attack time in the range [0.0, inf)
@@ -26,7 +51,49 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Default, Deserialize, Debug, Clone, Copy)]
+pub enum EnvelopeCurve {
+    #[default]
+    Linear,
+    Exponential,
+    Logarithmic,
+}
+impl EnvelopeCurve {
+    const CURVATURE: f32 = 5.0;
+
+    /// Interpolate from the level `a` to the level `b` where `t` is the
+    /// progress in the range [0.0, 1.0]
+    pub fn interpolate(&self, a: f32, b: f32, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        let convex = |u: f32| (Self::CURVATURE * u).exp_m1() / Self::CURVATURE.exp_m1();
+        match (self, b >= a) {
+            (EnvelopeCurve::Linear, _) => a + (b - a) * t,
+            (EnvelopeCurve::Exponential, true) => a + (b - a) * convex(t),
+            (EnvelopeCurve::Exponential, false) => b + (a - b) * convex(1.0 - t),
+            (EnvelopeCurve::Logarithmic, true) => a + (b - a) * (1.0 - convex(1.0 - t)),
+            (EnvelopeCurve::Logarithmic, false) => b + (a - b) * (1.0 - convex(t)),
+        }
+    }
+}
+
+#[derive(Default, Deserialize, Debug, Clone)]
+enum EnvelopeRetrigger {
+    #[default]
+    Zero,
+    Current,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+enum EnvelopeStage {
+    #[default]
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct EnvelopeGenerator {
@@ -40,12 +107,23 @@ pub struct EnvelopeGenerator {
     #[serde(skip)]
     children: Vec<Entity>,
 
+    #[serde(default)]
+    curves: [EnvelopeCurve; 3],
+    #[serde(default)]
+    retrigger: EnvelopeRetrigger,
+
+    #[serde(skip)]
+    stage: EnvelopeStage,
+    /// When the current stage started
+    #[serde(skip)]
+    stage_timestamp: f64,
+    /// The level that the current stage started from
     #[serde(skip)]
-    attack_timestamp: Option<f64>,
+    stage_level: f32,
     #[serde(skip)]
-    release_timestamp: Option<f64>,
+    level: f32,
     #[serde(skip)]
-    last_level: Option<f32>,
+    velocity: f32,
 
     knobs: [f32; 4],
 }
@@ -90,6 +168,12 @@ impl Module for EnvelopeGenerator {
         self.id = None;
         self.component = None;
         self.children = vec![];
+
+        self.stage = EnvelopeStage::Idle;
+        self.stage_timestamp = 0.0;
+        self.stage_level = 0.0;
+        self.level = 0.0;
+        self.velocity = 0.0;
     }
 
     fn id(&self) -> Option<usize> {
@@ -103,14 +187,17 @@ impl Module for EnvelopeGenerator {
     }
 
     fn inputs(&self) -> usize {
-        2
+        3
     }
     fn outputs(&self) -> usize {
-        1
+        4
     }
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("envelope_generator.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -122,7 +209,6 @@ impl Module for EnvelopeGenerator {
     fn step(&mut self, time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
         let attack = self.knobs[0];
         let decay = self.knobs[1];
-        let sustain = self.knobs[2];
         let release = self.knobs[3];
 
         let x = if ins[0].is_nan() {
@@ -134,94 +220,95 @@ impl Module for EnvelopeGenerator {
         if asr != 1.0 && asr != 0.0 && asr != -1.0 {
             error!("Invalid attack/sustain/release input value: {asr}");
         }
-        let mut y = 0.0f32;
-
-        match self.attack_timestamp {
-            Some(at) => {
-                match self.release_timestamp {
-                    Some(rt) => {
-                        if asr == 1.0 {
-                            self.attack_timestamp = Some(time);
-                            self.release_timestamp = None;
-                        } else if asr == -1.0 {
-                            error!("Can't release the envelope when it's already been released");
-                        } else if asr == 0.0 {
-                            let rdt = time - rt;
-                            if rdt < release as f64 {
-                                let level = if x == 0.0 {
-                                    if let Some(last_level) = self.last_level {
-                                        last_level as f64 * sustain as f64
-                                    } else {
-                                        sustain as f64
-                                    }
-                                } else {
-                                    x as f64 * sustain as f64
-                                };
-                                y = (level - rdt * level / release as f64) as f32;
-                            }
-                        }
-                    },
-                    None => {
-                        if asr == 1.0 {
-                            self.attack_timestamp = Some(time);
-                        } else if asr == -1.0 {
-                            self.release_timestamp = Some(time);
-                        } else if asr == 0.0 {
-                            let adt = time - at;
-                            if adt < attack as f64 {
-                                let level = x as f64;
-                                y = (adt * level / attack as f64) as f32;
-                            } else if (adt - attack as f64) < decay as f64 {
-                                let ddt = adt - attack as f64;
-                                let level = x as f64;
-                                y = (level + ddt / decay as f64 * (sustain as f64 - level)) as f32;
-                                if y < sustain {
-                                    y = sustain;
-                                }
-                            } else {
-                                y = sustain;
-                            }
-                        }
-                    },
-                }
-            },
-            None => {
-                match self.release_timestamp {
-                    Some(_rt) => unreachable!(),
-                    None => {
-                        if asr == 1.0 {
-                            self.attack_timestamp = Some(time);
-                        } else if asr == 0.0 {
-                            if x > 0.0 && !ins[0].is_nan() {
-                                error!("Can't sustain the envelope when it hasn't been triggered");
-                            }
-                        } else if asr == -1.0 {
-                            error!("Can't release the envelope when it hasn't been triggered");
-                        }
-
-                        // Leave y = 0.0 since that's where it starts
-                    },
-                }
-            },
-        }
 
-        if x != 0.0 {
-            self.last_level = Some(x);
+        if asr == 1.0 {
+            self.stage = EnvelopeStage::Attack;
+            self.stage_timestamp = time;
+            self.stage_level = match self.retrigger {
+                EnvelopeRetrigger::Zero => 0.0,
+                EnvelopeRetrigger::Current => self.level,
+            };
+            self.velocity = if ins[2].is_nan() {
+                1.0
+            } else {
+                ins[2]
+            };
+        } else if asr == -1.0 {
+            match self.stage {
+                EnvelopeStage::Idle => error!("Can't release the envelope when it hasn't been triggered"),
+                EnvelopeStage::Release => error!("Can't release the envelope when it's already been released"),
+                _ => {
+                    self.stage = EnvelopeStage::Release;
+                    self.stage_timestamp = time;
+                    self.stage_level = self.level;
+                },
+            }
+        } else if asr == 0.0 && self.stage == EnvelopeStage::Idle && x > 0.0 && !ins[0].is_nan() {
+            error!("Can't sustain the envelope when it hasn't been triggered");
         }
 
+        let peak = x * self.velocity;
+        let sustain = self.knobs[2] * self.velocity;
+        let progress = |dt: f64, len: f32| if len > 0.0 {
+            (dt / f64::from(len)) as f32
+        } else {
+            1.0
+        };
+
+        // Advance through any stages which have finished since the last step
+        let mut ends = [0.0; 3];
+        let mut y = loop {
+            let dt = time - self.stage_timestamp;
+            match self.stage {
+                EnvelopeStage::Idle => break 0.0,
+                EnvelopeStage::Attack => {
+                    let t = progress(dt, attack);
+                    if t < 1.0 {
+                        break self.curves[0].interpolate(self.stage_level, peak, t);
+                    }
+                    self.stage = EnvelopeStage::Decay;
+                    ends[0] = 1.0;
+                    self.stage_timestamp += f64::from(attack.max(0.0));
+                    self.stage_level = peak;
+                },
+                EnvelopeStage::Decay => {
+                    let t = progress(dt, decay);
+                    if t < 1.0 {
+                        break self.curves[1].interpolate(self.stage_level, sustain, t);
+                    }
+                    self.stage = EnvelopeStage::Sustain;
+                    ends[1] = 1.0;
+                    self.stage_timestamp += f64::from(decay.max(0.0));
+                    self.stage_level = sustain;
+                },
+                EnvelopeStage::Sustain => break sustain,
+                EnvelopeStage::Release => {
+                    let t = progress(dt, release);
+                    if t < 1.0 {
+                        break self.curves[2].interpolate(self.stage_level, 0.0, t);
+                    }
+                    self.stage = EnvelopeStage::Idle;
+                    ends[2] = 1.0;
+                    self.stage_timestamp += f64::from(release.max(0.0));
+                    self.stage_level = 0.0;
+                },
+            }
+        };
+
         if y.abs() < EPSILON {
             y = 0.0;
         }
+        self.level = y;
 
-        vec![y]
+        vec![y, ends[0], ends[1], ends[2]]
     }
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Attack: {}\n", self.knobs[0]);
-                text.sections[2].value = format!("K1 Decay: {}\n", self.knobs[1]);
-                text.sections[3].value = format!("K2 Sustain: {}\n", self.knobs[2]);
-                text.sections[4].value = format!("K3 Release: {}\n", self.knobs[3]);
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
             }
         }
     }
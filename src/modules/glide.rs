@@ -0,0 +1,200 @@
+/*!
+The `Glide` module takes an input and smoothly slides towards each new value
+instead of jumping to it, e.g. for portamento between the notes of a
+`Sequencer` before they reach an `Oscillator`.
+
+## Glide Modes
+ * `Exponential` - Slide like an analog lag circuit with a time constant of K0,
+   the default
+ * `ConstantTime` - Slide linearly so that every step takes K0 seconds no
+   matter how far apart the values are
+ * `ConstantRate` - Slide exponentially at K0 seconds per octave, so that
+   larger intervals take longer, which only applies between positive values
+   like frequencies
+
+## Inputs
+0. The signal to glide
+
+## Outputs
+0. The glided signal
+1. Whether the signal is currently gliding as 1.0, otherwise 0.0
+
+## Knobs
+0. Glide time in the range [0.0, inf) in seconds
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleMeshComponent, ModuleImageComponent}};
+
+#[derive(Default, Deserialize, Debug, Clone)]
+enum GlideMode {
+    #[default]
+    Exponential,
+    ConstantTime,
+    ConstantRate,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Glide {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default)]
+    mode: GlideMode,
+
+    #[serde(skip)]
+    value: Option<f32>,
+    #[serde(skip)]
+    target: f32,
+    /// The value that the current slide started from
+    #[serde(skip)]
+    start: f32,
+    /// The time since the current slide started
+    #[serde(skip)]
+    elapsed: f64,
+    #[serde(skip)]
+    last_time: Option<f64>,
+
+    knobs: [f32; 1],
+}
+impl Glide {
+    /// The distance from the target which is considered to be reached
+    const EPSILON: f32 = 1e-6;
+}
+#[typetag::deserialize]
+impl Module for Glide {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Glide\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("Mode: {:?}\n", self.mode), ts.clone()),
+                            TextSection::new("K0\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.value = None;
+        self.last_time = None;
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        2
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("glide.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let x = ins[0];
+        if x.is_nan() {
+            return vec![f32::NAN; self.outputs()];
+        }
+
+        let dt = self.last_time.map_or(0.0, |lt| (time - lt).max(0.0));
+        self.last_time = Some(time);
+        let glide = self.knobs[0].max(0.0);
+
+        let value = *self.value.get_or_insert(x);
+        if x != self.target {
+            self.start = value;
+            self.target = x;
+            self.elapsed = 0.0;
+        }
+        self.elapsed += dt;
+        let elapsed = self.elapsed as f32;
+
+        let value = match self.mode {
+            _ if glide == 0.0 => x,
+            GlideMode::Exponential => value + (x - value) * (1.0 - (-dt as f32 / glide).exp()),
+            GlideMode::ConstantTime => if elapsed < glide {
+                self.start + (x - self.start) * elapsed / glide
+            } else {
+                x
+            },
+            GlideMode::ConstantRate => {
+                let octaves = (x / self.start).log2();
+                let duration = octaves.abs() * glide;
+                if self.start > 0.0 && x > 0.0 && elapsed < duration {
+                    self.start * 2.0f32.powf(octaves * elapsed / duration)
+                } else {
+                    x
+                }
+            },
+        };
+        self.value = Some(value);
+
+        vec![
+            value,
+            if (x - value).abs() > Self::EPSILON { 1.0 } else { 0.0 },
+        ]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+            }
+        }
+    }
+}
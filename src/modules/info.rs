@@ -2,6 +2,53 @@
 The `Info` module is automatically created when the rack has an `[info]`
 section.
 
+Setting `seed` in the `[info]` section to a number or string makes every
+module which uses random numbers reproducible, e.g. for recordings. Otherwise a
+random seed is chosen and logged when the rack is loaded.
+
+Setting `sanitize` in the `[info]` section controls how module outputs are
+scrubbed before they're patched to other modules:
+ * `Flush` - replace infinite and denormal outputs with 0.0 so that they can't
+   poison the state of filters or feedback loops, the default
+ * `Off` - pass all outputs through as-is
+
+Any other mode fails to load the rack. The number of values scrubbed from each module is logged when the rack exits.
+
+Setting `calibrate` in the `[info]` section controls how patches between
+inputs and outputs with different signal standards are handled:
+ * `Warn` - log a warning for each mismatched patch, the default
+ * `Auto` - convert the patched values between the standards
+ * `Off` - don't check the patches
+
+Any other mode fails to load the rack.
+
+Setting `theme` in the `[info]` section to `Reactive` makes the background of
+each module and the patch cables pulse with the level of the rack's audio
+output, e.g. when the control screen is projected as part of the show. How far
+they're brightened towards white at full scale is set by `theme_depth` in the
+range [0.0, 1.0], defaulting to 0.25:
+
+```toml
+[info]
+theme = "Reactive"
+theme_depth = 0.4
+```
+
+The `Info` module also acts as a performance dashboard. It shows how long the
+whole performance and the current rack have been running, the name of the next
+rack in the folder, and any setlist `notes` from the `[info]` section, e.g.:
+
+```toml
+[info]
+name = "Opening"
+notes = "Bring in the bass at 2:00, fade out on the filter sweep"
+```
+
+Setting `duration` in the `[info]` section to a number of seconds
+automatically advances to the next rack in the folder once the rack has played
+for that long, e.g. for unattended installations, and the time left is shown
+next to the name of the next rack.
+
 ##### Note
 The `Info` module cannot be created directly.
 
@@ -16,11 +63,11 @@ None
 
 */
 
-use bevy::{prelude::*, ecs::system::EntityCommands, utils::HashMap};
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle, utils::HashMap};
 
 use serde::{Deserialize, de};
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
 
 #[derive(Debug, Clone)]
 pub struct Info {
@@ -31,9 +78,13 @@ pub struct Info {
     children: Vec<Entity>,
 
     info: HashMap<String, String>,
+    next_rack: Option<String>,
+
+    time: f64,
+    rack_start: Option<f64>,
 }
 impl Info {
-    pub fn new(info: HashMap<String, String>) -> Self {
+    pub fn new(info: HashMap<String, String>, next_rack: Option<String>) -> Self {
         Self {
             id: None,
             name: None,
@@ -42,6 +93,21 @@ impl Info {
             children: vec![],
 
             info,
+            next_rack,
+
+            time: 0.0,
+            rack_start: None,
+        }
+    }
+
+    /// Format the given number of seconds as a clock, e.g. 1:02:03
+    fn format_clock(secs: f64) -> String {
+        let secs = secs.max(0.0) as u64;
+        let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+        if h > 0 {
+            format!("{h}:{m:02}:{s:02}")
+        } else {
+            format!("{m}:{s:02}")
         }
     }
 }
@@ -72,8 +138,24 @@ impl Module for Info {
                             std::iter::once(TextSection::new(name, ts.clone()))
                                 .chain(
                                     self.info.iter()
+                                        .filter(|(k, _)| k.as_str() != "notes")
                                         .map(|(k, v)| TextSection::new(format!("{k}: {v}\n"), ts.clone()))
-                                )
+                                ).chain([
+                                    TextSection::new("Set: 0:00\n", ts.clone()),
+                                    TextSection::new("Rack: 0:00\n", ts.clone()),
+                                    TextSection::new(
+                                        self.next_rack.as_ref()
+                                            .map(|next| format!("Next: {next}\n"))
+                                            .unwrap_or_default(),
+                                        ts.clone(),
+                                    ),
+                                    TextSection::new(
+                                        self.info.get("notes")
+                                            .map(|notes| format!("Notes: {notes}\n"))
+                                            .unwrap_or_default(),
+                                        ts.clone(),
+                                    ),
+                                ])
                         ).with_style(Style {
                             width: Val::Px(150.0),
                             height: Val::Px(180.0),
@@ -91,6 +173,8 @@ impl Module for Info {
         self.id = None;
         self.component = None;
         self.children = vec![];
+
+        self.rack_start = None;
     }
 
     fn id(&self) -> Option<usize> {
@@ -112,10 +196,34 @@ impl Module for Info {
     fn knobs(&self) -> usize {
         0
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("info.rs")
+    }
+
+    fn step(&mut self, time: f64, _st: StepType, _ins: &[f32]) -> Vec<f32> {
+        self.time = time;
+        self.rack_start.get_or_insert(time);
 
-    fn step(&mut self, _time: f64, _st: StepType, _ins: &[f32]) -> Vec<f32> {
         vec![]
     }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                // The clocks follow the name and the other info
+                let clock = text.sections.len() - 4;
+                text.sections[clock].value = format!("Set: {}\n", Self::format_clock(self.time));
+                let elapsed = self.time - self.rack_start.unwrap_or(self.time);
+                text.sections[clock + 1].value = format!("Rack: {}\n", Self::format_clock(elapsed));
+
+                let duration = self.info.get("duration")
+                    .and_then(|d| d.parse::<f64>().ok())
+                    .filter(|d| *d > 0.0);
+                if let (Some(next), Some(duration)) = (&self.next_rack, duration) {
+                    text.sections[clock + 2].value = format!("Next: {next} in {}\n", Self::format_clock((duration - elapsed).ceil()));
+                }
+            }
+        }
+    }
 }
 impl<'de> Deserialize<'de> for Info {
     fn deserialize<D>(_deserializer: D) -> Result<Self, D::Error>
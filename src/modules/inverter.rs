@@ -16,7 +16,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent}};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Inverter {
@@ -88,6 +88,9 @@ impl Module for Inverter {
     fn knobs(&self) -> usize {
         0
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("inverter.rs")
+    }
 
     fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
         vec![1.0 - ins[0]]
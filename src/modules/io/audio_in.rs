@@ -1,26 +1,199 @@
 /*!
-The `AudioIn` module outputs a signal from the primary audio device, after first
-applying a gain to it.
+The `AudioIn` module outputs a signal from the primary audio device, or from
+its own input device, after first applying a gain to it.
+
+## Channels
+By default, all input channels are mixed down to a single output. If
+`channels` is set to a value greater than 1, each input channel will instead be
+output separately. The input device and which of its channels are captured can
+be configured in the rack's `[audio.input]` section:
+
+```toml
+[audio.input]
+device = "Scarlett" # Use the first device whose name contains this
+channels = [1, 2, 4] # Capture channels 1, 2, and 4 in that order
+latency_compensation = 0.25 # Delay the captured audio by 250 ms
+```
+
+## Devices
+Each `AudioIn` module can instead capture from its own input device by setting
+`device`, along with which of its channels to capture with `device_channels`,
+so that e.g. a mic and a line input can be brought in separately. The device is
+found in the same way as in the `[audio.input]` section, and all of its
+channels are captured if `device_channels` is empty:
+
+```toml
+1 = { type = "AudioIn", device = "USB Mic", knobs = [1.0] }
+2 = { type = "AudioIn", device = "Scarlett", device_channels = [3, 4], channels = 2, knobs = [1.0] }
+```
+
+Modules with their own device ignore the rack's `[audio.input]` section,
+including its latency compensation, and their audio isn't recorded by
+`VINCE_RECORD_INPUTS`. If the device runs at a different sample rate than the
+rack's audio output, its audio is linearly resampled to the rack's rate as it's
+captured so that the two don't drift apart.
+
+## Latency Compensation
+Live input always arrives later than the audio generated by the rack, by the
+round-trip latency which can be measured with the `LatencyTest` module. Since
+the input can't be moved earlier, `latency_compensation` instead delays it by a
+number of seconds so that it lines up with later sequenced material, e.g. the
+next beat of a loop when set to the beat length minus the measured latency.
 
 ## Inputs
 None
 
 ## Outputs
-0. The audio signal from the primary audio device
+0. The audio signal from the primary audio device, or the first channel if
+   `channels` is greater than 1
+1. The second channel, if `channels` is greater than 1
+2. etc.
 
 ##### Note
-If the audio buffer becomes empty, the output will be [f32::NAN].
+If the audio buffer becomes empty, the outputs will be [f32::NAN].
 
 ## Knobs
 0. Gain in the range [0.0, inf)
 
 */
 
+use std::{collections::VecDeque, sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU32, Ordering}, mpsc}, time::Duration};
+
 use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
+use cpal::traits::{DeviceTrait, StreamTrait};
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, config, rack, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+fn default_channels() -> usize {
+    1
+}
+
+/// The audio captured from a module's own input device by a thread which owns
+/// the stream, since streams can't be sent between threads on every platform
+#[derive(Clone)]
+struct DeviceInput {
+    buffer: Arc<Mutex<Vec<f32>>>,
+    channels: usize,
+    is_running: Arc<AtomicBool>,
+    device_rate: u32,
+    /// The sample rate of the rack that the captured audio is resampled to,
+    /// or 0 if it isn't known yet
+    rack_rate: Arc<AtomicU32>,
+}
+impl DeviceInput {
+    /// Open the input device whose name contains the given name and capture
+    /// the given 1-indexed channels, or every channel if empty, at the rack's
+    /// sample rate if it's known
+    fn open(name: &str, device_channels: &[usize], rack_rate: Option<u32>) -> Result<Self, String> {
+        let buffer: Arc<Mutex<Vec<f32>>> = Arc::new(Mutex::new(vec![]));
+        let is_running = Arc::new(AtomicBool::new(true));
+        let rack_rate = Arc::new(AtomicU32::new(rack_rate.unwrap_or(0)));
+
+        let (tx, rx) = mpsc::channel::<Result<(usize, u32), String>>();
+        let name = name.to_string();
+        let device_channels = device_channels.to_vec();
+        let inbuf = buffer.clone();
+        let running = is_running.clone();
+        let target_rate = rack_rate.clone();
+        std::thread::spawn(move || {
+            let stream = (|| {
+                let host = rack::audio_host(config::get().audio.host.as_deref());
+                let device = rack::find_input_device(&host, &name)
+                    .ok_or(format!("Failed to find audio input device {name}"))?;
+                let default_config = device.default_input_config()
+                    .map_err(|e| format!("Failed to get audio input config for {name}: {e}"))?;
+                let in_channels = usize::from(default_config.channels());
+
+                // Map the configured channels (1-indexed) to device channels
+                let channel_map: Vec<usize> = if device_channels.is_empty() {
+                    (0..in_channels).collect()
+                } else {
+                    device_channels.iter()
+                        .map(|c| {
+                            if *c == 0 || *c > in_channels {
+                                Err(format!("Audio input channel {c} is out of range for {name} with {in_channels} channels"))
+                            } else {
+                                Ok(c - 1)
+                            }
+                        }).collect::<Result<Vec<usize>, String>>()?
+                };
+                let channels = channel_map.len();
+                let device_rate = default_config.sample_rate().0;
+
+                // The last captured frame and the position of the next
+                // resampled frame after it, in captured frames
+                let mut last_frame = vec![0.0f32; channels];
+                let mut pos = 0.0f64;
+                let stream = device.build_input_stream(
+                    &cpal::StreamConfig {
+                        channels: default_config.channels(),
+                        sample_rate: default_config.sample_rate(),
+                        buffer_size: cpal::BufferSize::Default,
+                    },
+                    move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                        if let Ok(mut buf) = inbuf.lock() {
+                            let step = match target_rate.load(Ordering::Acquire) {
+                                0 => 1.0,
+                                rate => f64::from(device_rate) / f64::from(rate),
+                            };
+                            for frame in data.chunks_exact(in_channels) {
+                                // Interpolate the frames which fall between
+                                // the last captured frame and this one
+                                while pos < 1.0 {
+                                    buf.extend(
+                                        channel_map.iter()
+                                            .zip(&last_frame)
+                                            .map(|(c, last)| last + (frame[*c] - last) * pos as f32)
+                                    );
+                                    pos += step;
+                                }
+                                pos -= 1.0;
+
+                                for (last, c) in last_frame.iter_mut().zip(&channel_map) {
+                                    *last = frame[*c];
+                                }
+                            }
+                        }
+                    },
+                    |e| error!("{e}"),
+                    None,
+                ).map_err(|e| format!("Failed to build audio input stream for {name}: {e}"))?;
+                stream.play()
+                    .map_err(|e| format!("Failed to play audio input stream for {name}: {e}"))?;
+                Ok((stream, channels, device_rate))
+            })();
+
+            match stream {
+                Ok((_stream, channels, device_rate)) => {
+                    let _ = tx.send(Ok((channels, device_rate)));
+                    while running.load(Ordering::Acquire) {
+                        std::thread::sleep(Duration::from_millis(100));
+                    }
+                },
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                },
+            }
+        });
+
+        let (channels, device_rate) = rx.recv()
+            .map_err(|e| e.to_string())??;
+        Ok(Self {
+            buffer,
+            channels,
+            is_running,
+            device_rate,
+            rack_rate,
+        })
+    }
+}
+impl std::fmt::Debug for DeviceInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DeviceInput")
+    }
+}
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct AudioIn {
@@ -34,8 +207,20 @@ pub struct AudioIn {
     #[serde(skip)]
     children: Vec<Entity>,
 
+    #[serde(default = "default_channels")]
+    channels: usize,
+    #[serde(skip)]
+    audio_buffer: VecDeque<Vec<f32>>,
+
+    /// The module's own input device, instead of the rack's
+    #[serde(default)]
+    device: Option<String>,
+    #[serde(default)]
+    device_channels: Vec<usize>,
+    #[serde(skip)]
+    device_input: Option<DeviceInput>,
     #[serde(skip)]
-    audio_buffer: Vec<f32>,
+    sample_rate: Option<u32>,
 
     knobs: [f32; 1],
 }
@@ -64,6 +249,7 @@ impl Module for AudioIn {
                     parent.spawn((
                         TextBundle::from_sections([
                             TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("Channels: {}\n", self.channels), ts.clone()),
                             TextSection::new("K0\n", ts),
                         ]),
                         ModuleTextComponent,
@@ -72,11 +258,27 @@ impl Module for AudioIn {
             });
             self.component = Some(component.id());
         });
+
+        // Always open a new stream since a duplicated module would otherwise
+        // share the original's
+        if let Some(device) = &self.device {
+            self.device_input = DeviceInput::open(device, &self.device_channels, self.sample_rate)
+                .map_err(|e| error!("M{id} {e}"))
+                .ok();
+        }
     }
     fn exit(&mut self) {
         self.id = None;
         self.component = None;
         self.children = vec![];
+
+        if let Some(device_input) = self.device_input.take() {
+            device_input.is_running.store(false, Ordering::Release);
+        }
+        self.audio_buffer.clear();
+    }
+    fn is_replayable(&self) -> bool {
+        self.device.is_none()
     }
 
     fn id(&self) -> Option<usize> {
@@ -93,11 +295,14 @@ impl Module for AudioIn {
         0
     }
     fn outputs(&self) -> usize {
-        1
+        self.channels.max(1)
     }
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/audio_in.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -106,25 +311,61 @@ impl Module for AudioIn {
         self.knobs[i] = val;
     }
 
-    fn extend_audio_buffer(&mut self, ai: &[f32]) {
-        self.audio_buffer.extend(ai);
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = Some(sample_rate);
+        if let Some(device_input) = &self.device_input {
+            if device_input.device_rate != sample_rate {
+                info!("Resampling audio input {} from {} Hz to {sample_rate} Hz", self.device.as_deref().unwrap_or_default(), device_input.device_rate);
+            }
+            device_input.rack_rate.store(sample_rate, Ordering::Release);
+        }
+    }
+    fn extend_audio_buffer(&mut self, ai: &[f32], channels: usize) {
+        if channels == 0 || self.device.is_some() {
+            return;
+        }
+
+        self.audio_buffer.extend(
+            ai.chunks_exact(channels)
+                .map(|frame| frame.to_vec())
+        );
     }
 
     fn step(&mut self, _time: f64, st: StepType, _ins: &[f32]) -> Vec<f32> {
         if st == StepType::Video {
-            return vec![f32::NAN];
+            return vec![f32::NAN; self.outputs()];
+        }
+
+        if let (Some(device_input), true) = (&self.device_input, self.audio_buffer.is_empty()) {
+            if let Ok(mut buf) = device_input.buffer.try_lock() {
+                if device_input.channels > 0 {
+                    self.audio_buffer.extend(
+                        buf.chunks_exact(device_input.channels)
+                            .map(|frame| frame.to_vec())
+                    );
+                }
+                buf.clear();
+            }
         }
 
-        if self.audio_buffer.is_empty() {
-            vec![f32::NAN]
-        } else {
-            vec![self.audio_buffer.remove(0) * self.knobs[0]]
+        match self.audio_buffer.pop_front() {
+            Some(frame) if self.channels <= 1 => {
+                vec![frame.iter().sum::<f32>() / frame.len() as f32 * self.knobs[0]]
+            },
+            Some(frame) => {
+                (0..self.channels)
+                    .map(|c| {
+                        frame.get(c)
+                            .map_or(f32::NAN, |v| v * self.knobs[0])
+                    }).collect()
+            },
+            None => vec![f32::NAN; self.outputs()],
         }
     }
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Gain: {}\n", self.knobs[0]);
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
             }
         }
     }
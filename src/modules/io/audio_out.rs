@@ -3,8 +3,8 @@ The `AudioOut` module takes 2 inputs and plays them in stereo on the primary
 audio device.
 
 ## Inputs
-0. The left channel of the audio signal
-1. The right channel of the audio signal
+0. The left channel of the audio signal in the range [-1.0, 1.0]
+1. The right channel of the audio signal in the range [-1.0, 1.0]
 
 ##### Note
 If the right channel is [f32::NAN] (unpatched), then the left channel will be
@@ -22,7 +22,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct AudioOut {
@@ -100,6 +100,9 @@ impl Module for AudioOut {
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/audio_out.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -134,7 +137,7 @@ impl Module for AudioOut {
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Gain: {}\n", self.knobs[0]);
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
             }
         }
     }
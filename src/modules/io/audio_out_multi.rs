@@ -0,0 +1,175 @@
+/*!
+The `AudioOutMulti` module takes up to 8 inputs and plays each of them on the
+corresponding physical channel of the primary audio device.
+
+In order to hear more than 2 channels, the rack's audio output must be
+configured with a matching channel count in its `[audio.output]` section:
+
+```toml
+[audio.output]
+device = "Scarlett" # Use the first device whose name contains this
+channels = 4 # Either 2, 4, 6, or 8
+```
+
+## Inputs
+0. The signal for the first physical channel
+1. The signal for the second physical channel
+2. etc. up to `channels`
+
+##### Note
+Unpatched ([f32::NAN]) inputs will be silent. Inputs beyond the number of
+channels configured for the rack's audio output will be discarded.
+
+## Outputs
+None
+
+## Knobs
+0. Gain in the range [0.0, inf)
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+fn default_channels() -> usize {
+    4
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AudioOutMulti {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default = "default_channels")]
+    channels: usize,
+    #[serde(skip)]
+    audio_buffer: Vec<Vec<f32>>,
+
+    knobs: [f32; 1],
+}
+impl AudioOutMulti {
+    const MAX_CHANNELS: usize = 8;
+}
+#[typetag::deserialize]
+impl Module for AudioOutMulti {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Audio Out Multi\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("Channels: {}\n", self.channels), ts.clone()),
+                            TextSection::new("K0\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, _id: usize) {
+        if self.channels == 0 || self.channels > Self::MAX_CHANNELS {
+            error!("AudioOutMulti supports between 1 and {} channels, got {}", Self::MAX_CHANNELS, self.channels);
+            self.channels = self.channels.clamp(1, Self::MAX_CHANNELS);
+        }
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        self.channels.clamp(1, Self::MAX_CHANNELS)
+    }
+    fn outputs(&self) -> usize {
+        0
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/audio_out_multi.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn drain_multi_audio_buffer(&mut self) -> Vec<Vec<f32>> {
+        self.audio_buffer.drain(..).collect()
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        if st == StepType::Video {
+            return vec![];
+        }
+
+        if ins.iter().all(|i| i.is_nan()) {
+            return vec![];
+        }
+
+        self.audio_buffer.push(
+            ins.iter()
+                .map(|i| {
+                    if i.is_nan() {
+                        0.0
+                    } else {
+                        i * self.knobs[0]
+                    }
+                }).collect()
+        );
+
+        vec![]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+            }
+        }
+    }
+}
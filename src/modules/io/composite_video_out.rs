@@ -21,7 +21,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle, render
 
 use serde::Deserialize;
 
-use crate::{StepType, MainCameraComponent, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleMeshComponent, ModuleImageComponent, ModuleImageWindowComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, WindowOptions, ModuleComponent, ModuleTextComponent, ModuleMeshComponent, ModuleImageComponent, ModuleImageWindowComponent}};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct CompositeVideoOut {
@@ -44,6 +44,8 @@ pub struct CompositeVideoOut {
 
     #[serde(default)]
     is_own_window: bool,
+    #[serde(default)]
+    window: WindowOptions,
 }
 impl CompositeVideoOut {
     pub const WIDTH: usize = 80;
@@ -152,11 +154,14 @@ impl Module for CompositeVideoOut {
     fn is_own_window(&self) -> bool {
         self.is_own_window
     }
-    fn get_world_pos(&self, q_child: &Query<&Parent, With<ModuleComponent>>, q_transform: &Query<&GlobalTransform>, q_camera: &Query<(&Camera, &GlobalTransform), With<MainCameraComponent>>) -> Vec3 {
+    fn window_options(&self) -> WindowOptions {
+        self.window.clone()
+    }
+    fn get_world_pos(&self, q_child: &Query<&Parent, With<ModuleComponent>>, q_transform: &Query<&GlobalTransform>, main_camera: Option<(&Camera, &GlobalTransform)>) -> Vec3 {
         if let Some(component) = self.component() {
             if let Ok(parent) = q_child.get(component) {
                 if let Ok(pos_screen) = q_transform.get(parent.get()) {
-                    if let Ok(camera) = q_camera.get_single() {
+                    if let Some(camera) = main_camera {
                         if let Some(pos_world) = camera.0.viewport_to_world(camera.1, pos_screen.translation().truncate()) {
                             return Vec3::from((pos_world.origin.truncate(), 0.0))
                                 + Vec3::new(0.0, -250.0, 0.0);
@@ -187,6 +192,9 @@ impl Module for CompositeVideoOut {
     fn knobs(&self) -> usize {
         0
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/composite_video_out.rs")
+    }
 
     fn step(&mut self, time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
         let mut y = ins[0];
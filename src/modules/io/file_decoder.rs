@@ -1,25 +1,53 @@
 /*!
-The `FileDecoder` module takes either a WAV file or a Y4M file and outputs it,
-looping upon reaching the end.
+The `FileDecoder` module takes either a WAV file, a Y4M file, an animated GIF,
+or a directory of numbered PNG images and outputs it, looping upon reaching the
+end unless `should_loop` is disabled.
 
 ##### Note
 A proper Y4M video file can be produced using `ffmpeg` as follows:
 
-```
+```text
 $ ffmpeg -i video.mp4 -s 80x60 -f yuv4mpegpipe -filter:v fps=36.75 video.y4m
 ```
 
+Y4M files may use any resolution, in which case each frame is scaled to the
+size of the video bus, and either 4:2:0, 4:2:2, 4:4:4, or monochrome
+colorspaces at any bit depth. Frames are decoded as they're needed so only a
+single frame is held in memory at a time.
+
+An animated GIF or PNG sequence plays at the given `fps` which defaults to the
+GIF's own frame delays, or to 12 frames per second for PNG sequences. The
+frames of a PNG sequence are ordered by the last number in each filename.
+
+A video may be paired with a WAV file via `audio_filename` in order to output
+both at once. The audio is only read during audio steps so that it plays back
+at the correct rate alongside the video, and each file loops independently so
+both should be the same length.
+
 ## Inputs
-None
+0. Seek position in the range [0.0, 1.0], which is applied whenever the input
+   changes so that it can be used to scrub through the file
 
 ## Outputs
  * If given a WAV file:
    0. The left channel of the audio signal
    1. The right channel of the audio signal
- * If given a Y4M file:
+   2. The playback position in the range [0.0, 1.0]
+ * If given a Y4M file, GIF, or PNG sequence:
+   0. The red channel
+   1. The green channel
+   2. The blue channel
+   3. The playback position in the range [0.0, 1.0]
+ * If given a video along with an `audio_filename`:
    0. The red channel
    1. The green channel
    2. The blue channel
+   3. The left channel of the audio signal
+   4. The right channel of the audio signal
+   5. The playback position in the range [0.0, 1.0]
+
+##### Note
+Seeking within a video is deferred until the end of the current frame.
 
 ##### Note
 If given a WAV file and the right channel is missing, then the left channel
@@ -30,16 +58,20 @@ If the buffer becomes empty, the outputs will all be [f32::NAN].
 
 ## Knobs
 0. Gain in the range [0.0, inf)
+1. Loop start position in the range [0.0, 1.0]
+2. Loop end position in the range [0.0, 1.0]
 
 */
 
-use std::fs::File;
+use std::{fs::File, path::Path};
 
 use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use image::AnimationDecoder;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut}};
 
 pub struct WavReader {
     filename: String,
@@ -71,7 +103,6 @@ impl WavReader {
         };
 
         self.buffer.push([left, right]);
-        self.idx += 1;
     }
     fn rewind(&mut self) {
         // Finish reading before rewinding
@@ -81,26 +112,41 @@ impl WavReader {
 
         self.idx = 0;
     }
-    fn read_sample(&mut self, should_loop: bool) -> Option<[f32; 2]> {
-        let sample = self.reader.samples::<i16>().next();
-        match sample {
-            Some(s) => {
-                self.append_sample(s);
-            },
-            None => {
-                self.idx += 1;
-                if should_loop {
-                    self.idx %= self.buffer.len();
-                } else if self.idx-1 == self.buffer.len() {
-                    return None;
-                }
+    fn seek(&mut self, pos: f32) {
+        let target = ((pos * self.reader.duration() as f32) as usize)
+            .min(self.reader.duration() as usize);
+
+        // Read up to the target before seeking forward
+        while self.buffer.len() < target {
+            match self.reader.samples::<i16>().next() {
+                Some(s) => self.append_sample(s),
+                None => break,
             }
         }
-        if self.idx > 0 {
-            Some(self.buffer[self.idx-1])
-        } else {
-            Some(self.buffer[self.buffer.len() - 1])
+
+        self.idx = target.min(self.buffer.len());
+    }
+    fn position(&self) -> f32 {
+        match self.reader.duration() {
+            0 => 0.0,
+            d => self.idx as f32 / d as f32,
+        }
+    }
+    fn read_sample(&mut self, should_loop: bool) -> Option<[f32; 2]> {
+        if self.idx >= self.buffer.len() {
+            match self.reader.samples::<i16>().next() {
+                Some(s) => self.append_sample(s),
+                None => {
+                    if !should_loop || self.buffer.is_empty() {
+                        return None;
+                    }
+                    self.idx = 0;
+                },
+            }
         }
+
+        self.idx += 1;
+        Some(self.buffer[self.idx-1])
     }
 }
 impl std::fmt::Debug for WavReader {
@@ -119,89 +165,159 @@ pub struct Y4mReader {
     reader: y4m::Decoder<std::io::BufReader<std::fs::File>>,
     idx: usize,
     rgb_buffer: Vec<[f32; 3]>,
+    frame: usize,
+    frame_count: usize,
+    pending_seek: Option<usize>,
 }
 impl Y4mReader {
     pub(crate) fn new(filename: &str) -> Self {
-        let file = File::open(filename)
-            .unwrap_or_else(|e| panic!("Failed to open Y4M file {}: {e}", filename));
+        // Count the frames up front so that positions can be computed
+        let mut reader = Self::open(filename);
+        let mut frame_count = 0;
+        while reader.read_frame().is_ok() {
+            frame_count += 1;
+        }
+
         Y4mReader {
             filename: filename.to_string(),
-            reader: y4m::Decoder::new(std::io::BufReader::new(file))
-                .unwrap_or_else(|e| panic!("Failed to decode Y4M file {}: {e}", filename)),
+            reader: Self::open(filename),
             idx: 0,
             rgb_buffer: vec![],
+            frame: 0,
+            frame_count,
+            pending_seek: None,
         }
     }
-    fn read_all(&mut self) {
-        self.rgb_buffer.clear();
+    fn open(filename: &str) -> y4m::Decoder<std::io::BufReader<std::fs::File>> {
+        let file = File::open(filename)
+            .unwrap_or_else(|e| panic!("Failed to open Y4M file {}: {e}", filename));
+        y4m::Decoder::new(std::io::BufReader::new(file))
+            .unwrap_or_else(|e| panic!("Failed to decode Y4M file {}: {e}", filename))
+    }
+    /// Decode the next frame and scale it to the size of the video bus,
+    /// returning false upon reaching the end of the file
+    fn read_frame(&mut self) -> bool {
+        if let Some(target) = self.pending_seek.take() {
+            if target < self.frame {
+                self.reader = Self::open(&self.filename);
+                self.frame = 0;
+            }
+            while self.frame < target && self.reader.read_frame().is_ok() {
+                self.frame += 1;
+            }
+        }
 
         let width = self.reader.get_width();
         let height = self.reader.get_height();
-        let js: Vec<usize> = (0..(height/2))
-            .flat_map(|row| {
-                ((row*width)..(row*width+width))
-                    .map(|i| i / 2)
-                    .cycle()
-                    .take(width * 2)
-            }).collect();
+        let bytes_per_sample = self.reader.get_bytes_per_sample();
+        let max = ((1u32 << self.reader.get_bit_depth()) - 1) as f32;
+        let (cw, ch) = match self.reader.get_colorspace() {
+            y4m::Colorspace::C444 | y4m::Colorspace::C444p10 | y4m::Colorspace::C444p12 => (1, 1),
+            y4m::Colorspace::C422 | y4m::Colorspace::C422p10 | y4m::Colorspace::C422p12 => (2, 1),
+            _ => (2, 2),
+        };
+        let chroma_width = (width + cw - 1) / cw;
+
+        let frame = match self.reader.read_frame() {
+            Ok(frame) => frame,
+            Err(y4m::Error::EOF) => return false,
+            Err(e) => panic!("Failed to read Y4M frame from {}: {e}", self.filename),
+        };
+        self.frame += 1;
+        let ys = frame.get_y_plane();
+        let us = frame.get_u_plane();
+        let vs = frame.get_v_plane();
+
+        let sample = |plane: &[u8], i: usize| -> f32 {
+            if bytes_per_sample == 2 {
+                f32::from(u16::from_le_bytes([plane[2*i], plane[2*i + 1]])) / max
+            } else {
+                f32::from(plane[i]) / max
+            }
+        };
+        let yuv_to_rgb = |x: usize, y: usize| -> [f32; 3] {
+            let luma = sample(ys, y*width + x);
+
+            // Monochrome files have no chroma planes
+            let (u, v) = if us.is_empty() || vs.is_empty() {
+                (0.0, 0.0)
+            } else {
+                let j = (y / ch) * chroma_width + x / cw;
+                (sample(us, j) * 2.0 - 1.0, sample(vs, j) * 2.0 - 1.0)
+            };
+
+            // Standard YUV conversion
+            [
+                luma + 1.140*v,
+                luma - 0.395*u - 0.581*v,
+                luma + 2.032*u,
+            ]
+        };
 
-        loop {
-            match self.reader.read_frame() {
-                Ok(frame) => {
-                    let ys = frame.get_y_plane();
-                    let us = frame.get_u_plane();
-                    let vs = frame.get_v_plane();
-
-                    for (i, &y) in ys.iter()
-                        .enumerate()
-                    {
-                        let y = y as f32 / 255.0;
-
-                        let j = js[i];
-                        let u = us[j] as f32 / 127.5 - 1.0;
-                        let v = vs[j] as f32 / 127.5 - 1.0;
-
-                        // Standard YUV conversion
-                        let r = y + 1.140*v;
-                        let g = y - 0.395*u - 0.581*v;
-                        let b = y + 2.032*u;
-
-                        self.rgb_buffer.push([
-                            r.clamp(0.0, 1.0),
-                            g.clamp(0.0, 1.0),
-                            b.clamp(0.0, 1.0),
-                        ]);
+        // Scale by averaging the source pixels covered by each output pixel
+        let (w, h) = (ComponentVideoOut::WIDTH, ComponentVideoOut::HEIGHT);
+        self.rgb_buffer.clear();
+        for by in 0..h {
+            let y0 = by * height / h;
+            let y1 = ((by + 1) * height / h).max(y0 + 1).min(height);
+            for bx in 0..w {
+                let x0 = bx * width / w;
+                let x1 = ((bx + 1) * width / w).max(x0 + 1).min(width);
+
+                let mut rgb = [0.0; 3];
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        for (c, s) in rgb.iter_mut().zip(yuv_to_rgb(x, y)) {
+                            *c += s;
+                        }
                     }
-                },
-                Err(y4m::Error::EOF) => break,
-                Err(e) => panic!("Failed to read Y4M frame from {}: {e}", self.filename),
+                }
+
+                let count = ((x1 - x0) * (y1 - y0)) as f32;
+                self.rgb_buffer.push(rgb.map(|c| (c / count).clamp(0.0, 1.0)));
             }
         }
+
+        true
     }
     fn rewind(&mut self) {
-        if self.rgb_buffer.is_empty() {
-            self.read_all();
+        self.reader = Self::open(&self.filename);
+        self.rgb_buffer.clear();
+        self.idx = 0;
+        self.frame = 0;
+        self.pending_seek = None;
+    }
+    fn seek(&mut self, pos: f32) {
+        // Seeking is deferred until the next frame in order to avoid tearing
+        let target = ((pos * self.frame_count as f32) as usize)
+            .min(self.frame_count.saturating_sub(1));
+        self.pending_seek = Some(target);
+    }
+    fn position(&self) -> f32 {
+        if self.frame_count == 0 || self.rgb_buffer.is_empty() {
+            return 0.0;
         }
 
-        self.idx = 0;
+        let frame = self.frame.saturating_sub(1) as f32;
+        (frame + self.idx as f32 / self.rgb_buffer.len() as f32) / self.frame_count as f32
     }
     fn read_sample(&mut self, should_loop: bool) -> Option<[f32; 3]> {
-        if self.rgb_buffer.is_empty() {
-            self.read_all();
-        }
+        if self.idx >= self.rgb_buffer.len() {
+            if !self.read_frame() {
+                if !should_loop {
+                    return None;
+                }
 
-        self.idx += 1;
-        if should_loop {
-            self.idx %= self.rgb_buffer.len();
-        } else if self.idx > self.rgb_buffer.len() {
-            return None;
+                self.rewind();
+                if !self.read_frame() {
+                    return None;
+                }
+            }
+            self.idx = 0;
         }
 
-        if self.idx > 0 {
-            Some(self.rgb_buffer[self.idx-1])
-        } else {
-            Some(self.rgb_buffer[self.rgb_buffer.len() - 1])
-        }
+        self.idx += 1;
+        Some(self.rgb_buffer[self.idx-1])
     }
 }
 impl std::fmt::Debug for Y4mReader {
@@ -215,22 +331,204 @@ impl Clone for Y4mReader {
     }
 }
 
+pub struct AnimReader {
+    filename: String,
+    frames: Vec<Vec<[f32; 3]>>,
+    fps: f32,
+    start_time: Option<f64>,
+    frame: usize,
+    scan: usize,
+    pending_seek: Option<f32>,
+}
+impl AnimReader {
+    const DEFAULT_FPS: f32 = 12.0;
+
+    pub(crate) fn new(filename: &str, fps: Option<f32>) -> Self {
+        let (frames, default_fps) = if Path::new(filename).is_dir() {
+            (Self::read_png_sequence(filename), Self::DEFAULT_FPS)
+        } else {
+            Self::read_gif(filename)
+        };
+        if frames.is_empty() {
+            panic!("Failed to decode animation {}: no frames", filename);
+        }
+
+        AnimReader {
+            filename: filename.to_string(),
+            frames,
+            fps: fps.unwrap_or(default_fps),
+            start_time: None,
+            frame: 0,
+            scan: 0,
+            pending_seek: None,
+        }
+    }
+    fn convert_frame(image: &image::RgbaImage) -> Vec<[f32; 3]> {
+        image::imageops::resize(
+            image,
+            ComponentVideoOut::WIDTH as u32,
+            ComponentVideoOut::HEIGHT as u32,
+            image::imageops::FilterType::Triangle,
+        ).pixels()
+            .map(|p| {
+                // Composite transparent pixels over black
+                let a = f32::from(p.0[3]) / 255.0;
+                [
+                    f32::from(p.0[0]) / 255.0 * a,
+                    f32::from(p.0[1]) / 255.0 * a,
+                    f32::from(p.0[2]) / 255.0 * a,
+                ]
+            }).collect()
+    }
+    fn read_gif(filename: &str) -> (Vec<Vec<[f32; 3]>>, f32) {
+        let file = File::open(filename)
+            .unwrap_or_else(|e| panic!("Failed to open GIF file {}: {e}", filename));
+        let frames = image::codecs::gif::GifDecoder::new(std::io::BufReader::new(file))
+            .unwrap_or_else(|e| panic!("Failed to decode GIF file {}: {e}", filename))
+            .into_frames()
+            .collect_frames()
+            .unwrap_or_else(|e| panic!("Failed to read GIF frames from {}: {e}", filename));
+
+        let duration: f32 = frames.iter()
+            .map(|f| {
+                let (numer, denom) = f.delay().numer_denom_ms();
+                numer as f32 / denom as f32 / 1000.0
+            }).sum();
+        let fps = if duration > 0.0 {
+            frames.len() as f32 / duration
+        } else {
+            Self::DEFAULT_FPS
+        };
+
+        (
+            frames.iter()
+                .map(|f| Self::convert_frame(f.buffer()))
+                .collect(),
+            fps,
+        )
+    }
+    fn read_png_sequence(dirname: &str) -> Vec<Vec<[f32; 3]>> {
+        let frame_number = |p: &Path| -> Option<u64> {
+            let stem = p.file_stem()?.to_str()?;
+            let digits: String = stem.chars()
+                .rev()
+                .skip_while(|c| !c.is_ascii_digit())
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            digits.chars().rev().collect::<String>().parse().ok()
+        };
+
+        let mut paths: Vec<_> = std::fs::read_dir(dirname)
+            .unwrap_or_else(|e| panic!("Failed to read PNG sequence directory {}: {e}", dirname))
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| {
+                p.extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| ext.eq_ignore_ascii_case("png"))
+                    .unwrap_or(false)
+            }).collect();
+        paths.sort_by_key(|p| (frame_number(p), p.clone()));
+
+        paths.iter()
+            .map(|p| {
+                let image = image::open(p)
+                    .unwrap_or_else(|e| panic!("Failed to decode PNG file {}: {e}", p.display()));
+                Self::convert_frame(&image.into_rgba8())
+            }).collect()
+    }
+    fn rewind(&mut self) {
+        self.start_time = None;
+        self.frame = 0;
+        self.scan = 0;
+        self.pending_seek = None;
+    }
+    fn seek(&mut self, pos: f32) {
+        self.pending_seek = Some(pos);
+    }
+    fn position(&self) -> f32 {
+        let scan = self.scan as f32 / (ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT) as f32;
+        (self.frame as f32 + scan) / self.frames.len() as f32
+    }
+    fn read_sample(&mut self, time: f64, should_loop: bool) -> Option<[f32; 3]> {
+        // Only switch frames at the start of the scan to avoid tearing
+        if self.scan == 0 {
+            if let Some(pos) = self.pending_seek.take() {
+                let frame = (pos * self.frames.len() as f32).floor();
+                self.start_time = Some(time - f64::from(frame / self.fps));
+            }
+
+            let elapsed = time - *self.start_time.get_or_insert(time);
+            let frame = (elapsed * f64::from(self.fps)).max(0.0) as usize;
+            if should_loop {
+                self.frame = frame % self.frames.len();
+            } else if frame >= self.frames.len() {
+                return None;
+            } else {
+                self.frame = frame;
+            }
+        }
+
+        let sample = self.frames[self.frame][self.scan];
+
+        self.scan += 1;
+        self.scan %= ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT;
+
+        Some(sample)
+    }
+}
+impl std::fmt::Debug for AnimReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AnimReader {{ filename: \"{}\", fps: {} }}", self.filename, self.fps)
+    }
+}
+impl Clone for AnimReader {
+    fn clone(&self) -> Self {
+        AnimReader {
+            filename: self.filename.clone(),
+            frames: self.frames.clone(),
+            fps: self.fps,
+            start_time: None,
+            frame: 0,
+            scan: 0,
+            pending_seek: None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum FileReader {
     WavReader(WavReader),
     Y4mReader(Y4mReader),
+    AnimReader(AnimReader),
 }
 impl FileReader {
     pub(crate) fn rewind(&mut self) {
         match self {
             FileReader::WavReader(reader) => reader.rewind(),
             FileReader::Y4mReader(reader) => reader.rewind(),
+            FileReader::AnimReader(reader) => reader.rewind(),
         }
     }
-    pub(crate) fn read_sample(&mut self, should_loop: bool) -> Option<Vec<f32>> {
+    pub(crate) fn seek(&mut self, pos: f32) {
+        match self {
+            FileReader::WavReader(reader) => reader.seek(pos),
+            FileReader::Y4mReader(reader) => reader.seek(pos),
+            FileReader::AnimReader(reader) => reader.seek(pos),
+        }
+    }
+    pub(crate) fn position(&self) -> f32 {
+        match self {
+            FileReader::WavReader(reader) => reader.position(),
+            FileReader::Y4mReader(reader) => reader.position(),
+            FileReader::AnimReader(reader) => reader.position(),
+        }
+    }
+    pub(crate) fn read_sample(&mut self, time: f64, should_loop: bool) -> Option<Vec<f32>> {
         match self {
             FileReader::WavReader(reader) => reader.read_sample(should_loop).map(|a| a.to_vec()),
             FileReader::Y4mReader(reader) => reader.read_sample(should_loop).map(|a| a.to_vec()),
+            FileReader::AnimReader(reader) => reader.read_sample(time, should_loop).map(|a| a.to_vec()),
         }
     }
 }
@@ -249,9 +547,25 @@ pub struct FileDecoder {
 
     #[serde(skip)]
     reader: Option<FileReader>,
+    #[serde(skip)]
+    audio_reader: Option<WavReader>,
+    #[serde(skip)]
+    last_audio: [f32; 2],
+    #[serde(skip)]
+    last_seek: Option<f32>,
 
     filename: String,
-    knobs: [f32; 1],
+    #[serde(default)]
+    audio_filename: Option<String>,
+    #[serde(default)]
+    fps: Option<f32>,
+    #[serde(default = "default_should_loop")]
+    should_loop: bool,
+
+    knobs: [f32; 3],
+}
+fn default_should_loop() -> bool {
+    true
 }
 impl FileDecoder {
     pub fn new(filename: &str, gain: f32) -> Self {
@@ -263,9 +577,16 @@ impl FileDecoder {
             children: vec![],
 
             reader: None,
+            audio_reader: None,
+            last_audio: [0.0; 2],
+            last_seek: None,
 
             filename: filename.to_string(),
-            knobs: [gain],
+            audio_filename: None,
+            fps: None,
+            should_loop: true,
+
+            knobs: [gain, 0.0, 1.0],
         }
     }
 }
@@ -275,9 +596,21 @@ impl FileDecoder {
             self.reader = Some(FileReader::WavReader(WavReader::new(&self.filename)));
         } else if self.filename.ends_with(".y4m") {
             self.reader = Some(FileReader::Y4mReader(Y4mReader::new(&self.filename)));
+        } else if self.filename.ends_with(".gif") || Path::new(&self.filename).is_dir() {
+            self.reader = Some(FileReader::AnimReader(AnimReader::new(&self.filename, self.fps)));
         } else {
             panic!("Invalid file type for FileDecoder: {}", self.filename);
         }
+
+        if let Some(audio_filename) = &self.audio_filename {
+            if matches!(self.reader, Some(FileReader::WavReader(_))) {
+                warn!("Ignoring audio file {} for FileDecoder: {} is not a video", audio_filename, self.filename);
+            } else if audio_filename.ends_with(".wav") {
+                self.audio_reader = Some(WavReader::new(audio_filename));
+            } else {
+                panic!("Invalid audio file type for FileDecoder: {}", audio_filename);
+            }
+        }
     }
 }
 #[typetag::deserialize]
@@ -306,7 +639,16 @@ impl Module for FileDecoder {
                         TextBundle::from_sections([
                             TextSection::new(name, ts.clone()),
                             TextSection::new(format!("{}\n", self.filename), ts.clone()),
-                            TextSection::new("K0", ts),
+                            TextSection::new(
+                                self.audio_filename.as_ref()
+                                    .map(|f| format!("{f}\n"))
+                                    .unwrap_or_default(),
+                                ts.clone(),
+                            ),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("Position: 0.00\n", ts),
                         ]).with_style(Style {
                             width: Val::Px(150.0),
                             height: Val::Px(180.0),
@@ -341,51 +683,107 @@ impl Module for FileDecoder {
     }
 
     fn inputs(&self) -> usize {
-        0
+        1
     }
     fn outputs(&self) -> usize {
+        let audio_outputs = if self.audio_reader.is_some() {
+            2
+        } else {
+            0
+        };
         match self.reader {
-            Some(FileReader::WavReader(_)) => 2,
-            Some(FileReader::Y4mReader(_)) => 3,
+            Some(FileReader::WavReader(_)) => 2 + 1,
+            Some(FileReader::Y4mReader(_)) => 3 + audio_outputs + 1,
+            Some(FileReader::AnimReader(_)) => 3 + audio_outputs + 1,
             None => 0,
         }
     }
     fn knobs(&self) -> usize {
-        1
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/file_decoder.rs")
     }
 
-    fn step(&mut self, _time: f64, st: StepType, _ins: &[f32]) -> Vec<f32> {
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
         if self.reader.is_none() {
             self.init_reader()
         }
 
-        match &mut self.reader {
-            Some(FileReader::WavReader(reader)) => {
-                if st == StepType::Video {
-                    return vec![f32::NAN; self.outputs()];
-                }
+        let Some(reader) = &mut self.reader else {
+            return vec![f32::NAN; self.outputs()];
+        };
 
-                let sample = reader.read_sample(true).unwrap();
-                vec![
-                    sample[0] * self.knobs[0],
-                    sample[1] * self.knobs[0],
-                ]
+        // Seek whenever the input changes
+        let seek = ins[0];
+        if !seek.is_nan() && self.last_seek != Some(seek) {
+            let pos = seek.clamp(0.0, 1.0);
+            reader.seek(pos);
+            if let Some(audio_reader) = &mut self.audio_reader {
+                audio_reader.seek(pos);
+            }
+        }
+        self.last_seek = if seek.is_nan() {
+            None
+        } else {
+            Some(seek)
+        };
+
+        // Jump back to the start of the loop region upon reaching its end
+        let (loop_start, loop_end) = (self.knobs[1].clamp(0.0, 1.0), self.knobs[2].clamp(0.0, 1.0));
+        if self.should_loop && loop_start < loop_end && reader.position() >= loop_end {
+            reader.seek(loop_start);
+            if let Some(audio_reader) = &mut self.audio_reader {
+                audio_reader.seek(loop_start);
+            }
+        }
+
+        let (mut outs, channels) = match reader {
+            FileReader::WavReader(_) if st == StepType::Video => {
+                (vec![f32::NAN; 2], 2)
             },
-            Some(FileReader::Y4mReader(reader)) => {
-                let sample = reader.read_sample(true).unwrap();
-                vec![
-                    sample[0] * self.knobs[0],
-                    sample[1] * self.knobs[0],
-                    sample[2] * self.knobs[0],
-                ]
+            FileReader::WavReader(_) => {
+                (reader.read_sample(time, self.should_loop).unwrap_or(vec![f32::NAN; 2]), 2)
+            },
+            FileReader::Y4mReader(_) | FileReader::AnimReader(_) => {
+                (reader.read_sample(time, self.should_loop).unwrap_or(vec![f32::NAN; 3]), 3)
             },
-            None => vec![f32::NAN; self.outputs()],
+        };
+        outs.truncate(channels);
+        for s in &mut outs {
+            *s *= self.knobs[0];
         }
+        let position = reader.position();
+
+        if let Some(audio_reader) = &mut self.audio_reader {
+            // Hold the last audio sample during video steps
+            if st != StepType::Video {
+                self.last_audio = audio_reader.read_sample(self.should_loop)
+                    .unwrap_or([f32::NAN; 2]);
+            }
+
+            outs.extend(self.last_audio.map(|s| s * self.knobs[0]));
+        }
+
+        outs.push(position);
+        outs
     }
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[2].value = format!("K0 Gain: {}\n", self.knobs[0]);
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                if let Some(reader) = &self.reader {
+                    text.sections[6].value = format!("Position: {:.2}\n", reader.position());
+                }
             }
         }
     }
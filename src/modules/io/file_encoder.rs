@@ -1,158 +1,310 @@
 /*!
-The `FileEncoder` module takes either 2 or 3 inputs and writes them as either
-stereo to a WAV file or RGB data to a Y4M file.
+The `FileEncoder` module takes either 2, 3, or 5 inputs and writes them as
+either stereo to a WAV file, RGB data to a Y4M file, or H.264 video with
+optional AAC audio to an MP4 file, but only while recording is enabled.
+
+##### Note
+MP4 files are only supported when built with the `mp4` feature.
 
 ## Inputs
  * If writing to a WAV file:
    0. The left channel of the audio signal
    1. The right channel of the audio signal
- * If writing to a Y4M file:
+ * If writing to a Y4M or MP4 file:
+   0. The red channel
+   1. The green channel
+   2. The blue channel
+ * If writing to an MP4 file with `should_mux_audio` enabled:
    0. The red channel
    1. The green channel
    2. The blue channel
+   3. The left channel of the audio signal
+   4. The right channel of the audio signal
+ * Followed by the record trigger which toggles K0 whenever this becomes
+   positive
+ * Followed by the new take trigger which finalizes the current file and
+   starts a new take whenever this becomes positive while recording
 
 ##### Note
 If writing to a WAV file and the right channel is [f32::NAN] (unpatched), then
 the left channel will be copied to it.
 
+## Configuration
+ * WAV files default to 44.1 kHz 16-bit integer samples, which can be changed
+   with `sample_rate`, `bits_per_sample` (8, 16, 24, or 32), and
+   `sample_format` (`Int` or `Float`, which requires and defaults to 32 bits
+   per sample)
+ * WAV files can be peak normalized to the level in dBFS given by `normalize`,
+   e.g. `normalize = -1.0`, in which case each take is written to a
+   temporary float file next to it until it's finalized
+ * WAV files with 8 or 16-bit integer samples can be dithered by enabling
+   `should_dither`, which adds triangular noise of up to 1 LSB
+ * Y4M and MP4 files default to the size of the video bus at 147/4 fps, which
+   can be changed with `resolution` as `[width, height]` (both of which must be
+   even) and `framerate` as `[numerator, denominator]`. Frames are scaled to
+   the given resolution using nearest-neighbor sampling
+ * MP4 files can also mux 44.1 kHz stereo audio from the last 2 inputs by
+   enabling `should_mux_audio`
+
+##### Note
+The rack produces audio at 44.1 kHz and video at 147/4 fps, so a warning will
+be logged if the configuration doesn't match since the file will otherwise
+play back at the wrong speed.
+
+## Recording
+The file is only created once recording starts, and is finalized whenever
+recording stops so that it can be played back while the rack keeps running.
+Each time recording starts again, a new take is written next to the first with
+its number appended, e.g. `out.wav`, `out-2.wav`, `out-3.wav`, etc. Video is
+only started and stopped between frames so that each take starts on a whole
+frame.
+
+To keep long recordings from being lost if the app crashes, WAV headers are
+rewritten every `flush_interval` seconds, defaulting to 1.0, so that the file
+is playable up to the last flush. Files are also finalized when the module is
+dropped, e.g. when the app panics.
+
+##### Note
+MP4 files can only be played once they're finalized, so a recording which is
+killed without unwinding will be lost.
+
 ## Outputs
 None
 
 ## Knobs
-None
+0. Record enable, where the inputs are written while this is positive,
+   defaulting to 1.0
 
 */
 
-use bevy::{prelude::*, ecs::system::EntityCommands};
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use rand::Rng;
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, component_video_out::ComponentVideoOut}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut}};
+
+#[derive(Default, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+enum WavSampleFormat {
+    #[default]
+    Int,
+    Float,
+}
+
+/// Write a sample in the given format, with triangular dither of up to 1 LSB
+/// when writing 8 or 16-bit integers if enabled
+fn write_wav_sample<W: std::io::Write + std::io::Seek>(writer: &mut hound::WavWriter<W>, spec: hound::WavSpec, is_dithered: bool, sample: f32) -> Result<(), hound::Error> {
+    let sample = sample.clamp(-1.0, 1.0);
+    match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Float, _) => writer.write_sample(sample),
+        (hound::SampleFormat::Int, bits @ (8 | 16)) if is_dithered => {
+            let max = ((1i32 << (bits - 1)) - 1) as f32;
+            let mut rng = rand::thread_rng();
+            let s = (sample * max + rng.gen::<f32>() - rng.gen::<f32>())
+                .round()
+                .clamp(-max - 1.0, max);
+            if bits == 8 {
+                writer.write_sample(s as i8)
+            } else {
+                writer.write_sample(s as i16)
+            }
+        },
+        (hound::SampleFormat::Int, 8) => writer.write_sample((sample * i8::MAX as f32) as i8),
+        (hound::SampleFormat::Int, 16) => writer.write_sample((sample * i16::MAX as f32) as i16),
+        (hound::SampleFormat::Int, bits) => {
+            let max = ((1i64 << (bits - 1)) - 1) as f64;
+            writer.write_sample((f64::from(sample) * max) as i32)
+        },
+    }
+}
 
 struct WavWriter {
     filename: String,
+    spec: hound::WavSpec,
     writer: Option<hound::WavWriter<std::io::BufWriter<std::fs::File>>>,
+
+    normalize: Option<f32>,
+    is_dithered: bool,
+    peak: f32,
 }
 impl WavWriter {
-    fn new(filename: &str) -> Self {
+    fn new(filename: &str, sample_rate: u32, bits_per_sample: u16, sample_format: WavSampleFormat, normalize: Option<f32>, is_dithered: bool) -> Self {
         let spec = hound::WavSpec {
             channels: 2,
-            sample_rate: 44100,
-            bits_per_sample: 16,
-            sample_format: hound::SampleFormat::Int,
+            sample_rate,
+            bits_per_sample,
+            sample_format: match sample_format {
+                WavSampleFormat::Int => hound::SampleFormat::Int,
+                WavSampleFormat::Float => hound::SampleFormat::Float,
+            },
+        };
+
+        // When normalizing, the unscaled samples are written to a temporary
+        // float file which is converted once the peak is known
+        let writer = match normalize {
+            Some(_) => hound::WavWriter::create(Self::temp_filename(filename), hound::WavSpec {
+                bits_per_sample: 32,
+                sample_format: hound::SampleFormat::Float,
+                ..spec
+            }),
+            None => hound::WavWriter::create(filename, spec),
         };
         WavWriter {
             filename: filename.to_string(),
-            writer: Some(hound::WavWriter::create(filename, spec)
+            spec,
+            writer: Some(writer
                 .unwrap_or_else(|msg| panic!("Failed to create WAV file {}: {}", filename, msg))),
+
+            normalize,
+            is_dithered,
+            peak: 0.0,
         }
     }
+    fn temp_filename(filename: &str) -> String {
+        format!("{filename}.tmp")
+    }
     fn write_sample(&mut self, sample: f32) -> Result<(), hound::Error> {
-        let sample = (sample * i16::MAX as f32) as i16;
         if let Some(writer) = &mut self.writer {
-            writer.write_sample(sample)
+            if self.normalize.is_some() {
+                if !sample.is_nan() {
+                    self.peak = self.peak.max(sample.abs());
+                }
+                writer.write_sample(sample)
+            } else {
+                write_wav_sample(writer, self.spec, self.is_dithered, sample)
+            }
         } else {
             Ok(())
         }
     }
-    fn finalize(&mut self) -> Result<(), hound::Error> {
-        if let Some(writer) = self.writer.take() {
-            writer.finalize()
+    fn flush(&mut self) -> Result<(), hound::Error> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()
         } else {
             Ok(())
         }
     }
+    fn finalize(&mut self) -> Result<(), hound::Error> {
+        let Some(writer) = self.writer.take() else {
+            return Ok(());
+        };
+        writer.finalize()?;
+
+        // Scale the temporary file so that its peak is at the given level
+        if let Some(normalize) = self.normalize {
+            let temp_filename = Self::temp_filename(&self.filename);
+            let gain = if self.peak > 0.0 {
+                10.0f32.powf(normalize / 20.0) / self.peak
+            } else {
+                1.0
+            };
+
+            let mut reader = hound::WavReader::open(&temp_filename)?;
+            let mut writer = hound::WavWriter::create(&self.filename, self.spec)?;
+            for sample in reader.samples::<f32>() {
+                write_wav_sample(&mut writer, self.spec, self.is_dithered, sample? * gain)?;
+            }
+            writer.finalize()?;
+
+            std::fs::remove_file(&temp_filename)?;
+        }
+
+        Ok(())
+    }
 }
 impl std::fmt::Debug for WavWriter {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "WavWriter {{ filename: \"{}\" }}", self.filename)
     }
 }
-impl Clone for WavWriter {
-    fn clone(&self) -> Self {
-        WavWriter::new(&self.filename)
-    }
+
+/// The number of samples in a frame of the video bus
+const FRAME_LEN: usize = ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT * 3;
+
+/// Scale a frame of interleaved RGB samples from the size of the video bus to
+/// the given resolution using nearest-neighbor sampling
+fn scale_frame(frame: &[f32], resolution: [usize; 2]) -> Vec<[f32; 3]> {
+    let [width, height] = resolution;
+    (0..height)
+        .flat_map(|y| {
+            let by = y * ComponentVideoOut::HEIGHT / height;
+            (0..width)
+                .map(move |x| {
+                    let bx = x * ComponentVideoOut::WIDTH / width;
+                    by * ComponentVideoOut::WIDTH + bx
+                })
+        }).map(|i| [
+            frame[3*i],
+            frame[3*i + 1],
+            frame[3*i + 2],
+        ]).collect()
 }
 
 struct Y4mWriter {
     filename: String,
     writer: y4m::Encoder<std::io::BufWriter<std::fs::File>>,
+    resolution: [usize; 2],
 
     next_frame: Vec<f32>,
 }
 impl Y4mWriter {
-    fn new(filename: &str) -> Self {
+    fn new(filename: &str, resolution: [usize; 2], framerate: [usize; 2]) -> Self {
         Y4mWriter {
             filename: filename.to_string(),
             writer: y4m::EncoderBuilder::new(
-                ComponentVideoOut::WIDTH,
-                ComponentVideoOut::HEIGHT,
+                resolution[0],
+                resolution[1],
                 y4m::Ratio {
-                    num: 147,
-                    den: 4,
+                    num: framerate[0],
+                    den: framerate[1],
                 },
             ).with_colorspace(y4m::Colorspace::C420mpeg2)
             .write_header(std::io::BufWriter::new(
                 std::fs::File::create(filename)
                     .unwrap_or_else(|e| panic!("Failed to open Y4M file for writing {}: {e}", filename))
             )).unwrap_or_else(|e| panic!("Failed to write Y4M file header {}: {e}", filename)),
+            resolution,
 
             next_frame: vec![],
         }
     }
-    fn write_sample(&mut self, sample: f32) -> Result<(), y4m::Error> {
-        if self.next_frame.len() == ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT * 3 {
-            let ys = self.next_frame.chunks(3)
-                .map(|rgb| {
-                    let r = rgb[0];
-                    let g = rgb[1];
-                    let b = rgb[2];
-
-                    0.299 * r + 0.587 * g + 0.114 * b
-                }).map(|y| (y * 255.0) as u8)
-                .collect::<Vec<u8>>();
-
-            let rgbs_sub = self.next_frame.chunks(3 * ComponentVideoOut::WIDTH * 2)
-                .flat_map(|rgbs| {
-                    let mut rgbs_sub = vec![];
-                    for i in 0..(ComponentVideoOut::WIDTH / 2) {
-                        rgbs_sub.push(
-                            (rgbs[6*i] + rgbs[6*i + 3] + rgbs[6*i + 3*ComponentVideoOut::WIDTH] + rgbs[6*i + 3*ComponentVideoOut::WIDTH + 3])
-                            / 4.0
-                        );
-                        rgbs_sub.push(
-                            (rgbs[6*i + 1] + rgbs[6*i + 4] + rgbs[6*i + 3*ComponentVideoOut::WIDTH + 1] + rgbs[6*i + 3*ComponentVideoOut::WIDTH + 4])
-                            / 4.0
-                        );
-                        rgbs_sub.push(
-                            (rgbs[6*i + 2] + rgbs[6*i + 5] + rgbs[6*i + 3*ComponentVideoOut::WIDTH + 2] + rgbs[6*i + 3*ComponentVideoOut::WIDTH + 5])
-                            / 4.0
-                        );
+    fn write_frame(&mut self) -> Result<(), y4m::Error> {
+        let [width, height] = self.resolution;
+        let rgbs = scale_frame(&self.next_frame, self.resolution);
+
+        let ys = rgbs.iter()
+            .map(|[r, g, b]| 0.299 * r + 0.587 * g + 0.114 * b)
+            .map(|y| (y * 255.0) as u8)
+            .collect::<Vec<u8>>();
+
+        let rgbs_sub = (0..(height / 2))
+            .flat_map(|y| (0..(width / 2)).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let i = 2*y*width + 2*x;
+                let mut rgb = [0.0; 3];
+                for j in [i, i + 1, i + width, i + width + 1] {
+                    for (c, s) in rgb.iter_mut().zip(rgbs[j]) {
+                        *c += s / 4.0;
                     }
-                    rgbs_sub
-                }).collect::<Vec<f32>>();
-            let us = rgbs_sub.chunks(3)
-                .map(|rgb| {
-                    let r = rgb[0];
-                    let g = rgb[1];
-                    let b = rgb[2];
-
-                    -0.147 * r - 0.289 * g + 0.436 * b
-                }).map(|u| (u * 127.5 + 127.5) as u8)
-                .collect::<Vec<u8>>();
-            let vs = rgbs_sub.chunks(3)
-                .map(|rgb| {
-                    let r = rgb[0];
-                    let g = rgb[1];
-                    let b = rgb[2];
-
-                    0.615 * r - 0.515 * g + 0.100 * b
-                }).map(|u| (u * 127.5 + 127.5) as u8)
-                .collect::<Vec<u8>>();
-
-            let frame = y4m::Frame::new([&ys, &us, &vs], None);
-            self.writer.write_frame(&frame)?;
+                }
+                rgb
+            }).collect::<Vec<[f32; 3]>>();
+        let us = rgbs_sub.iter()
+            .map(|[r, g, b]| -0.147 * r - 0.289 * g + 0.436 * b)
+            .map(|u| (u * 127.5 + 127.5) as u8)
+            .collect::<Vec<u8>>();
+        let vs = rgbs_sub.iter()
+            .map(|[r, g, b]| 0.615 * r - 0.515 * g + 0.100 * b)
+            .map(|u| (u * 127.5 + 127.5) as u8)
+            .collect::<Vec<u8>>();
 
+        let frame = y4m::Frame::new([&ys, &us, &vs], None);
+        self.writer.write_frame(&frame)
+    }
+    fn write_sample(&mut self, sample: f32) -> Result<(), y4m::Error> {
+        if self.next_frame.len() == FRAME_LEN {
+            self.write_frame()?;
             self.next_frame = vec![];
         }
 
@@ -161,6 +313,12 @@ impl Y4mWriter {
         Ok(())
     }
     fn finalize(&mut self) -> Result<(), y4m::Error> {
+        // Write the last frame if it's complete
+        if self.next_frame.len() == FRAME_LEN {
+            self.write_frame()?;
+        }
+        self.next_frame = vec![];
+
         Ok(())
     }
 }
@@ -169,9 +327,251 @@ impl std::fmt::Debug for Y4mWriter {
         write!(f, "Y4mWriter {{ filename: \"{}\" }}", self.filename)
     }
 }
-impl Clone for Y4mWriter {
-    fn clone(&self) -> Self {
-        Y4mWriter::new(&self.filename)
+
+/// Split an Annex B H.264 bitstream into its NAL units without start codes
+#[cfg(any(feature = "mp4", feature = "stream"))]
+pub(crate) fn split_nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut units = vec![];
+    let mut start = None;
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(s) = start {
+                // Trim the leading zero of 4-byte start codes
+                let mut end = i;
+                while end > s && data[end - 1] == 0 {
+                    end -= 1;
+                }
+                units.push(&data[s..end]);
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    if let Some(s) = start {
+        units.push(&data[s..]);
+    }
+    units
+}
+
+#[cfg(feature = "mp4")]
+struct Mp4Writer {
+    filename: String,
+    writer: Option<mp4::Mp4Writer<std::io::BufWriter<std::fs::File>>>,
+    resolution: [usize; 2],
+    framerate: [usize; 2],
+    track_count: u32,
+
+    video_encoder: openh264::encoder::Encoder,
+    video_track: Option<u32>,
+    frame_count: u64,
+    next_frame: Vec<f32>,
+
+    audio_encoder: Option<fdk_aac::enc::Encoder>,
+    audio_track: Option<u32>,
+    audio_frame_count: u64,
+    next_audio: Vec<i16>,
+}
+#[cfg(feature = "mp4")]
+impl Mp4Writer {
+    const AAC_FRAME_SIZE: usize = 1024;
+    const AUDIO_SAMPLE_RATE: u32 = 44100;
+
+    fn new(filename: &str, resolution: [usize; 2], framerate: [usize; 2], should_mux_audio: bool) -> Self {
+        let config = mp4::Mp4Config {
+            major_brand: str::parse("isom").unwrap(),
+            minor_version: 512,
+            compatible_brands: vec![
+                str::parse("isom").unwrap(),
+                str::parse("iso2").unwrap(),
+                str::parse("avc1").unwrap(),
+                str::parse("mp41").unwrap(),
+            ],
+            timescale: 1000,
+        };
+        let mut writer = mp4::Mp4Writer::write_start(
+            std::io::BufWriter::new(
+                std::fs::File::create(filename)
+                    .unwrap_or_else(|e| panic!("Failed to open MP4 file for writing {}: {e}", filename))
+            ),
+            &config,
+        ).unwrap_or_else(|e| panic!("Failed to write MP4 file header {}: {e}", filename));
+
+        let video_encoder = openh264::encoder::Encoder::with_config(
+            openh264::encoder::EncoderConfig::new(resolution[0] as u32, resolution[1] as u32)
+        ).unwrap_or_else(|e| panic!("Failed to create H.264 encoder for MP4 file {}: {e}", filename));
+
+        // The video track is added once the encoder has produced its parameter
+        // sets but the audio track can be added right away
+        let mut track_count = 0;
+        let (audio_encoder, audio_track) = if should_mux_audio {
+            let encoder = fdk_aac::enc::Encoder::new(fdk_aac::enc::EncoderParams {
+                bit_rate: fdk_aac::enc::BitRate::VbrHigh,
+                sample_rate: Self::AUDIO_SAMPLE_RATE,
+                transport: fdk_aac::enc::Transport::Raw,
+                channels: fdk_aac::enc::ChannelMode::Stereo,
+            }).unwrap_or_else(|e| panic!("Failed to create AAC encoder for MP4 file {}: {e:?}", filename));
+
+            writer.add_track(&mp4::TrackConfig {
+                track_type: mp4::TrackType::Audio,
+                timescale: Self::AUDIO_SAMPLE_RATE,
+                language: "und".to_string(),
+                media_conf: mp4::MediaConfig::AacConfig(mp4::AacConfig {
+                    bitrate: 192000,
+                    profile: mp4::AudioObjectType::AacLowComplexity,
+                    freq_index: mp4::SampleFreqIndex::Freq44100,
+                    chan_conf: mp4::ChannelConfig::Stereo,
+                }),
+            }).unwrap_or_else(|e| panic!("Failed to add audio track to MP4 file {}: {e}", filename));
+            track_count += 1;
+
+            (Some(encoder), Some(track_count))
+        } else {
+            (None, None)
+        };
+
+        Mp4Writer {
+            filename: filename.to_string(),
+            writer: Some(writer),
+            resolution,
+            framerate,
+            track_count,
+
+            video_encoder,
+            video_track: None,
+            frame_count: 0,
+            next_frame: vec![],
+
+            audio_encoder,
+            audio_track,
+            audio_frame_count: 0,
+            next_audio: vec![],
+        }
+    }
+    fn write_frame(&mut self) -> Result<(), FileWriterError> {
+        let [width, height] = self.resolution;
+        let rgb: Vec<u8> = scale_frame(&self.next_frame, self.resolution)
+            .into_iter()
+            .flatten()
+            .map(|c| (c * 255.0) as u8)
+            .collect();
+        let yuv = openh264::formats::YUVBuffer::with_rgb(width, height, &rgb);
+        let bitstream = self.video_encoder.encode(&yuv)
+            .map_err(FileWriterError::H264Error)?
+            .to_vec();
+
+        // Convert from Annex B to length-prefixed NAL units
+        let (mut sps, mut pps) = (None, None);
+        let mut sample = vec![];
+        let mut is_sync = false;
+        for nal in split_nal_units(&bitstream) {
+            match nal.first().map(|h| h & 0x1f) {
+                Some(7) => sps = Some(nal.to_vec()),
+                Some(8) => pps = Some(nal.to_vec()),
+                Some(t) => {
+                    is_sync |= t == 5;
+                    sample.extend((nal.len() as u32).to_be_bytes());
+                    sample.extend(nal);
+                },
+                None => {},
+            }
+        }
+
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        if self.video_track.is_none() {
+            if let (Some(sps), Some(pps)) = (sps, pps) {
+                writer.add_track(&mp4::TrackConfig {
+                    track_type: mp4::TrackType::Video,
+                    timescale: self.framerate[0] as u32,
+                    language: "und".to_string(),
+                    media_conf: mp4::MediaConfig::AvcConfig(mp4::AvcConfig {
+                        width: width as u16,
+                        height: height as u16,
+                        seq_param_set: sps,
+                        pic_param_set: pps,
+                    }),
+                }).map_err(FileWriterError::Mp4Error)?;
+                self.track_count += 1;
+                self.video_track = Some(self.track_count);
+            }
+        }
+
+        if let Some(track) = self.video_track {
+            if !sample.is_empty() {
+                let duration = self.framerate[1] as u32;
+                writer.write_sample(track, &mp4::Mp4Sample {
+                    start_time: self.frame_count * u64::from(duration),
+                    duration,
+                    rendering_offset: 0,
+                    is_sync,
+                    bytes: bytes::Bytes::from(sample),
+                }).map_err(FileWriterError::Mp4Error)?;
+                self.frame_count += 1;
+            }
+        }
+
+        Ok(())
+    }
+    fn write_sample(&mut self, sample: f32) -> Result<(), FileWriterError> {
+        if self.next_frame.len() == FRAME_LEN {
+            self.write_frame()?;
+            self.next_frame = vec![];
+        }
+
+        self.next_frame.push(sample.clamp(0.0, 1.0));
+
+        Ok(())
+    }
+    fn write_audio_sample(&mut self, sample: f32) -> Result<(), FileWriterError> {
+        let (Some(encoder), Some(track), Some(writer)) = (&self.audio_encoder, self.audio_track, &mut self.writer) else {
+            return Ok(());
+        };
+
+        self.next_audio.push((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16);
+        if self.next_audio.len() == Self::AAC_FRAME_SIZE * 2 {
+            let mut output = [0; 1536];
+            let info = encoder.encode(&self.next_audio, &mut output)
+                .map_err(FileWriterError::AacError)?;
+            self.next_audio.clear();
+
+            // The encoder may buffer the first few frames
+            if info.output_size > 0 {
+                writer.write_sample(track, &mp4::Mp4Sample {
+                    start_time: self.audio_frame_count * Self::AAC_FRAME_SIZE as u64,
+                    duration: Self::AAC_FRAME_SIZE as u32,
+                    rendering_offset: 0,
+                    is_sync: true,
+                    bytes: bytes::Bytes::copy_from_slice(&output[..info.output_size]),
+                }).map_err(FileWriterError::Mp4Error)?;
+                self.audio_frame_count += 1;
+            }
+        }
+
+        Ok(())
+    }
+    fn finalize(&mut self) -> Result<(), FileWriterError> {
+        // Write the last frame if it's complete
+        if self.next_frame.len() == FRAME_LEN {
+            self.write_frame()?;
+        }
+        self.next_frame = vec![];
+
+        if let Some(mut writer) = self.writer.take() {
+            writer.write_end()
+                .map_err(FileWriterError::Mp4Error)?;
+        }
+
+        Ok(())
+    }
+}
+#[cfg(feature = "mp4")]
+impl std::fmt::Debug for Mp4Writer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Mp4Writer {{ filename: \"{}\" }}", self.filename)
     }
 }
 
@@ -181,14 +581,40 @@ enum FileWriterError {
     HoundError(hound::Error),
     #[allow(dead_code)]
     Y4mError(y4m::Error),
+    #[cfg(feature = "mp4")]
+    #[allow(dead_code)]
+    Mp4Error(mp4::Error),
+    #[cfg(feature = "mp4")]
+    #[allow(dead_code)]
+    H264Error(openh264::Error),
+    #[cfg(feature = "mp4")]
+    #[allow(dead_code)]
+    AacError(fdk_aac::enc::EncoderError),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 enum FileWriter {
     WavWriter(WavWriter),
     Y4mWriter(Y4mWriter),
+    #[cfg(feature = "mp4")]
+    Mp4Writer(Mp4Writer),
 }
 impl FileWriter {
+    /// Write the file's header and buffered data so that it's playable up to
+    /// this point
+    fn flush(&mut self) -> Result<(), FileWriterError> {
+        match self {
+            FileWriter::WavWriter(writer) => {
+                writer.flush()
+                    .map_err(FileWriterError::HoundError)
+            },
+            // Y4M files have no length in their header and MP4 files can't be
+            // played until their metadata is written at the end
+            FileWriter::Y4mWriter(_) => Ok(()),
+            #[cfg(feature = "mp4")]
+            FileWriter::Mp4Writer(_) => Ok(()),
+        }
+    }
     fn finalize(&mut self) -> Result<(), FileWriterError> {
         match self {
             FileWriter::WavWriter(writer) => {
@@ -199,11 +625,22 @@ impl FileWriter {
                 writer.finalize()
                     .map_err(FileWriterError::Y4mError)
             },
+            #[cfg(feature = "mp4")]
+            FileWriter::Mp4Writer(writer) => writer.finalize(),
+        }
+    }
+}
+impl Drop for FileWriter {
+    fn drop(&mut self) {
+        // Finalizing twice is a no-op so this only affects files which weren't
+        // finalized when recording stopped, e.g. after a panic
+        if let Err(e) = self.finalize() {
+            error!("Failed to finalize FileEncoder file on drop: {e:?}");
         }
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug)]
 pub struct FileEncoder {
     #[serde(skip)]
     id: Option<usize>,
@@ -217,13 +654,221 @@ pub struct FileEncoder {
 
     #[serde(skip)]
     writer: Option<FileWriter>,
+    #[serde(skip)]
+    take: usize,
+    #[serde(skip)]
+    take_start: Option<f64>,
+    #[serde(skip)]
+    elapsed: f64,
+    #[serde(skip)]
+    frame_idx: usize,
+    #[serde(skip)]
+    last_flush: f64,
+    #[serde(skip)]
+    last_record: f32,
+    #[serde(skip)]
+    last_new_take: f32,
+    #[serde(skip)]
+    is_new_take_pending: bool,
 
     filename: String,
+
+    #[serde(default = "default_sample_rate")]
+    sample_rate: u32,
+    #[serde(default)]
+    bits_per_sample: Option<u16>,
+    #[serde(default)]
+    sample_format: WavSampleFormat,
+    #[serde(default)]
+    normalize: Option<f32>,
+    #[serde(default)]
+    should_dither: bool,
+
+    #[serde(default = "default_resolution")]
+    resolution: [usize; 2],
+    #[serde(default = "default_framerate")]
+    framerate: [usize; 2],
+    #[serde(default)]
+    should_mux_audio: bool,
+
+    #[serde(default = "default_flush_interval")]
+    flush_interval: f64,
+
+    #[serde(default = "default_knobs")]
+    knobs: [f32; 1],
+}
+// Copies only have the configuration since the open file can't be shared, so a
+// duplicated module starts with recording stopped
+impl Clone for FileEncoder {
+    fn clone(&self) -> Self {
+        FileEncoder {
+            id: None,
+            name: self.name.clone(),
+
+            component: None,
+            children: vec![],
+
+            writer: None,
+            take: 0,
+            take_start: None,
+            elapsed: 0.0,
+            frame_idx: 0,
+            last_flush: 0.0,
+            last_record: 0.0,
+            last_new_take: 0.0,
+            is_new_take_pending: false,
+
+            filename: self.filename.clone(),
+
+            sample_rate: self.sample_rate,
+            bits_per_sample: self.bits_per_sample,
+            sample_format: self.sample_format,
+            normalize: self.normalize,
+            should_dither: self.should_dither,
+
+            resolution: self.resolution,
+            framerate: self.framerate,
+            should_mux_audio: self.should_mux_audio,
+
+            flush_interval: self.flush_interval,
+
+            knobs: self.knobs,
+        }
+    }
+}
+fn default_sample_rate() -> u32 {
+    44100
+}
+fn default_resolution() -> [usize; 2] {
+    [ComponentVideoOut::WIDTH, ComponentVideoOut::HEIGHT]
+}
+fn default_framerate() -> [usize; 2] {
+    [147, 4]
+}
+fn default_flush_interval() -> f64 {
+    1.0
+}
+fn default_knobs() -> [f32; 1] {
+    [1.0]
+}
+impl FileEncoder {
+    fn validate_video(&self) {
+        let [width, height] = self.resolution;
+        if width == 0 || height == 0 || width % 2 != 0 || height % 2 != 0 {
+            panic!("Invalid video resolution for FileEncoder {}: {width}x{height} must be non-zero and even", self.filename);
+        }
+        let [num, den] = self.framerate;
+        if num == 0 || den == 0 {
+            panic!("Invalid video framerate for FileEncoder {}: {num}/{den}", self.filename);
+        }
+        let [dnum, dden] = default_framerate();
+        if num * dden != dnum * den {
+            warn!("Video framerate for FileEncoder {} doesn't match the rack framerate: {num}/{den} != {dnum}/{dden}", self.filename);
+        }
+    }
+    fn validate(&self) {
+        if self.should_mux_audio && !self.filename.ends_with(".mp4") {
+            warn!("Ignoring should_mux_audio for FileEncoder {}: only MP4 files can mux audio", self.filename);
+        }
+
+        if self.filename.ends_with(".wav") {
+            match (self.sample_format, self.bits_per_sample()) {
+                (WavSampleFormat::Int, 8 | 16 | 24 | 32) | (WavSampleFormat::Float, 32) => {},
+                (format, bits) => panic!("Invalid WAV sample format for FileEncoder {}: {bits}-bit {format:?}", self.filename),
+            }
+            if self.sample_rate == 0 {
+                panic!("Invalid WAV sample rate for FileEncoder {}: {}", self.filename, self.sample_rate);
+            }
+            if self.sample_rate != default_sample_rate() {
+                warn!("WAV sample rate for FileEncoder {} doesn't match the rack sample rate: {} != {}", self.filename, self.sample_rate, default_sample_rate());
+            }
+            if let Some(normalize) = self.normalize {
+                if !normalize.is_finite() || normalize > 0.0 {
+                    panic!("Invalid WAV normalization level for FileEncoder {}: {normalize} dBFS must be at most 0.0", self.filename);
+                }
+            }
+            if self.should_dither && !matches!((self.sample_format, self.bits_per_sample()), (WavSampleFormat::Int, 8 | 16)) {
+                warn!("Ignoring should_dither for FileEncoder {}: only 8 and 16-bit integer samples are dithered", self.filename);
+            }
+        } else if self.filename.ends_with(".y4m") {
+            self.validate_video();
+        } else if self.filename.ends_with(".mp4") {
+            #[cfg(feature = "mp4")]
+            self.validate_video();
+            #[cfg(not(feature = "mp4"))]
+            panic!("Failed to create MP4 file {}: the mp4 feature is disabled", self.filename);
+        } else {
+            panic!("Invalid file type for FileEncoder: {}", self.filename);
+        }
+
+        if self.flush_interval.is_nan() || self.flush_interval <= 0.0 {
+            panic!("Invalid flush interval for FileEncoder {}: {} must be positive", self.filename, self.flush_interval);
+        }
+    }
+    /// The WAV bits per sample, defaulting to 16 for integer samples and 32 for
+    /// float samples
+    fn bits_per_sample(&self) -> u16 {
+        self.bits_per_sample.unwrap_or(match self.sample_format {
+            WavSampleFormat::Int => 16,
+            WavSampleFormat::Float => 32,
+        })
+    }
+    /// The number of inputs which are written to the file, not including the
+    /// triggers
+    fn file_inputs(&self) -> usize {
+        if self.filename.ends_with(".wav") {
+            2
+        } else if self.filename.ends_with(".mp4") && self.should_mux_audio {
+            5
+        } else {
+            3
+        }
+    }
+    /// The filename of the current take, where every take after the first has
+    /// its number appended
+    fn take_filename(&self) -> String {
+        if self.take <= 1 {
+            return self.filename.clone();
+        }
+        match self.filename.rsplit_once('.') {
+            Some((stem, ext)) => format!("{stem}-{}.{ext}", self.take),
+            None => format!("{}-{}", self.filename, self.take),
+        }
+    }
+    /// Create the file for the next take
+    fn start_take(&mut self, time: f64) {
+        self.take += 1;
+        self.take_start = Some(time);
+        self.elapsed = 0.0;
+        self.last_flush = time;
+
+        let filename = self.take_filename();
+        info!("Recording FileEncoder take {}: {filename}", self.take);
+        if self.filename.ends_with(".wav") {
+            self.writer = Some(FileWriter::WavWriter(WavWriter::new(&filename, self.sample_rate, self.bits_per_sample(), self.sample_format, self.normalize, self.should_dither)));
+        } else if self.filename.ends_with(".y4m") {
+            self.writer = Some(FileWriter::Y4mWriter(Y4mWriter::new(&filename, self.resolution, self.framerate)));
+        } else {
+            #[cfg(feature = "mp4")]
+            {
+                self.writer = Some(FileWriter::Mp4Writer(Mp4Writer::new(&filename, self.resolution, self.framerate, self.should_mux_audio)));
+            }
+        }
+    }
+    /// Finalize the file of the current take
+    fn stop_take(&mut self) {
+        if let Some(mut writer) = self.writer.take() {
+            writer.finalize()
+                .unwrap_or_else(|e| panic!("Failed to finalize FileEncoder file {}: {e:?}", self.take_filename()));
+        }
+        self.take_start = None;
+    }
 }
 #[typetag::deserialize]
 impl Module for FileEncoder {
     fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
         self.id = Some(id);
+        self.init_state(id);
         ec.with_children(|parent| {
             let mut component = parent.spawn((
                 NodeBundle {
@@ -245,7 +890,9 @@ impl Module for FileEncoder {
                     parent.spawn((
                         TextBundle::from_sections([
                             TextSection::new(name, ts.clone()),
-                            TextSection::new(format!("{}\n", self.filename), ts),
+                            TextSection::new(format!("{}\n", self.filename), ts.clone()),
+                            TextSection::new(format!("{}\n", self.describe().knob_row(0, self.knobs[0])), ts.clone()),
+                            TextSection::new("Stopped\n", ts),
                         ]).with_style(Style {
                             width: Val::Px(150.0),
                             height: Val::Px(180.0),
@@ -258,19 +905,17 @@ impl Module for FileEncoder {
             });
             self.component = Some(component.id());
         });
-
-        if self.filename.ends_with(".wav") {
-            self.writer = Some(FileWriter::WavWriter(WavWriter::new(&self.filename)));
-        } else if self.filename.ends_with(".y4m") {
-            self.writer = Some(FileWriter::Y4mWriter(Y4mWriter::new(&self.filename)))
-        } else {
-            panic!("Invalid file type for FileEncoder: {}", self.filename);
-        }
+    }
+    fn init_state(&mut self, _id: usize) {
+        self.validate();
+        self.take = 0;
+        self.frame_idx = 0;
+        self.last_record = 0.0;
+        self.last_new_take = 0.0;
+        self.is_new_take_pending = false;
     }
     fn exit(&mut self) {
-        if let Some(writer) = &mut self.writer {
-            writer.finalize().unwrap();
-        }
+        self.stop_take();
 
         self.id = None;
         self.component = None;
@@ -288,20 +933,68 @@ impl Module for FileEncoder {
     }
 
     fn inputs(&self) -> usize {
-        match &self.writer {
-            Some(FileWriter::WavWriter(_)) => 2,
-            Some(FileWriter::Y4mWriter(_)) => 3,
-            None => 0,
-        }
+        self.file_inputs() + 2
     }
     fn outputs(&self) -> usize {
         0
     }
     fn knobs(&self) -> usize {
-        0
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/file_encoder.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
     }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        let (record, new_take) = (ins[self.file_inputs()], ins[self.file_inputs() + 1]);
+        if record > 0.0 && (self.last_record.is_nan() || self.last_record <= 0.0) {
+            self.knobs[0] = if self.knobs[0] > 0.0 {
+                0.0
+            } else {
+                1.0
+            };
+        }
+        if new_take > 0.0 && (self.last_new_take.is_nan() || self.last_new_take <= 0.0) && self.writer.is_some() {
+            self.is_new_take_pending = true;
+        }
+        (self.last_record, self.last_new_take) = (record, new_take);
+
+        // Video is only started and stopped between frames
+        let is_frame_start = self.filename.ends_with(".wav") || self.frame_idx == 0;
+        if !self.filename.ends_with(".wav") {
+            self.frame_idx = (self.frame_idx + 3) % FRAME_LEN;
+        }
+        let is_recording = self.knobs[0] > 0.0;
+        if is_frame_start {
+            if self.is_new_take_pending {
+                self.is_new_take_pending = false;
+                self.stop_take();
+            }
+            if is_recording != self.writer.is_some() {
+                if is_recording {
+                    self.start_take(time);
+                } else {
+                    self.stop_take();
+                }
+            }
+        }
+        if let Some(start) = self.take_start {
+            self.elapsed = time - start;
+        }
+        if time - self.last_flush >= self.flush_interval {
+            self.last_flush = time;
+            if let Err(e) = self.writer.as_mut().map_or(Ok(()), FileWriter::flush) {
+                error!("Failed to flush FileEncoder file {}: {e:?}", self.take_filename());
+            }
+        }
 
-    fn step(&mut self, _time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
         match &mut self.writer {
             Some(FileWriter::WavWriter(writer)) => {
                 if st == StepType::Video {
@@ -316,21 +1009,56 @@ impl Module for FileEncoder {
                 };
 
                 writer.write_sample(left)
-                    .unwrap_or_else(|e| panic!("Failed to write sample to WAV file {}: {e}", self.filename));
+                    .unwrap_or_else(|e| panic!("Failed to write sample to WAV file {}: {e}", writer.filename));
                 writer.write_sample(right)
-                    .unwrap_or_else(|e| panic!("Failed to write sample to WAV file {}: {e}", self.filename));
+                    .unwrap_or_else(|e| panic!("Failed to write sample to WAV file {}: {e}", writer.filename));
             },
             Some(FileWriter::Y4mWriter(writer)) => {
                 writer.write_sample(ins[0])
-                    .unwrap_or_else(|e| panic!("Failed to write sample to Y4M file {}: {e}", self.filename));
+                    .unwrap_or_else(|e| panic!("Failed to write sample to Y4M file {}: {e}", writer.filename));
                 writer.write_sample(ins[1])
-                    .unwrap_or_else(|e| panic!("Failed to write sample to Y4M file {}: {e}", self.filename));
+                    .unwrap_or_else(|e| panic!("Failed to write sample to Y4M file {}: {e}", writer.filename));
                 writer.write_sample(ins[2])
-                    .unwrap_or_else(|e| panic!("Failed to write sample to Y4M file {}: {e}", self.filename));
+                    .unwrap_or_else(|e| panic!("Failed to write sample to Y4M file {}: {e}", writer.filename));
+            },
+            #[cfg(feature = "mp4")]
+            Some(FileWriter::Mp4Writer(writer)) => {
+                for &s in &ins[0..3] {
+                    writer.write_sample(s)
+                        .unwrap_or_else(|e| panic!("Failed to write sample to MP4 file {}: {e:?}", writer.filename));
+                }
+
+                if self.should_mux_audio && st != StepType::Video {
+                    let left = ins[3];
+                    let right = if ins[4].is_nan() {
+                        left
+                    } else {
+                        ins[4]
+                    };
+
+                    for s in [left, right] {
+                        writer.write_audio_sample(s)
+                            .unwrap_or_else(|e| panic!("Failed to write audio sample to MP4 file {}: {e:?}", writer.filename));
+                    }
+                }
             },
             None => {},
         }
 
         vec![]
     }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("{}\n", self.take_filename());
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[3].value = if self.writer.is_some() {
+                    let secs = self.elapsed as u64;
+                    format!("Recording {}:{:02}\n", secs / 60, secs % 60)
+                } else {
+                    "Stopped\n".to_string()
+                };
+            }
+        }
+    }
 }
@@ -0,0 +1,227 @@
+/*!
+The `ImageIn` module outputs 3 signals as RGB data from an image file or a
+slideshow of the image files in a directory.
+
+Each image is resized to [80](ComponentVideoOut::WIDTH) by
+[60](ComponentVideoOut::HEIGHT) when it's loaded. Directories are sorted by
+filename and only PNG and JPEG files are included.
+
+## Inputs
+None
+
+## Outputs
+0. Gamma-corrected red channel in the range [0.0, 1.0]
+1. Gamma-corrected green channel in the range [0.0, 1.0]
+2. Gamma-corrected blue channel in the range [0.0, 1.0]
+
+##### Note
+If no images could be loaded, the outputs will all be [f32::NAN].
+
+## Knobs
+0. Slideshow interval in seconds in the range (0.0, inf), ignored for single
+   images
+
+*/
+
+use std::path::Path;
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ImageIn {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    filename: String,
+    #[serde(skip)]
+    images: Vec<Vec<[f32; 3]>>,
+    #[serde(skip)]
+    current: usize,
+    #[serde(skip)]
+    scan: usize,
+
+    knobs: [f32; 1],
+}
+impl ImageIn {
+    const GAMMA: f32 = 2.2;
+    const EXTENSIONS: [&'static str; 3] = ["png", "jpg", "jpeg"];
+
+    fn load_image(path: &Path) -> Option<Vec<[f32; 3]>> {
+        let image = match image::open(path) {
+            Ok(image) => image,
+            Err(e) => {
+                error!("Failed to load image {}: {e}", path.display());
+                return None;
+            },
+        };
+
+        let image = image.resize_exact(
+            ComponentVideoOut::WIDTH as u32,
+            ComponentVideoOut::HEIGHT as u32,
+            image::imageops::FilterType::Triangle,
+        ).into_rgb8();
+
+        Some(
+            image.pixels()
+                .map(|p| {
+                    [
+                        (f32::from(p.0[0]) / 255.0).powf(Self::GAMMA),
+                        (f32::from(p.0[1]) / 255.0).powf(Self::GAMMA),
+                        (f32::from(p.0[2]) / 255.0).powf(Self::GAMMA),
+                    ]
+                }).collect()
+        )
+    }
+    fn load(&mut self) {
+        let path = Path::new(&self.filename);
+        self.images = if path.is_dir() {
+            let mut paths: Vec<_> = match std::fs::read_dir(path) {
+                Ok(entries) => entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.path())
+                    .filter(|p| {
+                        p.extension()
+                            .and_then(|ext| ext.to_str())
+                            .map(|ext| Self::EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+                            .unwrap_or(false)
+                    }).collect(),
+                Err(e) => {
+                    error!("Failed to read image directory {}: {e}", self.filename);
+                    vec![]
+                },
+            };
+            paths.sort();
+
+            paths.iter()
+                .filter_map(|p| Self::load_image(p))
+                .collect()
+        } else {
+            Self::load_image(path)
+                .into_iter()
+                .collect()
+        };
+    }
+}
+#[typetag::deserialize]
+impl Module for ImageIn {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Image In\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("{}\n", self.filename), ts.clone()),
+                            TextSection::new("Image 0/0\n", ts.clone()),
+                            TextSection::new("K0\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, _id: usize) {
+        self.load();
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.images = vec![];
+        self.current = 0;
+        self.scan = 0;
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        0
+    }
+    fn outputs(&self) -> usize {
+        3
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/image_in.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, time: f64, _st: StepType, _ins: &[f32]) -> Vec<f32> {
+        if self.images.is_empty() {
+            return vec![f32::NAN, f32::NAN, f32::NAN];
+        }
+
+        // Only switch images at the start of a frame to avoid tearing
+        if self.scan == 0 && self.knobs[0] > 0.0 {
+            self.current = (time / f64::from(self.knobs[0])) as usize % self.images.len();
+        }
+
+        let [r, g, b] = self.images[self.current][self.scan];
+
+        self.scan += 1;
+        self.scan %= ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT;
+
+        vec![r, g, b]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                let current = if self.images.is_empty() {
+                    0
+                } else {
+                    self.current + 1
+                };
+                text.sections[2].value = format!("Image {}/{}\n", current, self.images.len());
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+            }
+        }
+    }
+}
@@ -6,7 +6,7 @@ it's attack/sustain/release behavior.
 None
 
 ## Outputs
-0. The frequency signal
+0. The frequency signal in the range [0.0, inf) in Hz
 1. The attack/sustain/release signal
 
 ## Knobs
@@ -18,7 +18,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle, utils:
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum Asr {
@@ -103,6 +103,12 @@ impl Module for KeyboardIn {
     fn knobs(&self) -> usize {
         1
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/keyboard_in.rs")
+    }
+    fn is_focusable(&self) -> bool {
+        true
+    }
 
     fn keyboard_input(&mut self, keys: &Res<Input<KeyCode>>) {
         let valid_keys = [
@@ -166,7 +172,7 @@ impl Module for KeyboardIn {
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Octave: {}\n", self.knobs[0]);
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
             }
         }
     }
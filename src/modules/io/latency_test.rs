@@ -0,0 +1,198 @@
+/*!
+The `LatencyTest` module measures the round-trip latency of the audio devices.
+It periodically outputs a click which should be patched to an `AudioOut`
+module, and listens for that click on its input which should be patched from an
+`AudioIn` module while the output is looped back to the input, e.g. with a
+cable or by holding a microphone to a speaker.
+
+The latency is measured in the rack's time as the number of samples between
+sending each click and detecting it, and the average of the last few
+measurements is displayed. It includes any input latency compensation that's
+configured in the rack's `[audio.input]` section:
+
+```toml
+[audio.input]
+latency_compensation = 0.25 # Delay all AudioIn signals by 250 ms
+```
+
+## Inputs
+0. The looped back signal
+
+## Outputs
+0. The click signal
+1. The average measured latency in seconds, or [f32::NAN] if no click has
+   been detected yet
+
+## Knobs
+0. Detection threshold in the range (0.0, 1.0]
+1. Click interval in the range (0.0, inf) in seconds
+
+*/
+
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LatencyTest {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    samples: usize,
+    #[serde(skip)]
+    last_click: Option<usize>,
+    #[serde(skip)]
+    is_listening: bool,
+    #[serde(skip)]
+    latencies: VecDeque<usize>,
+
+    knobs: [f32; 2],
+}
+impl LatencyTest {
+    const MAX_LATENCIES: usize = 8;
+
+    fn average(&self) -> f32 {
+        if self.latencies.is_empty() {
+            f32::NAN
+        } else {
+            self.latencies.iter().sum::<usize>() as f32 / self.latencies.len() as f32
+        }
+    }
+}
+#[typetag::deserialize]
+impl Module for LatencyTest {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Latency Test\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("Latency: Not detected\n", ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.samples = 0;
+        self.last_click = None;
+        self.is_listening = false;
+        self.latencies.clear();
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        2
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/latency_test.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        let sr = 44100.0;
+        if st == StepType::Video {
+            return vec![0.0, self.average() / sr];
+        }
+
+        let samples = self.samples;
+        self.samples += 1;
+
+        // Listen for the last click
+        if let Some(last_click) = self.last_click {
+            if self.is_listening && samples > last_click && ins[0].abs() >= self.knobs[0] {
+                self.is_listening = false;
+
+                self.latencies.push_back(samples - last_click);
+                if self.latencies.len() > Self::MAX_LATENCIES {
+                    self.latencies.pop_front();
+                }
+            }
+        }
+
+        // Send the next click
+        let interval = (self.knobs[1] * sr).max(1.0) as usize;
+        let click = if self.last_click.map_or(true, |lc| samples - lc >= interval) {
+            self.last_click = Some(samples);
+            self.is_listening = true;
+            1.0
+        } else {
+            0.0
+        };
+
+        vec![click, self.average() / sr]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                let latency = self.average();
+                text.sections[1].value = if latency.is_nan() {
+                    "Latency: Not detected\n".to_string()
+                } else {
+                    format!("Latency: {:.1} ms ({} samples)\n", latency / 44.1, latency.round())
+                };
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+            }
+        }
+    }
+}
@@ -6,7 +6,7 @@ device.
 None
 
 ## Outputs
-0. The frequency signal from the given device
+0. The frequency signal from the given device in the range [0.0, inf) in Hz
 1. The amplitude signal from the given device
 2. Control signal 1
 3. Control signal 2
@@ -20,6 +20,40 @@ None
 ##### Note
 If the MIDI port becomes stalled, the outputs will all be [f32::NAN].
 
+## Ports and Channels
+By default the module listens to every MIDI port on every channel. Set `port`
+to only connect to the ports whose names contain the given text, ignoring case,
+and `channels` to only listen to the given channels in the range [1, 16], so
+that several `MidiIn` modules can listen to different devices or channels
+independently:
+
+```toml
+1 = { type = "MidiIn", port = "Launchkey", channels = [1] }
+2 = { type = "MidiIn", port = "Launchkey", channels = [10] }
+```
+
+## MPE
+Set `mpe = { voices = 4 }` to track MIDI Polyphonic Expression controllers,
+e.g. a LinnStrument or Seaboard, which send each note on its own channel. Each
+note is assigned to one of the given number of voices, replacing the voice
+which was released first or else the oldest held voice, and each voice adds 4
+outputs after the above:
+
+10. Voice 0 frequency, including its own pitch bend
+11. Voice 0 velocity, or 0.0 once released
+12. Voice 0 pressure from channel aftertouch
+13. Voice 0 timbre from controller 74
+14. Voice 1 frequency
+15. ...
+
+The pitch bend range of each note's channel defaults to 48 semitones and can
+be set with `bend_range`, e.g. `mpe = { voices = 8, bend_range = 24.0 }`.
+Messages on channel 1, the MPE master channel, only affect the above outputs.
+
+```toml
+1 = { type = "MidiIn", mpe = { voices = 4 } }
+```
+
 ## Knobs
 None
 
@@ -34,7 +68,7 @@ use serde::Deserialize;
 
 use midir::{MidiInput, MidiInputPort, MidiInputConnection};
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent}};
+use crate::{StepType, session, replay, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent}};
 
 #[derive(Default, Clone)]
 struct MidiInputContext {
@@ -47,6 +81,44 @@ impl std::fmt::Debug for MidiInputContext {
     }
 }
 
+fn default_mpe_voices() -> usize {
+    4
+}
+fn default_mpe_bend_range() -> f32 {
+    48.0
+}
+#[derive(Deserialize, Debug, Clone)]
+struct MpeConfig {
+    #[serde(default = "default_mpe_voices")]
+    voices: usize,
+    /// The pitch bend range of the member channels in semitones
+    #[serde(default = "default_mpe_bend_range")]
+    bend_range: f32,
+}
+/// The expression which has been sent on a single MPE member channel
+#[derive(Debug, Default, Clone, Copy)]
+struct MpeChannel {
+    bend: f32,
+    pressure: u8,
+    timbre: u8,
+}
+/// A note which is assigned to a voice
+#[derive(Debug, Clone, Copy)]
+struct MpeVoice {
+    channel: u4,
+    key: u7,
+    vel: u7,
+    is_held: bool,
+    /// The order in which the voice was triggered or released
+    age: u64,
+}
+
+/// Whether a message on the given channel should be handled, where channels
+/// are given in the range [1, 16]
+fn is_channel_enabled(channels: &[u8], channel: u4) -> bool {
+    channels.is_empty() || channels.contains(&(channel.as_int() + 1))
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct MidiIn {
     #[serde(skip)]
@@ -54,6 +126,13 @@ pub struct MidiIn {
     #[serde(default)]
     name: Option<String>,
 
+    /// Only connect to the ports whose names contain this, ignoring case
+    #[serde(default)]
+    port: Option<String>,
+    /// Only handle messages on these channels, or every channel if empty
+    #[serde(default)]
+    channels: Vec<u8>,
+
     #[serde(skip)]
     component: Option<Entity>,
     #[serde(skip)]
@@ -68,11 +147,100 @@ pub struct MidiIn {
     controllers: HashMap<u7, u7>,
     #[serde(skip)]
     bend: f32,
+
+    #[serde(default)]
+    mpe: Option<MpeConfig>,
+    #[serde(skip)]
+    mpe_channels: [MpeChannel; 16],
+    #[serde(skip)]
+    mpe_voices: Vec<Option<MpeVoice>>,
+    #[serde(skip)]
+    mpe_age: u64,
+}
+impl MidiIn {
+    /// Update the MPE voices and channels with a message from the given
+    /// channel
+    fn step_mpe(&mut self, channel: u4, msg: &MidiMessage) {
+        let ch = usize::from(channel.as_int());
+        self.mpe_age += 1;
+        match *msg {
+            MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                // Reuse the channel's voice, then the first released voice,
+                // then the oldest held voice
+                let voice = self.mpe_voices.iter()
+                    .position(|v| v.is_some_and(|v| v.channel == channel))
+                    .or_else(|| self.mpe_voices.iter().position(Option::is_none))
+                    .or_else(|| {
+                        self.mpe_voices.iter()
+                            .enumerate()
+                            .filter_map(|(i, v)| Some((i, (*v)?)))
+                            .min_by_key(|(_, v)| (v.is_held, v.age))
+                            .map(|(i, _)| i)
+                    });
+                if let Some(voice) = voice {
+                    self.mpe_voices[voice] = Some(MpeVoice {
+                        channel,
+                        key,
+                        vel,
+                        is_held: true,
+                        age: self.mpe_age,
+                    });
+                }
+            },
+            MidiMessage::NoteOff { key, .. } | MidiMessage::NoteOn { key, .. } => {
+                for voice in self.mpe_voices.iter_mut().flatten() {
+                    if voice.is_held && voice.channel == channel && voice.key == key {
+                        voice.is_held = false;
+                        voice.age = self.mpe_age;
+                    }
+                }
+            },
+            MidiMessage::PitchBend { bend } => {
+                self.mpe_channels[ch].bend = bend.as_f32();
+            },
+            MidiMessage::ChannelAftertouch { vel } => {
+                self.mpe_channels[ch].pressure = vel.as_int();
+            },
+            MidiMessage::Controller { controller, value } if controller == 74 => {
+                self.mpe_channels[ch].timbre = value.as_int();
+            },
+            _ => {},
+        }
+    }
+    /// Get the outputs of each MPE voice
+    fn mpe_outputs(&self) -> Vec<f32> {
+        let Some(mpe) = &self.mpe else {
+            return vec![];
+        };
+
+        let u7max: f32 = u7::max_value().as_int() as f32;
+        self.mpe_voices.iter()
+            .flat_map(|voice| match voice {
+                Some(voice) => {
+                    let channel = &self.mpe_channels[usize::from(voice.channel.as_int())];
+                    let semitones = f32::from(voice.key.as_int()) - 69.0 + channel.bend * mpe.bend_range;
+                    let freq = 2.0f32.powf(semitones / 12.0 + self.bend) * 440.0;
+                    let vel = if voice.is_held {
+                        voice.vel.as_int() as f32 / u7max
+                    } else {
+                        0.0
+                    };
+                    [
+                        freq,
+                        vel,
+                        f32::from(channel.pressure) / u7max,
+                        f32::from(channel.timbre) / u7max,
+                    ]
+                },
+                None => [0.0; 4],
+            }).collect()
+    }
 }
 #[typetag::deserialize]
 impl Module for MidiIn {
     fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
         self.id = Some(id);
+        self.init_state(id);
         ec.with_children(|parent| {
             let mut component = parent.spawn((
                 NodeBundle {
@@ -103,6 +271,8 @@ impl Module for MidiIn {
         });
 
         if self.midi_context.ports_names_conns.is_empty() {
+            let port_filter = self.port.as_ref()
+                .map(|port| port.to_lowercase());
             let mut midi_in = MidiInput::new("Vince MidiIn").expect("Failed to init MIDI Input");
             midi_in.ignore(midir::Ignore::None);
 
@@ -111,12 +281,33 @@ impl Module for MidiIn {
             {
                 let in_port_name = midi_in.port_name(in_port)
                     .unwrap_or_else(|msg| panic!("Failed to get MIDI Input name for port with index {}: {}", i, msg));
+                if port_filter.as_ref().is_some_and(|port| !in_port_name.to_lowercase().contains(port)) {
+                    continue;
+                }
+
                 let events = self.midi_context.events.clone();
+                let channels = self.channels.clone();
+                let port_name = in_port_name.clone();
                 let conn_in = midi_in.connect(in_port, "vince-midi-in", move |_, message, _| {
+                    session::record("midi", &[
+                        ("port", session::Field::Str(&port_name)),
+                        ("bytes", session::Field::Bytes(message)),
+                    ]);
+
+                    // The live input is replaced by the recorded input while
+                    // replaying
+                    if replay::is_replaying() {
+                        return;
+                    }
+                    replay::record_midi(id, message);
+
                     let event = LiveEvent::parse(message)
                         .unwrap_or_else(|msg| panic!("Failed to parse MIDI event: {:?}: {}", message, msg));
                     match event {
                         LiveEvent::Midi { channel, message } => {
+                            if !is_channel_enabled(&channels, channel) {
+                                return;
+                            }
                             if let Ok(mut events) = events.try_lock() {
                                 events.push_back((channel, message));
                             }
@@ -135,6 +326,10 @@ impl Module for MidiIn {
                 midi_in.ignore(midir::Ignore::None);
             }
 
+            if let (Some(port), true) = (&self.port, self.midi_context.ports_names_conns.is_empty()) {
+                warn!("M{id} MidiIn found no MIDI ports matching {port}");
+            }
+
             self.controllers.insert(u7::from(1), u7::from(0));
             self.controllers.insert(u7::from(2), u7::from(0));
             self.controllers.insert(u7::from(3), u7::from(0));
@@ -145,6 +340,18 @@ impl Module for MidiIn {
             self.controllers.insert(u7::from(8), u7::from(0));
         }
     }
+    fn init_state(&mut self, _id: usize) {
+        if let Some(c) = self.channels.iter().find(|c| !(1..=16).contains(*c)) {
+            panic!("MidiIn channel {c} must be in the range [1, 16]");
+        }
+
+        if let Some(mpe) = &self.mpe {
+            if mpe.voices == 0 {
+                panic!("MidiIn MPE must have at least 1 voice");
+            }
+            self.mpe_voices = vec![None; mpe.voices];
+        }
+    }
     fn exit(&mut self) {
         self.id = None;
         self.component = None;
@@ -165,92 +372,122 @@ impl Module for MidiIn {
         0
     }
     fn outputs(&self) -> usize {
-        10
+        10 + self.mpe.as_ref().map_or(0, |mpe| mpe.voices * 4)
     }
     fn knobs(&self) -> usize {
         0
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/midi_in.rs")
+    }
 
     fn step(&mut self, _time: f64, st: StepType, _ins: &[f32]) -> Vec<f32> {
         if st == StepType::Video {
-            return vec![f32::NAN; 10];
+            return vec![f32::NAN; self.outputs()];
         }
 
-        if let Ok(mut events) = self.midi_context.events.try_lock() {
-            let (mut note_key, mut note_depth) = self.notes.pop()
-                .unwrap_or((u7::from(0), u7::from(0)));
-
-            if let Some((_channel, msg)) = events.pop_front() {
-                match msg {
-                    MidiMessage::NoteOff { key, vel: _ } => {
-                        if note_key == key {
-                            note_depth = u7::from(0);
-                        } else {
-                            self.notes
-                                .extract_if(|(k, _d)| *k == key)
-                                .last();
-                        }
-                    },
-                    MidiMessage::NoteOn { key, vel } => {
-                        if note_key > 0 && note_depth > 0 && note_key != key {
-                            self.notes.push((note_key, note_depth));
-                        }
-                        note_key = key;
-                        note_depth = vel;
-                    },
-                    MidiMessage::Controller { controller, value } => {
-                        *self.controllers.entry(controller)
-                            .or_insert(u7::from(0)) = value;
-                    },
-                    MidiMessage::PitchBend { bend } => {
-                        self.bend = bend.as_f32();
-                    },
-                    _ => info!("Unhandled MIDI message: {:?}", msg),
+        if let Some(id) = self.id {
+            for message in replay::take_midi(id) {
+                if let Ok(LiveEvent::Midi { channel, message }) = LiveEvent::parse(&message) {
+                    if !is_channel_enabled(&self.channels, channel) {
+                        continue;
+                    }
+                    if let Ok(mut events) = self.midi_context.events.lock() {
+                        events.push_back((channel, message));
+                    }
                 }
-            } else if st == StepType::Key {
-                // note_depth -= u7::from(1);
             }
+        }
 
-            let freq = if note_key > 0 && note_depth > 0 {
-                self.notes.push((note_key, note_depth));
-
-                2.0f32.powf((note_key.as_int() as i16 - 69) as f32 / 12.0 + self.bend) * 440.0
-            } else {
-                0.0
-            };
-
-            let u7max: f32 = u7::max_value().as_int() as f32;
-            vec![
-                freq,
-                note_depth.as_int() as f32 / u7max,
-
-                self.controllers.get(&u7::from(1))
-                    .unwrap_or(&u7::from(0))
-                    .as_int() as f32 / u7max,
-                self.controllers.get(&u7::from(2))
-                    .unwrap_or(&u7::from(0))
-                    .as_int() as f32 / u7max,
-                self.controllers.get(&u7::from(3))
-                    .unwrap_or(&u7::from(0))
-                    .as_int() as f32 / u7max,
-                self.controllers.get(&u7::from(4))
-                    .unwrap_or(&u7::from(0))
-                    .as_int() as f32 / u7max,
-                self.controllers.get(&u7::from(5))
-                    .unwrap_or(&u7::from(0))
-                    .as_int() as f32 / u7max,
-                self.controllers.get(&u7::from(6))
-                    .unwrap_or(&u7::from(0))
-                    .as_int() as f32 / u7max,
-                self.controllers.get(&u7::from(7))
-                    .unwrap_or(&u7::from(0))
-                    .as_int() as f32 / u7max,
-                self.controllers.get(&u7::from(8))
-                    .unwrap_or(&u7::from(0))
-                    .as_int() as f32 / u7max,
-            ]
-        } else {
-            vec![f32::NAN; 10]
+        // Release the queue before stepping the MPE voices, which borrow
+        // the whole module
+        let event = match self.midi_context.events.try_lock() {
+            Ok(mut events) => events.pop_front(),
+            Err(_) => return vec![f32::NAN; self.outputs()],
+        };
+
+        let (mut note_key, mut note_depth) = self.notes.pop()
+            .unwrap_or((u7::from(0), u7::from(0)));
+
+        if let Some((channel, msg)) = event {
+            // Messages on the master channel only affect the
+            // non-MPE outputs
+            if self.mpe.is_some() && channel != 0 {
+                self.step_mpe(channel, &msg);
+            }
+
+            match msg {
+                MidiMessage::NoteOff { key, vel: _ } => {
+                    if note_key == key {
+                        note_depth = u7::from(0);
+                    } else {
+                        self.notes
+                            .extract_if(|(k, _d)| *k == key)
+                            .last();
+                    }
+                },
+                MidiMessage::NoteOn { key, vel } => {
+                    if note_key > 0 && note_depth > 0 && note_key != key {
+                        self.notes.push((note_key, note_depth));
+                    }
+                    note_key = key;
+                    note_depth = vel;
+                },
+                MidiMessage::Controller { controller, value } => {
+                    *self.controllers.entry(controller)
+                        .or_insert(u7::from(0)) = value;
+                },
+                MidiMessage::PitchBend { bend } => {
+                    if self.mpe.is_none() || channel == 0 {
+                        self.bend = bend.as_f32();
+                    }
+                },
+                MidiMessage::ChannelAftertouch { .. } if self.mpe.is_some() => {},
+                _ => info!("Unhandled MIDI message: {:?}", msg),
+            }
+        } else if st == StepType::Key {
+            // note_depth -= u7::from(1);
         }
+
+        let freq = if note_key > 0 && note_depth > 0 {
+            self.notes.push((note_key, note_depth));
+
+            2.0f32.powf((note_key.as_int() as i16 - 69) as f32 / 12.0 + self.bend) * 440.0
+        } else {
+            0.0
+        };
+
+        let u7max: f32 = u7::max_value().as_int() as f32;
+        let mut outs = vec![
+            freq,
+            note_depth.as_int() as f32 / u7max,
+
+            self.controllers.get(&u7::from(1))
+                .unwrap_or(&u7::from(0))
+                .as_int() as f32 / u7max,
+            self.controllers.get(&u7::from(2))
+                .unwrap_or(&u7::from(0))
+                .as_int() as f32 / u7max,
+            self.controllers.get(&u7::from(3))
+                .unwrap_or(&u7::from(0))
+                .as_int() as f32 / u7max,
+            self.controllers.get(&u7::from(4))
+                .unwrap_or(&u7::from(0))
+                .as_int() as f32 / u7max,
+            self.controllers.get(&u7::from(5))
+                .unwrap_or(&u7::from(0))
+                .as_int() as f32 / u7max,
+            self.controllers.get(&u7::from(6))
+                .unwrap_or(&u7::from(0))
+                .as_int() as f32 / u7max,
+            self.controllers.get(&u7::from(7))
+                .unwrap_or(&u7::from(0))
+                .as_int() as f32 / u7max,
+            self.controllers.get(&u7::from(8))
+                .unwrap_or(&u7::from(0))
+                .as_int() as f32 / u7max,
+        ];
+        outs.extend(self.mpe_outputs());
+        outs
     }
 }
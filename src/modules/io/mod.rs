@@ -1,23 +1,45 @@
 /*!
-The following I/O modules are defined here: `AudioOut`, `AudioIn`,
-`CompositeVideoOut`, `ComponentVideoOut`, `VideoIn`, `FileEncoder`,
-`FileDecoder`, `MidiIn`
+The following I/O modules are defined here: `AudioOut`, `AudioOutMulti`,
+`AudioIn`, `LatencyTest`, `CompositeVideoOut`, `ComponentVideoOut`,
+`VideoWallOut`, `VideoIn`, `MotionIn`, `PoseIn`, `FileEncoder`, `FileDecoder`,
+`ImageIn`, `StreamOut`, `NetSend`, `NetReceive`, `MidiIn`, `SerialIn`,
+`PointerIn`, `PadIn`
 */
 
 pub mod audio_out;
+pub mod audio_out_multi;
 pub mod audio_in;
+pub mod latency_test;
 
 pub mod composite_video_out;
 pub mod component_video_out;
+pub mod video_wall_out;
 #[cfg(feature = "video_in")]
 pub mod video_in;
+#[cfg(feature = "video_in")]
+pub mod motion_in;
+#[cfg(feature = "pose")]
+pub mod pose_in;
 
 #[cfg(feature = "files")]
 pub mod file_encoder;
 #[cfg(feature = "files")]
 pub mod file_decoder;
+#[cfg(feature = "files")]
+pub mod image_in;
+
+#[cfg(feature = "stream")]
+pub mod stream_out;
+
+pub mod net_send;
+pub mod net_receive;
 
 #[cfg(feature = "midi")]
 pub mod midi_in;
 
+#[cfg(feature = "serial")]
+pub mod serial_in;
+
 pub mod keyboard_in;
+pub mod pointer_in;
+pub mod pad_in;
@@ -0,0 +1,303 @@
+/*!
+The `MotionIn` module detects movement in front of a webcam and outputs the
+amount of activity within each of a set of regions, so that body movement can
+be used to control a patch.
+
+Frames are captured from the camera given by `camera`, which defaults to 0, on
+a separate thread and reduced to a grid of brightness values the same size as
+the video bus. The activity of a region is the average difference in
+brightness between consecutive frames over the cells within it.
+
+Each region is given as a subarray of `[x, y, width, height]` within the
+`regions` array, where the values are fractions of the frame in the range
+[0.0, 1.0] measured from the top left. By default there is a single region
+which covers the whole frame.
+
+##### Note
+This module is only available when built with the `video_in` feature.
+
+## Inputs
+None
+
+## Outputs
+0..N. The activity of each region in the range [0.0, inf)
+
+##### Note
+Until the camera has produced two frames, the outputs will all be
+[f32::NAN].
+
+## Knobs
+0. Gain in the range [0.0, inf)
+1. Release time in seconds in the range [0.0, inf)
+
+*/
+
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use nokhwa::Camera;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MotionIn {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default)]
+    camera: u32,
+    #[serde(default = "default_regions")]
+    regions: Vec<[f32; 4]>,
+
+    #[serde(skip)]
+    activity: Arc<Mutex<Vec<f32>>>,
+    #[serde(skip)]
+    outs: Vec<f32>,
+    #[serde(skip)]
+    is_running: Arc<AtomicBool>,
+    #[serde(skip)]
+    status: Arc<Mutex<String>>,
+
+    knobs: [f32; 2],
+}
+fn default_regions() -> Vec<[f32; 4]> {
+    vec![[0.0, 0.0, 1.0, 1.0]]
+}
+impl MotionIn {
+    /// Get the range of grid cells covered by a region
+    fn region_cells(region: &[f32; 4]) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        let w = ComponentVideoOut::WIDTH as f32;
+        let h = ComponentVideoOut::HEIGHT as f32;
+
+        let x0 = (region[0].clamp(0.0, 1.0) * w) as usize;
+        let y0 = (region[1].clamp(0.0, 1.0) * h) as usize;
+        let x1 = ((region[0] + region[2]).clamp(0.0, 1.0) * w).ceil() as usize;
+        let y1 = ((region[1] + region[3]).clamp(0.0, 1.0) * h).ceil() as usize;
+
+        (x0..x1.max(x0 + 1).min(w as usize), y0..y1.max(y0 + 1).min(h as usize))
+    }
+    fn start(&mut self) {
+        nokhwa::nokhwa_initialize(|granted| {
+            if !granted {
+                panic!("Failed to acquire camera permissions for motion input");
+            }
+        });
+
+        let mut camera = match Camera::new(
+            nokhwa::utils::CameraIndex::Index(self.camera),
+            nokhwa::utils::RequestedFormat::new::<nokhwa::pixel_format::RgbFormat>(
+                nokhwa::utils::RequestedFormatType::AbsoluteHighestFrameRate,
+            ),
+        ).and_then(|mut c| c.open_stream().map(|_| c)) {
+            Ok(camera) => camera,
+            Err(e) => {
+                error!("Failed to open camera {} for motion input: {e}", self.camera);
+                if let Ok(mut status) = self.status.lock() {
+                    *status = format!("Error: {e}");
+                }
+                return;
+            },
+        };
+
+        self.activity = Arc::new(Mutex::new(vec![f32::NAN; self.regions.len()]));
+        self.is_running = Arc::new(AtomicBool::new(true));
+
+        let regions = self.regions.clone();
+        let activity = self.activity.clone();
+        let is_running = self.is_running.clone();
+        let status = self.status.clone();
+        std::thread::spawn(move || {
+            let w = ComponentVideoOut::WIDTH;
+            let h = ComponentVideoOut::HEIGHT;
+
+            if let Ok(mut status) = status.lock() {
+                *status = "Capturing".to_string();
+            }
+
+            let mut last_grid: Option<Vec<f32>> = None;
+            while is_running.load(Ordering::Acquire) {
+                let image = match camera.frame()
+                    .and_then(|f| f.decode_image::<nokhwa::pixel_format::RgbFormat>())
+                {
+                    Ok(image) => image,
+                    Err(e) => {
+                        error!("Failed to capture camera frame for motion input: {e}");
+                        if let Ok(mut status) = status.lock() {
+                            *status = format!("Error: {e}");
+                        }
+                        break;
+                    },
+                };
+
+                // Reduce the frame to a grid of brightness values
+                let (iw, ih) = (image.width() as usize, image.height() as usize);
+                if iw == 0 || ih == 0 {
+                    continue;
+                }
+                let grid: Vec<f32> = (0..w*h).map(|i| {
+                    let x = ((i % w) * iw + iw / 2) / w;
+                    let y = ((i / w) * ih + ih / 2) / h;
+                    let p = image.get_pixel(x as u32, y as u32);
+                    (0.2126 * f32::from(p[0]) + 0.7152 * f32::from(p[1]) + 0.0722 * f32::from(p[2])) / 255.0
+                }).collect();
+
+                if let Some(lg) = &last_grid {
+                    let acts: Vec<f32> = regions.iter()
+                        .map(|r| {
+                            let (xs, ys) = Self::region_cells(r);
+                            let count = xs.len() * ys.len();
+                            let sum: f32 = ys.flat_map(|y| xs.clone().map(move |x| y * w + x))
+                                .map(|i| (grid[i] - lg[i]).abs())
+                                .sum();
+                            sum / count as f32
+                        }).collect();
+                    if let Ok(mut activity) = activity.lock() {
+                        *activity = acts;
+                    }
+                }
+                last_grid = Some(grid);
+            }
+        });
+    }
+}
+#[typetag::deserialize]
+impl Module for MotionIn {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Motion In\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("Camera {}, {} regions\n", self.camera, self.regions.len()), ts.clone()),
+                            TextSection::new("Connecting\n", ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+
+        self.start();
+    }
+    fn init_state(&mut self, _id: usize) {
+        if self.regions.is_empty() {
+            panic!("MotionIn requires at least one region");
+        }
+
+        self.outs = vec![f32::NAN; self.regions.len()];
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.is_running.store(false, Ordering::Release);
+    }
+    fn is_replayable(&self) -> bool {
+        false
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        0
+    }
+    fn outputs(&self) -> usize {
+        self.regions.len()
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/motion_in.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, _ins: &[f32]) -> Vec<f32> {
+        let gain = self.knobs[0];
+        if st == StepType::Video {
+            return self.outs.iter()
+                .map(|o| o * gain)
+                .collect();
+        }
+
+        // Rise immediately and fall according to the release time
+        let sr = 44100.0;
+        let a = if self.knobs[1] > 0.0 {
+            1.0 - (-1.0 / (self.knobs[1] * sr)).exp()
+        } else {
+            1.0
+        };
+
+        if let Ok(activity) = self.activity.try_lock() {
+            for (o, v) in self.outs.iter_mut().zip(activity.iter()) {
+                *o = if o.is_nan() || v.is_nan() || *v > *o {
+                    *v
+                } else {
+                    *o + a * (v - *o)
+                };
+            }
+        }
+
+        self.outs.iter()
+            .map(|o| o * gain)
+            .collect()
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                if let Ok(status) = self.status.try_lock() {
+                    text.sections[2].value = format!("{status}\n");
+                }
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+            }
+        }
+    }
+}
@@ -0,0 +1,313 @@
+/*!
+The `NetReceive` module listens for signals from a `NetSend` module in another
+instance of Vince and outputs them.
+
+The module listens on the given `address` via either UDP or TCP, which can be
+set with `protocol`, and must match the sender's configuration. Received frames
+are held in a jitter buffer which is filled up to the latency set by knob 0
+before being output in order to smooth over network delays. If the buffer runs
+dry, it will be refilled before continuing. Packets which arrive out of order
+are dropped.
+
+## Inputs
+None
+
+## Outputs
+0..N. The received signals, where N is set by `channels` in the range [1, 8]
+
+##### Note
+While the jitter buffer is filling, the outputs will all be [f32::NAN].
+
+## Knobs
+0. Jitter buffer latency in milliseconds in the range [0.0, 1000.0]
+
+*/
+
+use std::{collections::VecDeque, io::Read, net::{TcpListener, UdpSocket}, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, time::Duration};
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, net_send::{NetProtocol, NET_MAX_CHANNELS, decode_packet}}};
+
+type JitterBuffer = Arc<Mutex<VecDeque<Vec<f32>>>>;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NetReceive {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    address: String,
+    #[serde(default)]
+    protocol: NetProtocol,
+    #[serde(default = "default_channels")]
+    channels: usize,
+
+    #[serde(skip)]
+    buffer: JitterBuffer,
+    #[serde(skip)]
+    is_running: Arc<AtomicBool>,
+    #[serde(skip)]
+    is_filling: bool,
+    #[serde(skip)]
+    status: Arc<Mutex<String>>,
+
+    knobs: [f32; 1],
+}
+fn default_channels() -> usize {
+    2
+}
+impl NetReceive {
+    const MAX_LATENCY: f32 = 1000.0;
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Push the frames of a packet into the jitter buffer, dropping any packets
+    /// which arrive out of order and any frames beyond the maximum latency
+    fn receive(packet: &[u8], last_seq: &mut Option<u32>, buffer: &JitterBuffer) {
+        let sr = 44100.0;
+        let max_frames = (Self::MAX_LATENCY / 1000.0 * sr) as usize;
+
+        if let Some((seq, frames)) = decode_packet(packet) {
+            if let Some(last) = *last_seq {
+                // Compare using wrapping arithmetic so the sequence can overflow
+                if seq.wrapping_sub(last) as i32 <= 0 {
+                    return;
+                }
+            }
+            *last_seq = Some(seq);
+
+            if let Ok(mut buffer) = buffer.lock() {
+                buffer.extend(frames);
+                let excess = buffer.len().saturating_sub(max_frames);
+                buffer.drain(..excess);
+            }
+        }
+    }
+    fn start(&mut self) {
+        self.is_running = Arc::new(AtomicBool::new(true));
+
+        let address = self.address.clone();
+        let protocol = self.protocol;
+        let buffer = self.buffer.clone();
+        let is_running = self.is_running.clone();
+        let status = self.status.clone();
+        std::thread::spawn(move || {
+            let set_status = |msg: String| {
+                if let Ok(mut status) = status.lock() {
+                    *status = msg;
+                }
+            };
+
+            let mut last_seq = None;
+            let mut packet = vec![0; u16::MAX as usize];
+            match protocol {
+                NetProtocol::Udp => {
+                    let socket = match UdpSocket::bind(&address) {
+                        Ok(socket) => socket,
+                        Err(e) => {
+                            error!("Failed to bind UDP socket to {address}: {e}");
+                            set_status(format!("Error: {e}"));
+                            return;
+                        },
+                    };
+                    let _ = socket.set_read_timeout(Some(Self::TIMEOUT));
+                    set_status("Listening".to_string());
+
+                    while is_running.load(Ordering::Acquire) {
+                        if let Ok(n) = socket.recv(&mut packet) {
+                            Self::receive(&packet[..n], &mut last_seq, &buffer);
+                        }
+                    }
+                },
+                NetProtocol::Tcp => {
+                    let listener = match TcpListener::bind(&address) {
+                        Ok(listener) => listener,
+                        Err(e) => {
+                            error!("Failed to bind TCP listener to {address}: {e}");
+                            set_status(format!("Error: {e}"));
+                            return;
+                        },
+                    };
+                    let _ = listener.set_nonblocking(true);
+                    set_status("Listening".to_string());
+
+                    while is_running.load(Ordering::Acquire) {
+                        let mut stream = match listener.accept() {
+                            Ok((stream, peer)) => {
+                                set_status(format!("Receiving from {peer}"));
+                                stream
+                            },
+                            Err(_) => {
+                                std::thread::sleep(Self::TIMEOUT);
+                                continue;
+                            },
+                        };
+                        let _ = stream.set_nonblocking(false);
+                        let _ = stream.set_read_timeout(Some(Self::TIMEOUT));
+
+                        // Each packet is prefixed by its length
+                        last_seq = None;
+                        let mut pending = vec![];
+                        while is_running.load(Ordering::Acquire) {
+                            match stream.read(&mut packet) {
+                                Ok(0) => break,
+                                Ok(n) => pending.extend(&packet[..n]),
+                                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => continue,
+                                Err(_) => break,
+                            }
+
+                            while pending.len() >= 4 {
+                                let len = u32::from_le_bytes([pending[0], pending[1], pending[2], pending[3]]) as usize;
+                                if pending.len() < 4 + len {
+                                    break;
+                                }
+                                Self::receive(&pending[4..4 + len], &mut last_seq, &buffer);
+                                pending.drain(..4 + len);
+                            }
+                        }
+                        set_status("Listening".to_string());
+                    }
+                },
+            }
+        });
+    }
+}
+#[typetag::deserialize]
+impl Module for NetReceive {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Net Receive\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("{:?} {}\n", self.protocol, self.address), ts.clone()),
+                            TextSection::new("Connecting\n", ts.clone()),
+                            TextSection::new("K0\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+
+        self.start();
+    }
+    fn init_state(&mut self, _id: usize) {
+        if self.channels == 0 || self.channels > NET_MAX_CHANNELS {
+            panic!("Invalid channel count for NetReceive: {} must be in the range [1, {}]", self.channels, NET_MAX_CHANNELS);
+        }
+
+        self.is_filling = true;
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.is_running.store(false, Ordering::Release);
+        if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.clear();
+        }
+    }
+    fn is_replayable(&self) -> bool {
+        false
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        0
+    }
+    fn outputs(&self) -> usize {
+        self.channels
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/net_receive.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, _ins: &[f32]) -> Vec<f32> {
+        if st == StepType::Video {
+            return vec![f32::NAN; self.channels];
+        }
+
+        let sr = 44100.0;
+        let latency = (self.knobs[0].clamp(0.0, Self::MAX_LATENCY) / 1000.0 * sr) as usize;
+
+        let Ok(mut buffer) = self.buffer.try_lock() else {
+            return vec![f32::NAN; self.channels];
+        };
+        if self.is_filling {
+            if buffer.len() < latency.max(1) {
+                return vec![f32::NAN; self.channels];
+            }
+            self.is_filling = false;
+        }
+
+        match buffer.pop_front() {
+            Some(mut frame) => {
+                frame.resize(self.channels, f32::NAN);
+                frame
+            },
+            None => {
+                self.is_filling = true;
+                vec![f32::NAN; self.channels]
+            },
+        }
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                if let Ok(status) = self.status.try_lock() {
+                    text.sections[2].value = format!("{status}\n");
+                }
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+/*!
+The `NetSend` module takes a configurable number of inputs and streams them to
+a `NetReceive` module in another instance of Vince over the network.
+
+The inputs are sampled during every audio step and sent in small packets to
+the given `address` via either UDP or TCP, which can be set with `protocol`.
+UDP has lower latency but packets may be lost, in which case the receiver will
+skip ahead. TCP will automatically reconnect if the connection is lost.
+
+## Inputs
+0..N. The signals to send, where N is set by `channels` in the range [1, 8]
+
+## Outputs
+None
+
+## Knobs
+None
+
+*/
+
+use std::{io::Write, net::{TcpStream, UdpSocket}, sync::{Arc, Mutex, mpsc}, time::Duration};
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+pub(crate) const NET_MAGIC: &[u8; 4] = b"VNCE";
+pub(crate) const NET_HEADER_SIZE: usize = 11;
+pub(crate) const NET_MAX_CHANNELS: usize = 8;
+
+#[derive(Default, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum NetProtocol {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+/// Encode a packet of interleaved frames as the magic bytes, the sequence
+/// number, the channel count, the frame count, and then the samples
+pub(crate) fn encode_packet(seq: u32, channels: usize, samples: &[f32]) -> Vec<u8> {
+    let frames = (samples.len() / channels) as u16;
+    let mut packet = Vec::with_capacity(NET_HEADER_SIZE + samples.len() * 4);
+    packet.extend(NET_MAGIC);
+    packet.extend(seq.to_le_bytes());
+    packet.push(channels as u8);
+    packet.extend(frames.to_le_bytes());
+    for s in samples {
+        packet.extend(s.to_le_bytes());
+    }
+    packet
+}
+/// Decode a packet into its sequence number and frames, returning `None` if
+/// the packet is malformed
+pub(crate) fn decode_packet(packet: &[u8]) -> Option<(u32, Vec<Vec<f32>>)> {
+    if packet.len() < NET_HEADER_SIZE || &packet[0..4] != NET_MAGIC {
+        return None;
+    }
+
+    let seq = u32::from_le_bytes(packet[4..8].try_into().ok()?);
+    let channels = usize::from(packet[8]);
+    let frames = usize::from(u16::from_le_bytes(packet[9..11].try_into().ok()?));
+    if channels == 0 || packet.len() != NET_HEADER_SIZE + channels * frames * 4 {
+        return None;
+    }
+
+    let samples: Vec<f32> = packet[NET_HEADER_SIZE..].chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    Some((
+        seq,
+        samples.chunks(channels)
+            .map(|f| f.to_vec())
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NetSend {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    address: String,
+    #[serde(default)]
+    protocol: NetProtocol,
+    #[serde(default = "default_channels")]
+    channels: usize,
+
+    #[serde(skip)]
+    sender: Option<mpsc::Sender<Vec<u8>>>,
+    #[serde(skip)]
+    status: Arc<Mutex<String>>,
+    #[serde(skip)]
+    buffer: Vec<f32>,
+    #[serde(skip)]
+    seq: u32,
+}
+fn default_channels() -> usize {
+    2
+}
+impl NetSend {
+    const FRAMES_PER_PACKET: usize = 64;
+
+    fn start(&mut self) {
+        let (tx, rx) = mpsc::channel::<Vec<u8>>();
+        self.sender = Some(tx);
+
+        let address = self.address.clone();
+        let protocol = self.protocol;
+        let status = self.status.clone();
+        std::thread::spawn(move || {
+            let set_status = |msg: String| {
+                if let Ok(mut status) = status.lock() {
+                    *status = msg;
+                }
+            };
+
+            match protocol {
+                NetProtocol::Udp => {
+                    let socket = match UdpSocket::bind("0.0.0.0:0").and_then(|s| s.connect(&address).map(|_| s)) {
+                        Ok(socket) => socket,
+                        Err(e) => {
+                            error!("Failed to open UDP socket to {address}: {e}");
+                            set_status(format!("Error: {e}"));
+                            return;
+                        },
+                    };
+                    set_status("Sending".to_string());
+
+                    for packet in rx {
+                        // Lost packets are skipped by the receiver
+                        let _ = socket.send(&packet);
+                    }
+                },
+                NetProtocol::Tcp => {
+                    let mut stream: Option<TcpStream> = None;
+                    for packet in rx {
+                        if stream.is_none() {
+                            match TcpStream::connect(&address) {
+                                Ok(s) => {
+                                    let _ = s.set_nodelay(true);
+                                    stream = Some(s);
+                                    set_status("Sending".to_string());
+                                },
+                                Err(e) => {
+                                    set_status(format!("Reconnecting: {e}"));
+                                    std::thread::sleep(Duration::from_secs(1));
+                                    continue;
+                                },
+                            }
+                        }
+
+                        if let Some(s) = &mut stream {
+                            let len = (packet.len() as u32).to_le_bytes();
+                            if s.write_all(&len).and_then(|_| s.write_all(&packet)).is_err() {
+                                stream = None;
+                            }
+                        }
+                    }
+                },
+            }
+
+            set_status("Stopped".to_string());
+        });
+    }
+}
+#[typetag::deserialize]
+impl Module for NetSend {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Net Send\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("{:?} {}\n", self.protocol, self.address), ts.clone()),
+                            TextSection::new("Connecting\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+
+        self.start();
+    }
+    fn init_state(&mut self, _id: usize) {
+        if self.channels == 0 || self.channels > NET_MAX_CHANNELS {
+            panic!("Invalid channel count for NetSend: {} must be in the range [1, {}]", self.channels, NET_MAX_CHANNELS);
+        }
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        // Dropping the sender stops the network thread
+        self.sender = None;
+        self.buffer = vec![];
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        self.channels
+    }
+    fn outputs(&self) -> usize {
+        0
+    }
+    fn knobs(&self) -> usize {
+        0
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/net_send.rs")
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        if st == StepType::Video {
+            return vec![];
+        }
+
+        self.buffer.extend(ins);
+        if self.buffer.len() >= Self::FRAMES_PER_PACKET * self.channels {
+            let packet = encode_packet(self.seq, self.channels, &self.buffer);
+            self.seq = self.seq.wrapping_add(1);
+            self.buffer.clear();
+
+            if let Some(sender) = &self.sender {
+                if sender.send(packet).is_err() {
+                    self.sender = None;
+                }
+            }
+        }
+
+        vec![]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                if let Ok(status) = self.status.try_lock() {
+                    text.sections[2].value = format!("{status}\n");
+                }
+            }
+        }
+    }
+}
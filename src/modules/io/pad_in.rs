@@ -0,0 +1,271 @@
+/*!
+The `PadIn` module maps a set of computer keyboard keys to drum pads, each of
+which outputs a trigger signal and a velocity signal. Unlike `KeyboardIn`, every
+pad is independent so several may be struck at once.
+
+The keys are given by `keys` as an array of key names, such as letters `"A"`,
+digits `"1"`, or `"Space"`, and default to the two rows `Q W E R` and
+`A S D F`. The velocity is set by `velocity`.
+
+## Velocities
+ * `Fixed` - every hit has the velocity given by knob 0, the default
+ * `Timing` - hits have the velocity given by knob 0 scaled by how quickly the
+   pad is struck again, where any repeat faster than the window given by knob
+   1 is at full velocity and slower repeats are proportionally softer
+
+## Inputs
+None
+
+## Outputs
+0. The first pad's attack/release behavior according to the below table:
+   * If just struck this frame: 1.0
+   * If just released this frame: -1.0
+   * Otherwise: 0.0
+1. The first pad's velocity, held until the next hit
+...
+2N. The Nth pad's attack/release behavior
+2N+1. The Nth pad's velocity
+
+## Knobs
+0. Velocity in the range [0.0, 1.0]
+1. Timing window in seconds in the range (0.0, inf)
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Default, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+enum PadVelocity {
+    #[default]
+    Fixed,
+    Timing,
+}
+
+#[derive(Default, Debug, Copy, Clone)]
+struct Pad {
+    key: Option<KeyCode>,
+    is_pressed: bool,
+    is_struck: bool,
+    is_released: bool,
+    velocity: f32,
+    last_hit: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PadIn {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default = "default_keys")]
+    keys: Vec<String>,
+    #[serde(default)]
+    velocity: PadVelocity,
+
+    #[serde(skip)]
+    pads: Vec<Pad>,
+
+    knobs: [f32; 2],
+}
+fn default_keys() -> Vec<String> {
+    ["Q", "W", "E", "R", "A", "S", "D", "F"].into_iter()
+        .map(String::from)
+        .collect()
+}
+impl PadIn {
+    /// Parse the name of a key into its key code
+    fn parse_key(name: &str) -> Option<KeyCode> {
+        let key = match name.to_uppercase().as_str() {
+            "A" => KeyCode::A, "B" => KeyCode::B, "C" => KeyCode::C, "D" => KeyCode::D,
+            "E" => KeyCode::E, "F" => KeyCode::F, "G" => KeyCode::G, "H" => KeyCode::H,
+            "I" => KeyCode::I, "J" => KeyCode::J, "K" => KeyCode::K, "L" => KeyCode::L,
+            "M" => KeyCode::M, "N" => KeyCode::N, "O" => KeyCode::O, "P" => KeyCode::P,
+            "Q" => KeyCode::Q, "R" => KeyCode::R, "S" => KeyCode::S, "T" => KeyCode::T,
+            "U" => KeyCode::U, "V" => KeyCode::V, "W" => KeyCode::W, "X" => KeyCode::X,
+            "Y" => KeyCode::Y, "Z" => KeyCode::Z,
+
+            "0" => KeyCode::Key0, "1" => KeyCode::Key1, "2" => KeyCode::Key2,
+            "3" => KeyCode::Key3, "4" => KeyCode::Key4, "5" => KeyCode::Key5,
+            "6" => KeyCode::Key6, "7" => KeyCode::Key7, "8" => KeyCode::Key8,
+            "9" => KeyCode::Key9,
+
+            "," | "COMMA" => KeyCode::Comma,
+            "." | "PERIOD" => KeyCode::Period,
+            "/" | "SLASH" => KeyCode::Slash,
+            ";" | "SEMICOLON" => KeyCode::Semicolon,
+            "'" | "APOSTROPHE" => KeyCode::Apostrophe,
+            "[" | "BRACKETLEFT" => KeyCode::BracketLeft,
+            "]" | "BRACKETRIGHT" => KeyCode::BracketRight,
+            "-" | "MINUS" => KeyCode::Minus,
+            "=" | "EQUALS" => KeyCode::Equals,
+            "SPACE" => KeyCode::Space,
+
+            _ => return None,
+        };
+        Some(key)
+    }
+}
+#[typetag::deserialize]
+impl Module for PadIn {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Pad In\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("{:?}: {}\n", self.velocity, self.keys.join(" ")), ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, _id: usize) {
+        self.pads = self.keys.iter()
+            .map(|k| {
+                let key = Self::parse_key(k);
+                if key.is_none() {
+                    error!("Unknown key for PadIn: {k}");
+                }
+                Pad {
+                    key,
+                    ..default()
+                }
+            }).collect();
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.pads = vec![];
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        0
+    }
+    fn outputs(&self) -> usize {
+        2 * self.keys.len()
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/pad_in.rs")
+    }
+    fn is_focusable(&self) -> bool {
+        true
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn keyboard_input(&mut self, keys: &Res<Input<KeyCode>>) {
+        for pad in &mut self.pads {
+            if let Some(key) = pad.key {
+                if keys.just_pressed(key) {
+                    pad.is_struck = true;
+                    pad.is_released = false;
+                } else if keys.just_released(key) {
+                    pad.is_released = true;
+                }
+                pad.is_pressed = keys.pressed(key);
+            }
+        }
+    }
+    fn step(&mut self, time: f64, st: StepType, _ins: &[f32]) -> Vec<f32> {
+        let is_audio = st != StepType::Video;
+        let velocity = self.knobs[0].clamp(0.0, 1.0);
+        let window = self.knobs[1];
+
+        let mut outs = Vec::with_capacity(2 * self.pads.len());
+        for pad in &mut self.pads {
+            let asr = if is_audio && pad.is_struck {
+                pad.is_struck = false;
+                pad.velocity = match (self.velocity, pad.last_hit) {
+                    (PadVelocity::Timing, Some(lh)) if window > 0.0 => {
+                        let interval = (time - lh) as f32;
+                        velocity * (window / interval).min(1.0)
+                    },
+                    _ => velocity,
+                };
+                pad.last_hit = Some(time);
+                1.0
+            } else if is_audio && pad.is_released && !pad.is_struck {
+                pad.is_released = false;
+                -1.0
+            } else {
+                0.0
+            };
+
+            outs.push(asr);
+            outs.push(pad.velocity);
+        }
+        outs
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                let held: Vec<&str> = self.keys.iter()
+                    .zip(&self.pads)
+                    .filter(|(_, p)| p.is_pressed)
+                    .map(|(k, _)| k.as_str())
+                    .collect();
+                text.sections[1].value = format!("{:?}: {}\n", self.velocity, if held.is_empty() {
+                    self.keys.join(" ")
+                } else {
+                    format!("[{}]", held.join(" "))
+                });
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+/*!
+The `PointerIn` module outputs the position of the mouse cursor within the
+primary window along with its buttons, so that the mouse or a pen tablet can be
+used as a performance controller.
+
+When a touch or stylus is active, its position is used instead of the cursor
+and its pressure and tilt are output where the platform reports them.
+
+## Inputs
+None
+
+## Outputs
+0. The X position in the range [0.0, 1.0] from left to right
+1. The Y position in the range [0.0, 1.0] from bottom to top
+2. The left button gate, either 0.0 or 1.0
+3. The right button gate, either 0.0 or 1.0
+4. The middle button gate, either 0.0 or 1.0
+5. The pressure in the range [0.0, 1.0]
+6. The tilt in the range [0.0, 1.0] from upright to flat
+
+##### Note
+If the cursor leaves the window, its last position will be held.
+
+##### Note
+Without a pressure-sensitive device, the pressure will follow the left button
+gate and the tilt will be [f32::NAN].
+
+## Knobs
+None
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, input::touch::ForceTouch};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PointerIn {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    window_size: Vec2,
+    #[serde(skip)]
+    pos: Vec2,
+    #[serde(skip)]
+    buttons: [bool; 3],
+    #[serde(skip)]
+    touch: Option<(Vec2, Option<f32>, Option<f32>)>,
+}
+#[typetag::deserialize]
+impl Module for PointerIn {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Pointer In\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.buttons = [false; 3];
+        self.touch = None;
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        0
+    }
+    fn outputs(&self) -> usize {
+        7
+    }
+    fn knobs(&self) -> usize {
+        0
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/pointer_in.rs")
+    }
+
+    fn mouse_input(&mut self, mouse_buttons: &Res<Input<MouseButton>>, window: &Window, _q_child: &Query<&Parent, With<ModuleComponent>>, _q_transform: &Query<&GlobalTransform>) {
+        self.window_size = Vec2::new(window.width(), window.height());
+        if let Some(mpos) = window.cursor_position() {
+            self.pos = mpos;
+        }
+
+        self.buttons = [
+            mouse_buttons.pressed(MouseButton::Left),
+            mouse_buttons.pressed(MouseButton::Right),
+            mouse_buttons.pressed(MouseButton::Middle),
+        ];
+    }
+    fn touch_input(&mut self, touches: &Res<Touches>) {
+        self.touch = touches.iter()
+            .next()
+            .map(|touch| {
+                let (pressure, tilt) = match touch.force() {
+                    Some(ForceTouch::Calibrated { force, max_possible_force, altitude_angle }) => {
+                        let pressure = (force / max_possible_force) as f32;
+                        let tilt = altitude_angle.map(|a| 1.0 - (a / std::f64::consts::FRAC_PI_2) as f32);
+                        (Some(pressure), tilt)
+                    },
+                    Some(ForceTouch::Normalized(force)) => (Some(force as f32), None),
+                    None => (None, None),
+                };
+                (touch.position(), pressure, tilt)
+            });
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, _ins: &[f32]) -> Vec<f32> {
+        let (pos, pressure, tilt) = match self.touch {
+            Some((pos, pressure, tilt)) => (pos, pressure.unwrap_or(1.0), tilt),
+            None => {
+                let pressure = if self.buttons[0] {
+                    1.0
+                } else {
+                    0.0
+                };
+                (self.pos, pressure, None)
+            },
+        };
+
+        let (x, y) = if self.window_size.x > 0.0 && self.window_size.y > 0.0 {
+            (
+                (pos.x / self.window_size.x).clamp(0.0, 1.0),
+                (1.0 - pos.y / self.window_size.y).clamp(0.0, 1.0),
+            )
+        } else {
+            (0.0, 0.0)
+        };
+
+        let gate = |b: bool| if b {
+            1.0
+        } else {
+            0.0
+        };
+        vec![
+            x,
+            y,
+            gate(self.buttons[0] || self.touch.is_some()),
+            gate(self.buttons[1]),
+            gate(self.buttons[2]),
+            pressure.clamp(0.0, 1.0),
+            tilt.map(|t| t.clamp(0.0, 1.0)).unwrap_or(f32::NAN),
+        ]
+    }
+}
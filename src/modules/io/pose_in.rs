@@ -0,0 +1,341 @@
+/*!
+The `PoseIn` module tracks a hand or body in front of a webcam with a landmark
+model and outputs the coordinates of each landmark, so that gestures can be
+used to control a patch.
+
+Frames are captured from the camera given by `camera`, which defaults to 0, on
+a separate thread and passed through the ONNX model given by `model`. The model
+should be a MediaPipe-style landmark model which takes a single RGB image with
+values in the range [0.0, 1.0] and whose first output contains `landmarks`
+groups of `[x, y, z]` coordinates in pixels of the input image, such as the
+hand landmark model with 21 landmarks, the default.
+
+## Model Configuration
+ * `input_size` - the width and height of the model's input image, which
+   defaults to 224
+ * `layout` - either `Nhwc`, the default, or `Nchw` depending on whether the
+   model expects its color channels last or first
+
+##### Note
+This module is only available when built with the `pose` feature.
+
+## Inputs
+None
+
+## Outputs
+0. The first landmark's X position in the range [0.0, 1.0] from left to right
+1. The first landmark's Y position in the range [0.0, 1.0] from bottom to top
+...
+2N. The Nth landmark's X position
+2N+1. The Nth landmark's Y position
+
+##### Note
+Until the model has produced its first result, the outputs will all be
+[f32::NAN].
+
+## Knobs
+0. Smoothing time in seconds in the range [0.0, inf)
+
+*/
+
+use std::{path::Path, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}};
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use nokhwa::Camera;
+use tract_onnx::prelude::*;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Default, Deserialize, Debug, Copy, Clone)]
+enum TensorLayout {
+    #[default]
+    Nhwc,
+    Nchw,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PoseIn {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    model: String,
+    #[serde(default)]
+    camera: u32,
+    #[serde(default = "default_landmarks")]
+    landmarks: usize,
+    #[serde(default = "default_input_size")]
+    input_size: usize,
+    #[serde(default)]
+    layout: TensorLayout,
+
+    #[serde(skip)]
+    positions: Arc<Mutex<Vec<f32>>>,
+    #[serde(skip)]
+    outs: Vec<f32>,
+    #[serde(skip)]
+    is_running: Arc<AtomicBool>,
+    #[serde(skip)]
+    status: Arc<Mutex<String>>,
+
+    knobs: [f32; 1],
+}
+fn default_landmarks() -> usize {
+    21
+}
+fn default_input_size() -> usize {
+    224
+}
+impl PoseIn {
+    fn start(&mut self) {
+        let path = Path::new(&self.model);
+        let size = self.input_size;
+        let shape: [usize; 4] = match self.layout {
+            TensorLayout::Nhwc => [1, size, size, 3],
+            TensorLayout::Nchw => [1, 3, size, size],
+        };
+        let model = match tract_onnx::onnx()
+            .model_for_path(path)
+            .and_then(|m| m.with_input_fact(0, f32::fact(shape).into()))
+            .and_then(|m| m.into_optimized())
+            .and_then(|m| m.into_runnable())
+        {
+            Ok(model) => model,
+            Err(e) => {
+                error!("Failed to load pose model {}: {e}", path.display());
+                if let Ok(mut status) = self.status.lock() {
+                    *status = format!("Error: {e}");
+                }
+                return;
+            },
+        };
+
+        nokhwa::nokhwa_initialize(|granted| {
+            if !granted {
+                panic!("Failed to acquire camera permissions for pose input");
+            }
+        });
+        let mut camera = match Camera::new(
+            nokhwa::utils::CameraIndex::Index(self.camera),
+            nokhwa::utils::RequestedFormat::new::<nokhwa::pixel_format::RgbFormat>(
+                nokhwa::utils::RequestedFormatType::AbsoluteHighestFrameRate,
+            ),
+        ).and_then(|mut c| c.open_stream().map(|_| c)) {
+            Ok(camera) => camera,
+            Err(e) => {
+                error!("Failed to open camera {} for pose input: {e}", self.camera);
+                if let Ok(mut status) = self.status.lock() {
+                    *status = format!("Error: {e}");
+                }
+                return;
+            },
+        };
+
+        self.positions = Arc::new(Mutex::new(vec![f32::NAN; 2 * self.landmarks]));
+        self.is_running = Arc::new(AtomicBool::new(true));
+
+        let landmarks = self.landmarks;
+        let layout = self.layout;
+        let positions = self.positions.clone();
+        let is_running = self.is_running.clone();
+        let status = self.status.clone();
+        std::thread::spawn(move || {
+            let set_status = |msg: String| {
+                if let Ok(mut status) = status.lock() {
+                    *status = msg;
+                }
+            };
+            set_status("Tracking".to_string());
+
+            while is_running.load(Ordering::Acquire) {
+                let image = match camera.frame()
+                    .and_then(|f| f.decode_image::<nokhwa::pixel_format::RgbFormat>())
+                {
+                    Ok(image) => image,
+                    Err(e) => {
+                        error!("Failed to capture camera frame for pose input: {e}");
+                        set_status(format!("Error: {e}"));
+                        break;
+                    },
+                };
+                let (iw, ih) = (image.width() as usize, image.height() as usize);
+                if iw == 0 || ih == 0 {
+                    continue;
+                }
+
+                // Scale the frame to the model's input size
+                let pixel = |x: usize, y: usize, c: usize| {
+                    let p = image.get_pixel((x * iw / size) as u32, (y * ih / size) as u32);
+                    f32::from(p[c]) / 255.0
+                };
+                let input: Tensor = match layout {
+                    TensorLayout::Nhwc => tract_ndarray::Array4::from_shape_fn((1, size, size, 3), |(_, y, x, c)| pixel(x, y, c)).into(),
+                    TensorLayout::Nchw => tract_ndarray::Array4::from_shape_fn((1, 3, size, size), |(_, c, y, x)| pixel(x, y, c)).into(),
+                };
+
+                let result = match model.run(tvec!(input.into())) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        error!("Failed to run pose model: {e}");
+                        set_status(format!("Error: {e}"));
+                        break;
+                    },
+                };
+                let Ok(coords) = result[0].as_slice::<f32>() else {
+                    set_status("Error: Invalid model output".to_string());
+                    break;
+                };
+                if coords.len() < 3 * landmarks {
+                    error!("Pose model output has {} values but {} landmarks require {}", coords.len(), landmarks, 3 * landmarks);
+                    set_status("Error: Too few landmarks".to_string());
+                    break;
+                }
+
+                let ps: Vec<f32> = coords.chunks_exact(3)
+                    .take(landmarks)
+                    .flat_map(|c| [
+                        (c[0] / size as f32).clamp(0.0, 1.0),
+                        (1.0 - c[1] / size as f32).clamp(0.0, 1.0),
+                    ]).collect();
+                if let Ok(mut positions) = positions.lock() {
+                    *positions = ps;
+                }
+            }
+        });
+    }
+}
+#[typetag::deserialize]
+impl Module for PoseIn {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Pose In\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("{}, {} landmarks\n", self.model, self.landmarks), ts.clone()),
+                            TextSection::new("Loading\n", ts.clone()),
+                            TextSection::new("K0\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+
+        self.start();
+    }
+    fn init_state(&mut self, _id: usize) {
+        if self.landmarks == 0 {
+            panic!("PoseIn requires at least one landmark");
+        }
+        if self.input_size == 0 {
+            panic!("Invalid input size for PoseIn: {}", self.input_size);
+        }
+
+        self.outs = vec![f32::NAN; 2 * self.landmarks];
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.is_running.store(false, Ordering::Release);
+    }
+    fn is_replayable(&self) -> bool {
+        false
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        0
+    }
+    fn outputs(&self) -> usize {
+        2 * self.landmarks
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/pose_in.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, _ins: &[f32]) -> Vec<f32> {
+        if st == StepType::Video {
+            return self.outs.clone();
+        }
+
+        let sr = 44100.0;
+        let a = if self.knobs[0] > 0.0 {
+            1.0 - (-1.0 / (self.knobs[0] * sr)).exp()
+        } else {
+            1.0
+        };
+
+        if let Ok(positions) = self.positions.try_lock() {
+            for (o, v) in self.outs.iter_mut().zip(positions.iter()) {
+                *o = if o.is_nan() || v.is_nan() {
+                    *v
+                } else {
+                    *o + a * (v - *o)
+                };
+            }
+        }
+
+        self.outs.clone()
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                if let Ok(status) = self.status.try_lock() {
+                    text.sections[2].value = format!("{status}\n");
+                }
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+            }
+        }
+    }
+}
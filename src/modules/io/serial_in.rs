@@ -0,0 +1,308 @@
+/*!
+The `SerialIn` module reads values from a serial port, such as an Arduino or
+another microcontroller, and outputs them as signals.
+
+The serial port is opened at the given `port` and `baud_rate`, which defaults
+to 9600, and is read on a separate thread. The most recent values are held
+until new ones arrive.
+
+## Formats
+ * `Text` - lines of numbers separated by commas or whitespace, e.g.
+   `512,0.25,1`, the default
+ * `Binary` - frames starting with the sync bytes `0xA5 0x5A` followed by a
+   little-endian 32-bit float for each channel
+
+##### Note
+This module is only available when built with the `serial` feature.
+
+## Inputs
+None
+
+## Outputs
+0..N. The received values, where N is set by `channels` in the range [1, 16]
+
+##### Note
+Until a value has been received for a channel, its output will be
+[f32::NAN].
+
+## Knobs
+0. Smoothing time in seconds in the range [0.0, inf)
+
+*/
+
+use std::{io::Read, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, time::Duration};
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Default, Deserialize, Debug, Copy, Clone)]
+enum SerialFormat {
+    #[default]
+    Text,
+    Binary,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SerialIn {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    port: String,
+    #[serde(default = "default_baud_rate")]
+    baud_rate: u32,
+    #[serde(default)]
+    format: SerialFormat,
+    #[serde(default = "default_channels")]
+    channels: usize,
+
+    #[serde(skip)]
+    values: Arc<Mutex<Vec<f32>>>,
+    #[serde(skip)]
+    outs: Vec<f32>,
+    #[serde(skip)]
+    is_running: Arc<AtomicBool>,
+    #[serde(skip)]
+    status: Arc<Mutex<String>>,
+
+    knobs: [f32; 1],
+}
+fn default_baud_rate() -> u32 {
+    9600
+}
+fn default_channels() -> usize {
+    1
+}
+impl SerialIn {
+    const MAX_CHANNELS: usize = 16;
+    const SYNC: [u8; 2] = [0xa5, 0x5a];
+
+    /// Parse all complete lines or frames from the pending bytes
+    fn parse(format: SerialFormat, channels: usize, pending: &mut Vec<u8>, values: &Mutex<Vec<f32>>) {
+        match format {
+            SerialFormat::Text => {
+                while let Some(i) = pending.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = pending.drain(..=i).collect();
+                    let line = String::from_utf8_lossy(&line);
+                    let vs: Vec<f32> = line.split(|c: char| c == ',' || c.is_whitespace())
+                        .filter(|v| !v.is_empty())
+                        .filter_map(|v| v.parse().ok())
+                        .collect();
+
+                    if let Ok(mut values) = values.lock() {
+                        for (o, v) in values.iter_mut().zip(vs) {
+                            *o = v;
+                        }
+                    }
+                }
+            },
+            SerialFormat::Binary => {
+                let frame_size = Self::SYNC.len() + 4 * channels;
+                loop {
+                    // Skip ahead to the next sync bytes
+                    match pending.windows(2).position(|w| *w == Self::SYNC) {
+                        Some(i) => {
+                            pending.drain(..i);
+                        },
+                        None => {
+                            let keep = pending.len().min(1);
+                            pending.drain(..pending.len() - keep);
+                            break;
+                        },
+                    }
+                    if pending.len() < frame_size {
+                        break;
+                    }
+
+                    let frame: Vec<u8> = pending.drain(..frame_size).collect();
+                    if let Ok(mut values) = values.lock() {
+                        for (o, b) in values.iter_mut().zip(frame[Self::SYNC.len()..].chunks_exact(4)) {
+                            *o = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+                        }
+                    }
+                }
+            },
+        }
+    }
+    fn start(&mut self) {
+        self.values = Arc::new(Mutex::new(vec![f32::NAN; self.channels]));
+        self.is_running = Arc::new(AtomicBool::new(true));
+
+        let port = self.port.clone();
+        let baud_rate = self.baud_rate;
+        let format = self.format;
+        let channels = self.channels;
+        let values = self.values.clone();
+        let is_running = self.is_running.clone();
+        let status = self.status.clone();
+        std::thread::spawn(move || {
+            let set_status = |msg: String| {
+                if let Ok(mut status) = status.lock() {
+                    *status = msg;
+                }
+            };
+
+            let mut serial = match serialport::new(&port, baud_rate)
+                .timeout(Duration::from_millis(100))
+                .open()
+            {
+                Ok(serial) => serial,
+                Err(e) => {
+                    error!("Failed to open serial port {port}: {e}");
+                    set_status(format!("Error: {e}"));
+                    return;
+                },
+            };
+            set_status(format!("{baud_rate} baud"));
+
+            let mut buffer = [0; 1024];
+            let mut pending = vec![];
+            while is_running.load(Ordering::Acquire) {
+                match serial.read(&mut buffer) {
+                    Ok(n) => {
+                        pending.extend(&buffer[..n]);
+                        Self::parse(format, channels, &mut pending, &values);
+                    },
+                    Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {},
+                    Err(e) => {
+                        error!("Failed to read from serial port {port}: {e}");
+                        set_status(format!("Error: {e}"));
+                        return;
+                    },
+                }
+            }
+        });
+    }
+}
+#[typetag::deserialize]
+impl Module for SerialIn {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Serial In\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("{} {:?}\n", self.port, self.format), ts.clone()),
+                            TextSection::new("Connecting\n", ts.clone()),
+                            TextSection::new("K0\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+
+        self.start();
+    }
+    fn init_state(&mut self, _id: usize) {
+        if self.channels == 0 || self.channels > Self::MAX_CHANNELS {
+            panic!("Invalid channel count for SerialIn: {} must be in the range [1, {}]", self.channels, Self::MAX_CHANNELS);
+        }
+
+        self.outs = vec![f32::NAN; self.channels];
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.is_running.store(false, Ordering::Release);
+    }
+    fn is_replayable(&self) -> bool {
+        false
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        0
+    }
+    fn outputs(&self) -> usize {
+        self.channels
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/serial_in.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, _ins: &[f32]) -> Vec<f32> {
+        if st == StepType::Video {
+            return self.outs.clone();
+        }
+
+        let sr = 44100.0;
+        let a = if self.knobs[0] > 0.0 {
+            1.0 - (-1.0 / (self.knobs[0] * sr)).exp()
+        } else {
+            1.0
+        };
+
+        if let Ok(values) = self.values.try_lock() {
+            for (o, v) in self.outs.iter_mut().zip(values.iter()) {
+                *o = if o.is_nan() || v.is_nan() {
+                    *v
+                } else {
+                    *o + a * (v - *o)
+                };
+            }
+        }
+
+        self.outs.clone()
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                if let Ok(status) = self.status.try_lock() {
+                    text.sections[2].value = format!("{status}\n");
+                }
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+            }
+        }
+    }
+}
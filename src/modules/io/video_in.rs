@@ -38,7 +38,7 @@ use serde::Deserialize;
 use screenshots::Screen;
 use nokhwa::Camera;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, component_video_out::ComponentVideoOut}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, component_video_out::ComponentVideoOut}};
 
 #[derive(Clone)]
 struct ScreenSource {
@@ -198,6 +198,9 @@ impl Module for VideoIn {
         self.component = None;
         self.children = vec![];
     }
+    fn is_replayable(&self) -> bool {
+        false
+    }
 
     fn id(&self) -> Option<usize> {
         self.id
@@ -218,6 +221,9 @@ impl Module for VideoIn {
     fn knobs(&self) -> usize {
         0
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/video_in.rs")
+    }
 
     fn step(&mut self, _time: f64, st: StepType, _ins: &[f32]) -> Vec<f32> {
         // Fetch video input
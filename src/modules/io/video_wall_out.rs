@@ -0,0 +1,282 @@
+/*!
+The `VideoWallOut` module takes 3 inputs and displays them as RGB data like
+`ComponentVideoOut`, but also splits the screen into a grid of tiles which are
+each shown in their own window, so that the video can be spread across several
+projectors or displays.
+
+The grid is given by `grid` as `[columns, rows]` and defaults to `[2, 1]`. Each
+tile's window can be configured with the tables in the `windows` array in the
+same way as the `window` table of other modules, e.g. to place each tile
+fullscreen on a different monitor. Since every tile shows part of the same
+screen, their scan positions always stay in sync.
+
+## Inputs
+0. Red channel in the range [0.0, 1.0]
+1. Green channel in the range [0.0, 1.0]
+2. Blue channel in the range [0.0, 1.0]
+
+## Outputs
+None
+
+## Knobs
+None
+
+*/
+
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle, render::{render_resource::{Extent3d, TextureDescriptor, TextureFormat, TextureUsages, TextureDimension}, camera::{RenderTarget, ScalingMode}}, window::WindowRef};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, WindowOptions, ModuleComponent, ModuleTextComponent, ModuleMeshComponent, ModuleImageComponent, ModuleImageWindowComponent, ModuleExtraWindowComponent, component_video_out::ComponentVideoOut}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct VideoWallOut {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default = "default_grid")]
+    grid: [usize; 2],
+    #[serde(default)]
+    windows: Vec<WindowOptions>,
+
+    #[serde(skip)]
+    scan: usize,
+    #[serde(skip)]
+    rgb: VecDeque<(f64, [f32; 3])>,
+}
+fn default_grid() -> [usize; 2] {
+    [2, 1]
+}
+impl VideoWallOut {
+    const MAX_LEN: usize = 4096;
+    const MAX_TILES: usize = 16;
+}
+#[typetag::deserialize]
+impl Module for VideoWallOut {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+
+        let [cols, rows] = self.grid;
+        if cols == 0 || rows == 0 || cols * rows > Self::MAX_TILES {
+            panic!("Invalid grid for VideoWallOut: {cols}x{rows} must have between 1 and {} tiles", Self::MAX_TILES);
+        }
+
+        let size = Extent3d {
+            width: ComponentVideoOut::WIDTH as u32,
+            height: ComponentVideoOut::HEIGHT as u32,
+            ..default()
+        };
+        let mut image = Image {
+            texture_descriptor: TextureDescriptor {
+                label: None,
+                size,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                mip_level_count: 1,
+                sample_count: 1,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            ..default()
+        };
+        image.resize(size);
+        image.data = [0, 0, 0, 255].repeat(image.data.len() / 4);
+        let image_handle = images.add(image);
+
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => format!("M{id} Video Wall Out"),
+        };
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(format!("{name}\n"), ts.clone()),
+                            TextSection::new(format!("{cols}x{rows} tiles\n"), ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+
+                self.children.push(
+                    parent.spawn((
+                        ImageBundle {
+                            style: Style {
+                                position_type: PositionType::Relative,
+                                top: Val::Px(10.0),
+                                width: Val::Px(f32::from(640u16)),
+                                height: Val::Px(f32::from(480u16)),
+                                ..default()
+                            },
+                            image: UiImage::new(image_handle.clone()),
+                            ..default()
+                        },
+                        ModuleImageComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+
+        // Show each tile in its own window
+        let tw = ComponentVideoOut::WIDTH as f32 / cols as f32;
+        let th = ComponentVideoOut::HEIGHT as f32 / rows as f32;
+        let tile_size = Vec2::new(640.0 / cols as f32, 480.0 / rows as f32);
+        for t in 0..cols*rows {
+            let (x, y) = (t % cols, t / cols);
+            let pos = Vec3::new(640.0*id as f32, 1080.0*(3 + t) as f32, 0.0);
+
+            let options = self.windows.get(t)
+                .cloned()
+                .unwrap_or_default();
+            let window = ec.commands().spawn(ModuleExtraWindowComponent {
+                title: format!("Vince Audio-Video Synth - {name} - Tile {x},{y}"),
+                resolution: tile_size.into(),
+                options,
+            }).id();
+            ec.commands().spawn((
+                Camera2dBundle {
+                    camera: Camera {
+                        target: RenderTarget::Window(WindowRef::Entity(window)),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(pos + Vec3::Z),
+                    projection: OrthographicProjection {
+                        scaling_mode: ScalingMode::Fixed {
+                            width: tile_size.x,
+                            height: tile_size.y,
+                        },
+                        ..default()
+                    },
+                    ..default()
+                },
+                UiCameraConfig {
+                    show_ui: false,
+                },
+            ));
+            ec.commands().spawn((
+                SpriteBundle {
+                    texture: image_handle.clone(),
+                    sprite: Sprite {
+                        custom_size: Some(tile_size),
+                        rect: Some(Rect::new(
+                            x as f32 * tw,
+                            y as f32 * th,
+                            (x + 1) as f32 * tw,
+                            (y + 1) as f32 * th,
+                        )),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(pos),
+                    ..default()
+                },
+                ModuleImageWindowComponent,
+            ));
+        }
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.scan = 0;
+    }
+
+    fn is_large(&self) -> bool {
+        true
+    }
+    fn get_world_pos(&self, q_child: &Query<&Parent, With<ModuleComponent>>, q_transform: &Query<&GlobalTransform>, main_camera: Option<(&Camera, &GlobalTransform)>) -> Vec3 {
+        if let Some(component) = self.component() {
+            if let Ok(parent) = q_child.get(component) {
+                if let Ok(pos_screen) = q_transform.get(parent.get()) {
+                    if let Some(camera) = main_camera {
+                        if let Some(pos_world) = camera.0.viewport_to_world(camera.1, pos_screen.translation().truncate()) {
+                            return Vec3::from((pos_world.origin.truncate(), 0.0))
+                                + Vec3::new(0.0, -250.0, 0.0);
+                        }
+                    }
+                }
+            }
+        }
+        Vec3::ZERO
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        3
+    }
+    fn outputs(&self) -> usize {
+        0
+    }
+    fn knobs(&self) -> usize {
+        0
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("io/video_wall_out.rs")
+    }
+
+    fn step(&mut self, time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        if ins.iter().all(|i| i.is_nan()) {
+            return vec![];
+        }
+
+        let c = |v: f32| if v.is_nan() {
+            0.0
+        } else {
+            v.clamp(0.0, 1.0)
+        };
+
+        if self.rgb.len() > Self::MAX_LEN {
+            self.rgb.remove(0);
+        }
+        self.rgb.push_back((time, [c(ins[0]), c(ins[1]), c(ins[2])]));
+
+        vec![]
+    }
+    fn render(&mut self, images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(1) {
+            if let Ok(h_image) = q_image.get_mut(*component) {
+                if let Some(image) = images.get_mut(&h_image.texture) {
+                    for rgb in self.rgb.drain(..) {
+                        image.data[self.scan] = (rgb.1[0] * 255.0) as u8;
+                        image.data[self.scan+1] = (rgb.1[1] * 255.0) as u8;
+                        image.data[self.scan+2] = (rgb.1[2] * 255.0) as u8;
+                        image.data[self.scan+3] = 255;
+
+                        self.scan = (self.scan+4) % image.data.len();
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+/*!
+The `MathUtil` module takes up to 2 inputs and applies a math operation to
+them, e.g. for conditioning control signals.
+
+## Math Functions
+ * `Abs` - The absolute value of input 0, i.e. a full-wave rectifier, the
+   default
+ * `HalfRectify` - Input 0 where it's positive, otherwise 0.0
+ * `Min` - The minimum of inputs 0 and 1
+ * `Max` - The maximum of inputs 0 and 1
+ * `Clamp` - Input 0 limited to the range [K0, K1]
+ * `Wrap` - Input 0 wrapped around into the range [K0, K1)
+ * `Fold` - Input 0 reflected back and forth into the range [K0, K1]
+
+## Inputs
+0. The first signal
+1. The second signal for `Min` and `Max`, which is replaced by K0 if
+   unpatched
+
+## Outputs
+0. The result
+
+## Knobs
+0. Lower bound in the range (-inf, inf)
+1. Upper bound in the range (-inf, inf)
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Default, Deserialize, Debug, Clone)]
+enum MathUtilFunc {
+    #[default]
+    Abs,
+    HalfRectify,
+    Min,
+    Max,
+    Clamp,
+    Wrap,
+    Fold,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MathUtil {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default)]
+    func: MathUtilFunc,
+
+    knobs: [f32; 2],
+}
+#[typetag::deserialize]
+impl Module for MathUtil {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} MathUtil\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("Func: {:?}\n", self.func), ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        2
+    }
+    fn outputs(&self) -> usize {
+        1
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("math_util.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let x = ins[0];
+        if x.is_nan() {
+            return vec![f32::NAN];
+        }
+        let y = if ins[1].is_nan() {
+            self.knobs[0]
+        } else {
+            ins[1]
+        };
+        let lo = self.knobs[0].min(self.knobs[1]);
+        let hi = self.knobs[0].max(self.knobs[1]);
+        let range = hi - lo;
+
+        vec![match self.func {
+            MathUtilFunc::Abs => x.abs(),
+            MathUtilFunc::HalfRectify => x.max(0.0),
+            MathUtilFunc::Min => x.min(y),
+            MathUtilFunc::Max => x.max(y),
+            MathUtilFunc::Clamp => x.clamp(lo, hi),
+            MathUtilFunc::Wrap => if range > 0.0 {
+                lo + (x - lo).rem_euclid(range)
+            } else {
+                lo
+            },
+            MathUtilFunc::Fold => if range > 0.0 {
+                let t = (x - lo).rem_euclid(2.0 * range);
+                lo + if t > range {
+                    2.0 * range - t
+                } else {
+                    t
+                }
+            } else {
+                lo
+            },
+        }]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+/*!
+The `Meter` module takes an input and measures its level, displaying the peak,
+RMS, and short-term loudness as a bar graph.
+
+The bars are displayed in the range [-60.0, 0.0] dBFS and turn red above -1.0
+dBFS. The short-term loudness is K-weighted over the last 3 seconds as
+described in ITU-R BS.1770.
+
+## Inputs
+0. The signal to measure
+
+## Outputs
+0. The peak level in the range [0.0, inf)
+1. The RMS level in the range [0.0, inf)
+2. The short-term loudness in the range [-70.0, inf) LUFS
+
+## Knobs
+0. Peak decay time in the range (0.0, inf) in seconds
+1. RMS integration time in the range (0.0, inf) in seconds
+
+*/
+
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle, render::render_resource::{Extent3d, TextureDescriptor, TextureFormat, TextureUsages, TextureDimension}};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, audio::equalizer::{EqualizerFunc, Biquad}}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Meter {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    peak: f32,
+    #[serde(skip)]
+    mean_square: f32,
+    #[serde(skip)]
+    k_weighting: [Biquad; 2],
+    #[serde(skip)]
+    block: (f32, usize),
+    #[serde(skip)]
+    blocks: VecDeque<f32>,
+    #[serde(skip)]
+    loudness: f32,
+
+    knobs: [f32; 2],
+}
+impl Meter {
+    const WIDTH: usize = 150;
+    const HEIGHT: usize = 100;
+    const MIN_DB: f32 = -60.0;
+    const MIN_LUFS: f32 = -70.0;
+    const BLOCK_LEN: f32 = 0.1;
+    const BLOCKS: usize = 30;
+
+    fn k_weight(&mut self, x: f32) -> f32 {
+        let (a, b) = EqualizerFunc::HighShelf.coefficients(1681.97, 0.7072, 10.0f32.powf(0.4));
+        let x = self.k_weighting[0].process(&a, &b, x);
+        let (a, b) = EqualizerFunc::HPF.coefficients(38.13, 0.5003, 1.0);
+        self.k_weighting[1].process(&a, &b, x)
+    }
+    fn db_to_height(db: f32) -> usize {
+        let h = (db - Self::MIN_DB) / -Self::MIN_DB * Self::HEIGHT as f32;
+        if h.is_nan() {
+            0
+        } else {
+            (h.max(0.0) as usize).min(Self::HEIGHT)
+        }
+    }
+}
+#[typetag::deserialize]
+impl Module for Meter {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        let size = Extent3d {
+            width: Self::WIDTH as u32,
+            height: Self::HEIGHT as u32,
+            ..default()
+        };
+        let mut image = Image {
+            texture_descriptor: TextureDescriptor {
+                label: None,
+                size,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                mip_level_count: 1,
+                sample_count: 1,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            ..default()
+        };
+        image.resize(size);
+        image.data = [0, 0, 0, 255].repeat(image.data.len() / 4);
+        let image_handle = images.add(image);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Meter\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("Peak\n", ts.clone()),
+                            TextSection::new("RMS\n", ts.clone()),
+                            TextSection::new("LUFS\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+
+                self.children.push(
+                    parent.spawn((
+                        ImageBundle {
+                            style: Style {
+                                position_type: PositionType::Relative,
+                                top: Val::Px(10.0),
+                                width: Val::Px(f32::from(Self::WIDTH as u16)),
+                                height: Val::Px(f32::from(Self::HEIGHT as u16)),
+                                ..default()
+                            },
+                            image: UiImage::new(image_handle),
+                            ..default()
+                        },
+                        ModuleImageComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, _id: usize) {
+        self.loudness = Self::MIN_LUFS;
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        3
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("meter.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        let x = ins[0];
+        if st == StepType::Video || x.is_nan() {
+            return vec![self.peak, self.mean_square.sqrt(), self.loudness];
+        }
+
+        let sr = 44100.0;
+
+        let decay = (-1.0 / (self.knobs[0] * sr)).exp();
+        if x.abs() > self.peak {
+            self.peak = x.abs();
+        } else if decay.is_finite() {
+            self.peak *= decay;
+        }
+
+        let integration = 1.0 - (-1.0 / (self.knobs[1] * sr)).exp();
+        if integration.is_finite() {
+            self.mean_square += (x * x - self.mean_square) * integration;
+        }
+
+        let k = self.k_weight(x);
+        self.block.0 += k * k;
+        self.block.1 += 1;
+        if self.block.1 as f32 >= Self::BLOCK_LEN * sr {
+            self.blocks.push_back(self.block.0 / self.block.1 as f32);
+            if self.blocks.len() > Self::BLOCKS {
+                self.blocks.pop_front();
+            }
+            self.block = (0.0, 0);
+
+            let mean = self.blocks.iter().sum::<f32>() / self.blocks.len() as f32;
+            self.loudness = if mean > 0.0 {
+                (-0.691 + 10.0 * mean.log10()).max(Self::MIN_LUFS)
+            } else {
+                Self::MIN_LUFS
+            };
+        }
+
+        vec![self.peak, self.mean_square.sqrt(), self.loudness]
+    }
+    fn render(&mut self, images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        let peak_db = 20.0 * self.peak.log10();
+        let rms_db = 20.0 * self.mean_square.sqrt().log10();
+
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("Peak: {:.1} dB\n", peak_db.max(Self::MIN_DB));
+                text.sections[2].value = format!("RMS: {:.1} dB\n", rms_db.max(Self::MIN_DB));
+                text.sections[3].value = format!("LUFS: {:.1}\n", self.loudness);
+            }
+        }
+
+        if let Some(component) = self.children.get(1) {
+            if let Ok(h_image) = q_image.get_mut(*component) {
+                if let Some(image) = images.get_mut(&h_image.texture) {
+                    let bars = [
+                        (Self::db_to_height(peak_db), [0, 255, 0]),
+                        (Self::db_to_height(rms_db), [255, 255, 0]),
+                        (Self::db_to_height(self.loudness), [0, 128, 255]),
+                    ];
+                    let bar_width = Self::WIDTH / bars.len();
+                    let red_height = Self::db_to_height(-1.0);
+
+                    for y in 0..Self::HEIGHT {
+                        let h = Self::HEIGHT - y;
+                        for x in 0..Self::WIDTH {
+                            let (bar_height, color) = bars[(x / bar_width).min(bars.len() - 1)];
+                            let rgb = if x % bar_width < 4 || h > bar_height {
+                                [0, 0, 0]
+                            } else if h > red_height {
+                                [255, 0, 0]
+                            } else {
+                                color
+                            };
+
+                            let idx = (y * Self::WIDTH + x) * 4;
+                            image.data[idx..idx+3].copy_from_slice(&rgb);
+                            image.data[idx+3] = 255;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
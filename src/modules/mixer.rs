@@ -23,7 +23,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Mixer {
@@ -105,6 +105,9 @@ impl Module for Mixer {
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("mixer.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -130,14 +133,14 @@ impl Module for Mixer {
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Gain 1: {}\n", self.knobs[0]);
-                text.sections[2].value = format!("K1 Gain 2: {}\n", self.knobs[1]);
-                text.sections[3].value = format!("K2 Gain 3: {}\n", self.knobs[2]);
-                text.sections[4].value = format!("K3 Gain 4: {}\n", self.knobs[3]);
-                text.sections[5].value = format!("K4 Gain 5: {}\n", self.knobs[4]);
-                text.sections[6].value = format!("K5 Gain 6: {}\n", self.knobs[5]);
-                text.sections[7].value = format!("K6 Gain 7: {}\n", self.knobs[6]);
-                text.sections[8].value = format!("K7 Gain 8: {}\n", self.knobs[7]);
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(4, self.knobs[4]));
+                text.sections[6].value = format!("{}\n", self.describe().knob_row(5, self.knobs[5]));
+                text.sections[7].value = format!("{}\n", self.describe().knob_row(6, self.knobs[6]));
+                text.sections[8].value = format!("{}\n", self.describe().knob_row(7, self.knobs[7]));
             }
         }
     }
@@ -10,7 +10,19 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::{Deserialize, de::{Visitor, self}};
 
-use crate::{StepType, MainCameraComponent};
+use crate::StepType;
+
+/// Describe a module from the doc comment of its source file, given relative to
+/// `src/modules`. Only the doc comment is embedded, by the build script, and
+/// it's parsed the first time the module is described
+macro_rules! module_description {
+    ($path:literal) => {{
+        static DESCRIPTION: std::sync::OnceLock<$crate::modules::ModuleDescription> = std::sync::OnceLock::new();
+        DESCRIPTION.get_or_init(|| {
+            $crate::modules::ModuleDescription::from_doc(include_str!(concat!(env!("OUT_DIR"), "/module_docs/", $path)))
+        })
+    }};
+}
 
 pub mod io;
 use io::*;
@@ -18,33 +30,394 @@ use io::*;
 pub mod info;
 
 pub mod oscilloscope;
+pub mod meter;
+pub mod vector_scope;
 pub mod oscillator;
+pub mod super_osc;
 pub mod noise;
+pub mod noise_field;
 pub mod sequencer;
 pub mod multi_sequencer;
+pub mod counter;
 pub mod envelope_generator;
+pub mod mseg;
 
 pub mod scaler;
 pub mod multiplier;
 pub mod mixer;
 pub mod multi_mixer;
 pub mod inverter;
+pub mod attenuverter;
+pub mod crossfade;
+pub mod math_util;
+pub mod glide;
 
 pub mod audio;
 
 pub mod video;
 
 pub mod conway;
+pub mod reaction_diffusion;
 
 #[derive(Debug, Clone)]
 pub struct MouseClick {
     pub pos: Vec2,
+    /// The position relative to the module's top left corner in the range
+    /// [0.0, 1.0]
+    pub rel_pos: Vec2,
     pub button: MouseButton,
 }
 
+/// A description of one of a module's inputs, outputs, or knobs
+#[derive(Debug, Default, Clone)]
+pub struct SignalDescription {
+    pub label: String,
+    /// The range of the signal as written in the docs, e.g. `[0.0, 1.0]`
+    pub range: Option<String>,
+    /// Any remaining details, such as the units of the range
+    pub details: String,
+}
+impl SignalDescription {
+    fn parse(item: &str) -> Self {
+        let item = item.split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        if let Some(start) = item.find(" in the range ") {
+            let rest = &item[start + " in the range ".len()..];
+            if rest.starts_with(['[', '(']) {
+                if let Some(end) = rest.find([']', ')']) {
+                    return Self {
+                        label: item[..start].to_string(),
+                        range: Some(rest[..=end].to_string()),
+                        details: rest[end+1..].trim_start_matches([',', ' ']).to_string(),
+                    };
+                }
+            }
+        }
+
+        Self {
+            label: item,
+            ..default()
+        }
+    }
+    /// Get the range of a knob from its description, if it has numeric bounds
+    pub fn knob_range(&self) -> Option<KnobRange> {
+        let range = self.range.as_ref()?;
+        let (lo, hi) = range.get(1..range.len()-1)?
+            .split_once(',')?;
+        let parse = |s: &str| match s.trim() {
+            "inf" => Some(f32::INFINITY),
+            "-inf" => Some(f32::NEG_INFINITY),
+            s => s.parse::<f32>().ok(),
+        };
+        let (mut min, mut max) = (parse(lo)?, parse(hi)?);
+
+        // Keep exclusive bounds just inside the range
+        if range.starts_with('(') && min.is_finite() {
+            min += f32::EPSILON * min.abs().max(1.0);
+        }
+        if range.ends_with(')') && max.is_finite() {
+            max -= f32::EPSILON * max.abs().max(1.0);
+        }
+
+        Some(KnobRange {
+            min,
+            max,
+            step: None,
+            unit: self.unit().map(str::to_string),
+            scaling: KnobScaling::None,
+        })
+    }
+    /// Get the standard that the signal follows from its range and units in
+    /// the docs, if it has one
+    pub fn standard(&self) -> Option<SignalStandard> {
+        if self.unit() == Some("Hz") {
+            return Some(SignalStandard::Frequency);
+        }
+
+        match self.range.as_deref() {
+            Some("[-1.0, 1.0]") => Some(SignalStandard::Audio),
+            Some("[0.0, 1.0]") => Some(SignalStandard::Unipolar),
+            _ => None,
+        }
+    }
+    /// The units of the signal from its details in the docs, e.g. `Hz`
+    pub fn unit(&self) -> Option<&str> {
+        self.details.strip_prefix("in ")
+            .and_then(|d| d.split_whitespace().next())
+            .map(|u| u.trim_end_matches([',', '.']))
+    }
+    fn to_help_line(&self) -> String {
+        let mut line = self.label.clone();
+        if let Some(range) = &self.range {
+            line = format!("{line} {range}");
+        }
+        if !self.details.is_empty() {
+            line = format!("{line} {}", self.details);
+        }
+        line
+    }
+}
+/// The standard ranges that signals are expected to be in, which are used to
+/// check and calibrate patches between modules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalStandard {
+    /// Bipolar audio in the range [-1.0, 1.0]
+    Audio,
+    /// Unipolar control or video in the range [0.0, 1.0]
+    Unipolar,
+    /// A frequency in Hz, which is mapped exponentially onto the audible range
+    /// when converted
+    Frequency,
+}
+impl SignalStandard {
+    const MIN_FREQ: f32 = 20.0;
+    const MAX_FREQ: f32 = 20000.0;
+
+    fn normalize(self, val: f32) -> f32 {
+        match self {
+            Self::Audio => (val + 1.0) / 2.0,
+            Self::Unipolar => val,
+            Self::Frequency => {
+                (val.max(Self::MIN_FREQ) / Self::MIN_FREQ).ln()
+                    / (Self::MAX_FREQ / Self::MIN_FREQ).ln()
+            },
+        }.clamp(0.0, 1.0)
+    }
+    fn denormalize(self, val: f32) -> f32 {
+        match self {
+            Self::Audio => val * 2.0 - 1.0,
+            Self::Unipolar => val,
+            Self::Frequency => Self::MIN_FREQ * (Self::MAX_FREQ / Self::MIN_FREQ).powf(val),
+        }
+    }
+    /// Convert a value in this standard to the given standard
+    pub fn convert(self, to: Self, val: f32) -> f32 {
+        if self == to || val.is_nan() {
+            val
+        } else {
+            to.denormalize(self.normalize(val))
+        }
+    }
+}
+/// How a patched value is mapped onto a knob's range
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnobScaling {
+    /// The value is used as-is
+    #[default]
+    None,
+    /// The range [0.0, 1.0] is mapped linearly onto the knob's range
+    Linear,
+    /// The range [0.0, 1.0] is mapped exponentially onto the knob's range,
+    /// e.g. for frequencies
+    Exp,
+}
+/// The range that a knob is held to, as given in the rack's `[knob_ranges]`
+/// table
+#[derive(Debug, Clone)]
+pub struct KnobRange {
+    pub min: f32,
+    pub max: f32,
+    pub step: Option<f32>,
+    pub unit: Option<String>,
+    pub scaling: KnobScaling,
+}
+impl KnobRange {
+    /// Scale, quantize, and clamp a patched value
+    pub fn apply(&self, val: f32) -> f32 {
+        let val = match self.scaling {
+            KnobScaling::None => val,
+            KnobScaling::Linear => self.min + (self.max - self.min) * val.clamp(0.0, 1.0),
+            KnobScaling::Exp => self.min * (self.max / self.min).powf(val.clamp(0.0, 1.0)),
+        };
+        self.clamp(val)
+    }
+    /// Quantize and clamp a value which is already in the knob's range, e.g.
+    /// one set by MIDI or the mouse
+    pub fn clamp(&self, val: f32) -> f32 {
+        let val = match self.step {
+            Some(step) if step > 0.0 => {
+                let base = if self.min.is_finite() {
+                    self.min
+                } else {
+                    0.0
+                };
+                base + ((val - base) / step).round() * step
+            },
+            _ => val,
+        };
+        val.clamp(self.min, self.max)
+    }
+    /// Override the documented range with the options from the rack
+    pub fn with_options(mut self, options: &KnobOptions) -> Self {
+        self.min = options.min.unwrap_or(self.min);
+        self.max = options.max.unwrap_or(self.max);
+        self.step = options.step.or(self.step);
+        self.scaling = options.scaling;
+
+        let is_valid = match self.scaling {
+            KnobScaling::None => true,
+            KnobScaling::Linear => self.min.is_finite() && self.max.is_finite(),
+            KnobScaling::Exp => self.min > 0.0 && self.max.is_finite(),
+        };
+        if !is_valid {
+            error!("Knob scaling {:?} isn't supported for the range [{}, {}]", self.scaling, self.min, self.max);
+            self.scaling = KnobScaling::None;
+        }
+        self
+    }
+}
+/// The knob options given in the rack's `[knob_ranges]` table
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct KnobOptions {
+    #[serde(default)]
+    pub min: Option<f32>,
+    #[serde(default)]
+    pub max: Option<f32>,
+    #[serde(default)]
+    pub step: Option<f32>,
+    #[serde(default)]
+    pub scaling: KnobScaling,
+}
+
+/// A structured description of a module, as returned by `Module::describe`
+#[derive(Debug, Default, Clone)]
+pub struct ModuleDescription {
+    /// The first paragraph of the module's docs
+    pub summary: String,
+    pub inputs: Vec<SignalDescription>,
+    pub outputs: Vec<SignalDescription>,
+    pub knobs: Vec<SignalDescription>,
+}
+impl ModuleDescription {
+    /// The description of a module without docs
+    pub const EMPTY: Self = Self {
+        summary: String::new(),
+        inputs: Vec::new(),
+        outputs: Vec::new(),
+        knobs: Vec::new(),
+    };
+
+    /// Parse a description from the numbered lists in the `## Inputs`,
+    /// `## Outputs`, and `## Knobs` sections of a module's doc comment
+    pub fn from_doc(src: &str) -> Self {
+        let doc = src.split_once("/*!")
+            .and_then(|(_, doc)| doc.split_once("*/"))
+            .map_or("", |(doc, _)| doc);
+
+        let mut description = Self {
+            summary: doc.trim_start()
+                .split("\n\n")
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" "),
+            ..default()
+        };
+
+        let mut section: Option<&mut Vec<SignalDescription>> = None;
+        let mut item: Option<String> = None;
+        for line in doc.lines() {
+            let is_item = line.split_once(". ")
+                .is_some_and(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+            if line.starts_with('#') || line.trim().is_empty() || is_item {
+                if let (Some(section), Some(item)) = (&mut section, item.take()) {
+                    section.push(SignalDescription::parse(&item));
+                }
+            }
+
+            if line.starts_with('#') {
+                section = match line {
+                    "## Inputs" => Some(&mut description.inputs),
+                    "## Outputs" => Some(&mut description.outputs),
+                    "## Knobs" => Some(&mut description.knobs),
+                    _ => None,
+                };
+            } else if is_item {
+                item = line.split_once(". ")
+                    .map(|(_, text)| text.to_string());
+            } else if let Some(item) = &mut item {
+                item.push(' ');
+                item.push_str(line.trim());
+            }
+        }
+        if let (Some(section), Some(item)) = (section, item) {
+            section.push(SignalDescription::parse(&item));
+        }
+
+        description
+    }
+    /// Format a row of a module's panel showing the value of the given knob,
+    /// labelled with its name and units from the docs, e.g. `K0 Frequency: 440
+    /// Hz`
+    pub fn knob_row(&self, i: usize, val: f32) -> String {
+        match self.knobs.get(i) {
+            Some(knob) => match knob.unit() {
+                Some(unit) => format!("K{i} {}: {val} {unit}", knob.label),
+                None => format!("K{i} {}: {val}", knob.label),
+            },
+            None => format!("K{i}: {val}"),
+        }
+    }
+    /// Format the description as text for the help overlay, listing the
+    /// module's current number of inputs, outputs, and knobs along with its
+    /// current knob values
+    pub fn to_help_text(&self, name: &str, m: &dyn Module) -> String {
+        let lines = |title: &str, prefix: &str, descs: &[SignalDescription], count: usize| {
+            let mut text = format!("\n{title}\n");
+            if count == 0 {
+                text.push_str(" None\n");
+            }
+            for i in 0..count {
+                let line = descs.get(i)
+                    .map(SignalDescription::to_help_line)
+                    .unwrap_or_default();
+                text.push_str(&format!(" {prefix}{i} {line}\n"));
+            }
+            text
+        };
+
+        let mut text = format!("{name}\n{}\n", self.summary);
+        text.push_str(&lines("Inputs", "", &self.inputs, m.inputs()));
+        text.push_str(&lines("Outputs", "", &self.outputs, m.outputs()));
+        text.push_str(&lines("Knobs", "K", &self.knobs, m.knobs()));
+
+        let knobs = m.get_knobs();
+        if !knobs.is_empty() {
+            text.push_str("\nValues\n");
+            for (i, k) in knobs.iter().enumerate() {
+                text.push_str(&format!(" K{i} = {k}\n"));
+            }
+        }
+        text
+    }
+}
+
+/// The options for modules which have their own window, given as the `window`
+/// table of the module
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct WindowOptions {
+    /// The index of the monitor to open the window on
+    #[serde(default)]
+    pub monitor: Option<usize>,
+    /// The window's width and height, which defaults to the module's size
+    #[serde(default)]
+    pub resolution: Option<[f32; 2]>,
+    #[serde(default)]
+    pub is_always_on_top: bool,
+    /// Whether to make the window borderless fullscreen on its monitor
+    #[serde(default)]
+    pub is_fullscreen: bool,
+}
+
 #[typetag::deserialize(tag = "type")]
 pub trait Module: std::fmt::Debug + ModuleClone + Send + Sync {
     fn init(&mut self, id: usize, ec: EntityCommands, images: &mut ResMut<Assets<Image>>, meshes: &mut ResMut<Assets<Mesh>>, materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle);
+    /// Set up the module's state for stepping without spawning its UI, e.g.
+    /// when stepping it headlessly with the [crate::engine]. Modules which
+    /// have such state also call this at the start of [Module::init]
+    fn init_state(&mut self, _id: usize) {}
     fn exit(&mut self);
 
     fn is_init(&self) -> bool {
@@ -56,6 +429,25 @@ pub trait Module: std::fmt::Debug + ModuleClone + Send + Sync {
     fn is_own_window(&self) -> bool {
         false
     }
+    /// Whether the module's external inputs are recorded by [crate::replay],
+    /// otherwise a warning is logged when the module is loaded while replaying
+    /// since it'll read from its live source instead
+    fn is_replayable(&self) -> bool {
+        true
+    }
+    /// Whether the module takes keyboard input, so that it can be focused to
+    /// receive the keystrokes
+    fn is_focusable(&self) -> bool {
+        false
+    }
+    fn window_options(&self) -> WindowOptions {
+        WindowOptions::default()
+    }
+    /// The factor to oversample the module's steps by, see
+    /// [crate::oversampling]
+    fn oversampling(&self) -> usize {
+        1
+    }
     fn get_screen_pos(&self, q_child: &Query<&Parent, With<ModuleComponent>>, q_transform: &Query<&GlobalTransform>) -> Vec2 {
         if let Some(component) = self.component() {
             if let Ok(parent) = q_child.get(component) {
@@ -66,13 +458,13 @@ pub trait Module: std::fmt::Debug + ModuleClone + Send + Sync {
         }
         Vec2::ZERO
     }
-    fn get_world_pos(&self, q_child: &Query<&Parent, With<ModuleComponent>>, q_transform: &Query<&GlobalTransform>, q_main_camera: &Query<(&Camera, &GlobalTransform), With<MainCameraComponent>>) -> Vec3 {
+    fn get_world_pos(&self, q_child: &Query<&Parent, With<ModuleComponent>>, q_transform: &Query<&GlobalTransform>, main_camera: Option<(&Camera, &GlobalTransform)>) -> Vec3 {
         let pos_screen = self.get_screen_pos(q_child, q_transform);
         if pos_screen == Vec2::ZERO {
             return Vec3::ZERO;
         }
 
-        if let Ok(main_camera) = q_main_camera.get_single() {
+        if let Some(main_camera) = main_camera {
             if let Some(pos_world) = main_camera.0.viewport_to_world(main_camera.1, pos_screen) {
                 return Vec3::from((pos_world.origin.truncate(), 0.0))
                     + Vec3::new(0.0, -100.0, 0.0);
@@ -84,6 +476,12 @@ pub trait Module: std::fmt::Debug + ModuleClone + Send + Sync {
     fn id(&self) -> Option<usize>;
     fn name(&self) -> Option<String>;
     fn component(&self) -> Option<Entity>;
+    /// Describe the module's inputs, outputs, and knobs for the help overlay
+    /// and the labels of its knob rows
+    fn describe(&self) -> &'static ModuleDescription {
+        static EMPTY: ModuleDescription = ModuleDescription::EMPTY;
+        &EMPTY
+    }
 
     fn inputs(&self) -> usize;
     fn outputs(&self) -> usize;
@@ -97,25 +495,40 @@ pub trait Module: std::fmt::Debug + ModuleClone + Send + Sync {
     fn drain_audio_buffer(&mut self) -> Vec<[f32; 2]> {
         vec![]
     }
-    fn extend_audio_buffer(&mut self, _ai: &[f32]) {}
+    fn drain_multi_audio_buffer(&mut self) -> Vec<Vec<f32>> {
+        vec![]
+    }
+    fn extend_audio_buffer(&mut self, _ai: &[f32], _channels: usize) {}
+    /// Set the sample rate that the rack steps at once its audio output is
+    /// opened, e.g. to resample audio captured from another device
+    fn set_sample_rate(&mut self, _sample_rate: u32) {}
 
     fn keyboard_input(&mut self, _keys: &Res<Input<KeyCode>>) {}
+    fn get_screen_size(&self) -> Vec2 {
+        if self.is_large() {
+            Vec2::new(660.0, 550.0)
+        } else {
+            Vec2::new(170.0, 200.0)
+        }
+    }
+    /// Whether the given screen position is over the module
+    fn is_hovered(&self, pos: Vec2, q_child: &Query<&Parent, With<ModuleComponent>>, q_transform: &Query<&GlobalTransform>) -> bool {
+        let screen_pos = self.get_screen_pos(q_child, q_transform);
+        let Vec2 { x: w, y: h } = self.get_screen_size();
+
+        pos.x >= screen_pos.x - w/2.0 && pos.x < screen_pos.x + w/2.0
+            && pos.y >= screen_pos.y - h/2.0 && pos.y < screen_pos.y + h/2.0
+    }
     fn mouse_input(&mut self, mouse_buttons: &Res<Input<MouseButton>>, window: &Window, q_child: &Query<&Parent, With<ModuleComponent>>, q_transform: &Query<&GlobalTransform>) {
         if let Some(mpos) = window.cursor_position() {
-            let screen_pos = self.get_screen_pos(q_child, q_transform);
-            let (w, h) = if self.is_large() {
-                (660.0, 550.0)
-            } else {
-                (170.0, 200.0)
-            };
-
-
-            if mpos.x >= screen_pos.x - w/2.0 && mpos.x < screen_pos.x + w/2.0
-                && mpos.y >= screen_pos.y - h/2.0 && mpos.y < screen_pos.y + h/2.0
-            {
+            if self.is_hovered(mpos, q_child, q_transform) {
+                let screen_pos = self.get_screen_pos(q_child, q_transform);
+                let size = self.get_screen_size();
+                let rel_pos = (mpos - screen_pos + size / 2.0) / size;
                 for &button in mouse_buttons.get_just_released() {
                     self.mouse_click(MouseClick {
                         pos: mpos,
+                        rel_pos,
                         button,
                     });
                 }
@@ -123,6 +536,7 @@ pub trait Module: std::fmt::Debug + ModuleClone + Send + Sync {
         }
     }
     fn mouse_click(&mut self, _mouse_click: MouseClick) {}
+    fn touch_input(&mut self, _touches: &Res<Touches>) {}
     fn step(&mut self, time: f64, st: StepType, ins: &[f32]) -> Vec<f32>;
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {}
 }
@@ -154,6 +568,15 @@ pub struct ModuleMeshComponent;
 pub struct ModuleImageComponent;
 #[derive(Component, Debug, Clone)]
 pub struct ModuleImageWindowComponent;
+/// A window which a module needs besides its own, e.g. for each tile of a
+/// video wall, which the app opens with the given options
+#[derive(Component, Debug, Clone)]
+pub struct ModuleExtraWindowComponent {
+    pub title: String,
+    /// The window's size unless the options set a resolution
+    pub resolution: [f32; 2],
+    pub options: WindowOptions,
+}
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum ModuleIOK {
@@ -182,6 +605,16 @@ pub struct ModuleKey {
     pub id: usize,
     pub iok: ModuleIOK,
 }
+impl std::fmt::Display for ModuleKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.iok {
+            ModuleIOK::None => write!(f, "{}M", self.id),
+            ModuleIOK::Input(i) => write!(f, "{}M{i}I", self.id),
+            ModuleIOK::Output(i) => write!(f, "{}M{i}O", self.id),
+            ModuleIOK::Knob(i) => write!(f, "{}M{i}K", self.id),
+        }
+    }
+}
 struct ModuleKeyVisitor;
 impl<'de> Visitor<'de> for ModuleKeyVisitor {
     type Value = ModuleKey;
@@ -236,3 +669,19 @@ impl<'de> Deserialize<'de> for ModuleKey {
         deserializer.deserialize_str(ModuleKeyVisitor)
     }
 }
+/// Deserialize a module's knobs, filling in the trailing knobs which were
+/// added after the first `required` knobs from the given defaults so that
+/// older racks still load
+pub(crate) fn deserialize_knobs<'de, D, const N: usize>(deserializer: D, required: usize, defaults: [f32; N]) -> Result<[f32; N], D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let knobs = Vec::<f32>::deserialize(deserializer)?;
+    if knobs.len() < required || knobs.len() > N {
+        return Err(de::Error::custom(format!("invalid knob count {}, expected {required} to {N} knobs", knobs.len())));
+    }
+
+    let mut padded = defaults;
+    padded[..knobs.len()].copy_from_slice(&knobs);
+    Ok(padded)
+}
@@ -0,0 +1,273 @@
+/*!
+The `Mseg` module outputs a multi-segment envelope for evolving modulation
+shapes which the 4 stages of the `EnvelopeGenerator` module can't express.
+
+The segments are listed in `segments`, where each moves from the previous
+level to its `level` over its `time` in seconds with the given `curve` from
+the `EnvelopeGenerator` module, which defaults to `Linear`. The first segment
+starts from the envelope's current level, and the envelope holds the level of
+the last segment once it finishes.
+
+While the gate is held the envelope stops at the end of the `sustain` segment
+if it's given, and jumps from the end of the last segment of `loop` back to its
+first segment if it's given. When the gate is released the envelope continues
+from its current level with the segment after the sustain point or loop. If
+the gate is unpatched the envelope starts immediately and is always held, so a
+looping envelope acts like a custom LFO.
+
+```toml
+1 = { type = "Mseg", sustain = 2, knobs = [1.0], segments = [
+    { level = 1.0, time = 0.01 },
+    { level = 0.3, time = 0.2, curve = "Exponential" },
+    { level = 0.6, time = 0.5, curve = "Logarithmic" },
+    { level = 0.0, time = 1.0, curve = "Exponential" },
+] }
+```
+
+## Inputs
+0. The gate which starts the envelope whenever this becomes positive and
+   releases it whenever this stops being positive
+
+## Outputs
+0. The envelope's level
+1. The index of the current segment, or -1.0 when the envelope has finished or
+   hasn't been started
+
+## Knobs
+0. Time scale in the range [0.0, inf) which multiplies the time of every
+   segment
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, envelope_generator::EnvelopeCurve}};
+
+#[derive(Deserialize, Debug, Clone)]
+struct MsegSegment {
+    level: f32,
+    time: f32,
+    #[serde(default)]
+    curve: EnvelopeCurve,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Mseg {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    segments: Vec<MsegSegment>,
+    #[serde(default)]
+    sustain: Option<usize>,
+    #[serde(default, rename = "loop")]
+    looping: Option<[usize; 2]>,
+
+    #[serde(skip)]
+    segment: Option<usize>,
+    /// When the current segment started
+    #[serde(skip)]
+    segment_timestamp: f64,
+    /// The level that the current segment started from
+    #[serde(skip)]
+    segment_level: f32,
+    #[serde(skip)]
+    level: f32,
+    #[serde(skip)]
+    is_started: bool,
+    #[serde(skip)]
+    was_held: bool,
+
+    knobs: [f32; 1],
+}
+impl Mseg {
+    /// Get the last segment that's held while the gate is held, starting from
+    /// the given segment
+    fn hold_end(&self, i: usize) -> Option<usize> {
+        [self.sustain, self.looping.map(|l| l[1])].into_iter()
+            .flatten()
+            .filter(|&e| e >= i)
+            .min()
+    }
+    /// Start the given segment from the current level
+    fn start_segment(&mut self, time: f64, segment: Option<usize>) {
+        self.segment = segment.filter(|&i| i < self.segments.len());
+        self.segment_timestamp = time;
+        self.segment_level = self.level;
+    }
+}
+#[typetag::deserialize]
+impl Module for Mseg {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Mseg\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("Segment: -/{}\n", self.segments.len()), ts.clone()),
+                            TextSection::new("K0\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, _id: usize) {
+        let len = self.segments.len();
+        if len == 0 {
+            panic!("Mseg requires at least one segment");
+        }
+        if let Some(sustain) = self.sustain {
+            if sustain >= len {
+                panic!("Invalid sustain for Mseg: {sustain} must be less than the {len} segments");
+            }
+        }
+        if let Some([first, last]) = self.looping {
+            if first > last || last >= len {
+                panic!("Invalid loop for Mseg: [{first}, {last}] must be in order and less than the {len} segments");
+            }
+        }
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.segment = None;
+        self.segment_timestamp = 0.0;
+        self.segment_level = 0.0;
+        self.level = 0.0;
+        self.is_started = false;
+        self.was_held = false;
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        2
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("mseg.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let gate = ins[0];
+        let is_held = gate.is_nan() || gate > 0.0;
+
+        if is_held && (!self.was_held || !self.is_started) {
+            self.is_started = true;
+            self.start_segment(time, Some(0));
+        } else if !is_held && self.was_held {
+            if let Some(end) = self.segment.and_then(|i| self.hold_end(i)) {
+                self.start_segment(time, Some(end + 1));
+            }
+        }
+        self.was_held = is_held;
+
+        // Advance through any segments which have finished since the last
+        // step, at most once per segment so that zero-length loops can't hang
+        let scale = self.knobs[0].max(0.0);
+        let mut y = None;
+        for _ in 0..=self.segments.len() {
+            let Some(i) = self.segment else {
+                y = Some(self.level);
+                break;
+            };
+            let seg = &self.segments[i];
+
+            let len = f64::from(seg.time * scale);
+            let t = if len > 0.0 {
+                (time - self.segment_timestamp) / len
+            } else {
+                1.0
+            };
+            if t < 1.0 {
+                y = Some(seg.curve.interpolate(self.segment_level, seg.level, t as f32));
+                break;
+            }
+            if is_held && self.sustain == Some(i) {
+                y = Some(seg.level);
+                break;
+            }
+
+            self.level = seg.level;
+            let next = match self.looping {
+                Some([first, last]) if is_held && last == i => Some(first),
+                _ => Some(i + 1),
+            };
+            self.start_segment(self.segment_timestamp + len, next);
+        }
+        let y = y.unwrap_or_else(|| {
+            self.segment_timestamp = time;
+            self.level
+        });
+        self.level = y;
+
+        vec![
+            y,
+            self.segment.map_or(-1.0, |i| i as f32),
+        ]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                let len = self.segments.len();
+                text.sections[1].value = match self.segment {
+                    Some(i) => format!("Segment: {i}/{len}\n"),
+                    None => format!("Segment: -/{len}\n"),
+                };
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+            }
+        }
+    }
+}
@@ -11,8 +11,26 @@ Note that the child sequencer modules will not be stepped unless activated by
 this parent module. Additionally, their outputs cannot be patched except by
 patching the parent's outputs.
 
+## Song Mode
+Optionally, a `song` can be given as an array of subarrays whose elements are
+the index of a sequencer and its repeat count. The repeat count is multiplied
+by the sequencer's total length, the same as the duration above. When a song
+is given, it will be played in order instead of the list of sequencers, and
+every index in it must be one of the sequencers, otherwise the rack fails to
+load.
+
+```toml
+song = [
+    [0, 2.0], # Play sequencer 0 twice
+    [1, 1.0], # Then sequencer 1 once
+    [0, 4.0], # Then sequencer 0 four times before looping
+]
+```
+
 ## Inputs
-None
+0. Whether to advance to the next song position, triggered whenever this
+   becomes non-zero
+1. The song position to jump to whenever this value changes
 
 ## Outputs
 0. The note's frequency
@@ -31,28 +49,64 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, sequencer::Sequencer}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, sequencer::Sequencer}};
+
+#[derive(Deserialize)]
+struct MultiSequencerDef {
+    #[serde(default)]
+    name: Option<String>,
+
+    sequencers: Vec<(Sequencer, f32)>,
+    #[serde(default)]
+    song: Vec<(usize, f32)>,
+}
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "MultiSequencerDef")]
 pub struct MultiSequencer {
-    #[serde(skip)]
     id: Option<usize>,
-    #[serde(default)]
     name: Option<String>,
 
-    #[serde(skip)]
     component: Option<Entity>,
-    #[serde(skip)]
     children: Vec<Entity>,
 
     sequencers: Vec<(Sequencer, f32)>,
-    #[serde(skip)]
+    song: Vec<(usize, f32)>,
+    song_pos: usize,
     last_seq: Option<String>,
-    #[serde(skip)]
+    last_advance: f32,
+    last_jump: Option<f32>,
     time: f64,
-    #[serde(skip)]
     last_time: Option<f64>,
 }
+impl TryFrom<MultiSequencerDef> for MultiSequencer {
+    type Error = String;
+
+    fn try_from(def: MultiSequencerDef) -> Result<Self, Self::Error> {
+        for (pos, (pattern, _)) in def.song.iter().enumerate() {
+            if *pattern >= def.sequencers.len() {
+                return Err(format!("Invalid song for MultiSequencer: position {pos} references sequencer {pattern} but there are only {} sequencers", def.sequencers.len()));
+            }
+        }
+
+        Ok(Self {
+            id: None,
+            name: def.name,
+
+            component: None,
+            children: vec![],
+
+            sequencers: def.sequencers,
+            song: def.song,
+            song_pos: 0,
+            last_seq: None,
+            last_advance: 0.0,
+            last_jump: None,
+            time: 0.0,
+            last_time: None,
+        })
+    }
+}
 #[typetag::deserialize]
 impl Module for MultiSequencer {
     fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
@@ -79,6 +133,7 @@ impl Module for MultiSequencer {
                         TextBundle::from_sections(
                             std::iter::once(TextSection::new(name, ts.clone()))
                                 .chain(std::iter::once(TextSection::new("Active\n", ts.clone())))
+                                .chain(std::iter::once(TextSection::new("Position\n", ts.clone())))
                                 .chain(std::iter::once(TextSection::new("\nChildren:\n", ts.clone())))
                                 .chain(
                                     self.sequencers.iter()
@@ -105,6 +160,10 @@ impl Module for MultiSequencer {
         self.id = None;
         self.component = None;
         self.children = vec![];
+
+        self.song_pos = 0;
+        self.time = 0.0;
+        self.last_time = None;
     }
 
     fn id(&self) -> Option<usize> {
@@ -118,7 +177,7 @@ impl Module for MultiSequencer {
     }
 
     fn inputs(&self) -> usize {
-        0
+        2
     }
     fn outputs(&self) -> usize {
         3
@@ -126,33 +185,73 @@ impl Module for MultiSequencer {
     fn knobs(&self) -> usize {
         0
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("multi_sequencer.rs")
+    }
 
     fn step(&mut self, time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
-        let lengths: Vec<f32> = self.sequencers.iter()
+        let advance = ins[0];
+        let jump = ins[1];
+
+        let entries: Vec<(usize, f32)> = if self.song.is_empty() {
+            self.sequencers.iter()
+                .enumerate()
+                .map(|(i, seq)| (i, seq.1))
+                .collect()
+        } else {
+            self.song.clone()
+        };
+        if entries.is_empty() {
+            return vec![0.0, 0.0, 0.0];
+        }
+
+        let lengths: Vec<f64> = self.sequencers.iter()
             .map(|seq| {
-                seq.0.notes.iter()
+                (seq.0.notes.iter()
                     .map(|n| n.2)
                     .sum::<f32>()
-                * 60.0 / seq.0.get_knobs()[0]
-                * seq.1
+                * 60.0 / seq.0.get_knobs()[0]) as f64
             }).collect();
 
+        if !advance.is_nan() && advance != 0.0 && (self.last_advance.is_nan() || self.last_advance == 0.0) {
+            self.song_pos += 1;
+            self.time = 0.0;
+        }
+        self.last_advance = advance;
+
+        if !jump.is_nan() {
+            if self.last_jump.is_some_and(|lj| lj != jump) {
+                self.song_pos = jump.max(0.0) as usize;
+                self.time = 0.0;
+            }
+            self.last_jump = Some(jump);
+        }
+        self.song_pos %= entries.len();
+
         self.time += time - self.last_time.unwrap_or(time);
-        self.time %= lengths.iter().sum::<f32>() as f64;
         self.last_time = Some(time);
 
-        let mut time_left = self.time;
-        for (i, seq) in self.sequencers.iter_mut()
-            .enumerate()
-        {
-            time_left -= lengths[i] as f64;
-            if time_left < 0.0 {
-                self.last_seq = seq.0.name();
-
-                seq.0.time = time_left.rem_euclid(lengths[i] as f64);
-                seq.0.last_time = None;
-                return seq.0.step(time, st, ins);
+        for _ in 0..entries.len() {
+            let (pattern, repeat) = entries[self.song_pos];
+            let length = lengths.get(pattern).copied().unwrap_or(0.0) * repeat as f64;
+            if !length.is_finite() || length <= 0.0 || self.time < length {
+                break;
             }
+
+            self.time -= length;
+            self.song_pos = (self.song_pos + 1) % entries.len();
+        }
+
+        let pattern = entries[self.song_pos].0;
+        if let Some(seq) = self.sequencers.get_mut(pattern) {
+            self.last_seq = Some(
+                seq.0.name()
+                    .unwrap_or_else(|| format!("SEQ{pattern}"))
+            );
+
+            seq.0.time = self.time.rem_euclid(lengths[pattern]);
+            seq.0.last_time = None;
+            return seq.0.step(time, st, ins);
         }
 
         vec![0.0, 0.0, 0.0]
@@ -165,6 +264,13 @@ impl Module for MultiSequencer {
                 } else {
                     text.sections[1].value = "Active: None\n".to_string();
                 }
+
+                let song_len = if self.song.is_empty() {
+                    self.sequencers.len()
+                } else {
+                    self.song.len()
+                };
+                text.sections[2].value = format!("Position: {}/{}\n", self.song_pos + 1, song_len);
             }
         }
     }
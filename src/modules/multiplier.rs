@@ -17,7 +17,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent}};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Multiplier {
@@ -89,6 +89,9 @@ impl Module for Multiplier {
     fn knobs(&self) -> usize {
         0
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("multiplier.rs")
+    }
 
     fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
         vec![
@@ -2,9 +2,12 @@
 The `Noise` module outputs a noise signal with a given gain.
 
 ## Noise Functions
- * `White` - random data from the [rand] crate, the default
- * <strike>`Fractional(f32)` - white noise with a fractional frequency spectrum
-   </strike> Not yet supported, try using an [Equalizer](crate::modules::audio::equalizer)
+ * `White` - random data from the [rand] crate, the default, which is
+   reproducible when the rack's `seed` is set
+ * `Pink` - white noise filtered to a 1/f spectrum, i.e. -3 dB per octave
+ * `Brown` - integrated white noise with a 1/f² spectrum, i.e. -6 dB per octave
+ * `Blue` - differentiated pink noise with an approximately f spectrum, i.e.
+   +3 dB per octave
  * `Perlin` - smoothed 1-dimensional Perlin noise
  * `Simplex` - smoothed 1-dimensional Simplex noise
 
@@ -16,6 +19,8 @@ None
 
 ## Knobs
 0. Gain in the range [0.0, inf)
+1. Sample-and-hold rate in the range [0.0, inf) in Hz, where 0.0 generates a
+   new value on every step
 
 */
 
@@ -25,13 +30,15 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleMeshComponent, ModuleImageComponent}};
+use crate::{StepType, rack::module_rng, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleMeshComponent, ModuleImageComponent}};
 
 #[derive(Default, Deserialize, Debug, Clone)]
 enum NoiseFunc {
     #[default]
     White,
-    // Fractional(f32),
+    Pink,
+    Brown,
+    Blue,
 
     Perlin,
     Simplex,
@@ -51,12 +58,46 @@ pub struct Noise {
 
     #[serde(default)]
     func: NoiseFunc,
-    knobs: [f32; 1],
+    #[serde(skip)]
+    rng: Option<StdRng>,
+    /// The state of the pink noise filter
+    #[serde(skip)]
+    pink: [f32; 7],
+    #[serde(skip)]
+    last_pink: f32,
+    #[serde(skip)]
+    brown: f32,
+    /// The time and unscaled value of the last sample
+    #[serde(skip)]
+    hold: Option<(f64, f32)>,
+
+    knobs: [f32; 2],
+}
+impl Noise {
+    fn white(&mut self) -> f32 {
+        let rng = self.rng.get_or_insert_with(StdRng::from_entropy);
+        rng.gen_range(-1.0..=1.0)
+    }
+    /// Generate pink noise with Paul Kellet's refined filter
+    fn pink(&mut self) -> f32 {
+        let w = self.white();
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + w * 0.0555179;
+        b[1] = 0.99332 * b[1] + w * 0.0750759;
+        b[2] = 0.96900 * b[2] + w * 0.153852;
+        b[3] = 0.86650 * b[3] + w * 0.3104856;
+        b[4] = 0.55000 * b[4] + w * 0.5329522;
+        b[5] = -0.7616 * b[5] - w * 0.0168980;
+        let y = (b.iter().sum::<f32>() + w * 0.5362) * 0.11;
+        b[6] = w * 0.115926;
+        y.clamp(-1.0, 1.0)
+    }
 }
 #[typetag::deserialize]
 impl Module for Noise {
     fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
         self.id = Some(id);
+        self.init_state(id);
         ec.with_children(|parent| {
             let mut component = parent.spawn((
                 NodeBundle {
@@ -80,7 +121,8 @@ impl Module for Noise {
                             TextSection::new(name, ts.clone()),
                             TextSection::new("Func:\n", ts.clone()),
                             TextSection::new("F\n", ts.clone()),
-                            TextSection::new("K0\n", ts),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts),
                         ]),
                         ModuleTextComponent,
                     )).id()
@@ -89,10 +131,19 @@ impl Module for Noise {
             self.component = Some(component.id());
         });
     }
+    fn init_state(&mut self, id: usize) {
+        self.rng = Some(module_rng(id));
+    }
     fn exit(&mut self) {
         self.id = None;
         self.component = None;
         self.children = vec![];
+
+        self.rng = None;
+        self.pink = [0.0; 7];
+        self.last_pink = 0.0;
+        self.brown = 0.0;
+        self.hold = None;
     }
 
     fn id(&self) -> Option<usize> {
@@ -112,26 +163,49 @@ impl Module for Noise {
         1
     }
     fn knobs(&self) -> usize {
-        1
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("noise.rs")
     }
 
     fn step(&mut self, time: f64, _st: StepType, _ins: &[f32]) -> Vec<f32> {
-        match self.func {
-            NoiseFunc::White => vec![thread_rng().gen_range(-1.0..=1.0) * self.knobs[0]],
-            // NoiseFunc::Fractional(_p) => {
-            //     // FIXME actually do this
-            //     vec![thread_rng().gen_range(-1.0..=1.0) * self.knobs[0]]
-            // },
-
-            NoiseFunc::Perlin => vec![perlin(time) as f32 * self.knobs[0]],
-            NoiseFunc::Simplex => vec![simplex(time) as f32 * self.knobs[0]],
+        // Hold the last sample until the next one is due
+        let rate = f64::from(self.knobs[1]);
+        if let Some((t, x)) = self.hold {
+            if rate > 0.0 && time >= t && time - t < 1.0 / rate {
+                return vec![x * self.knobs[0]];
+            }
         }
+
+        let x = match self.func {
+            NoiseFunc::White => self.white(),
+            NoiseFunc::Pink => self.pink(),
+            NoiseFunc::Brown => {
+                let w = self.white();
+                self.brown = (self.brown + 0.02 * w) / 1.02;
+                (self.brown * 3.5).clamp(-1.0, 1.0)
+            },
+            NoiseFunc::Blue => {
+                let p = self.pink();
+                let y = ((p - self.last_pink) * 3.0).clamp(-1.0, 1.0);
+                self.last_pink = p;
+                y
+            },
+
+            NoiseFunc::Perlin => perlin(time) as f32,
+            NoiseFunc::Simplex => simplex(time) as f32,
+        };
+        self.hold = Some((time, x));
+
+        vec![x * self.knobs[0]]
     }
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
                 text.sections[2].value = format!("{:?}\n", self.func);
-                text.sections[3].value = format!("K0 Level: {}\n", self.knobs[0]);
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
             }
         }
     }
@@ -213,3 +287,58 @@ fn simplex(x: f64) -> f64 {
 
     0.395 * (n0+n1)
 }
+/// Improved 3-dimensional Perlin noise in the range [-1.0, 1.0]
+pub(crate) fn perlin3(mut x: f64, mut y: f64, mut z: f64) -> f64 {
+    fn fade(t: f64) -> f64 {
+        t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+    }
+    fn grad(hash: i32, x: f64, y: f64, z: f64) -> f64 {
+        let h = hash & 15;
+        let u = if h < 8 { x } else { y };
+        let v = if h < 4 {
+            y
+        } else if h == 12 || h == 14 {
+            x
+        } else {
+            z
+        };
+        (if h & 1 == 0 { u } else { -u })
+            + (if h & 2 == 0 { v } else { -v })
+    }
+    fn lerp(t: f64, a: f64, b: f64) -> f64 {
+        a + t * (b-a)
+    }
+
+    let p = |i: usize| PERM[i & 255] as usize;
+
+    let xi = x.floor() as i64 as usize & 255;
+    let yi = y.floor() as i64 as usize & 255;
+    let zi = z.floor() as i64 as usize & 255;
+    x -= x.floor();
+    y -= y.floor();
+    z -= z.floor();
+    let u = fade(x);
+    let v = fade(y);
+    let w = fade(z);
+
+    let a = p(xi) + yi;
+    let aa = p(a) + zi;
+    let ab = p(a + 1) + zi;
+    let b = p(xi + 1) + yi;
+    let ba = p(b) + zi;
+    let bb = p(b + 1) + zi;
+
+    lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, grad(PERM[aa & 255], x, y, z), grad(PERM[ba & 255], x-1.0, y, z)),
+            lerp(u, grad(PERM[ab & 255], x, y-1.0, z), grad(PERM[bb & 255], x-1.0, y-1.0, z)),
+        ),
+        lerp(
+            v,
+            lerp(u, grad(PERM[(aa+1) & 255], x, y, z-1.0), grad(PERM[(ba+1) & 255], x-1.0, y, z-1.0)),
+            lerp(u, grad(PERM[(ab+1) & 255], x, y-1.0, z-1.0), grad(PERM[(bb+1) & 255], x-1.0, y-1.0, z-1.0)),
+        ),
+    )
+}
@@ -0,0 +1,208 @@
+/*!
+The `NoiseField` module outputs a 2-dimensional noise signal for each pixel of
+the video scan, which evolves over time.
+
+## Noise Functions
+ * `Perlin` - fractal 3-dimensional Perlin noise sliced through time, the
+   default
+ * `Plasma` - the classic demoscene plasma made from summed sine waves
+ * `Grain` - an uncorrelated random value for each feature which changes K1
+   times per second, e.g. film grain when K0 matches the screen width, which is
+   reproducible when the rack's `seed` is set
+
+## Inputs
+None
+
+## Outputs
+0. The noise signal for each pixel in the range [0.0, K3] where K3 is knob 3
+
+## Knobs
+0. Scale in the range (0.0, inf), i.e. the number of features across the screen
+1. Speed in the range (-inf, inf)
+2. Octaves in the range [1.0, 8.0]
+3. Gain in the range [0.0, inf)
+
+*/
+
+use rand::Rng;
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, rack::module_rng, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut, noise::perlin3}};
+
+#[derive(Default, Deserialize, Debug, Clone)]
+enum NoiseFieldFunc {
+    #[default]
+    Perlin,
+    Plasma,
+    Grain,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NoiseField {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default)]
+    func: NoiseFieldFunc,
+    #[serde(skip)]
+    scan: usize,
+    #[serde(skip)]
+    seed: u64,
+
+    knobs: [f32; 4],
+}
+impl NoiseField {
+    const MAX_OCTAVES: usize = 8;
+
+    /// Hash the given lattice point into a random value in the range
+    /// [-1.0, 1.0]
+    fn hash(&self, x: i64, y: i64, z: i64) -> f64 {
+        let mut h = self.seed
+            ^ (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+            ^ (z as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+        h ^= h >> 30;
+        h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h ^= h >> 27;
+        h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^= h >> 31;
+        (h >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+    }
+}
+#[typetag::deserialize]
+impl Module for NoiseField {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Noise Field\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("Func: {:?}\n", self.func), ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, id: usize) {
+        self.seed = module_rng(id).gen();
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.scan = 0;
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        0
+    }
+    fn outputs(&self) -> usize {
+        1
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("noise_field.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, time: f64, _st: StepType, _ins: &[f32]) -> Vec<f32> {
+        let scale = f64::from(self.knobs[0]);
+        let t = time * f64::from(self.knobs[1]);
+
+        let x = (self.scan % ComponentVideoOut::WIDTH) as f64 / ComponentVideoOut::WIDTH as f64 * scale;
+        let y = (self.scan / ComponentVideoOut::WIDTH) as f64 / ComponentVideoOut::WIDTH as f64 * scale;
+
+        self.scan += 1;
+        self.scan %= ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT;
+
+        let n = match self.func {
+            NoiseFieldFunc::Perlin => {
+                let octaves = (self.knobs[2].round() as usize).clamp(1, Self::MAX_OCTAVES);
+                let (n, max) = (0..octaves)
+                    .fold((0.0, 0.0), |(n, max), o| {
+                        let f = f64::from(1 << o);
+                        let a = 1.0 / f;
+                        (n + perlin3(x * f, y * f, t * f) * a, max + a)
+                    });
+                n / max
+            },
+            NoiseFieldFunc::Plasma => {
+                let cx = x + 0.5 * (t / 5.0).sin();
+                let cy = y + 0.5 * (t / 3.0).cos();
+                (
+                    (x + t).sin()
+                    + ((y + t) / 2.0).sin()
+                    + ((x + y + t) / 2.0).sin()
+                    + ((cx * cx + cy * cy + 1.0).sqrt() + t).sin()
+                ) / 4.0
+            },
+            NoiseFieldFunc::Grain => self.hash(x.floor() as i64, y.floor() as i64, t.floor() as i64),
+        };
+
+        vec![((n as f32 + 1.0) / 2.0) * self.knobs[3]]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+            }
+        }
+    }
+}
@@ -12,9 +12,27 @@ The `Oscillator` module outputs a generated wave with a given gain.
  * `Horizontal` - Apply horizontal syncing, i.e. reset the phase every video
    frame
  * `Vertical` - Apply vertical syncing, i.e. reset the phase every video line
+ * `Hard` - Apply hard syncing, i.e. reset the phase whenever the sync input
+   rises above 0.0
+ * `Soft` - Apply soft syncing, i.e. reverse the direction of the wave whenever
+   the sync input rises above 0.0
+
+## Band-Limiting
+The `Square` and `Saw` waves alias audibly at high frequencies, so set
+`band_limited = true` to smooth their discontinuities with polyBLEP. This
+should usually be left off for video-rate use where the hard edges are wanted.
+
+```toml
+1 = { type = "Oscillator", func = "Saw", band_limited = true, knobs = [0.0, 880.0, 1.0, 0.0] }
+```
 
 ## Inputs
-None
+0. The sync signal for the `Hard` and `Soft` sync modes, usually another
+   `Oscillator`
+1. Phase modulation in the range (-inf, inf) in cycles, added to the phase at
+   audio rate
+2. Pulse width of the `Square` wave in the range [0.0, 1.0], or 0.5 if
+   unpatched
 
 ## Outputs
 0. The wave signal in the range [-K2, K2] where K2 is knob 2
@@ -33,7 +51,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut}};
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut}};
 
 #[derive(Default, Deserialize, Debug, Clone)]
 enum OscillatorFunc {
@@ -49,6 +67,8 @@ enum OscillatorSync {
     None,
     Horizontal,
     Vertical,
+    Hard,
+    Soft,
 }
 #[derive(Default, Deserialize, Debug, Clone)]
 pub struct Oscillator {
@@ -65,13 +85,37 @@ pub struct Oscillator {
     func: OscillatorFunc,
     #[serde(default)]
     sync: OscillatorSync,
+    #[serde(default)]
+    band_limited: bool,
     #[serde(skip)]
     sync_phase: f64,
     #[serde(skip)]
     sync_count: usize,
+    /// The time, synced time, and direction of the last hard or soft sync
+    #[serde(skip)]
+    sync_anchor: Option<(f64, f64, f64)>,
+    #[serde(skip)]
+    last_sync_in: f32,
+    #[serde(skip)]
+    last_time: Option<f64>,
 
     knobs: [f32; 4],
 }
+/// Get the polyBLEP residual for a discontinuity at the start of the cycle,
+/// where `p` is the position in the cycle and `dt` is the phase increment per
+/// step, both in cycles
+fn poly_blep(p: f64, dt: f64) -> f64 {
+    if p < dt {
+        let x = p / dt;
+        2.0 * x - x * x - 1.0
+    } else if p > 1.0 - dt {
+        let x = (p - 1.0) / dt;
+        x * x + 2.0 * x + 1.0
+    } else {
+        0.0
+    }
+}
+
 #[typetag::deserialize]
 impl Module for Oscillator {
     fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
@@ -115,6 +159,10 @@ impl Module for Oscillator {
         self.id = None;
         self.component = None;
         self.children = vec![];
+
+        self.sync_anchor = None;
+        self.last_sync_in = 0.0;
+        self.last_time = None;
     }
 
     fn id(&self) -> Option<usize> {
@@ -128,7 +176,7 @@ impl Module for Oscillator {
     }
 
     fn inputs(&self) -> usize {
-        0
+        3
     }
     fn outputs(&self) -> usize {
         1
@@ -136,6 +184,9 @@ impl Module for Oscillator {
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("oscillator.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -144,8 +195,8 @@ impl Module for Oscillator {
         self.knobs[i] = val;
     }
 
-    fn step(&mut self, time: f64, _st: StepType, _ins: &[f32]) -> Vec<f32> {
-        let t = time;
+    fn step(&mut self, time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let mut t = time;
         let shift = f64::from(self.knobs[0]);
         let speed = f64::from(self.knobs[1]);
         let depth = f64::from(self.knobs[2]);
@@ -173,16 +224,61 @@ impl Module for Oscillator {
                     self.sync_count = 0;
                 }
             },
+            OscillatorSync::Hard | OscillatorSync::Soft => { // Reset or reverse on the rising edge of the sync input
+                self.sync_phase = 0.0;
+                self.sync_count = 0;
+
+                let synced_time = |anchor: Option<(f64, f64, f64)>| match anchor {
+                    Some((t0, st0, dir)) => st0 + dir * (time - t0),
+                    None => time,
+                };
+                let s = ins[0];
+                if s > 0.0 && (self.last_sync_in.is_nan() || self.last_sync_in <= 0.0) {
+                    self.sync_anchor = Some(match self.sync {
+                        OscillatorSync::Hard => (time, 0.0, 1.0),
+                        _ => (time, synced_time(self.sync_anchor), -self.sync_anchor.map_or(1.0, |a| a.2)),
+                    });
+                }
+                self.last_sync_in = s;
+
+                t = synced_time(self.sync_anchor);
+            },
         }
         let phase = f64::from(self.knobs[3]) + self.sync_phase;
+        let pm = if ins[1].is_nan() {
+            0.0
+        } else {
+            f64::from(ins[1])
+        };
+        let width = if ins[2].is_nan() {
+            0.5
+        } else {
+            f64::from(ins[2].clamp(0.0, 1.0))
+        };
+
+        // The phase increment since the last step in cycles
+        let dt = self.last_time.map_or(0.0, |lt| (speed * (time - lt)).abs().min(0.5));
+        self.last_time = Some(time);
+        let is_band_limited = self.band_limited && dt > 0.0;
 
         let val = match self.func {
-            OscillatorFunc::Sine => (speed * t * 2.0*PI - phase).sin() * depth + shift,
-            OscillatorFunc::Triangle => 2.0 / PI * depth * ((speed * t * 2.0*PI - phase).sin()).asin() + shift,
-            OscillatorFunc::Square => if (speed * t * 2.0*PI - phase).sin() >= 0.0 { depth+shift } else { -depth+shift },
+            OscillatorFunc::Sine => (speed * t * 2.0*PI - phase - pm * 2.0*PI).sin() * depth + shift,
+            OscillatorFunc::Triangle => 2.0 / PI * depth * ((speed * t * 2.0*PI - phase - pm * 2.0*PI).sin()).asin() + shift,
+            OscillatorFunc::Square => {
+                let cycle = ((speed * t * 2.0*PI - phase) / (2.0*PI) - pm).rem_euclid(1.0);
+                let mut y = if cycle < width { 1.0 } else { -1.0 };
+                if is_band_limited {
+                    y += poly_blep(cycle, dt) - poly_blep((cycle - width).rem_euclid(1.0), dt);
+                }
+                y * depth + shift
+            },
             OscillatorFunc::Saw => {
-                let tp = (t - phase) * speed;
-                2.0 * (tp - (0.5 + tp).floor()) * depth + shift
+                let tp = (t - phase) * speed - pm;
+                let mut y = 2.0 * (tp - (0.5 + tp).floor());
+                if is_band_limited {
+                    y -= poly_blep((tp + 0.5).rem_euclid(1.0), dt);
+                }
+                y * depth + shift
             },
         };
 
@@ -195,10 +291,10 @@ impl Module for Oscillator {
             if let Ok(mut text) = q_text.get_mut(*component) {
                 text.sections[1].value = format!("Func: {:?}\n", self.func);
                 text.sections[2].value = format!("Sync: {:?}\n", self.sync);
-                text.sections[3].value = format!("K0 Shift: {}\n", self.knobs[0]);
-                text.sections[4].value = format!("K1 Speed: {}\n", self.knobs[1]);
-                text.sections[5].value = format!("K2 Depth: {}\n", self.knobs[2]);
-                text.sections[6].value = format!("K3 Phase: {}\n", self.knobs[3]);
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[6].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
             }
         }
     }
@@ -14,6 +14,11 @@ None
 ## Knobs
 None
 
+## Mouse
+ * Left click to freeze or unfreeze the current trace
+ * Right click to export the current trace to a CSV file (and a PNG file if the
+   `export` feature is enabled) in the `exports` directory
+
 */
 
 use std::collections::VecDeque;
@@ -22,7 +27,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::{Mesh2dHandle, Mater
 
 use serde::Deserialize;
 
-use crate::{StepType, CameraComponent, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleMeshComponent, ModuleImageComponent, ModuleImageWindowComponent}};
+use crate::{StepType, modules::{Module, ModuleDescription, WindowOptions, MouseClick, ModuleComponent, ModuleTextComponent, ModuleMeshComponent, ModuleImageComponent, ModuleImageWindowComponent}};
 
 #[derive(Default, Deserialize, Debug, Clone)]
 pub struct Oscilloscope {
@@ -46,9 +51,15 @@ pub struct Oscilloscope {
     vals: [VecDeque<(f64, f32)>; Oscilloscope::MAX_GRAPHS],
     #[serde(skip)]
     cycles: [usize; Oscilloscope::MAX_GRAPHS],
+    #[serde(skip)]
+    is_frozen: bool,
+    #[serde(skip)]
+    should_export: bool,
 
     #[serde(default)]
     is_own_window: bool,
+    #[serde(default)]
+    window: WindowOptions,
 }
 impl Oscilloscope {
     const WIDTH: usize = 150;
@@ -95,11 +106,55 @@ impl Oscilloscope {
         }
         points
     }
+    fn export(&self, points: &[Vec<Vec3>; Oscilloscope::MAX_GRAPHS]) {
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => format!("M{}-Oscilloscope", self.id.unwrap_or_default()),
+        };
+
+        let rows = self.vals.iter()
+            .enumerate()
+            .flat_map(|(i, vals)| {
+                vals.iter()
+                    .map(move |(t, v)| vec![i as f64, *t, f64::from(*v)])
+            });
+        if let Err(e) = crate::export::export_csv(&name, &["input", "time", "value"], rows) {
+            error!("Failed to export Oscilloscope CSV: {e}");
+        }
+
+        #[cfg(feature = "export")]
+        {
+            let colors = [
+                [0, 255, 0],
+                [255, 0, 0],
+                [255, 255, 0],
+                [0, 0, 255],
+            ];
+            let mut rgba = [0, 0, 0, 255].repeat(Self::WIDTH * Self::HEIGHT);
+            for (i, points) in points.iter().enumerate() {
+                for p in points {
+                    // Match the mesh transform which is centered and scaled by half
+                    let x = p.x as isize;
+                    let y = (f32::from(Self::HEIGHT as u16) / 2.0 - p.y * 0.5) as isize;
+                    if x >= 0 && (x as usize) < Self::WIDTH && y >= 0 && (y as usize) < Self::HEIGHT {
+                        let idx = (y as usize * Self::WIDTH + x as usize) * 4;
+                        rgba[idx..idx+3].copy_from_slice(&colors[i]);
+                    }
+                }
+            }
+            if let Err(e) = crate::export::export_png(&name, Self::WIDTH, Self::HEIGHT, &rgba) {
+                error!("Failed to export Oscilloscope PNG: {e}");
+            }
+        }
+        #[cfg(not(feature = "export"))]
+        let _ = points;
+    }
 }
 #[typetag::deserialize]
 impl Module for Oscilloscope {
     fn init(&mut self, id: usize, mut ec: EntityCommands, images: &mut ResMut<Assets<Image>>, meshes: &mut ResMut<Assets<Mesh>>, materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
         self.id = Some(id);
+        self.init_state(id);
 
         let size = Extent3d {
             width: Self::WIDTH as u32,
@@ -172,7 +227,6 @@ impl Module for Oscilloscope {
             UiCameraConfig {
                 show_ui: false,
             },
-            CameraComponent,
             layer,
         ));
 
@@ -198,7 +252,8 @@ impl Module for Oscilloscope {
                         TextBundle::from_sections([
                             TextSection::new(name, ts.clone()),
                             TextSection::new("Average\n", ts.clone()),
-                            TextSection::new("Max\n", ts),
+                            TextSection::new("Max\n", ts.clone()),
+                            TextSection::new("", ts),
                         ]),
                         ModuleTextComponent,
                     )).id()
@@ -238,7 +293,8 @@ impl Module for Oscilloscope {
                 ModuleImageWindowComponent,
             ));
         }
-
+    }
+    fn init_state(&mut self, _id: usize) {
         self.vals = vec![VecDeque::with_capacity(512); Oscilloscope::MAX_GRAPHS]
             .try_into()
             .unwrap();
@@ -248,10 +304,16 @@ impl Module for Oscilloscope {
         self.component = None;
         self.mesh = None;
         self.children = vec![];
+
+        self.is_frozen = false;
+        self.should_export = false;
     }
     fn is_own_window(&self) -> bool {
         self.is_own_window
     }
+    fn window_options(&self) -> WindowOptions {
+        self.window.clone()
+    }
 
     fn id(&self) -> Option<usize> {
         self.id
@@ -272,9 +334,19 @@ impl Module for Oscilloscope {
     fn knobs(&self) -> usize {
         0
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("oscilloscope.rs")
+    }
 
+    fn mouse_click(&mut self, mouse_click: MouseClick) {
+        match mouse_click.button {
+            MouseButton::Left => self.is_frozen = !self.is_frozen,
+            MouseButton::Right => self.should_export = true,
+            _ => {},
+        }
+    }
     fn step(&mut self, time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
-        if st == StepType::Video {
+        if st == StepType::Video || self.is_frozen {
             return vec![];
         }
 
@@ -315,10 +387,21 @@ impl Module for Oscilloscope {
                     });
                 text.sections[1].value = format!("Average: {:+}\n", avg);
                 text.sections[2].value = format!("Max: {}\n", max);
+                text.sections[3].value = if self.is_frozen {
+                    "Frozen\n".to_string()
+                } else {
+                    String::new()
+                };
             }
         }
 
-        for (i, gen_points) in self.gen_points().into_iter().enumerate() {
+        let points = self.gen_points();
+        if self.should_export {
+            self.should_export = false;
+            self.export(&points);
+        }
+
+        for (i, gen_points) in points.into_iter().enumerate() {
             if let Some(component) = self.mesh {
                 if let Ok(h_mesh) = q_mesh.get_mut(component[i]) {
                     if let Some(mesh) = meshes.get_mut(&h_mesh.0) {
@@ -0,0 +1,244 @@
+/*!
+The `ReactionDiffusion` module outputs a signal based on a Gray-Scott
+reaction-diffusion simulation.
+
+The simulation contains two chemicals, U and V, which react and diffuse across
+the grid. The grid is initially filled with U and seeded with random patches of
+V based on the given `seed` and `density`. If no `seed` is given, the rack's
+seed is used instead.
+
+## Inputs
+0. Whether to reset the simulation, any non-zero value for yes
+
+## Outputs
+0. The concentration of V for each pixel in the range [0.0, 1.0]
+1. The concentration of U for each pixel in the range [0.0, 1.0]
+
+## Knobs
+0. Feed rate in the range [0.0, 0.1], e.g. 0.055
+1. Kill rate in the range [0.0, 0.1], e.g. 0.062
+2. Diffusion rate of U in the range [0.0, 1.0], e.g. 1.0
+3. Diffusion rate of V in the range [0.0, 1.0], e.g. 0.5
+4. Simulation iterations per frame in the range [0.0, inf)
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::{StepType, rack::module_rng, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut}};
+
+fn default_density() -> f64 {
+    0.05
+}
+
+#[derive(Default, Deserialize, Debug, Clone)]
+pub struct ReactionDiffusion {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default)]
+    seed: String,
+    #[serde(default = "default_density")]
+    density: f64,
+    #[serde(skip)]
+    grid: Option<Vec<[f32; 2]>>,
+    #[serde(skip)]
+    scan: usize,
+
+    knobs: [f32; 5],
+}
+impl ReactionDiffusion {
+    const SEED_SIZE: usize = 3;
+
+    fn init_grid(&mut self) {
+        let mut rng: rand::rngs::StdRng = if self.seed.is_empty() {
+            module_rng(self.id.unwrap_or_default())
+        } else {
+            let seed = self.seed.chars()
+                .map(|c| c as u8)
+                .chain([0u8; 32])
+                .collect::<Vec<u8>>();
+            rand::SeedableRng::from_seed(seed[..32].try_into().unwrap())
+        };
+
+        let mut grid = vec![[1.0, 0.0]; ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT];
+        for y in 0..ComponentVideoOut::HEIGHT {
+            for x in 0..ComponentVideoOut::WIDTH {
+                if rng.gen_bool(self.density / (Self::SEED_SIZE * Self::SEED_SIZE) as f64) {
+                    for dy in 0..Self::SEED_SIZE {
+                        for dx in 0..Self::SEED_SIZE {
+                            let i = ((y + dy) % ComponentVideoOut::HEIGHT) * ComponentVideoOut::WIDTH
+                                + (x + dx) % ComponentVideoOut::WIDTH;
+                            grid[i] = [0.5, 0.25];
+                        }
+                    }
+                }
+            }
+        }
+        self.grid = Some(grid);
+    }
+    fn simulate(&mut self) {
+        let feed = self.knobs[0];
+        let kill = self.knobs[1];
+        let du = self.knobs[2];
+        let dv = self.knobs[3];
+
+        if let Some(grid) = &mut self.grid {
+            let w = ComponentVideoOut::WIDTH;
+            let h = ComponentVideoOut::HEIGHT;
+            let old = grid.clone();
+            for y in 0..h {
+                for x in 0..w {
+                    let xm = (x + w - 1) % w;
+                    let xp = (x + 1) % w;
+                    let ym = (y + h - 1) % h;
+                    let yp = (y + 1) % h;
+
+                    // Laplacian with a 3x3 convolution
+                    let laplacian = |c: usize| {
+                        -old[y*w + x][c]
+                        + 0.2 * (old[ym*w + x][c] + old[yp*w + x][c] + old[y*w + xm][c] + old[y*w + xp][c])
+                        + 0.05 * (old[ym*w + xm][c] + old[ym*w + xp][c] + old[yp*w + xm][c] + old[yp*w + xp][c])
+                    };
+
+                    let [u, v] = old[y*w + x];
+                    let uvv = u * v * v;
+                    grid[y*w + x] = [
+                        (u + du * laplacian(0) - uvv + feed * (1.0 - u)).clamp(0.0, 1.0),
+                        (v + dv * laplacian(1) + uvv - (kill + feed) * v).clamp(0.0, 1.0),
+                    ];
+                }
+            }
+        }
+    }
+}
+#[typetag::deserialize]
+impl Module for ReactionDiffusion {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Reaction Diffusion\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("Seed: {}\n", self.seed), ts.clone()),
+                            TextSection::new(format!("Density: {}\n", self.density), ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts.clone()),
+                            TextSection::new("K4\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+
+        if self.grid.is_none() {
+            self.init_grid();
+        }
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.grid = None;
+        self.scan = 0;
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        1
+    }
+    fn outputs(&self) -> usize {
+        2
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("reaction_diffusion.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let reset = ins[0];
+        if !reset.is_nan() && reset != 0.0 {
+            self.grid = None;
+            self.scan = 0;
+        }
+
+        if self.grid.is_none() {
+            self.init_grid();
+        }
+
+        let [u, v] = self.grid.as_ref()
+            .map_or([1.0, 0.0], |grid| grid[self.scan]);
+
+        self.scan += 1;
+        self.scan %= ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT;
+
+        if self.scan == 0 {
+            for _ in 0..(self.knobs[4].max(0.0) as usize) {
+                self.simulate();
+            }
+        }
+
+        vec![v, u]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[6].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+                text.sections[7].value = format!("{}\n", self.describe().knob_row(4, self.knobs[4]));
+            }
+        }
+    }
+}
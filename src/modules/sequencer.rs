@@ -30,6 +30,8 @@ None
 
 ## Knobs
 0. Tempo in the range (0.0, inf)
+1. Swing in the range [0.0, 1.0), the amount to delay every other sixteenth
+   note as a fraction of its length, which is 0.0 when only the tempo is given
 
 */
 
@@ -37,7 +39,7 @@ use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
 
 use serde::Deserialize;
 
-use crate::{StepType, modules::{Module, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+use crate::{StepType, modules::{self, Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Sequencer {
@@ -59,7 +61,38 @@ pub struct Sequencer {
     #[serde(skip)]
     pub(crate) last_time: Option<f64>,
 
-    knobs: [f32; 1],
+    #[serde(deserialize_with = "deserialize_knobs")]
+    knobs: [f32; 2],
+}
+/// Racks from before the swing knob was added only give the tempo
+fn deserialize_knobs<'de, D>(deserializer: D) -> Result<[f32; 2], D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    modules::deserialize_knobs(deserializer, 1, [0.0, 0.0])
+}
+impl Sequencer {
+    const SUBDIVISION: f64 = 0.25;
+
+    fn unswing(beat: f64, swing: f64) -> f64 {
+        let swing = swing.clamp(0.0, 0.99);
+        if swing == 0.0 {
+            return beat;
+        }
+
+        let pair = Self::SUBDIVISION * 2.0;
+        let pos = beat.rem_euclid(pair);
+        let offbeat = Self::SUBDIVISION * (1.0 + swing);
+
+        // Stretch the onbeat and squash the offbeat of each pair
+        let straight = if pos < offbeat {
+            pos / (1.0 + swing)
+        } else {
+            Self::SUBDIVISION + (pos - offbeat) / (1.0 - swing)
+        };
+
+        beat - pos + straight
+    }
 }
 #[typetag::deserialize]
 impl Module for Sequencer {
@@ -86,7 +119,8 @@ impl Module for Sequencer {
                     parent.spawn((
                         TextBundle::from_sections([
                             TextSection::new(name, ts.clone()),
-                            TextSection::new("K0\n", ts),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts),
                         ]),
                         ModuleTextComponent,
                     )).id()
@@ -120,6 +154,9 @@ impl Module for Sequencer {
     fn knobs(&self) -> usize {
         self.knobs.len()
     }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("sequencer.rs")
+    }
 
     fn get_knobs(&self) -> Vec<f32> {
         self.knobs.to_vec()
@@ -142,12 +179,12 @@ impl Module for Sequencer {
         self.time %= length as f64 * 60.0 / tempo as f64;
         self.last_time = Some(time);
 
-        let mut time_left = self.time;
+        let mut beats_left = Self::unswing(self.time * tempo as f64 / 60.0, f64::from(self.knobs[1]));
         for (i, n) in self.notes.iter()
             .enumerate()
         {
-            time_left -= n.2 as f64 * 60.0 / tempo as f64;
-            if time_left < 0.0 {
+            beats_left -= n.2 as f64;
+            if beats_left < 0.0 {
                 let note = match self.last_note {
                     Some(last_note) if last_note == i => {
                         (n.0, n.1, 0.0)
@@ -175,7 +212,8 @@ impl Module for Sequencer {
     fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
         if let Some(component) = self.children.get(0) {
             if let Ok(mut text) = q_text.get_mut(*component) {
-                text.sections[1].value = format!("K0 Tempo: {}\n", self.knobs[0]);
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
             }
         }
     }
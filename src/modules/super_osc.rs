@@ -0,0 +1,234 @@
+/*!
+The `SuperOsc` module outputs a stack of detuned unison voices of the same
+wave, e.g. the classic supersaw, spread across the stereo field.
+
+The number of voices is given by `voices` and defaults to 7. The voices are
+detuned evenly between [-K1, K1] semitones around the center frequency and
+panned evenly between the left and right according to the spread. Each voice
+starts at a random phase which is reproducible when the rack's `seed` is set.
+
+```toml
+1 = { type = "SuperOsc", func = "Saw", voices = 7, knobs = [220.0, 0.2, 1.0, 1.0] }
+```
+
+## Wave Functions
+ * `Sine` - a smooth wave
+ * `Triangle` - a linear sine-like wave
+ * `Square` - a binary wave, for a super-square
+ * `Saw` - a linear ramp-like wave, for a supersaw, the default
+
+## Inputs
+None
+
+## Outputs
+0. The summed signal of all voices in the range [-K3, K3] where K3 is knob 3
+1. The left channel of the spread voices
+2. The right channel of the spread voices
+
+## Knobs
+0. Frequency in the range (-inf, inf) in Hz
+1. Detune in the range [0.0, inf) in semitones for the outermost voices
+2. Stereo spread in the range [0.0, 1.0]
+3. Depth in the range [0.0, inf), equivalent to the gain
+
+*/
+
+use std::f64::consts::PI;
+
+use rand::prelude::*;
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, rack::module_rng, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Default, Deserialize, Debug, Clone)]
+enum SuperOscFunc {
+    Sine,
+    Triangle,
+    Square,
+    #[default]
+    Saw,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SuperOsc {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default)]
+    func: SuperOscFunc,
+    #[serde(default = "default_voices")]
+    voices: usize,
+
+    #[serde(skip)]
+    phases: Vec<f64>,
+    #[serde(skip)]
+    last_time: Option<f64>,
+
+    knobs: [f32; 4],
+}
+fn default_voices() -> usize {
+    7
+}
+impl SuperOsc {
+    const MAX_VOICES: usize = 16;
+
+    /// Get the position of the given voice in the range [-1.0, 1.0]
+    fn position(&self, i: usize) -> f64 {
+        if self.voices > 1 {
+            2.0 * i as f64 / (self.voices - 1) as f64 - 1.0
+        } else {
+            0.0
+        }
+    }
+    fn wave(&self, p: f64) -> f64 {
+        match self.func {
+            SuperOscFunc::Sine => (2.0 * PI * p).sin(),
+            SuperOscFunc::Triangle => 1.0 - 4.0 * (p - 0.25 - (p + 0.25).floor()).abs(),
+            SuperOscFunc::Square => if p < 0.5 { 1.0 } else { -1.0 },
+            SuperOscFunc::Saw => 2.0 * p - 1.0,
+        }
+    }
+}
+#[typetag::deserialize]
+impl Module for SuperOsc {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} SuperOsc\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("Func\n", ts.clone()),
+                            TextSection::new("Voices\n", ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, id: usize) {
+        if self.voices == 0 || self.voices > Self::MAX_VOICES {
+            panic!("Invalid voices for SuperOsc: {} must be between 1 and {}", self.voices, Self::MAX_VOICES);
+        }
+
+        let mut rng = module_rng(id);
+        self.phases = (0..self.voices)
+            .map(|_| rng.gen_range(0.0..1.0))
+            .collect();
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.phases.clear();
+        self.last_time = None;
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        0
+    }
+    fn outputs(&self) -> usize {
+        3
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("super_osc.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, time: f64, _st: StepType, _ins: &[f32]) -> Vec<f32> {
+        let freq = f64::from(self.knobs[0]);
+        let detune = f64::from(self.knobs[1].max(0.0));
+        let spread = f64::from(self.knobs[2].clamp(0.0, 1.0));
+        let depth = f64::from(self.knobs[3]);
+
+        // Advance by the elapsed time so that audio and video steps stay in
+        // phase with each other
+        let dt = self.last_time.map_or(0.0, |lt| time - lt);
+        self.last_time = Some(time);
+
+        let (mut mono, mut left, mut right) = (0.0, 0.0, 0.0);
+        for i in 0..self.phases.len() {
+            let pos = self.position(i);
+            let y = self.wave(self.phases[i]);
+            mono += y;
+            left += y * (1.0 - pos * spread);
+            right += y * (1.0 + pos * spread);
+
+            let f = freq * 2.0f64.powf(pos * detune / 12.0);
+            self.phases[i] = (self.phases[i] + f * dt).rem_euclid(1.0);
+        }
+
+        let n = self.phases.len().max(1) as f64;
+        vec![
+            (mono / n * depth) as f32,
+            (left / n * depth) as f32,
+            (right / n * depth) as f32,
+        ]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("Func: {:?}\n", self.func);
+                text.sections[2].value = format!("Voices: {}\n", self.voices);
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[6].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+            }
+        }
+    }
+}
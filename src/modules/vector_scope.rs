@@ -0,0 +1,341 @@
+/*!
+The `VectorScope` module takes 2 inputs and plots them against each other,
+useful for viewing stereo imaging or for drawing Lissajous figures.
+
+## Modes
+ * `XY` - plot input 0 horizontally and input 1 vertically, the default
+ * `MidSide` - rotate the plot by 45 degrees so that a mono signal is vertical,
+   like a traditional stereo vectorscope
+
+## Inputs
+0. The X signal or the left channel
+1. The Y signal or the right channel
+
+## Outputs
+None
+
+## Knobs
+0. Persistence in the range [0.0, 1.0] where 0.0 clears the trace every frame
+1. Scale in the range (0.0, inf) where 1.0 fits the range [-1.0, 1.0]
+
+## Mouse
+ * Left click to freeze or unfreeze the current trace
+ * Right click to export the current trace to a PNG file in the `exports`
+   directory, if the `export` feature is enabled
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle, render::render_resource::{Extent3d, TextureDescriptor, TextureFormat, TextureUsages, TextureDimension}};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, WindowOptions, MouseClick, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, ModuleImageWindowComponent}};
+
+#[derive(Default, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+enum VectorScopeMode {
+    #[default]
+    XY,
+    MidSide,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct VectorScope {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(default)]
+    mode: VectorScopeMode,
+    #[serde(skip)]
+    points: Vec<(f32, f32)>,
+    #[serde(skip)]
+    last_point: Option<(isize, isize)>,
+    #[serde(skip)]
+    is_frozen: bool,
+    #[serde(skip)]
+    should_export: bool,
+
+    #[serde(default)]
+    is_own_window: bool,
+    #[serde(default)]
+    window: WindowOptions,
+
+    knobs: [f32; 2],
+}
+impl VectorScope {
+    const WIDTH: usize = 150;
+    const HEIGHT: usize = 150;
+    const MAX_LEN: usize = 8192;
+
+    fn to_pixel(&self, x: f32, y: f32) -> (isize, isize) {
+        let (x, y) = match self.mode {
+            VectorScopeMode::XY => (x, y),
+            VectorScopeMode::MidSide => (
+                (x - y) * std::f32::consts::FRAC_1_SQRT_2,
+                (x + y) * std::f32::consts::FRAC_1_SQRT_2,
+            ),
+        };
+
+        let scale = self.knobs[1];
+        (
+            ((x * scale + 1.0) / 2.0 * Self::WIDTH as f32) as isize,
+            ((1.0 - y * scale) / 2.0 * Self::HEIGHT as f32) as isize,
+        )
+    }
+    fn plot(data: &mut [u8], x: isize, y: isize) {
+        if x >= 0 && (x as usize) < Self::WIDTH && y >= 0 && (y as usize) < Self::HEIGHT {
+            let idx = (y as usize * Self::WIDTH + x as usize) * 4;
+            data[idx] = 64;
+            data[idx+1] = 255;
+            data[idx+2] = 64;
+        }
+    }
+    fn draw_line(data: &mut [u8], (x0, y0): (isize, isize), (x1, y1): (isize, isize)) {
+        // Bresenham's line algorithm
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let sx = if x0 < x1 { 1 } else { -1 };
+        let sy = if y0 < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+        for _ in 0..(Self::WIDTH + Self::HEIGHT) * 2 {
+            Self::plot(data, x, y);
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+}
+#[typetag::deserialize]
+impl Module for VectorScope {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+
+        let size = Extent3d {
+            width: Self::WIDTH as u32,
+            height: Self::HEIGHT as u32,
+            ..default()
+        };
+        let mut image = Image {
+            texture_descriptor: TextureDescriptor {
+                label: None,
+                size,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                mip_level_count: 1,
+                sample_count: 1,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            ..default()
+        };
+        image.resize(size);
+        image.data = [0, 0, 0, 255].repeat(image.data.len() / 4);
+        let image_handle = images.add(image);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Vector Scope\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+
+                self.children.push(
+                    parent.spawn((
+                        ImageBundle {
+                            style: Style {
+                                position_type: PositionType::Relative,
+                                top: Val::Px(10.0),
+                                width: Val::Px(f32::from(Self::WIDTH as u16)),
+                                height: Val::Px(f32::from(Self::HEIGHT as u16)),
+                                ..default()
+                            },
+                            image: UiImage::new(image_handle.clone()),
+                            ..default()
+                        },
+                        ModuleImageComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+
+        if self.is_own_window() {
+            ec.commands().spawn((
+                SpriteBundle {
+                    texture: image_handle,
+                    sprite: Sprite {
+                        custom_size: Some(Vec2::new(100.0, 100.0)),
+                        ..default()
+                    },
+                    transform: Transform::from_xyz(640.0*id as f32, 1080.0*2.0, 0.0),
+                    ..default()
+                },
+                ModuleImageWindowComponent,
+            ));
+        }
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.points.clear();
+        self.last_point = None;
+        self.is_frozen = false;
+        self.should_export = false;
+    }
+    fn is_own_window(&self) -> bool {
+        self.is_own_window
+    }
+    fn window_options(&self) -> WindowOptions {
+        self.window.clone()
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        2
+    }
+    fn outputs(&self) -> usize {
+        0
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("vector_scope.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn mouse_click(&mut self, mouse_click: MouseClick) {
+        match mouse_click.button {
+            MouseButton::Left => self.is_frozen = !self.is_frozen,
+            MouseButton::Right => self.should_export = true,
+            _ => {},
+        }
+    }
+    fn step(&mut self, _time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+        if st == StepType::Video || self.is_frozen {
+            return vec![];
+        }
+
+        let x = ins[0];
+        let y = ins[1];
+        if x.is_nan() && y.is_nan() {
+            return vec![];
+        }
+
+        if self.points.len() < Self::MAX_LEN {
+            self.points.push((
+                if x.is_nan() { 0.0 } else { x },
+                if y.is_nan() { 0.0 } else { y },
+            ));
+        }
+
+        vec![]
+    }
+    fn render(&mut self, images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+            }
+        }
+
+        if let Some(component) = self.children.get(1) {
+            if let Ok(h_image) = q_image.get_mut(*component) {
+                if let Some(image) = images.get_mut(&h_image.texture) {
+                    if self.should_export {
+                        self.should_export = false;
+
+                        #[cfg(feature = "export")]
+                        {
+                            let name = match &self.name {
+                                Some(name) => name.clone(),
+                                None => format!("M{}-VectorScope", self.id.unwrap_or_default()),
+                            };
+                            if let Err(e) = crate::export::export_png(&name, Self::WIDTH, Self::HEIGHT, &image.data) {
+                                error!("Failed to export VectorScope PNG: {e}");
+                            }
+                        }
+                    }
+                    if self.is_frozen {
+                        return;
+                    }
+
+                    // Fade the previous trace
+                    let persistence = self.knobs[0].clamp(0.0, 1.0);
+                    for px in image.data.chunks_exact_mut(4) {
+                        px[0] = (f32::from(px[0]) * persistence) as u8;
+                        px[1] = (f32::from(px[1]) * persistence) as u8;
+                        px[2] = (f32::from(px[2]) * persistence) as u8;
+                    }
+
+                    let points: Vec<(isize, isize)> = self.points.iter()
+                        .map(|(x, y)| self.to_pixel(*x, *y))
+                        .collect();
+                    self.points.clear();
+                    for p in points {
+                        match self.last_point {
+                            Some(lp) => Self::draw_line(&mut image.data, lp, p),
+                            None => Self::plot(&mut image.data, p.0, p.1),
+                        }
+                        self.last_point = Some(p);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,208 @@
+/*!
+The `DiffKey` module takes 6 inputs and outputs the first 3 wherever they
+differ from a captured reference frame, and the second 3 elsewhere. This allows
+a subject to be keyed out from a static background without a green screen.
+
+The reference frame is captured from the first 3 inputs over the first full
+frame and again over the next full frame whenever the capture input rises
+above 0.0. The edges of the key are blended over the given softness.
+
+## Inputs
+0. First red channel in the range [0.0, 1.0]
+1. First green channel in the range [0.0, 1.0]
+2. First blue channel in the range [0.0, 1.0]
+3. Second red channel in the range [0.0, 1.0]
+4. Second green channel in the range [0.0, 1.0]
+5. Second blue channel in the range [0.0, 1.0]
+6. Capture trigger
+
+## Outputs
+0. Red channel in the range [0.0, 1.0]
+1. Green channel in the range [0.0, 1.0]
+2. Blue channel in the range [0.0, 1.0]
+
+##### Note
+If all inputs are [f32::NAN] (unpatched), the output will be [f32::NAN].
+Otherwise the signal that's not NAN will be used. If only some channels are
+NAN, then NANs are treated as 0.0.
+
+##### Note
+While the reference frame is being captured, the first 3 inputs are output.
+
+## Knobs
+0. Difference threshold in the range [0.0, 1.0]
+1. Softness in the range [0.0, 1.0]
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut, video::luma_key::{key_inputs, key_mix, soft_edge}}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DiffKey {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    scan: usize,
+    #[serde(skip)]
+    reference: Vec<[f32; 3]>,
+    #[serde(skip)]
+    is_capture_pending: bool,
+    #[serde(skip)]
+    is_capturing: bool,
+    #[serde(skip)]
+    last_trigger: f32,
+
+    knobs: [f32; 2],
+}
+#[typetag::deserialize]
+impl Module for DiffKey {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} DiffKey\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("Waiting\n", ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, _id: usize) {
+        self.scan = 0;
+        self.reference = vec![[0.0; 3]; ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT];
+        self.is_capture_pending = true;
+        self.is_capturing = false;
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.reference = vec![];
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        7
+    }
+    fn outputs(&self) -> usize {
+        3
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("video/diff_key.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        if self.reference.is_empty() {
+            return vec![f32::NAN; 3];
+        }
+
+        let trigger = if ins[6].is_nan() {
+            0.0
+        } else {
+            ins[6]
+        };
+        if trigger > 0.0 && self.last_trigger <= 0.0 {
+            self.is_capture_pending = true;
+        }
+        self.last_trigger = trigger;
+
+        if self.scan == 0 && self.is_capture_pending {
+            self.is_capture_pending = false;
+            self.is_capturing = true;
+        }
+
+        let scan = self.scan;
+        self.scan += 1;
+        if self.scan >= self.reference.len() {
+            self.scan = 0;
+            self.is_capturing = false;
+        }
+
+        let (c0, c1) = match key_inputs(&ins[0..6]) {
+            Ok(cs) => cs,
+            Err(out) => return out,
+        };
+        if self.is_capturing {
+            self.reference[scan] = c0;
+            return c0.to_vec();
+        }
+
+        let r = self.reference[scan];
+        let diff = c0.iter()
+            .zip(r)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        let alpha = soft_edge(self.knobs[0], self.knobs[1].clamp(0.0, 1.0), diff);
+
+        key_mix(c0, c1, alpha)
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = if self.is_capturing || self.is_capture_pending {
+                    "Capturing\n".to_string()
+                } else {
+                    "Keying\n".to_string()
+                };
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+            }
+        }
+    }
+}
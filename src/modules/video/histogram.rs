@@ -0,0 +1,231 @@
+/*!
+The `Histogram` module takes 3 inputs and displays the distribution of their
+levels over each frame of the video scan.
+
+The red, green, and blue distributions are drawn on top of each other so that
+overlapping levels mix together, with the darkest levels on the left and the
+brightest levels on the right.
+
+## Inputs
+0. Red channel in the range [0.0, 1.0]
+1. Green channel in the range [0.0, 1.0]
+2. Blue channel in the range [0.0, 1.0]
+
+## Outputs
+None
+
+## Knobs
+0. Vertical gain in the range (0.0, inf)
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle, render::render_resource::{Extent3d, TextureDescriptor, TextureFormat, TextureUsages, TextureDimension}};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Histogram {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    scan: usize,
+    #[serde(skip)]
+    bins: Vec<[usize; 3]>,
+    #[serde(skip)]
+    frame: Vec<[usize; 3]>,
+
+    knobs: [f32; 1],
+}
+impl Histogram {
+    const WIDTH: usize = 150;
+    const HEIGHT: usize = 100;
+}
+#[typetag::deserialize]
+impl Module for Histogram {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        let size = Extent3d {
+            width: Self::WIDTH as u32,
+            height: Self::HEIGHT as u32,
+            ..default()
+        };
+        let mut image = Image {
+            texture_descriptor: TextureDescriptor {
+                label: None,
+                size,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                mip_level_count: 1,
+                sample_count: 1,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            ..default()
+        };
+        image.resize(size);
+        image.data = [0, 0, 0, 255].repeat(image.data.len() / 4);
+        let image_handle = images.add(image);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Histogram\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("K0\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+
+                self.children.push(
+                    parent.spawn((
+                        ImageBundle {
+                            style: Style {
+                                position_type: PositionType::Relative,
+                                top: Val::Px(10.0),
+                                width: Val::Px(f32::from(Self::WIDTH as u16)),
+                                height: Val::Px(f32::from(Self::HEIGHT as u16)),
+                                ..default()
+                            },
+                            image: UiImage::new(image_handle),
+                            ..default()
+                        },
+                        ModuleImageComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, _id: usize) {
+        self.bins = vec![[0; 3]; Self::WIDTH];
+        self.frame = vec![[0; 3]; Self::WIDTH];
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.scan = 0;
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        3
+    }
+    fn outputs(&self) -> usize {
+        0
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("video/histogram.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        if ins.iter().all(|i| i.is_nan()) || self.bins.is_empty() {
+            return vec![];
+        }
+
+        for (c, v) in ins.iter().enumerate() {
+            let v = if v.is_nan() {
+                0.0
+            } else {
+                v.clamp(0.0, 1.0)
+            };
+            let bin = (v * (Self::WIDTH - 1) as f32).round() as usize;
+            self.bins[bin][c] += 1;
+        }
+
+        self.scan += 1;
+        if self.scan >= ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT {
+            self.scan = 0;
+            self.frame = std::mem::replace(&mut self.bins, vec![[0; 3]; Self::WIDTH]);
+        }
+
+        vec![]
+    }
+    fn render(&mut self, images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+            }
+        }
+
+        if let Some(component) = self.children.get(1) {
+            if let Ok(h_image) = q_image.get_mut(*component) {
+                if let Some(image) = images.get_mut(&h_image.texture) {
+                    let max = self.frame.iter()
+                        .flatten()
+                        .copied()
+                        .max()
+                        .unwrap_or(0)
+                        .max(1) as f32;
+
+                    for (x, bin) in self.frame.iter().enumerate() {
+                        let heights = bin.map(|b| {
+                            (b as f32 / max * self.knobs[0] * Self::HEIGHT as f32) as usize
+                        });
+                        for y in 0..Self::HEIGHT {
+                            let h = Self::HEIGHT - y;
+                            let idx = (y * Self::WIDTH + x) * 4;
+                            for (c, height) in heights.iter().enumerate() {
+                                image.data[idx+c] = if *height >= h {
+                                    255
+                                } else {
+                                    0
+                                };
+                            }
+                            image.data[idx+3] = 255;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
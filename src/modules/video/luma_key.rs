@@ -0,0 +1,194 @@
+/*!
+The `LumaKey` module takes 6 inputs and outputs the first 3 wherever their luma
+is between the given thresholds, and the second 3 elsewhere. The edges of the
+key are blended over the given softness.
+
+## Inputs
+0. First red channel in the range [0.0, 1.0]
+1. First green channel in the range [0.0, 1.0]
+2. First blue channel in the range [0.0, 1.0]
+3. Second red channel in the range [0.0, 1.0]
+4. Second green channel in the range [0.0, 1.0]
+5. Second blue channel in the range [0.0, 1.0]
+
+## Outputs
+0. Red channel in the range [0.0, 1.0]
+1. Green channel in the range [0.0, 1.0]
+2. Blue channel in the range [0.0, 1.0]
+
+##### Note
+If all inputs are [f32::NAN] (unpatched), the output will be [f32::NAN].
+Otherwise the signal that's not NAN will be used. If only some channels are
+NAN, then NANs are treated as 0.0.
+
+## Knobs
+0. Low threshold in the range [0.0, 1.0]
+1. High threshold in the range [0.0, 1.0]
+2. Softness in the range [0.0, 1.0]
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+/// Split the inputs of a keyer into its two colors, returning the color to
+/// output directly if either is unpatched
+pub(crate) fn key_inputs(ins: &[f32]) -> Result<([f32; 3], [f32; 3]), Vec<f32>> {
+    let c0 = &ins[0..3];
+    let c1 = &ins[3..6];
+    let is_nan0 = c0.iter().all(|v| v.is_nan());
+    let is_nan1 = c1.iter().all(|v| v.is_nan());
+    if is_nan0 && is_nan1 {
+        return Err(vec![f32::NAN; 3]);
+    } else if is_nan0 {
+        return Err(c1.to_vec());
+    } else if is_nan1 {
+        return Err(c0.to_vec());
+    }
+
+    let c = |v: f32| if v.is_nan() {
+        0.0
+    } else {
+        v
+    };
+    Ok((
+        [c(c0[0]), c(c0[1]), c(c0[2])],
+        [c(c1[0]), c(c1[1]), c(c1[2])],
+    ))
+}
+/// Get the amount of the first color to output as `x` rises past the edge,
+/// blended over the given softness
+pub(crate) fn soft_edge(edge: f32, softness: f32, x: f32) -> f32 {
+    if softness <= 0.0 {
+        return if x >= edge {
+            1.0
+        } else {
+            0.0
+        };
+    }
+
+    let t = ((x - edge) / softness + 0.5).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+/// Mix the two colors by the given amount of the first
+pub(crate) fn key_mix(c0: [f32; 3], c1: [f32; 3], alpha: f32) -> Vec<f32> {
+    c0.iter()
+        .zip(c1)
+        .map(|(a, b)| b + alpha * (a - b))
+        .collect()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LumaKey {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    knobs: [f32; 3],
+}
+#[typetag::deserialize]
+impl Module for LumaKey {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} LumaKey\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        6
+    }
+    fn outputs(&self) -> usize {
+        3
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("video/luma_key.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        let (c0, c1) = match key_inputs(ins) {
+            Ok(cs) => cs,
+            Err(out) => return out,
+        };
+
+        let low = self.knobs[0];
+        let high = self.knobs[1];
+        let softness = self.knobs[2].clamp(0.0, 1.0);
+
+        let y = 0.299 * c0[0] + 0.587 * c0[1] + 0.114 * c0[2];
+        let alpha = soft_edge(low, softness, y) * soft_edge(-high, softness, -y);
+
+        key_mix(c0, c1, alpha)
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+            }
+        }
+    }
+}
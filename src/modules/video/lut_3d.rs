@@ -0,0 +1,278 @@
+/*!
+The `Lut3d` module takes 3 inputs as RGB data and maps them through a 3D
+lookup table loaded from the given .cube file, so that film-style looks can be
+applied to the video.
+
+The file is loaded from the given `filename` and may contain a `TITLE`, a
+`LUT_3D_SIZE` in the range [2, 256], an optional `DOMAIN_MIN` and
+`DOMAIN_MAX`, and then one line of red, green, and blue values for each entry
+with red changing fastest. Colors between the entries are found with trilinear
+interpolation.
+
+## Inputs
+0. Red channel in the range [0.0, 1.0]
+1. Green channel in the range [0.0, 1.0]
+2. Blue channel in the range [0.0, 1.0]
+
+## Outputs
+0. Red channel in the range [0.0, 1.0]
+1. Green channel in the range [0.0, 1.0]
+2. Blue channel in the range [0.0, 1.0]
+
+##### Note
+If all inputs are [f32::NAN] (unpatched), the output will be [f32::NAN].
+Otherwise NANs are treated as 0.0.
+
+## Knobs
+0. Intensity in the range [0.0, 1.0] which blends between the input and the
+   mapped colors
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent}};
+
+#[derive(Default, Debug, Clone)]
+struct CubeLut {
+    title: Option<String>,
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    table: Vec<[f32; 3]>,
+}
+impl CubeLut {
+    fn load(filename: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(filename)
+            .map_err(|e| e.to_string())?;
+
+        let mut lut = CubeLut {
+            title: None,
+            size: 0,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            table: vec![],
+        };
+        let parse_triple = |vs: &[&str], line: usize| -> Result<[f32; 3], String> {
+            if vs.len() != 3 {
+                return Err(format!("Expected 3 values on line {line}"));
+            }
+            let mut t = [0.0; 3];
+            for (t, v) in t.iter_mut().zip(vs) {
+                *t = v.parse()
+                    .map_err(|e| format!("Invalid value {v} on line {line}: {e}"))?;
+            }
+            Ok(t)
+        };
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens[0] {
+                "TITLE" => {
+                    lut.title = Some(line["TITLE".len()..].trim().trim_matches('"').to_string());
+                },
+                "LUT_3D_SIZE" => {
+                    lut.size = tokens.get(1)
+                        .and_then(|s| s.parse().ok())
+                        .filter(|s| (2..=256).contains(s))
+                        .ok_or(format!("Invalid LUT_3D_SIZE on line {}", i + 1))?;
+                },
+                "LUT_1D_SIZE" => return Err("1D LUTs are unsupported".to_string()),
+                "DOMAIN_MIN" => lut.domain_min = parse_triple(&tokens[1..], i + 1)?,
+                "DOMAIN_MAX" => lut.domain_max = parse_triple(&tokens[1..], i + 1)?,
+                t if t.chars().next().is_some_and(|c| c.is_ascii_alphabetic()) => {
+                    warn!("Ignoring unknown .cube keyword {t} on line {}", i + 1);
+                },
+                _ => lut.table.push(parse_triple(&tokens, i + 1)?),
+            }
+        }
+
+        if lut.size == 0 {
+            return Err("Missing LUT_3D_SIZE".to_string());
+        }
+        if lut.table.len() != lut.size.pow(3) {
+            return Err(format!("Expected {} entries but found {}", lut.size.pow(3), lut.table.len()));
+        }
+        Ok(lut)
+    }
+    fn get(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+        self.table[r + self.size * (g + self.size * b)]
+    }
+    /// Map a color through the table with trilinear interpolation
+    fn apply(&self, c: [f32; 3]) -> [f32; 3] {
+        let n = (self.size - 1) as f32;
+        let mut i0 = [0; 3];
+        let mut i1 = [0; 3];
+        let mut f = [0.0; 3];
+        for ch in 0..3 {
+            let range = self.domain_max[ch] - self.domain_min[ch];
+            let x = if range > 0.0 {
+                ((c[ch] - self.domain_min[ch]) / range).clamp(0.0, 1.0) * n
+            } else {
+                0.0
+            };
+            i0[ch] = (x.floor() as usize).min(self.size - 1);
+            i1[ch] = (i0[ch] + 1).min(self.size - 1);
+            f[ch] = x - i0[ch] as f32;
+        }
+
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| [
+            a[0] + t * (b[0] - a[0]),
+            a[1] + t * (b[1] - a[1]),
+            a[2] + t * (b[2] - a[2]),
+        ];
+        let c00 = lerp(self.get(i0[0], i0[1], i0[2]), self.get(i1[0], i0[1], i0[2]), f[0]);
+        let c10 = lerp(self.get(i0[0], i1[1], i0[2]), self.get(i1[0], i1[1], i0[2]), f[0]);
+        let c01 = lerp(self.get(i0[0], i0[1], i1[2]), self.get(i1[0], i0[1], i1[2]), f[0]);
+        let c11 = lerp(self.get(i0[0], i1[1], i1[2]), self.get(i1[0], i1[1], i1[2]), f[0]);
+
+        let c0 = lerp(c00, c10, f[1]);
+        let c1 = lerp(c01, c11, f[1]);
+        lerp(c0, c1, f[2])
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Lut3d {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    filename: String,
+    #[serde(skip)]
+    lut: Option<CubeLut>,
+
+    knobs: [f32; 1],
+}
+#[typetag::deserialize]
+impl Module for Lut3d {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Lut3D\n"),
+                };
+                let title = match self.lut.as_ref().and_then(|l| l.title.as_ref()) {
+                    Some(title) => format!("{title}\n"),
+                    None => format!("{}\n", self.filename),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(title, ts.clone()),
+                            TextSection::new("K0\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, _id: usize) {
+        self.lut = match CubeLut::load(&self.filename) {
+            Ok(lut) => Some(lut),
+            Err(e) => {
+                error!("Failed to load LUT {}: {e}", self.filename);
+                None
+            },
+        };
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.lut = None;
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        3
+    }
+    fn outputs(&self) -> usize {
+        3
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("video/lut_3d.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        if ins.iter().all(|i| i.is_nan()) {
+            return vec![f32::NAN; 3];
+        }
+
+        let c = |v: f32| if v.is_nan() {
+            0.0
+        } else {
+            v
+        };
+        let input = [c(ins[0]), c(ins[1]), c(ins[2])];
+
+        let Some(lut) = &self.lut else {
+            return input.to_vec();
+        };
+        let mapped = lut.apply(input);
+
+        let intensity = self.knobs[0].clamp(0.0, 1.0);
+        input.iter()
+            .zip(mapped)
+            .map(|(i, m)| i + intensity * (m - i))
+            .collect()
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+            }
+        }
+    }
+}
@@ -1,11 +1,23 @@
 /*!
-The following video modules are defined here: `Brightness`, `Contrast`, `Luma`,
-`ChromaKey`
+The following video modules are defined here: `Brightness`, `Contrast`,
+`Lut3d`, `Luma`, `ChromaKey`, `LumaKey`, `DiffKey`, `Histogram`,
+`WaveformMonitor`, `Scene3D`, `PhotosensitivityGuard`
 */
 
 pub mod brightness;
 pub mod contrast;
+pub mod lut_3d;
 
 pub mod luma;
 
 pub mod chroma_key;
+pub mod luma_key;
+pub mod diff_key;
+
+pub mod histogram;
+pub mod waveform_monitor;
+
+#[cfg(feature = "scene3d")]
+pub mod scene_3d;
+
+pub mod photosensitivity_guard;
@@ -0,0 +1,274 @@
+/*!
+The `PhotosensitivityGuard` module takes 3 inputs as RGB data and outputs them
+with any rapid flashing limited, so that a glitching patch can't produce
+dangerous strobing on a projector or screen.
+
+Each frame is held for one frame so that its average luma can be compared
+with that of the previous output frame. A change in average luma of at least
+the threshold given by knob 0 is a transition, and a pair of opposing
+transitions is a flash. When a frame would cause more flashes within the last
+second than allowed by knob 1, it is blended with the previous output frame so
+that its change in luma stays below the threshold. Every change in luma is also
+limited to the intensity given by knob 2.
+
+The defaults suggested by common broadcast guidelines are a threshold of 0.1,
+3 flashes per second, and an intensity of 1.0.
+
+## Inputs
+0. Red channel in the range [0.0, 1.0]
+1. Green channel in the range [0.0, 1.0]
+2. Blue channel in the range [0.0, 1.0]
+
+## Outputs
+0. Red channel in the range [0.0, 1.0]
+1. Green channel in the range [0.0, 1.0]
+2. Blue channel in the range [0.0, 1.0]
+
+##### Note
+If all inputs are [f32::NAN] (unpatched), the output will be [f32::NAN].
+Otherwise NANs are treated as 0.0.
+
+## Knobs
+0. Transition threshold in the range [0.0, 1.0]
+1. Maximum flashes per second in the range [0.0, inf)
+2. Maximum luma change per frame in the range [0.0, 1.0]
+
+*/
+
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PhotosensitivityGuard {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    scan: usize,
+    #[serde(skip)]
+    input: Vec<Option<[f32; 3]>>,
+    #[serde(skip)]
+    output: Vec<Option<[f32; 3]>>,
+    #[serde(skip)]
+    last_luma: Option<f32>,
+    #[serde(skip)]
+    transitions: VecDeque<(f64, bool)>,
+    #[serde(skip)]
+    is_limiting: bool,
+
+    knobs: [f32; 3],
+}
+impl PhotosensitivityGuard {
+    fn luma(c: &[f32; 3]) -> f32 {
+        0.299 * c[0] + 0.587 * c[1] + 0.114 * c[2]
+    }
+    /// Count the pairs of opposing transitions
+    fn flashes(&self) -> usize {
+        self.transitions.iter()
+            .zip(self.transitions.iter().skip(1))
+            .filter(|(t0, t1)| t0.1 != t1.1)
+            .count()
+    }
+    /// Decide how much of the held frame to let through and blend it with the
+    /// previous output frame
+    fn process(&mut self, time: f64) {
+        let threshold = self.knobs[0].clamp(0.0, 1.0);
+        let max_flashes = self.knobs[1].max(0.0);
+        let max_change = self.knobs[2].clamp(0.0, 1.0);
+
+        while self.transitions.front().is_some_and(|t| time - t.0 > 1.0) {
+            self.transitions.pop_front();
+        }
+
+        let frame: Vec<Option<[f32; 3]>> = std::mem::replace(&mut self.input, vec![None; self.output.len()]);
+        let count = frame.iter().flatten().count();
+        if count == 0 {
+            self.output = frame;
+            return;
+        }
+        let luma = frame.iter()
+            .flatten()
+            .map(Self::luma)
+            .sum::<f32>() / count as f32;
+
+        let Some(last_luma) = self.last_luma else {
+            self.last_luma = Some(luma);
+            self.output = frame;
+            return;
+        };
+
+        let delta = luma - last_luma;
+        let mut k = 1.0;
+        self.is_limiting = false;
+        if delta.abs() >= threshold && threshold > 0.0 {
+            let is_rising = delta > 0.0;
+            let is_flash = self.transitions.back().is_some_and(|t| t.1 != is_rising);
+            if is_flash && (self.flashes() + 1) as f32 > max_flashes {
+                // Keep the change just below a transition
+                k = 0.9 * threshold / delta.abs();
+                self.is_limiting = true;
+            }
+        }
+        if delta.abs() * k > max_change {
+            k = max_change / delta.abs();
+            self.is_limiting = true;
+        }
+        if delta.abs() * k >= threshold && threshold > 0.0 {
+            self.transitions.push_back((time, delta > 0.0));
+        }
+
+        self.output = frame.into_iter()
+            .zip(&self.output)
+            .map(|(c, p)| {
+                c.map(|c| {
+                    let p = p.unwrap_or([0.0; 3]);
+                    [
+                        p[0] + k * (c[0] - p[0]),
+                        p[1] + k * (c[1] - p[1]),
+                        p[2] + k * (c[2] - p[2]),
+                    ]
+                })
+            }).collect();
+        self.last_luma = Some(last_luma + k * delta);
+    }
+}
+#[typetag::deserialize]
+impl Module for PhotosensitivityGuard {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Photosensitivity Guard\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("Passing\n", ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, _id: usize) {
+        let size = ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT;
+        self.scan = 0;
+        self.input = vec![None; size];
+        self.output = vec![None; size];
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.input = vec![];
+        self.output = vec![];
+        self.last_luma = None;
+        self.transitions.clear();
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        3
+    }
+    fn outputs(&self) -> usize {
+        3
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("video/photosensitivity_guard.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        if self.input.is_empty() {
+            return vec![f32::NAN; 3];
+        }
+
+        self.input[self.scan] = if ins.iter().all(|i| i.is_nan()) {
+            None
+        } else {
+            let c = |v: f32| if v.is_nan() {
+                0.0
+            } else {
+                v
+            };
+            Some([c(ins[0]), c(ins[1]), c(ins[2])])
+        };
+        let out = self.output[self.scan];
+
+        self.scan += 1;
+        if self.scan >= self.input.len() {
+            self.scan = 0;
+            self.process(time);
+        }
+
+        match out {
+            Some(c) => c.to_vec(),
+            None => vec![f32::NAN; 3],
+        }
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = if self.is_limiting {
+                    "Limiting\n".to_string()
+                } else {
+                    format!("Passing, {} flashes/s\n", self.flashes())
+                };
+                text.sections[2].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+            }
+        }
+    }
+}
@@ -0,0 +1,336 @@
+/*!
+The `Scene3D` module loads a glTF model and renders it into the video scan,
+outputting the color of each pixel.
+
+The model is rasterized on the CPU at the resolution of the video bus once per
+frame, i.e. whenever the scan wraps around, so that the result can be
+processed by any other video module. Each mesh is normalized so that it fits
+within the unit sphere and is shaded with a single directional light.
+
+## Inputs
+None
+
+## Outputs
+0. The red component of each pixel in the range [0.0, 1.0]
+1. The green component of each pixel in the range [0.0, 1.0]
+2. The blue component of each pixel in the range [0.0, 1.0]
+
+## Knobs
+0. Camera azimuth in degrees in the range (-inf, inf)
+1. Camera elevation in degrees in the range [-89.0, 89.0]
+2. Camera distance in the range (1.0, inf)
+3. Light azimuth in degrees in the range (-inf, inf)
+4. Light elevation in degrees in the range [-90.0, 90.0]
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut}};
+
+#[derive(Debug, Clone)]
+struct Scene3DTriangle {
+    verts: [Vec3; 3],
+    color: Vec3,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Scene3D {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    filename: String,
+    #[serde(default = "default_background")]
+    background: [f32; 3],
+    #[serde(skip)]
+    triangles: Vec<Scene3DTriangle>,
+    #[serde(skip)]
+    frame: Vec<Vec3>,
+    #[serde(skip)]
+    depth: Vec<f32>,
+    #[serde(skip)]
+    scan: usize,
+
+    knobs: [f32; 5],
+}
+fn default_background() -> [f32; 3] {
+    [0.0, 0.0, 0.0]
+}
+impl Scene3D {
+    const FOV: f32 = std::f32::consts::FRAC_PI_4;
+    const AMBIENT: f32 = 0.15;
+
+    fn load_node(node: gltf::Node, parent: Mat4, buffers: &[gltf::buffer::Data], triangles: &mut Vec<Scene3DTriangle>) {
+        let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    continue;
+                }
+
+                let reader = primitive.reader(|b| buffers.get(b.index()).map(|data| &data.0[..]));
+                let positions: Vec<Vec3> = match reader.read_positions() {
+                    Some(positions) => positions
+                        .map(|p| transform.transform_point3(Vec3::from(p)))
+                        .collect(),
+                    None => continue,
+                };
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..positions.len() as u32).collect(),
+                };
+
+                let [r, g, b, _] = primitive.material()
+                    .pbr_metallic_roughness()
+                    .base_color_factor();
+                let color = Vec3::new(r, g, b);
+
+                for tri in indices.chunks_exact(3) {
+                    let verts = [
+                        positions.get(tri[0] as usize),
+                        positions.get(tri[1] as usize),
+                        positions.get(tri[2] as usize),
+                    ];
+                    if let [Some(a), Some(b), Some(c)] = verts {
+                        triangles.push(Scene3DTriangle {
+                            verts: [*a, *b, *c],
+                            color,
+                        });
+                    }
+                }
+            }
+        }
+        for child in node.children() {
+            Self::load_node(child, transform, buffers, triangles);
+        }
+    }
+    fn load(&mut self) {
+        let (document, buffers, _images) = match gltf::import(&self.filename) {
+            Ok(gltf) => gltf,
+            Err(e) => {
+                error!("Failed to load glTF model {}: {e}", self.filename);
+                return;
+            },
+        };
+
+        let mut triangles = vec![];
+        if let Some(scene) = document.default_scene().or_else(|| document.scenes().next()) {
+            for node in scene.nodes() {
+                Self::load_node(node, Mat4::IDENTITY, &buffers, &mut triangles);
+            }
+        }
+
+        // Center the model and scale it to fit within the unit sphere
+        let (min, max) = triangles.iter()
+            .flat_map(|t| t.verts)
+            .fold((Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)), |(min, max), v| {
+                (min.min(v), max.max(v))
+            });
+        let center = (min + max) / 2.0;
+        let radius = triangles.iter()
+            .flat_map(|t| t.verts)
+            .map(|v| v.distance(center))
+            .fold(0.0, f32::max);
+        if radius > 0.0 {
+            for t in &mut triangles {
+                for v in &mut t.verts {
+                    *v = (*v - center) / radius;
+                }
+            }
+        }
+
+        self.triangles = triangles;
+    }
+
+    fn direction(azimuth: f32, elevation: f32) -> Vec3 {
+        let (az, el) = (azimuth.to_radians(), elevation.to_radians());
+        Vec3::new(el.cos() * az.sin(), el.sin(), el.cos() * az.cos())
+    }
+    fn draw(&mut self) {
+        let (w, h) = (ComponentVideoOut::WIDTH, ComponentVideoOut::HEIGHT);
+        let background = Vec3::from(self.background);
+        self.frame = vec![background; w * h];
+        self.depth = vec![f32::INFINITY; w * h];
+
+        let eye = Self::direction(self.knobs[0], self.knobs[1].clamp(-89.0, 89.0))
+            * self.knobs[2].max(1.0 + f32::EPSILON);
+        let view = Mat4::look_at_rh(eye, Vec3::ZERO, Vec3::Y);
+        let proj = Mat4::perspective_rh(Self::FOV, w as f32 / h as f32, 0.01, 100.0);
+        let view_proj = proj * view;
+        let light = Self::direction(self.knobs[3], self.knobs[4].clamp(-90.0, 90.0));
+
+        for t in &self.triangles {
+            let normal = (t.verts[1] - t.verts[0]).cross(t.verts[2] - t.verts[0]).normalize_or_zero();
+            let shade = Self::AMBIENT + (1.0 - Self::AMBIENT) * normal.dot(light).abs();
+            let color = (t.color * shade).clamp(Vec3::ZERO, Vec3::ONE);
+
+            // Project to screen space, skipping any triangles behind the camera
+            let mut screen = [Vec3::ZERO; 3];
+            let mut is_visible = true;
+            for (s, v) in screen.iter_mut().zip(t.verts) {
+                let clip = view_proj * v.extend(1.0);
+                if clip.w <= 0.0 {
+                    is_visible = false;
+                    break;
+                }
+                let ndc = clip.truncate() / clip.w;
+                *s = Vec3::new(
+                    (ndc.x + 1.0) / 2.0 * w as f32,
+                    (1.0 - ndc.y) / 2.0 * h as f32,
+                    ndc.z,
+                );
+            }
+            if !is_visible {
+                continue;
+            }
+
+            let [a, b, c] = screen;
+            let area = (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+            if area.abs() < f32::EPSILON {
+                continue;
+            }
+
+            let x0 = a.x.min(b.x).min(c.x).floor().max(0.0) as usize;
+            let x1 = (a.x.max(b.x).max(c.x).ceil().max(0.0) as usize).min(w);
+            let y0 = a.y.min(b.y).min(c.y).floor().max(0.0) as usize;
+            let y1 = (a.y.max(b.y).max(c.y).ceil().max(0.0) as usize).min(h);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                    let w0 = ((b.x - px) * (c.y - py) - (b.y - py) * (c.x - px)) / area;
+                    let w1 = ((c.x - px) * (a.y - py) - (c.y - py) * (a.x - px)) / area;
+                    let w2 = 1.0 - w0 - w1;
+                    if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                        continue;
+                    }
+
+                    let z = w0 * a.z + w1 * b.z + w2 * c.z;
+                    let i = y * w + x;
+                    if z < self.depth[i] {
+                        self.depth[i] = z;
+                        self.frame[i] = color;
+                    }
+                }
+            }
+        }
+    }
+}
+#[typetag::deserialize]
+impl Module for Scene3D {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Scene 3D\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new(format!("{}\n", self.filename), ts.clone()),
+                            TextSection::new(format!("Triangles: {}\n", self.triangles.len()), ts.clone()),
+                            TextSection::new("K0\n", ts.clone()),
+                            TextSection::new("K1\n", ts.clone()),
+                            TextSection::new("K2\n", ts.clone()),
+                            TextSection::new("K3\n", ts.clone()),
+                            TextSection::new("K4\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, _id: usize) {
+        self.load();
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.triangles = vec![];
+        self.frame = vec![];
+        self.depth = vec![];
+        self.scan = 0;
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        0
+    }
+    fn outputs(&self) -> usize {
+        3
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("video/scene_3d.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, _ins: &[f32]) -> Vec<f32> {
+        if self.scan == 0 || self.frame.is_empty() {
+            self.draw();
+        }
+
+        let c = self.frame[self.scan];
+
+        self.scan += 1;
+        self.scan %= ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT;
+
+        vec![c.x, c.y, c.z]
+    }
+    fn render(&mut self, _images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, _q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[3].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+                text.sections[4].value = format!("{}\n", self.describe().knob_row(1, self.knobs[1]));
+                text.sections[5].value = format!("{}\n", self.describe().knob_row(2, self.knobs[2]));
+                text.sections[6].value = format!("{}\n", self.describe().knob_row(3, self.knobs[3]));
+                text.sections[7].value = format!("{}\n", self.describe().knob_row(4, self.knobs[4]));
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+/*!
+The `WaveformMonitor` module takes 3 inputs and plots the luma of each pixel
+against its horizontal position in the video scan, the same as a traditional
+waveform monitor.
+
+Every scanline of a frame is drawn on top of the others so that the brightness
+of each point shows how many pixels share that level.
+
+## Inputs
+0. Red channel in the range [0.0, 1.0]
+1. Green channel in the range [0.0, 1.0]
+2. Blue channel in the range [0.0, 1.0]
+
+## Outputs
+None
+
+## Knobs
+0. Trace intensity in the range (0.0, inf)
+
+*/
+
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::Mesh2dHandle, render::render_resource::{Extent3d, TextureDescriptor, TextureFormat, TextureUsages, TextureDimension}};
+
+use serde::Deserialize;
+
+use crate::{StepType, modules::{Module, ModuleDescription, ModuleComponent, ModuleTextComponent, ModuleImageComponent, ModuleMeshComponent, component_video_out::ComponentVideoOut}};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WaveformMonitor {
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(skip)]
+    component: Option<Entity>,
+    #[serde(skip)]
+    children: Vec<Entity>,
+
+    #[serde(skip)]
+    scan: usize,
+    #[serde(skip)]
+    counts: Vec<u16>,
+    #[serde(skip)]
+    frame: Vec<u16>,
+
+    knobs: [f32; 1],
+}
+impl WaveformMonitor {
+    const WIDTH: usize = ComponentVideoOut::WIDTH * 2;
+    const HEIGHT: usize = 100;
+}
+#[typetag::deserialize]
+impl Module for WaveformMonitor {
+    fn init(&mut self, id: usize, mut ec: EntityCommands, images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, _materials: &mut ResMut<Assets<ColorMaterial>>, ts: TextStyle) {
+        self.id = Some(id);
+        self.init_state(id);
+
+        let size = Extent3d {
+            width: Self::WIDTH as u32,
+            height: Self::HEIGHT as u32,
+            ..default()
+        };
+        let mut image = Image {
+            texture_descriptor: TextureDescriptor {
+                label: None,
+                size,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                mip_level_count: 1,
+                sample_count: 1,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            ..default()
+        };
+        image.resize(size);
+        image.data = [0, 0, 0, 255].repeat(image.data.len() / 4);
+        let image_handle = images.add(image);
+
+        ec.with_children(|parent| {
+            let mut component = parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Relative,
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                ModuleComponent,
+            ));
+            component.with_children(|parent| {
+                let name = match &self.name {
+                    Some(name) => format!("{name}\n"),
+                    None => format!("M{id} Waveform Monitor\n"),
+                };
+                self.children.push(
+                    parent.spawn((
+                        TextBundle::from_sections([
+                            TextSection::new(name, ts.clone()),
+                            TextSection::new("K0\n", ts),
+                        ]),
+                        ModuleTextComponent,
+                    )).id()
+                );
+
+                self.children.push(
+                    parent.spawn((
+                        ImageBundle {
+                            style: Style {
+                                position_type: PositionType::Relative,
+                                top: Val::Px(10.0),
+                                width: Val::Px(f32::from(Self::WIDTH as u16)),
+                                height: Val::Px(f32::from(Self::HEIGHT as u16)),
+                                ..default()
+                            },
+                            image: UiImage::new(image_handle),
+                            ..default()
+                        },
+                        ModuleImageComponent,
+                    )).id()
+                );
+            });
+            self.component = Some(component.id());
+        });
+    }
+    fn init_state(&mut self, _id: usize) {
+        self.counts = vec![0; ComponentVideoOut::WIDTH * Self::HEIGHT];
+        self.frame = vec![0; ComponentVideoOut::WIDTH * Self::HEIGHT];
+    }
+    fn exit(&mut self) {
+        self.id = None;
+        self.component = None;
+        self.children = vec![];
+
+        self.scan = 0;
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    fn component(&self) -> Option<Entity> {
+        self.component
+    }
+
+    fn inputs(&self) -> usize {
+        3
+    }
+    fn outputs(&self) -> usize {
+        0
+    }
+    fn knobs(&self) -> usize {
+        self.knobs.len()
+    }
+    fn describe(&self) -> &'static ModuleDescription {
+        module_description!("video/waveform_monitor.rs")
+    }
+
+    fn get_knobs(&self) -> Vec<f32> {
+        self.knobs.to_vec()
+    }
+    fn set_knob(&mut self, i: usize, val: f32) {
+        self.knobs[i] = val;
+    }
+
+    fn step(&mut self, _time: f64, _st: StepType, ins: &[f32]) -> Vec<f32> {
+        if ins.iter().all(|i| i.is_nan()) || self.counts.is_empty() {
+            return vec![];
+        }
+
+        let [r, g, b] = [ins[0], ins[1], ins[2]]
+            .map(|v| {
+                if v.is_nan() {
+                    0.0
+                } else {
+                    v.clamp(0.0, 1.0)
+                }
+            });
+        let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+
+        let x = self.scan % ComponentVideoOut::WIDTH;
+        let y = ((1.0 - luma) * (Self::HEIGHT - 1) as f32).round() as usize;
+        let count = &mut self.counts[y * ComponentVideoOut::WIDTH + x];
+        *count = count.saturating_add(1);
+
+        self.scan += 1;
+        if self.scan >= ComponentVideoOut::WIDTH * ComponentVideoOut::HEIGHT {
+            self.scan = 0;
+            self.frame = std::mem::replace(&mut self.counts, vec![0; ComponentVideoOut::WIDTH * Self::HEIGHT]);
+        }
+
+        vec![]
+    }
+    fn render(&mut self, images: &mut ResMut<Assets<Image>>, _meshes: &mut ResMut<Assets<Mesh>>, q_text: &mut Query<&mut Text, With<ModuleTextComponent>>, q_image: &mut Query<&mut UiImage, With<ModuleImageComponent>>, _q_mesh: &mut Query<&mut Mesh2dHandle, With<ModuleMeshComponent>>) {
+        if let Some(component) = self.children.get(0) {
+            if let Ok(mut text) = q_text.get_mut(*component) {
+                text.sections[1].value = format!("{}\n", self.describe().knob_row(0, self.knobs[0]));
+            }
+        }
+
+        if let Some(component) = self.children.get(1) {
+            if let Ok(h_image) = q_image.get_mut(*component) {
+                if let Some(image) = images.get_mut(&h_image.texture) {
+                    let scale = self.knobs[0] * 255.0 / ComponentVideoOut::HEIGHT as f32 * 4.0;
+                    for y in 0..Self::HEIGHT {
+                        for x in 0..Self::WIDTH {
+                            let count = self.frame[y * ComponentVideoOut::WIDTH + x * ComponentVideoOut::WIDTH / Self::WIDTH];
+                            let v = (f32::from(count) * scale).min(255.0) as u8;
+
+                            let idx = (y * Self::WIDTH + x) * 4;
+                            image.data[idx] = v / 4;
+                            image.data[idx+1] = v;
+                            image.data[idx+2] = v / 4;
+                            image.data[idx+3] = 255;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
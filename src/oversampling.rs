@@ -0,0 +1,124 @@
+/*!
+An oversampling wrapper for nonlinear modules which would otherwise alias at
+high gains.
+
+Modules opt in by returning a factor of 2 or 4 from `Module::oversampling`,
+usually from an `oversampling` field in their TOML definition. The rack then
+upsamples their inputs, steps them that many times per sample, and downsamples
+their outputs again with polyphase FIR filters, so the modules themselves don't
+need to know about it.
+
+The filters add a latency of 16 samples to the module's outputs.
+
+*/
+
+use std::{collections::VecDeque, f32::consts::PI};
+
+/// The number of filter taps per phase
+const TAPS: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct Oversampler {
+    factor: usize,
+    filter: Vec<f32>,
+
+    /// The history of each input at the original sample rate, newest first
+    ins: Vec<VecDeque<f32>>,
+    /// The history of each output at the oversampled rate, newest first
+    outs: Vec<VecDeque<f32>>,
+}
+impl Oversampler {
+    pub fn new(factor: usize, inputs: usize, outputs: usize) -> Self {
+        // Windowed sinc lowpass with its cutoff just below the original Nyquist
+        let len = factor * TAPS;
+        let fc = 0.45 / factor as f32;
+        let mid = (len - 1) as f32 / 2.0;
+        let mut filter: Vec<f32> = (0..len)
+            .map(|i| {
+                let x = i as f32 - mid;
+                let sinc = if x == 0.0 {
+                    2.0 * fc
+                } else {
+                    (2.0 * PI * fc * x).sin() / (PI * x)
+                };
+                let blackman = 0.42
+                    - 0.5 * (2.0 * PI * i as f32 / (len - 1) as f32).cos()
+                    + 0.08 * (4.0 * PI * i as f32 / (len - 1) as f32).cos();
+                sinc * blackman
+            }).collect();
+        let sum: f32 = filter.iter().sum();
+        for h in &mut filter {
+            *h /= sum;
+        }
+
+        Self {
+            factor,
+            filter,
+            ins: vec![VecDeque::from(vec![0.0; TAPS]); inputs],
+            outs: vec![VecDeque::from(vec![0.0; len]); outputs],
+        }
+    }
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    /// Upsample the inputs, step them with the given function at the
+    /// oversampled rate, and downsample the outputs
+    ///
+    /// Unpatched [f32::NAN] inputs are passed through as-is and outputs which
+    /// are [f32::NAN] for any sub-step are output as [f32::NAN].
+    pub fn process(&mut self, ins: &[f32], mut step: impl FnMut(&[f32]) -> Vec<f32>) -> Vec<f32> {
+        for (history, x) in self.ins.iter_mut().zip(ins) {
+            history.pop_back();
+            history.push_front(if x.is_nan() {
+                0.0
+            } else {
+                *x
+            });
+        }
+
+        let mut is_nan = vec![false; self.outs.len()];
+        for phase in 0..self.factor {
+            // Each phase of the interpolation uses every nth filter tap
+            let sub_ins: Vec<f32> = self.ins.iter()
+                .zip(ins)
+                .map(|(history, x)| {
+                    if x.is_nan() {
+                        return f32::NAN;
+                    }
+
+                    history.iter()
+                        .enumerate()
+                        .map(|(k, x)| self.filter[k * self.factor + phase] * x)
+                        .sum::<f32>() * self.factor as f32
+                }).collect();
+
+            let sub_outs = step(&sub_ins);
+            for (o, history) in self.outs.iter_mut().enumerate() {
+                let y = sub_outs.get(o).copied().unwrap_or(f32::NAN);
+                is_nan[o] |= y.is_nan();
+
+                history.pop_back();
+                history.push_front(if y.is_nan() {
+                    0.0
+                } else {
+                    y
+                });
+            }
+        }
+
+        // Decimate by only filtering at the original sample rate
+        self.outs.iter()
+            .zip(is_nan)
+            .map(|(history, is_nan)| {
+                if is_nan {
+                    return f32::NAN;
+                }
+
+                history.iter()
+                    .zip(&self.filter)
+                    .map(|(y, h)| y * h)
+                    .sum()
+            }).collect()
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::{HashMap, HashSet};
 
-use bevy::prelude::Component;
+use bevy::prelude::{Color, Component, Vec2};
 use serde::Deserialize;
 
 use crate::modules::ModuleKey;
@@ -15,6 +15,10 @@ impl Patches {
                     .map(move |input| (output, input))
             })
     }
+    /// Iterate over the outputs which are patched to at least one input
+    pub fn outputs(&self) -> impl Iterator<Item = &'_ ModuleKey> {
+        self.0.keys()
+    }
 }
 
 impl<'a> FromIterator<(&'a ModuleKey, &'a ModuleKey)> for Patches {
@@ -38,4 +42,32 @@ impl<'a> IntoIterator for &'a Patches {
 }
 
 #[derive(Component, Debug, Clone)]
-pub struct PatchComponent;
\ No newline at end of file
+pub struct PatchComponent;
+/// The output carried by a patch cable along with the cable's points in world
+/// space, used to find the cable under the cursor, and the cable's color when
+/// it carries a full-scale signal
+#[derive(Component, Debug, Clone)]
+pub struct PatchCableComponent {
+    pub output: ModuleKey,
+    pub points: Vec<Vec2>,
+    pub color: Color,
+    /// How far the dashes have flowed along the cable in the range [0.0, 1.0)
+    pub flow: f32,
+}
+impl PatchCableComponent {
+    /// The distance from the given world position to the nearest point on the
+    /// cable
+    pub fn distance(&self, pos: Vec2) -> f32 {
+        self.points.windows(2)
+            .map(|seg| {
+                let (a, b) = (seg[0], seg[1]);
+                let ab = b - a;
+                let t = if ab.length_squared() > 0.0 {
+                    ((pos - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                pos.distance(a + ab * t)
+            }).fold(f32::INFINITY, f32::min)
+    }
+}
@@ -0,0 +1,266 @@
+/*!
+A terser text format for racks which is compiled into the same structures as a
+rack's TOML file, so that racks can be written without keeping track of module
+indices and patch strings. Rack files with the `.vince` extension are loaded
+with this format.
+
+```text
+# Comments start with a #
+@name "Bass Drone"      # An entry in the rack's [info] section
+@duration 120
+
+out: AudioOut 0.1
+osc1: Oscillator Sine 0 440 1 0 -> out.0
+lfo: Oscillator Triangle 0 1 1 0 -> osc1.k1, scope.0
+scope: Oscilloscope group="Scopes"
+env: EnvelopeGenerator curves=["Linear", "Exponential", "Linear"] 0.01 0.1 0.5 0.5
+
+lfo.0 -> env.0
+```
+
+Each module is defined on its own line as `<name>: <type>` followed by any of:
+
+ * a capitalized word, which sets the module's `func`, e.g. `Sine`
+ * `<field>=<value>` pairs, which set any other field as in TOML, where a value
+   without quotes which isn't valid TOML is used as a string, e.g.
+   `group=Drums`
+ * numbers, which set the module's knobs in order
+ * `-> <input>, ...`, which patches the module's output 0 to each input
+
+The modules are numbered in the order that they're defined, starting from 0,
+and each module's name is shown on its panel unless it sets a `name` field.
+
+Outputs are written as `<name>` or `<name>.<output>`, and are patched with
+`<output> -> <input>, ...` on their own lines, where inputs are written as
+`<name>.<input>` or `<name>.k<knob>` to patch a knob. Modules can be patched
+before they're defined.
+
+*/
+
+use std::collections::BTreeMap;
+
+use bevy::{asset::{AssetLoader, LoadContext, LoadedAsset}, utils::BoxedFuture};
+
+use crate::rack::Rack;
+
+/// Split a line into whitespace separated tokens, keeping quoted strings and
+/// bracketed values together
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut in_quotes = false;
+    let mut depth = 0usize;
+    for c in line.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '[' | '{' if !in_quotes => depth += 1,
+            ']' | '}' if !in_quotes => {
+                depth = depth.checked_sub(1)
+                    .ok_or(format!("unmatched {c}"))?;
+            },
+            '#' if !in_quotes && depth == 0 => break,
+            c if c.is_whitespace() && !in_quotes && depth == 0 => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                continue;
+            },
+            _ => {},
+        }
+        token.push(c);
+    }
+
+    if in_quotes {
+        return Err("unterminated string".to_string());
+    }
+    if depth > 0 {
+        return Err("unterminated brackets".to_string());
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+/// Parse a value as TOML, or as a string if it isn't valid TOML
+fn parse_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("v = {value}"))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+/// A patch from the output of a module to the input or knob of another, by
+/// their names
+struct PatchDef {
+    line: usize,
+    output: String,
+    inputs: Vec<String>,
+}
+
+/// Convert an output like `osc1` or `osc1.2` to its patch string
+fn output_key(ids: &BTreeMap<String, usize>, output: &str) -> Result<String, String> {
+    let (name, idx) = output.split_once('.')
+        .unwrap_or((output, "0"));
+    let id = ids.get(name)
+        .ok_or(format!("unknown module {name}"))?;
+    let idx: usize = idx.parse()
+        .map_err(|_| format!("invalid output {output}, expected <name>.<output>"))?;
+    Ok(format!("{id}M{idx}O"))
+}
+/// Convert an input like `out.0` or a knob like `osc1.k1` to its patch string
+fn input_key(ids: &BTreeMap<String, usize>, input: &str) -> Result<String, String> {
+    let (name, idx) = input.split_once('.')
+        .ok_or(format!("invalid input {input}, expected <name>.<input> or <name>.k<knob>"))?;
+    let id = ids.get(name)
+        .ok_or(format!("unknown module {name}"))?;
+    let (idx, t) = match idx.strip_prefix('k') {
+        Some(knob) => (knob, 'K'),
+        None => (idx, 'I'),
+    };
+    let idx: usize = idx.parse()
+        .map_err(|_| format!("invalid input {input}, expected <name>.<input> or <name>.k<knob>"))?;
+    Ok(format!("{id}M{idx}{t}"))
+}
+
+/// Compile a rack from the text format into its TOML representation
+pub fn compile(src: &str) -> Result<toml::Table, String> {
+    let mut info = toml::Table::new();
+    let mut modules = toml::Table::new();
+    let mut ids = BTreeMap::new();
+    let mut patch_defs = vec![];
+
+    for (i, line) in src.lines().enumerate() {
+        let line_num = i + 1;
+        let err = |e: String| format!("line {line_num}: {e}");
+
+        let tokens = tokenize(line).map_err(err)?;
+        let Some(first) = tokens.first() else {
+            continue;
+        };
+
+        // Info entries
+        if let Some(key) = first.strip_prefix('@') {
+            let value = tokens[1..].join(" ");
+            if key.is_empty() || value.is_empty() {
+                return Err(err(format!("invalid info {line}, expected @<key> <value>")));
+            }
+            let value = match parse_value(&value) {
+                toml::Value::String(s) => s,
+                v => v.to_string(),
+            };
+            info.insert(key.to_string(), toml::Value::String(value));
+            continue;
+        }
+
+        let (defn, patches) = match tokens.iter().position(|t| t == "->") {
+            Some(arrow) => (&tokens[..arrow], Some(&tokens[arrow + 1..])),
+            None => (&tokens[..], None),
+        };
+        let inputs = patches.map(|inputs| {
+            inputs.join(" ")
+                .split(',')
+                .map(|input| input.trim().to_string())
+                .filter(|input| !input.is_empty())
+                .collect::<Vec<String>>()
+        });
+
+        // Module definitions
+        if let Some(name) = first.strip_suffix(':') {
+            if name.is_empty() || name.contains('.') {
+                return Err(err(format!("invalid module name {name}")));
+            }
+            let ty = defn.get(1)
+                .ok_or(err(format!("missing type for module {name}")))?;
+
+            let mut module = toml::Table::new();
+            module.insert("type".to_string(), toml::Value::String(ty.clone()));
+            module.insert("name".to_string(), toml::Value::String(name.to_string()));
+            let mut knobs = vec![];
+            for token in &defn[2..] {
+                if let Some((field, value)) = token.split_once('=') {
+                    module.insert(field.to_string(), parse_value(value));
+                } else if let Ok(knob) = token.parse::<f64>() {
+                    knobs.push(toml::Value::Float(knob));
+                } else if token.starts_with(char::is_uppercase) {
+                    module.insert("func".to_string(), toml::Value::String(token.clone()));
+                } else {
+                    return Err(err(format!("unexpected {token} for module {name}")));
+                }
+            }
+            if !knobs.is_empty() {
+                module.insert("knobs".to_string(), toml::Value::Array(knobs));
+            }
+
+            let id = ids.len();
+            if ids.insert(name.to_string(), id).is_some() {
+                return Err(err(format!("module {name} is defined more than once")));
+            }
+            modules.insert(format!("{id}M"), toml::Value::Table(module));
+
+            if let Some(inputs) = inputs {
+                patch_defs.push(PatchDef {
+                    line: line_num,
+                    output: name.to_string(),
+                    inputs,
+                });
+            }
+            continue;
+        }
+
+        // Patches
+        match (defn, inputs) {
+            ([output], Some(inputs)) => patch_defs.push(PatchDef {
+                line: line_num,
+                output: output.clone(),
+                inputs,
+            }),
+            _ => return Err(err(format!("invalid line {line}, expected a module, patch, or info"))),
+        }
+    }
+
+    // Resolve the patches now that every module has been defined
+    let mut patches = toml::Table::new();
+    for pd in patch_defs {
+        let err = |e: String| format!("line {}: {e}", pd.line);
+        let output = output_key(&ids, &pd.output).map_err(err)?;
+        let inputs = patches.entry(output)
+            .or_insert_with(|| toml::Value::Array(vec![]));
+        if let toml::Value::Array(inputs) = inputs {
+            for input in &pd.inputs {
+                inputs.push(toml::Value::String(input_key(&ids, input).map_err(err)?));
+            }
+        }
+    }
+
+    let mut rack = toml::Table::new();
+    if !info.is_empty() {
+        rack.insert("info".to_string(), toml::Value::Table(info));
+    }
+    rack.insert("modules".to_string(), toml::Value::Table(modules));
+    rack.insert("patches".to_string(), toml::Value::Table(patches));
+    Ok(rack)
+}
+/// Compile and load a rack from the text format
+pub fn parse(src: &str) -> Result<Rack, String> {
+    toml::Value::Table(compile(src)?)
+        .try_into()
+        .map_err(|e| e.to_string())
+}
+
+/// Loads `.vince` files as racks
+#[derive(Default)]
+pub struct PatchLangLoader;
+impl AssetLoader for PatchLangLoader {
+    fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let rack = parse(std::str::from_utf8(bytes)?)
+                .map_err(|e| bevy::asset::Error::msg(format!("{}: {e}", load_context.path().display())))?;
+            load_context.set_default_asset(LoadedAsset::new(rack));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["vince"]
+    }
+}
@@ -0,0 +1,254 @@
+/*!
+Registering modules from outside of Vince, either from a front-end which embeds
+the library or from plugins which are loaded at startup.
+
+Modules defined in Vince are registered at compile time, so a plugin is instead
+a `cdylib` crate which depends on the same version of `vince_core` with the
+same features that Vince was built with and registers its modules with
+[export_plugin]:
+
+```ignore
+use vince_core::{export_plugin, plugins::PluginRegistry};
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct Wavefolder {
+    // ...
+}
+#[typetag::deserialize]
+impl vince_core::modules::Module for Wavefolder {
+    // ...
+}
+
+fn register(registry: &mut PluginRegistry) {
+    registry.register::<Wavefolder>("Wavefolder");
+}
+export_plugin!(register);
+```
+
+With the `plugins` feature, every library in the plugin directory is loaded
+before any racks, after which its modules can be used in racks like any other
+module, e.g. `1 = { type = "Wavefolder", knobs = [0.5] }`. The plugin
+directory defaults to `plugins` and can be set with `plugin_dir` in
+`vince.toml` or with the `--plugin-dir` flag.
+
+##### Note
+Modules are passed between Vince and its plugins as Rust trait objects, which
+have no stable ABI, so loading a plugin is only sound if it was built with
+exactly the same compiler, target, and `vince_core` version and features as
+Vince. These are recorded in [PLUGIN_ABI], which each plugin exports through a
+C function and which is compared before anything else in the plugin is called.
+A plugin whose ABI string matches but which was built differently in some other
+way, e.g. with different dependency versions, is still undefined behavior, so
+plugins should only be built from the same lockfile as Vince.
+
+Plugins allocate through Vince's allocator so that modules can be dropped by
+either side, but every other static in `vince_core` is duplicated in the
+plugin's own copy of the library. In particular, modules in plugins see the
+default config rather than the one loaded from `vince.toml` (`CONFIG`), and
+[module_rng](crate::rack::module_rng) in a plugin isn't seeded from the current
+rack (`RACK_SEED`), so plugin modules shouldn't rely on either.
+
+*/
+
+use std::{alloc::{GlobalAlloc, Layout, System}, collections::BTreeMap, ptr, sync::{Mutex, atomic::{AtomicPtr, Ordering}}};
+
+use serde::de::DeserializeOwned;
+
+use crate::modules::Module;
+
+macro_rules! plugin_abi {
+    () => {
+        concat!(
+            "vince_core ", env!("CARGO_PKG_VERSION"),
+            " (", env!("VINCE_RUSTC_VERSION"),
+            ", ", env!("VINCE_TARGET"),
+            ", features: ", env!("VINCE_FEATURES"), ")",
+        )
+    };
+}
+/// The version and features of `vince_core` and the compiler and target that
+/// it was built with, all of which plugins must match
+pub const PLUGIN_ABI: &str = plugin_abi!();
+/// [PLUGIN_ABI] as a nul-terminated string for [export_plugin]
+#[doc(hidden)]
+pub const PLUGIN_ABI_NUL: &str = concat!(plugin_abi!(), "\0");
+
+/// Construct a module from its TOML definition in a rack
+pub type ModuleConstructor = fn(toml::Value) -> Result<Box<dyn Module>, String>;
+
+static PLUGIN_MODULES: Mutex<BTreeMap<String, ModuleConstructor>> = Mutex::new(BTreeMap::new());
+
+/// The modules registered by a plugin
+#[derive(Default)]
+pub struct PluginRegistry {
+    modules: BTreeMap<String, ModuleConstructor>,
+}
+impl PluginRegistry {
+    /// Register a module which is deserialized from its TOML definition, where
+    /// the name is used as its `type` in racks
+    pub fn register<M: Module + DeserializeOwned + 'static>(&mut self, name: &str) {
+        self.register_with(name, deserialize_module::<M>);
+    }
+    /// Register a module with a custom constructor
+    pub fn register_with(&mut self, name: &str, constructor: ModuleConstructor) {
+        self.modules.insert(name.to_string(), constructor);
+    }
+}
+
+fn deserialize_module<M: Module + DeserializeOwned + 'static>(value: toml::Value) -> Result<Box<dyn Module>, String> {
+    value.try_into::<M>()
+        .map(|m| Box::new(m) as Box<dyn Module>)
+        .map_err(|e| e.to_string())
+}
+
+/// Register the modules of the given registry, e.g. from a front-end which
+/// embeds the library, replacing any modules with the same names
+pub fn register(registry: PluginRegistry) {
+    PLUGIN_MODULES.lock()
+        .unwrap()
+        .extend(registry.modules);
+}
+/// Get the constructor of the registered module with the given name
+pub(crate) fn constructor(name: &str) -> Option<ModuleConstructor> {
+    PLUGIN_MODULES.lock()
+        .ok()?
+        .get(name)
+        .copied()
+}
+
+/// Vince's allocator functions, which are passed to plugins when they're
+/// registered
+pub struct HostAllocFns {
+    alloc: unsafe fn(Layout) -> *mut u8,
+    alloc_zeroed: unsafe fn(Layout) -> *mut u8,
+    dealloc: unsafe fn(*mut u8, Layout),
+    realloc: unsafe fn(*mut u8, Layout, usize) -> *mut u8,
+}
+#[cfg(feature = "plugins")]
+static HOST_ALLOC_FNS: HostAllocFns = HostAllocFns {
+    alloc: std::alloc::alloc,
+    alloc_zeroed: std::alloc::alloc_zeroed,
+    dealloc: std::alloc::dealloc,
+    realloc: std::alloc::realloc,
+};
+
+static HOST_ALLOC: AtomicPtr<HostAllocFns> = AtomicPtr::new(ptr::null_mut());
+
+/// The global allocator of plugins, which forwards to Vince's allocator once
+/// the plugin has been registered and to the system allocator before then
+pub struct HostAllocator;
+impl HostAllocator {
+    pub fn init(host: &'static HostAllocFns) {
+        HOST_ALLOC.store((host as *const HostAllocFns).cast_mut(), Ordering::Release);
+    }
+    fn host() -> Option<&'static HostAllocFns> {
+        // SAFETY: the pointer is either null or was stored from a static
+        // reference
+        unsafe { HOST_ALLOC.load(Ordering::Acquire).as_ref() }
+    }
+}
+unsafe impl GlobalAlloc for HostAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match Self::host() {
+            Some(host) => (host.alloc)(layout),
+            None => System.alloc(layout),
+        }
+    }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        match Self::host() {
+            Some(host) => (host.alloc_zeroed)(layout),
+            None => System.alloc_zeroed(layout),
+        }
+    }
+    unsafe fn dealloc(&self, p: *mut u8, layout: Layout) {
+        match Self::host() {
+            Some(host) => (host.dealloc)(p, layout),
+            None => System.dealloc(p, layout),
+        }
+    }
+    unsafe fn realloc(&self, p: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        match Self::host() {
+            Some(host) => (host.realloc)(p, layout, new_size),
+            None => System.realloc(p, layout, new_size),
+        }
+    }
+}
+
+/// Export the functions which Vince uses to load a plugin, given the function
+/// which registers the plugin's modules
+#[macro_export]
+macro_rules! export_plugin {
+    ($register:path) => {
+        #[global_allocator]
+        static VINCE_PLUGIN_ALLOC: $crate::plugins::HostAllocator = $crate::plugins::HostAllocator;
+
+        #[no_mangle]
+        pub extern "C" fn vince_plugin_abi_string() -> *const ::std::ffi::c_char {
+            $crate::plugins::PLUGIN_ABI_NUL.as_ptr().cast()
+        }
+        #[no_mangle]
+        pub fn vince_plugin_register(host: &'static $crate::plugins::HostAllocFns, registry: &mut $crate::plugins::PluginRegistry) {
+            $crate::plugins::HostAllocator::init(host);
+            $register(registry);
+        }
+    };
+}
+
+#[cfg(feature = "plugins")]
+static LIBRARIES: Mutex<Vec<libloading::Library>> = Mutex::new(Vec::new());
+
+/// Load every plugin in the given directory, logging any which fail to load
+#[cfg(feature = "plugins")]
+pub fn load_dir(dir: &std::path::Path) {
+    use bevy::prelude::{info, error};
+
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut paths: Vec<std::path::PathBuf> = entries.filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == std::env::consts::DLL_EXTENSION))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        // SAFETY: plugins are trusted to be built in the same way as Vince,
+        // which is checked as far as possible before anything else is called
+        match unsafe { load(&path) } {
+            Ok(names) => info!("Loaded plugin {} with modules {}", path.display(), names.join(", ")),
+            Err(e) => error!("Failed to load plugin {}: {e}", path.display()),
+        }
+    }
+}
+#[cfg(feature = "plugins")]
+unsafe fn load(path: &std::path::Path) -> Result<Vec<String>, String> {
+    let lib = libloading::Library::new(path)
+        .map_err(|e| e.to_string())?;
+
+    // The ABI string is returned through the C ABI so that it can be checked
+    // before calling anything which depends on the Rust ABI
+    let abi = lib.get::<extern "C" fn() -> *const std::ffi::c_char>(b"vince_plugin_abi_string\0")
+        .map_err(|e| e.to_string())?;
+    let abi = std::ffi::CStr::from_ptr(abi())
+        .to_string_lossy();
+    if abi != PLUGIN_ABI {
+        return Err(format!("built against {abi} but this is {PLUGIN_ABI}"));
+    }
+
+    let register_plugin = lib.get::<fn(&'static HostAllocFns, &mut PluginRegistry)>(b"vince_plugin_register\0")
+        .map_err(|e| e.to_string())?;
+    let mut registry = PluginRegistry::default();
+    register_plugin(&HOST_ALLOC_FNS, &mut registry);
+
+    let names = registry.modules.keys()
+        .cloned()
+        .collect();
+    register(registry);
+
+    // Keep the plugin loaded for as long as its modules might be used
+    LIBRARIES.lock()
+        .unwrap()
+        .push(lib);
+
+    Ok(names)
+}
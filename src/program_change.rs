@@ -0,0 +1,105 @@
+/*!
+Switching racks with MIDI Program Change messages, so that a set can be driven
+hands-free from a foot controller.
+
+Listening is enabled by the `[program_change]` table in `vince.toml`, where
+`port` only connects to the ports whose names contain the given text, ignoring
+case, and `channel` is the MIDI channel to listen on in the range [1, 16]:
+
+```toml
+[program_change]
+port = "FCB1010"
+channel = 16
+```
+
+Each program selects a rack by its `program` info, e.g. `program = 3`, or
+otherwise by its index in the rack order. Bank Select messages, i.e.
+controllers 0 and 32, select further groups of 128 programs so that program 0
+of bank 1 is program 128. Selecting the current rack's program reloads it,
+which recalls the knob values saved in its file.
+
+*/
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use bevy::prelude::*;
+
+use midly::{live::LiveEvent, MidiMessage};
+use midir::{MidiInput, MidiInputConnection};
+
+use crate::config;
+
+static PROGRAMS: Mutex<VecDeque<u32>> = Mutex::new(VecDeque::new());
+static CONNECTIONS: Mutex<Vec<MidiInputConnection<()>>> = Mutex::new(Vec::new());
+
+/// Start listening for program changes if enabled in the config
+pub fn init() {
+    let Some(pc) = &config::get().program_change else {
+        return;
+    };
+    let port_filter = pc.port.as_ref()
+        .map(|port| port.to_lowercase());
+
+    let mut midi_in = match MidiInput::new("Vince Program Change") {
+        Ok(midi_in) => midi_in,
+        Err(e) => {
+            error!("Failed to init MIDI input for program changes: {e}");
+            return;
+        },
+    };
+    let mut conns = CONNECTIONS.lock().unwrap();
+    for in_port in midi_in.ports() {
+        let Ok(port_name) = midi_in.port_name(&in_port) else {
+            continue;
+        };
+        if port_filter.as_ref().is_some_and(|port| !port_name.to_lowercase().contains(port)) {
+            continue;
+        }
+
+        let channel = pc.channel - 1;
+        let mut bank = [0u32; 2];
+        let conn = midi_in.connect(&in_port, "vince-program-change", move |_, message, _| {
+            let Ok(LiveEvent::Midi { channel: ch, message }) = LiveEvent::parse(message) else {
+                return;
+            };
+            if ch.as_int() != channel {
+                return;
+            }
+
+            match message {
+                MidiMessage::Controller { controller, value } if controller == 0 => bank[0] = value.as_int().into(),
+                MidiMessage::Controller { controller, value } if controller == 32 => bank[1] = value.as_int().into(),
+                MidiMessage::ProgramChange { program } => {
+                    let program = (bank[0] * 128 + bank[1]) * 128 + u32::from(program.as_int());
+                    if let Ok(mut programs) = PROGRAMS.lock() {
+                        programs.push_back(program);
+                    }
+                },
+                _ => {},
+            }
+        }, ());
+        match conn {
+            Ok(conn) => {
+                info!("Listening for program changes from {port_name}");
+                conns.push(conn);
+            },
+            Err(e) => error!("Failed to connect to MIDI port {port_name} for program changes: {e}"),
+        }
+
+        midi_in = match MidiInput::new("Vince Program Change") {
+            Ok(midi_in) => midi_in,
+            Err(_) => break,
+        };
+    }
+
+    if conns.is_empty() {
+        warn!("No MIDI ports found for program changes");
+    }
+}
+/// Take the most recently received program, if any
+pub fn take() -> Option<u32> {
+    let mut programs = PROGRAMS.lock().ok()?;
+    let program = programs.pop_back();
+    programs.clear();
+    program
+}
@@ -1,37 +1,153 @@
-use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU64, Ordering}};
+use std::time::Duration;
 
-use bevy::{prelude::*, reflect::TypePath, utils::HashMap, reflect::TypeUuid, sprite::Mesh2dHandle};
+use bevy::{prelude::*, reflect::TypePath, utils::{HashMap, Instant}, reflect::TypeUuid, sprite::Mesh2dHandle};
 
 use cpal::traits::{HostTrait, DeviceTrait, StreamTrait};
 use oddio::Signal;
-use serde::Deserialize;
+use rand::{SeedableRng, rngs::StdRng};
+use serde::{Deserialize, de};
 
 use crate::modules::ModuleIOK;
-use crate::{StepType, patch::Patches, modules::{ModuleKey, Module, ModuleComponent, ModuleTextComponent, ModuleMeshComponent, ModuleImageComponent}};
+use crate::{StepType, config, patch_lang, plugins, session, replay, patch::Patches, oversampling::Oversampler, modules::{ModuleKey, Module, ModuleDescription, KnobRange, KnobOptions, KnobScaling, SignalStandard, ModuleComponent, ModuleTextComponent, ModuleMeshComponent, ModuleImageComponent}};
 
 const AUDIO_BUFFER_SIZE: usize = 512;
 const AUDIO_STREAM_SIZE: usize = 16384;
+/// How often to check for changed audio devices or to retry missing ones
+const AUDIO_MONITOR_INTERVAL: Duration = Duration::from_secs(1);
+/// How much the output level falls on each step, about a quarter of a second
+/// at 44.1 kHz
+const OUTPUT_LEVEL_RELEASE: f32 = 0.9999;
 
-static mut AUDIO_OUTPUT_STREAM: Option<cpal::Stream> = None;
-static mut AUDIO_INPUT_STREAM: Option<cpal::Stream> = None;
+static RACK_SEED: AtomicU64 = AtomicU64::new(0);
 
+/// Create a random number generator for the given module which is seeded from
+/// the current rack's seed, so that each module draws from its own
+/// reproducible sequence
+pub fn module_rng(id: usize) -> StdRng {
+    let seed = RACK_SEED.load(Ordering::Acquire);
+    StdRng::seed_from_u64(seed ^ (id as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+/// The audio streams for the current rack's `AudioContext`
+///
+/// Streams can't be sent between threads on every platform so they're owned by
+/// a non-send resource instead of the rack. They're stopped whenever they're
+/// replaced, when the rack exits, and when the resource is dropped.
+#[derive(Default)]
+pub struct AudioStreams {
+    output: Option<cpal::Stream>,
+    input: Option<cpal::Stream>,
+}
+impl AudioStreams {
+    /// Stop and drop the current streams, if any
+    pub fn stop(&mut self) {
+        for stream in [self.output.take(), self.input.take()].into_iter().flatten() {
+            if let Err(e) = stream.pause() {
+                warn!("Failed to pause audio stream: {e}");
+            }
+        }
+    }
+}
+impl Drop for AudioStreams {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Flush a feedback state value to 0.0 if it's denormal, which would slow down
+/// processing, or if it isn't finite, which would poison the state forever
+pub fn flush_state(x: f32) -> f32 {
+    if x.is_finite() && !x.is_subnormal() {
+        x
+    } else {
+        0.0
+    }
+}
+/// Replace the infinite and denormal values in a module's outputs with 0.0,
+/// counting how many values have been scrubbed for the module
+fn sanitize_outputs(scrubbed: &mut HashMap<usize, usize>, id: usize, mouts: &mut [f32]) {
+    for mo in mouts.iter_mut() {
+        if mo.is_infinite() || mo.is_subnormal() {
+            let count = scrubbed.entry(id).or_insert(0);
+            if *count == 0 && mo.is_infinite() {
+                warn!("M{id} output an infinite value, it will be scrubbed");
+            }
+            *count += 1;
+            *mo = 0.0;
+        }
+    }
+}
+
+pub(crate) enum AudioOutputSink {
+    Stereo(oddio::Handle<oddio::Stream<[f32; 2]>>),
+    Multi(Arc<Mutex<VecDeque<f32>>>),
+}
 pub struct AudioContextOutput {
     _device: cpal::Device,
     pub(crate) config: cpal::StreamConfig,
 
-    buf_stream_handle: oddio::Handle<oddio::Stream<[f32; 2]>>,
-    buffer: Vec<[f32; 2]>,
+    sink: AudioOutputSink,
+    buffer: Vec<Vec<f32>>,
 }
 pub struct AudioContextInput {
     _device: cpal::Device,
     _config: cpal::StreamConfig,
 
+    channels: usize,
     buffer: Arc<Mutex<Vec<f32>>>,
+    /// The number of frames of silence still to insert before the captured
+    /// audio for latency compensation
+    delay: usize,
 }
 pub(crate) struct AudioContext {
-    _host: cpal::Host,
+    host: cpal::Host,
     pub(crate) output: AudioContextOutput,
     pub(crate) input: Option<AudioContextInput>,
+
+    output_name: Option<String>,
+    is_lost: Arc<AtomicBool>,
+}
+/// Get the audio host with the given name, or the default host
+pub(crate) fn audio_host(name: Option<&str>) -> cpal::Host {
+    match name {
+        Some(name) => {
+            cpal::available_hosts()
+                .into_iter()
+                .find(|id| id.name().eq_ignore_ascii_case(name))
+                .and_then(|id| cpal::host_from_id(id).ok())
+                .unwrap_or_else(|| {
+                    warn!("Failed to init audio host {name}, using the default");
+                    cpal::default_host()
+                })
+        },
+        None => cpal::default_host(),
+    }
+}
+/// Find the first audio input device whose name contains the given name
+pub(crate) fn find_input_device(host: &cpal::Host, name: &str) -> Option<cpal::Device> {
+    host.input_devices()
+        .ok()
+        .and_then(|mut devices| {
+            devices.find(|d| {
+                d.name()
+                    .is_ok_and(|dn| dn.contains(name))
+            })
+        })
+}
+/// Create an error callback for an audio stream which marks the audio context
+/// as lost when its device disappears
+fn stream_error_callback(is_lost: &Arc<AtomicBool>) -> impl FnMut(cpal::StreamError) + Send + 'static {
+    let is_lost = is_lost.clone();
+    move |err| {
+        error!("{err}");
+        session::record("error", &[("message", session::Field::Str(&err.to_string()))]);
+        if let cpal::StreamError::DeviceNotAvailable = err {
+            is_lost.store(true, Ordering::Release);
+        }
+    }
 }
 impl std::fmt::Debug for AudioContext {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -39,144 +155,947 @@ impl std::fmt::Debug for AudioContext {
     }
 }
 
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct AudioInputConfig {
+    #[serde(default)]
+    pub device: Option<String>,
+    #[serde(default)]
+    pub channels: Vec<usize>,
+    /// The number of seconds to delay all captured audio by
+    #[serde(default)]
+    pub latency_compensation: f32,
+}
+fn default_output_channels() -> u16 {
+    2
+}
+#[derive(Deserialize, Debug, Clone)]
+pub struct AudioOutputConfig {
+    #[serde(default)]
+    pub device: Option<String>,
+    #[serde(default = "default_output_channels")]
+    pub channels: u16,
+}
+impl Default for AudioOutputConfig {
+    fn default() -> Self {
+        Self {
+            device: None,
+            channels: default_output_channels(),
+        }
+    }
+}
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct AudioConfig {
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub input: AudioInputConfig,
+    #[serde(default)]
+    pub output: AudioOutputConfig,
+}
+
+/// The range of values of a patched output over each recent frame, used for
+/// the signal tooltips
+#[derive(Debug, Default, Clone)]
+pub struct SignalHistory {
+    /// The minimum and maximum value of each frame, oldest first
+    pub frames: VecDeque<(f32, f32)>,
+    current: Option<(f32, f32)>,
+}
+
+/// A summary of a rack's modules, knobs, and patches, used to show what changed
+/// when the rack is hot-reloaded
+#[derive(Debug, Default, Clone)]
+pub struct RackSummary {
+    modules: BTreeMap<usize, (String, Vec<f32>)>,
+    patches: BTreeSet<(ModuleKey, ModuleKey)>,
+}
+impl RackSummary {
+    /// Describe each change from this summary to the given one, prefixed with
+    /// `+` for additions, `-` for removals, and `~` for modifications
+    pub fn diff(&self, new: &RackSummary) -> Vec<String> {
+        let mut changes = vec![];
+        for (id, (label, knobs)) in &self.modules {
+            match new.modules.get(id) {
+                None => changes.push(format!("- {label}")),
+                Some((new_label, _)) if new_label != label => changes.push(format!("~ {label} -> {new_label}")),
+                Some((_, new_knobs)) => {
+                    for (i, (k, nk)) in knobs.iter().zip(new_knobs).enumerate() {
+                        if k != nk && !(k.is_nan() && nk.is_nan()) {
+                            changes.push(format!("~ {label} K{i}: {k} -> {nk}"));
+                        }
+                    }
+                },
+            }
+        }
+        for (id, (label, _)) in &new.modules {
+            if !self.modules.contains_key(id) {
+                changes.push(format!("+ {label}"));
+            }
+        }
+
+        for (o, i) in self.patches.difference(&new.patches) {
+            changes.push(format!("- {o} -> {i}"));
+        }
+        for (o, i) in new.patches.difference(&self.patches) {
+            changes.push(format!("+ {o} -> {i}"));
+        }
+
+        changes
+    }
+}
+
+/// The layout options which can be given for each module in the rack's
+/// `[modules]` table
+#[derive(Default, Debug, Clone)]
+pub struct ModuleLayout {
+    /// The name of the group to display the module in
+    pub group: Option<String>,
+    /// The color to tint the module's background with
+    pub color: Option<Color>,
+    /// The position of the module's top left corner in pixels, which
+    /// overrides the automatic layout
+    pub pos: Option<Vec2>,
+}
+/// A module in the rack's `[modules]` table along with its layout options
+struct ModuleEntry {
+    module: Box<dyn Module>,
+    layout: ModuleLayout,
+}
+impl<'de> Deserialize<'de> for ModuleEntry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let mut value = toml::Value::deserialize(deserializer)?;
+
+        let mut layout = ModuleLayout::default();
+        if let Some(table) = value.as_table_mut() {
+            match table.remove("group") {
+                Some(toml::Value::String(group)) => layout.group = Some(group),
+                Some(group) => return Err(de::Error::custom(format!("invalid module group {group}"))),
+                None => {},
+            }
+            match table.remove("color") {
+                Some(toml::Value::String(color)) => {
+                    layout.color = Some(
+                        Color::hex(&color)
+                            .map_err(|e| de::Error::custom(format!("invalid module color {color}: {e:?}")))?
+                    );
+                },
+                Some(color) => return Err(de::Error::custom(format!("invalid module color {color}"))),
+                None => {},
+            }
+            match table.remove("pos") {
+                Some(toml::Value::Array(pos)) => {
+                    let coords: Vec<f32> = pos.iter()
+                        .filter_map(|c| match c {
+                            toml::Value::Integer(c) => Some(*c as f32),
+                            toml::Value::Float(c) => Some(*c as f32),
+                            _ => None,
+                        }).collect();
+                    match coords[..] {
+                        [x, y] => layout.pos = Some(Vec2::new(x, y)),
+                        _ => return Err(de::Error::custom(format!("invalid module pos {pos:?}, expected [x, y]"))),
+                    }
+                },
+                Some(pos) => return Err(de::Error::custom(format!("invalid module pos {pos}, expected [x, y]"))),
+                None => {},
+            }
+        }
+
+        // Construct modules which were registered by plugins, otherwise fall
+        // back to the built-in modules
+        let constructor = value.get("type")
+            .and_then(toml::Value::as_str)
+            .and_then(plugins::constructor);
+        let module = match constructor {
+            Some(constructor) => constructor(value).map_err(de::Error::custom)?,
+            None => value.try_into().map_err(de::Error::custom)?,
+        };
+
+        Ok(Self {
+            module,
+            layout,
+        })
+    }
+}
+/// Deserialize the `[info]` section, keeping numbers and booleans as strings
+/// so that e.g. `order = 2` is accepted as well as `order = "2"`
+fn deserialize_info<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    HashMap::<String, toml::Value>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(k, v)| match v {
+            toml::Value::String(v) => Ok((k, v)),
+            toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => Ok((k, v.to_string())),
+            v => Err(de::Error::custom(format!("invalid info value for {k}: {v}"))),
+        }).collect()
+}
+#[derive(Deserialize)]
+struct RackDef {
+    #[serde(default, deserialize_with = "deserialize_info")]
+    info: HashMap<String, String>,
+    #[serde(default)]
+    audio: AudioConfig,
+
+    modules: HashMap<ModuleKey, ModuleEntry>,
+    patches: Patches,
+    #[serde(default)]
+    knob_ranges: HashMap<ModuleKey, KnobOptions>,
+}
+
+/// Registers the loaders for racks in formats other than TOML
+pub struct RackLoaderPlugin;
+impl Plugin for RackLoaderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset_loader::<patch_lang::PatchLangLoader>();
+        #[cfg(feature = "vcv")]
+        app.init_asset_loader::<crate::vcv::VcvLoader>();
+    }
+}
+
+/// How module outputs are scrubbed before they're patched, from the rack's
+/// `sanitize` info
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum SanitizeMode {
+    #[default]
+    Flush,
+    Off,
+}
+/// How patches between different signal standards are handled, from the
+/// rack's `calibrate` info
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum CalibrateMode {
+    #[default]
+    Warn,
+    Auto,
+    Off,
+}
+
 #[derive(Deserialize, TypeUuid, Debug, TypePath)]
+#[serde(try_from = "RackDef")]
 #[uuid = "23f4f379-ed3e-4e41-9093-58b4e73ea9a9"]
 pub struct Rack {
-    #[serde(skip)]
     pub(crate) audio_context: Option<AudioContext>,
 
-    #[serde(default)]
     pub info: HashMap<String, String>,
+    pub audio: AudioConfig,
 
     pub modules: HashMap<ModuleKey, Box<dyn Module>>,
+    pub layout: HashMap<usize, ModuleLayout>,
     pub patches: Patches,
+    pub knob_ranges: HashMap<ModuleKey, KnobOptions>,
 
-    #[serde(skip)]
     outs: HashMap<ModuleKey, f32>,
+    last_audio_check: Option<Instant>,
+    oversamplers: HashMap<usize, Oversampler>,
+    scrubbed: HashMap<usize, usize>,
+    ranges: HashMap<ModuleKey, KnobRange>,
+    /// The standards of the output and input of each patch which is converted
+    /// between them
+    calibrations: HashMap<(ModuleKey, ModuleKey), (SignalStandard, SignalStandard)>,
+    signal_history: HashMap<ModuleKey, SignalHistory>,
+    bypassed: HashMap<usize, String>,
+    /// The time of the first step and the time of the last step
+    times: Option<(f64, f64)>,
+    /// The peak level of the generated audio with a short release
+    output_level: f32,
+    /// The index of the module which receives keyboard input
+    focused: Option<usize>,
+    sanitize: SanitizeMode,
+    calibrate: CalibrateMode,
+    /// Whether generated audio is left in the module buffers when there's no
+    /// audio context so that it can be drained by the caller, otherwise it's
+    /// discarded
+    pub(crate) keep_audio: bool,
+}
+impl TryFrom<RackDef> for Rack {
+    type Error = String;
+
+    fn try_from(def: RackDef) -> Result<Self, Self::Error> {
+        let sanitize = match def.info.get("sanitize").map(|s| s.as_str()) {
+            Some("Flush") | None => SanitizeMode::Flush,
+            Some("Off") => SanitizeMode::Off,
+            Some(s) => return Err(format!("Unknown rack sanitize mode: {s}")),
+        };
+        let calibrate = match def.info.get("calibrate").map(|s| s.as_str()) {
+            Some("Warn") | None => CalibrateMode::Warn,
+            Some("Auto") => CalibrateMode::Auto,
+            Some("Off") => CalibrateMode::Off,
+            Some(s) => return Err(format!("Unknown rack calibrate mode: {s}")),
+        };
+
+        let mut modules = HashMap::with_capacity(def.modules.len());
+        let mut layout = HashMap::new();
+        for (k, entry) in def.modules {
+            modules.insert(k, entry.module);
+            layout.insert(k.id, entry.layout);
+        }
+
+        // Use the default audio host and devices from the config unless the
+        // rack sets its own
+        let mut audio = def.audio;
+        let defaults = &config::get().audio;
+        audio.host = audio.host.or_else(|| defaults.host.clone());
+        audio.input.device = audio.input.device.or_else(|| defaults.input.device.clone());
+        audio.output.device = audio.output.device.or_else(|| defaults.output.device.clone());
+
+        Ok(Self {
+            audio_context: None,
+
+            info: def.info,
+            audio,
+
+            modules,
+            layout,
+            patches: def.patches,
+            knob_ranges: def.knob_ranges,
+
+            outs: HashMap::default(),
+            last_audio_check: None,
+            oversamplers: HashMap::default(),
+            scrubbed: HashMap::default(),
+            ranges: HashMap::default(),
+            calibrations: HashMap::default(),
+            signal_history: HashMap::default(),
+            bypassed: HashMap::default(),
+            times: None,
+            output_level: 0.0,
+            focused: None,
+            sanitize,
+            calibrate,
+            keep_audio: false,
+        })
+    }
+}
+/// Apply the knob's range to a patched value, if it has one
+fn apply_knob_range(ranges: &HashMap<ModuleKey, KnobRange>, id: usize, i: usize, val: f32) -> f32 {
+    let key = ModuleKey {
+        id,
+        iok: ModuleIOK::Knob(i),
+    };
+    match ranges.get(&key) {
+        Some(range) => range.apply(val),
+        None => val,
+    }
+}
+/// Convert a patched value between the standards of its output and input, if
+/// the patch is calibrated
+fn apply_calibration(calibrations: &HashMap<(ModuleKey, ModuleKey), (SignalStandard, SignalStandard)>, output: &ModuleKey, input: &ModuleKey, val: f32) -> f32 {
+    match calibrations.get(&(*output, *input)) {
+        Some((from, to)) => from.convert(*to, val),
+        None => val,
+    }
+}
+/// Bypass the given module so that it's no longer stepped, logging the reason
+/// the first time
+fn bypass_module(bypassed: &mut HashMap<usize, String>, id: usize, reason: String) {
+    bypassed.entry(id)
+        .or_insert_with(|| {
+            error!("M{id} has been bypassed: {reason}");
+            session::record("error", &[
+                ("module", session::Field::Num(id as f64)),
+                ("message", session::Field::Str(&format!("bypassed: {reason}"))),
+            ]);
+            reason
+        });
+}
+/// Call one of a module's functions, catching any panic so that a single
+/// module can't crash the whole rack, in which case the module is bypassed
+fn catch_module_panic<T>(bypassed: &mut HashMap<usize, String>, id: usize, f: impl FnOnce() -> T) -> Option<T> {
+    match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(t) => Some(t),
+        Err(payload) => {
+            let msg = payload.downcast_ref::<&str>()
+                .map(|msg| msg.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            bypass_module(bypassed, id, format!("panicked: {msg}"));
+            None
+        },
+    }
+}
+/// Step a module, oversampling it if requested, or output [f32::NAN] if it's
+/// bypassed
+fn step_module(m: &mut Box<dyn Module>, oversamplers: &mut HashMap<usize, Oversampler>, bypassed: &mut HashMap<usize, String>, id: usize, time: f64, st: StepType, ins: &[f32]) -> Vec<f32> {
+    if bypassed.contains_key(&id) {
+        return vec![f32::NAN; m.outputs()];
+    }
+
+    let factor = m.oversampling();
+    let mouts = catch_module_panic(bypassed, id, || {
+        if factor <= 1 || st == StepType::Video {
+            return m.step(time, st, ins);
+        }
+
+        let oversampler = oversamplers.entry(id)
+            .or_insert_with(|| Oversampler::new(factor, m.inputs(), m.outputs()));
+        if oversampler.factor() != factor {
+            *oversampler = Oversampler::new(factor, m.inputs(), m.outputs());
+        }
+        oversampler.process(ins, |ins| m.step(time, st, ins))
+    });
+    mouts.unwrap_or_else(|| vec![f32::NAN; m.outputs()])
+}
+/// Set a patched knob on a module unless it's bypassed, bypassing it if the
+/// knob doesn't exist
+fn set_module_knob(m: &mut Box<dyn Module>, ranges: &HashMap<ModuleKey, KnobRange>, bypassed: &mut HashMap<usize, String>, id: usize, i: usize, val: f32) {
+    if bypassed.contains_key(&id) {
+        return;
+    }
+    if i >= m.knobs() {
+        bypass_module(bypassed, id, format!("knob {i} is patched but the module only has {} knobs", m.knobs()));
+        return;
+    }
+
+    catch_module_panic(bypassed, id, || m.set_knob(i, apply_knob_range(ranges, id, i, val)));
 }
 impl Rack {
-    pub(crate) fn init_audio(&mut self) {
-        let host = cpal::default_host();
-        let out_device = host.default_output_device().expect("no audio output device available");
-        let sample_rate = out_device.default_output_config().unwrap().sample_rate();
+    /// Set the seed for all module random number generators from the rack's
+    /// `seed` info, or randomly if it isn't given
+    pub fn init_seed(&self) {
+        let seed = match self.info.get("seed") {
+            Some(seed) => seed.parse().unwrap_or_else(|_| {
+                // Hash non-numeric seeds with FNV-1a
+                seed.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| (h ^ u64::from(b)).wrapping_mul(0x100_0000_01b3))
+            }),
+            None => rand::random(),
+        };
+        let seed = replay::sync_seed(seed);
+        if !self.info.contains_key("seed") {
+            info!("Using rack seed {seed}, set `seed` in the rack's [info] to reproduce it");
+        }
+        RACK_SEED.store(seed, Ordering::Release);
+    }
+    /// Collect the knob ranges from the rack's `[knob_ranges]` table, where
+    /// any bounds that aren't given default to the knob's documented range.
+    /// Knobs without options aren't held to their documented ranges, which are
+    /// only advisory
+    pub fn init_knob_ranges(&mut self) {
+        self.ranges.clear();
+        for (k, m) in &self.modules {
+            for (i, knob) in m.describe().knobs.iter().enumerate() {
+                let key = ModuleKey {
+                    id: k.id,
+                    iok: ModuleIOK::Knob(i),
+                };
+                if let Some(options) = self.knob_ranges.get(&key) {
+                    let range = knob.knob_range().unwrap_or(KnobRange {
+                        min: f32::NEG_INFINITY,
+                        max: f32::INFINITY,
+                        step: None,
+                        unit: None,
+                        scaling: KnobScaling::None,
+                    });
+                    self.ranges.insert(key, range.with_options(options));
+                }
+            }
+        }
+
+        for key in self.knob_ranges.keys() {
+            if !self.ranges.contains_key(key) {
+                error!("Knob range given for unknown knob {key:?}");
+            }
+        }
+    }
+    /// Check that each patch connects an output and input with the same signal
+    /// standard from their docs, warning about mismatches and converting
+    /// between them if the rack's `calibrate` info is `Auto`
+    pub fn init_calibrations(&mut self) {
+        self.calibrations.clear();
+        let should_convert = match self.calibrate {
+            CalibrateMode::Warn => false,
+            CalibrateMode::Auto => true,
+            CalibrateMode::Off => return,
+        };
+
+        let descriptions: HashMap<usize, &ModuleDescription> = self.modules.iter()
+            .map(|(k, m)| (k.id, m.describe()))
+            .collect();
+        let standard = |key: &ModuleKey| {
+            let description = descriptions.get(&key.id)?;
+            match key.iok {
+                ModuleIOK::Input(i) => description.inputs.get(i)?.standard(),
+                ModuleIOK::Output(i) => description.outputs.get(i)?.standard(),
+                _ => None,
+            }
+        };
+        for (output, input) in self.patches.iter() {
+            if !matches!(input.iok, ModuleIOK::Input(_)) {
+                continue;
+            }
+            if let (Some(from), Some(to)) = (standard(output), standard(input)) {
+                if from != to {
+                    if should_convert {
+                        info!("Converting patch {output:?} -> {input:?} from {from:?} to {to:?}");
+                        self.calibrations.insert((*output, *input), (from, to));
+                    } else {
+                        warn!("Patch {output:?} -> {input:?} connects a {from:?} signal to a {to:?} input, set `calibrate = \"Auto\"` in the rack's [info] to convert it");
+                    }
+                }
+            }
+        }
+    }
+    /// Init the audio context, replacing any previous streams
+    pub fn init_audio(&mut self, streams: &mut AudioStreams) {
+        streams.stop();
+        self.audio_context = None;
+        self.outs = HashMap::with_capacity(self.modules.len());
 
+        let host = audio_host(self.audio.host.as_deref());
+        let out_device = match &self.audio.output.device {
+            Some(name) => {
+                host.output_devices()
+                    .ok()
+                    .and_then(|mut devices| {
+                        devices.find(|d| {
+                            d.name()
+                                .is_ok_and(|dn| dn.contains(name.as_str()))
+                        })
+                    }).or_else(|| {
+                        warn!("Failed to find audio output device {name}, using the default");
+                        host.default_output_device()
+                    })
+            },
+            None => host.default_output_device(),
+        };
+        let Some(out_device) = out_device else {
+            warn!("No audio output device available, the rack will run silently");
+            return;
+        };
+        let sample_rate = match out_device.default_output_config() {
+            Ok(config) => config.sample_rate(),
+            Err(e) => {
+                error!("Failed to get audio output config: {e}");
+                return;
+            },
+        };
+        let is_lost = Arc::new(AtomicBool::new(false));
+
+        let out_channels = match self.audio.output.channels {
+            c @ (2 | 4 | 6 | 8) => c,
+            c => {
+                error!("Unsupported number of audio output channels {c}, using stereo");
+                2
+            },
+        };
         let out_config = cpal::StreamConfig {
-            channels: 2,
+            channels: out_channels,
             sample_rate,
             buffer_size: cpal::BufferSize::Default,
         };
 
-        let (out_buf_stream_handle, out_buf_stream) = oddio::split(oddio::Stream::<[f32; 2]>::new(sample_rate.0, AUDIO_STREAM_SIZE));
+        let out_stream = if out_channels == 2 {
+            let (out_buf_stream_handle, out_buf_stream) = oddio::split(oddio::Stream::<[f32; 2]>::new(sample_rate.0, AUDIO_STREAM_SIZE));
 
-        let out_stream = out_device.build_output_stream(
-            &out_config,
-            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                let frames = oddio::frame_stereo(data);
-                oddio::run(&out_buf_stream, sample_rate.0, frames);
-            },
-            |err| {
-                error!("{err}");
+            let out_stream = out_device.build_output_stream(
+                &out_config,
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    let frames = oddio::frame_stereo(data);
+                    oddio::run(&out_buf_stream, sample_rate.0, frames);
+                },
+                stream_error_callback(&is_lost),
+                None,
+            );
+
+            out_stream.map(|s| (AudioOutputSink::Stereo(out_buf_stream_handle), s))
+        } else {
+            let out_buffer: Arc<Mutex<VecDeque<f32>>> = Arc::new(Mutex::new(VecDeque::with_capacity(AUDIO_STREAM_SIZE * usize::from(out_channels))));
+            let outbuf = out_buffer.clone();
+
+            let out_stream = out_device.build_output_stream(
+                &out_config,
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    if let Ok(mut buf) = outbuf.lock() {
+                        for sample in data.iter_mut() {
+                            *sample = buf.pop_front().unwrap_or(0.0);
+                        }
+                    } else {
+                        data.fill(0.0);
+                    }
+                },
+                stream_error_callback(&is_lost),
+                None,
+            );
+
+            out_stream.map(|s| (AudioOutputSink::Multi(out_buffer), s))
+        };
+        let (sink, out_stream) = match out_stream {
+            Ok(out_stream) => out_stream,
+            Err(e) => {
+                error!("Failed to build audio output stream: {e}");
+                return;
             },
-            None,
-        ).unwrap();
-        out_stream.play().unwrap();
-        unsafe {
-            AUDIO_OUTPUT_STREAM = Some(out_stream);
+        };
+        if let Err(e) = out_stream.play() {
+            error!("Failed to play audio output stream: {e}");
+            return;
+        }
+        streams.output = Some(out_stream);
+        for m in self.modules.values_mut() {
+            m.set_sample_rate(sample_rate.0);
         }
 
-        let input = match host.default_input_device() {
-            Some(in_device) => {
-                let in_channels = in_device.default_input_config().unwrap().channels();
+        let in_device = match &self.audio.input.device {
+            Some(name) => {
+                find_input_device(&host, name)
+                    .or_else(|| {
+                        warn!("Failed to find audio input device {name}, using the default");
+                        host.default_input_device()
+                    })
+            },
+            None => host.default_input_device(),
+        };
+        let input = match in_device.map(|d| (d.default_input_config(), d)) {
+            Some((Ok(default_in_config), in_device)) => {
+                let in_channels = default_in_config.channels();
                 let in_config = cpal::StreamConfig {
                     channels: in_channels,
-                    sample_rate: in_device.default_input_config().unwrap().sample_rate(),
+                    sample_rate: default_in_config.sample_rate(),
                     buffer_size: cpal::BufferSize::Default,
                 };
 
+                // Map the configured channels (1-indexed) to device channels
+                let channel_map: Vec<usize> = if self.audio.input.channels.is_empty() {
+                    (0..usize::from(in_channels)).collect()
+                } else {
+                    self.audio.input.channels.iter()
+                        .filter_map(|c| {
+                            if *c == 0 || *c > usize::from(in_channels) {
+                                error!("Audio input channel {c} is out of range for a device with {in_channels} channels");
+                                None
+                            } else {
+                                Some(c - 1)
+                            }
+                        }).collect()
+                };
+                let channels = channel_map.len();
+                let latency_compensation = if self.audio.input.latency_compensation >= 0.0 {
+                    self.audio.input.latency_compensation
+                } else {
+                    error!("Audio input latency compensation must be non-negative");
+                    0.0
+                };
+                let delay = (latency_compensation * in_config.sample_rate.0 as f32) as usize;
+
                 let in_buffer: Arc<Mutex<Vec<f32>>> = Arc::new(Mutex::new(vec![]));
                 let inbuf = in_buffer.clone();
 
-                let in_stream = if in_channels == 1 {
-                    in_device.build_input_stream(
-                        &in_config,
-                        move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                            if let Ok(mut buf) = inbuf.lock() {
-                                buf.extend(data);
-                            } else {
-                                error!("Rack dropped audio input");
-                            }
-                        },
-                        |err| {
-                            error!("{err}");
-                        },
-                        None
-                    ).unwrap()
-                } else if in_channels == 2 {
-                    in_device.build_input_stream(
-                        &in_config,
-                        move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                            if let Ok(mut buf) = inbuf.lock() {
+                let in_stream = in_device.build_input_stream(
+                    &in_config,
+                    move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                        if let Ok(mut buf) = inbuf.lock() {
+                            for frame in data.chunks_exact(usize::from(in_channels)) {
                                 buf.extend(
-                                    data.iter()
-                                        .array_chunks::<2>()
-                                        .map(|[left, right]| {
-                                            (left + right) / 2.0
-                                        })
+                                    channel_map.iter()
+                                        .map(|c| frame[*c])
                                 );
-                            } else {
-                                error!("Rack dropped audio input");
                             }
-                        },
-                        |err| {
-                            error!("{err}");
-                        },
+                        } else {
+                            error!("Rack dropped audio input");
+                        }
+                    },
+                    stream_error_callback(&is_lost),
+                    None
+                );
+                match in_stream.map(|s| s.play().map(|_| s)) {
+                    Ok(Ok(in_stream)) => {
+                        streams.input = Some(in_stream);
+
+                        Some(AudioContextInput {
+                            _device: in_device,
+                            _config: in_config,
+                            channels,
+                            buffer: in_buffer,
+                            delay,
+                        })
+                    },
+                    Ok(Err(e)) => {
+                        error!("Failed to play audio input stream: {e}");
                         None
-                    ).unwrap()
-                } else {
-                    panic!("Failed to init audio input stream: unsupported number of channels");
-                };
-                in_stream.play().unwrap();
-                unsafe {
-                    AUDIO_INPUT_STREAM = Some(in_stream);
+                    },
+                    Err(e) => {
+                        error!("Failed to build audio input stream: {e}");
+                        None
+                    },
                 }
-
-                Some(AudioContextInput {
-                    _device: in_device,
-                    _config: in_config,
-                    buffer: in_buffer,
-                })
+            },
+            Some((Err(e), _)) => {
+                error!("Failed to get audio input config: {e}");
+                None
             },
             None => None,
         };
 
         self.audio_context = Some(AudioContext {
-            _host: host,
+            output_name: out_device.name().ok(),
+            host,
             output: AudioContextOutput {
                 _device: out_device,
                 config: out_config,
-                buf_stream_handle: out_buf_stream_handle,
+                sink,
                 buffer: vec![],
             },
             input,
+            is_lost,
         });
+    }
+    /// Check whether the audio devices have been lost or the default output
+    /// device has changed, in which case the audio context is rebuilt. If
+    /// there's no audio context, e.g. when no device was available, then it's
+    /// retried instead. The modules keep their state so the rack continues
+    /// from silence once the new streams start.
+    pub fn monitor_audio(&mut self, streams: &mut AudioStreams) {
+        let Some(audio_context) = &self.audio_context else {
+            if self.last_audio_check.map_or(true, |t| t.elapsed() >= AUDIO_MONITOR_INTERVAL) {
+                self.last_audio_check = Some(Instant::now());
+                self.init_audio(streams);
+            }
+            return;
+        };
 
-        self.outs = HashMap::with_capacity(self.modules.len());
+        let is_lost = audio_context.is_lost.load(Ordering::Acquire);
+        let is_changed = self.audio.output.device.is_none()
+            && self.last_audio_check.map_or(true, |t| t.elapsed() >= AUDIO_MONITOR_INTERVAL)
+            && {
+                self.last_audio_check = Some(Instant::now());
+                audio_context.host.default_output_device()
+                    .and_then(|d| d.name().ok())
+                    .is_some_and(|name| Some(name) != audio_context.output_name)
+            };
+        if is_lost || is_changed {
+            info!("Audio device changed, rebuilding audio...");
+            self.last_audio_check = Some(Instant::now());
+            self.init_audio(streams);
+        }
     }
 
+    /// Pass the keyboard input to the focused module, focusing the first
+    /// focusable module if none is focused yet
     pub fn keyboard_input(&mut self, keys: &Res<Input<KeyCode>>) {
-        for m in self.modules.values_mut() {
+        if self.focused_module().is_none() {
+            self.focused = self.focusable_ids().first().copied();
+        }
+        let Some(id) = self.focused else {
+            return;
+        };
+        if let Some(m) = self.modules.get_mut(&ModuleKey {
+            id,
+            iok: ModuleIOK::None,
+        }) {
             m.keyboard_input(keys);
         }
     }
+    /// Pass the mouse input to every module, and focus the focusable module
+    /// which is clicked on
     pub fn mouse_input(&mut self, mouse_buttons: &Res<Input<MouseButton>>, window: &Window, q_child: &Query<&Parent, With<ModuleComponent>>, q_transform: &Query<&GlobalTransform>) {
+        if mouse_buttons.just_pressed(MouseButton::Left) {
+            if let Some(mpos) = window.cursor_position() {
+                if let Some((k, _)) = self.modules.iter()
+                    .find(|(k, m)| k.id != usize::MAX && m.is_init() && m.is_focusable() && m.is_hovered(mpos, q_child, q_transform))
+                {
+                    self.focused = Some(k.id);
+                }
+            }
+        }
+
         for m in self.modules.values_mut() {
             m.mouse_input(mouse_buttons, window, q_child, q_transform);
         }
     }
+    /// Get the indices of the modules which take keyboard input in index
+    /// order
+    fn focusable_ids(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = self.modules.iter()
+            .filter(|(k, m)| k.id != usize::MAX && m.is_init() && m.is_focusable())
+            .map(|(k, _)| k.id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+    /// Get the index of the module which receives keyboard input, if it
+    /// still exists
+    pub fn focused_module(&self) -> Option<usize> {
+        self.focused
+            .filter(|id| self.focusable_ids().contains(id))
+    }
+    /// Move the focus to the next focusable module in index order, or the
+    /// previous one when reversed, wrapping around at either end
+    pub fn focus_next(&mut self, is_reversed: bool) {
+        let ids = self.focusable_ids();
+        if ids.is_empty() {
+            self.focused = None;
+            return;
+        }
+
+        let pos = self.focused_module()
+            .and_then(|id| ids.iter().position(|i| *i == id));
+        let next = match (pos, is_reversed) {
+            (Some(pos), false) => (pos + 1) % ids.len(),
+            (Some(pos), true) => (pos + ids.len() - 1) % ids.len(),
+            (None, false) => 0,
+            (None, true) => ids.len() - 1,
+        };
+        self.focused = Some(ids[next]);
+    }
+    /// Get the next free module index
+    pub fn next_module_id(&self) -> usize {
+        self.modules.keys()
+            .map(|k| k.id)
+            .filter(|id| *id != usize::MAX)
+            .max()
+            .map_or(0, |id| id + 1)
+    }
+    /// Get the index and label of each module in index order, e.g. for
+    /// searching in the command palette
+    pub fn module_labels(&self) -> Vec<(usize, String)> {
+        let mut labels: Vec<(usize, String)> = self.modules.iter()
+            .filter(|(k, _)| k.id != usize::MAX)
+            .map(|(k, m)| {
+                let name = m.name()
+                    .unwrap_or_else(|| {
+                        format!("{m:?}")
+                            .split_whitespace()
+                            .next()
+                            .unwrap_or_default()
+                            .to_string()
+                    });
+                (k.id, format!("M{} {name}", k.id))
+            }).collect();
+        labels.sort_by_key(|(id, _)| *id);
+        labels
+    }
+    /// Summarize the rack's modules, knobs, and patches
+    pub fn summary(&self) -> RackSummary {
+        RackSummary {
+            modules: self.module_labels()
+                .into_iter()
+                .map(|(id, label)| {
+                    let knobs = self.modules.get(&ModuleKey {
+                        id,
+                        iok: ModuleIOK::None,
+                    }).map(|m| m.get_knobs())
+                        .unwrap_or_default();
+                    (id, (label, knobs))
+                }).collect(),
+            patches: self.patches.iter()
+                .map(|(o, i)| (*o, *i))
+                .collect(),
+        }
+    }
+    /// Get the help text for the module at the given screen position, if any
+    pub fn help_text(&self, pos: Vec2, q_child: &Query<&Parent, With<ModuleComponent>>, q_transform: &Query<&GlobalTransform>) -> Option<String> {
+        self.modules.iter()
+            .find(|(_, m)| m.is_init() && m.is_hovered(pos, q_child, q_transform))
+            .map(|(k, m)| {
+                let name = match m.name() {
+                    Some(name) => format!("M{} {name}", k.id),
+                    None => format!("M{}", k.id),
+                };
+                m.describe().to_help_text(&name, m.as_ref())
+            })
+    }
+    /// Get the current value of the given output, if it isn't [f32::NAN]
+    pub fn signal(&self, k: &ModuleKey) -> Option<f32> {
+        self.outs.get(k).copied()
+    }
+    /// Get the index of each module which has been bypassed after an error
+    /// along with the reason
+    pub fn bypassed(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.bypassed.iter()
+            .map(|(id, reason)| (*id, reason.as_str()))
+    }
+    /// Get the recent history of the given patched output
+    pub fn signal_history(&self, k: &ModuleKey) -> Option<&SignalHistory> {
+        self.signal_history.get(k)
+    }
+    pub fn touch_input(&mut self, touches: &Res<Touches>) {
+        for m in self.modules.values_mut() {
+            m.touch_input(touches);
+        }
+    }
+    /// The peak level of the rack's audio output, which falls back to 0.0
+    /// over about a quarter of a second once the audio goes quiet
+    pub fn output_level(&self) -> f32 {
+        self.output_level
+    }
+    /// How long the rack has been stepping for in seconds
+    pub fn elapsed(&self) -> f64 {
+        self.times.map_or(0.0, |(start, last)| last - start)
+    }
+    /// How long to play the rack for before advancing to the next rack, from
+    /// the rack's `duration` info in seconds
+    pub fn duration(&self) -> Option<f64> {
+        self.info.get("duration")
+            .and_then(|d| d.parse().ok())
+            .filter(|d: &f64| *d > 0.0)
+    }
+    /// The sample rate of the audio output device, if there is one
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.audio_context.as_ref()
+            .map(|ac| ac.output.config.sample_rate.0)
+    }
+    /// Drain the audio generated by every module since the last drain, mixed
+    /// down into frames of the given number of channels
+    pub fn drain_audio(&mut self, channels: usize) -> Vec<Vec<f32>> {
+        let mut ao: Vec<Vec<f32>> = vec![];
+        for m in self.modules.values_mut() {
+            let stereo = m.drain_audio_buffer()
+                .into_iter()
+                .map(|sample| sample.to_vec());
+            let multi = m.drain_multi_audio_buffer();
+            for b in [stereo.collect::<Vec<Vec<f32>>>(), multi] {
+                for (i, sample) in b.iter().enumerate() {
+                    if i >= ao.len() {
+                        ao.push(vec![0.0; channels]);
+                    }
+                    for (c, s) in sample.iter().take(channels).enumerate() {
+                        ao[i][c] += s;
+                    }
+                }
+            }
+        }
+        ao
+    }
+    /// Hold knobs which were changed outside of patches, e.g. by MIDI, the
+    /// mouse, or duplicating a module, to their ranges, while patched values
+    /// are scaled onto the ranges by [set_module_knob]
+    fn clamp_knobs(&mut self) {
+        for (key, range) in &self.ranges {
+            let ModuleIOK::Knob(i) = key.iok else {
+                continue;
+            };
+            if self.bypassed.contains_key(&key.id) {
+                continue;
+            }
+            let Some(m) = self.modules.iter_mut()
+                .find(|(k, _)| k.id == key.id)
+                .map(|(_, m)| m)
+            else {
+                continue;
+            };
+
+            let Some(&val) = m.get_knobs().get(i) else {
+                continue;
+            };
+            let clamped = range.clamp(val);
+            if !val.is_nan() && clamped != val {
+                catch_module_panic(&mut self.bypassed, key.id, || m.set_knob(i, clamped));
+            }
+        }
+    }
     pub fn step(&mut self, time: f64, st: StepType) {
-        if self.audio_context.is_none() {
-            self.init_audio();
+        match &mut self.times {
+            Some((_, last)) => *last = time,
+            times @ None => *times = Some((time, time)),
         }
+        if st == StepType::Key {
+            self.clamp_knobs();
+        }
+
+        let should_sanitize = self.sanitize == SanitizeMode::Flush;
 
         let mut stepped: Vec<usize> = Vec::with_capacity(self.modules.len());
 
@@ -188,7 +1107,10 @@ impl Rack {
                     .any(|p| p.1.id == k.id)
             )
         {
-            let mouts = m.step(time, st, &vec![0.0; m.inputs()]);
+            let mut mouts = step_module(m, &mut self.oversamplers, &mut self.bypassed, k.id, time, st, &vec![0.0; m.inputs()]);
+            if should_sanitize {
+                sanitize_outputs(&mut self.scrubbed, k.id, &mut mouts);
+            }
             stepped.push(k.id);
             for (i, mo) in mouts.iter().enumerate() {
                 self.outs.insert(ModuleKey {
@@ -217,10 +1139,13 @@ impl Rack {
                         for p in inpatches {
                             if let Some(o) = self.outs.iter().find(|o| o.0 == p.0) {
                                 match p.1.iok {
-                                    ModuleIOK::Input(i) => mins[i] = *o.1,
+                                    ModuleIOK::Input(i) => match mins.get_mut(i) {
+                                        Some(min) => *min = apply_calibration(&self.calibrations, p.0, p.1, *o.1),
+                                        None => bypass_module(&mut self.bypassed, k.id, format!("input {i} is patched but the module only has {} inputs", mins.len())),
+                                    },
                                     ModuleIOK::Knob(i) => {
                                         if !o.1.is_nan() {
-                                            m.set_knob(i, *o.1)
+                                            set_module_knob(m, &self.ranges, &mut self.bypassed, k.id, i, *o.1);
                                         }
                                     },
                                     ModuleIOK::Output(_) => error!("Can't patch an output to another output"),
@@ -229,7 +1154,10 @@ impl Rack {
                             }
                         }
 
-                        let mouts = m.step(time, st, &mins);
+                        let mut mouts = step_module(m, &mut self.oversamplers, &mut self.bypassed, k.id, time, st, &mins);
+                        if should_sanitize {
+                            sanitize_outputs(&mut self.scrubbed, k.id, &mut mouts);
+                        }
                         stepped.push(k.id);
                         step_count += 0;
                         for (i, mo) in mouts.iter().enumerate() {
@@ -254,48 +1182,88 @@ impl Rack {
             step_count = 0;
         }
 
-        if let Some(audio_context) = &mut self.audio_context {
-            // Play generated audio
-            let ao: Vec<[f32; 2]> = self.modules.iter_mut()
-                .map(|m| m.1.drain_audio_buffer())
-                .fold(vec![], |mut ao, b| {
-                    for (i, sample) in b.iter().enumerate() {
-                        if i < ao.len() {
-                            ao[i][0] += sample[0];
-                            ao[i][1] += sample[1];
-                        } else {
-                            ao.push(*sample);
-                        }
-                    }
-                    ao
-                });
+        // Play generated audio
+        let ao = self.audio_context.as_ref()
+            .map(|ac| usize::from(ac.output.config.channels))
+            .map(|channels| self.drain_audio(channels));
+        if let (Some(audio_context), Some(ao)) = (&mut self.audio_context, ao) {
+            let channels = usize::from(audio_context.output.config.channels);
+            let peak = ao.iter()
+                .flatten()
+                .filter(|s| !s.is_nan())
+                .fold(0.0f32, |peak, s| peak.max(s.abs()));
+            self.output_level = peak.max(self.output_level * OUTPUT_LEVEL_RELEASE);
 
             audio_context.output.buffer.extend(ao);
-            if audio_context.output.buffer.len() == AUDIO_BUFFER_SIZE {
-                let sr = audio_context.output.config.sample_rate.0;
-                let frames = oddio::Frames::from_slice(sr, &audio_context.output.buffer);
-                let signal = oddio::FramesSignal::from(frames);
+            if audio_context.output.buffer.len() >= AUDIO_BUFFER_SIZE {
+                match &mut audio_context.output.sink {
+                    AudioOutputSink::Stereo(buf_stream_handle) => {
+                        let buffer: Vec<[f32; 2]> = audio_context.output.buffer.iter()
+                            .map(|frame| [frame[0], frame[1]])
+                            .collect();
+
+                        let sr = audio_context.output.config.sample_rate.0;
+                        let frames = oddio::Frames::from_slice(sr, &buffer);
+                        let signal = oddio::FramesSignal::from(frames);
 
-                let reinhard = oddio::Reinhard::new(signal);
+                        let reinhard = oddio::Reinhard::new(signal);
 
-                let mut samples = [[0.0; 2]; AUDIO_BUFFER_SIZE];
-                reinhard.sample(1.0 / sr as f32, &mut samples);
-                audio_context.output.buf_stream_handle
-                    .control::<oddio::Stream<_>, _>()
-                    .write(&samples);
+                        let mut samples = vec![[0.0; 2]; buffer.len()];
+                        reinhard.sample(1.0 / sr as f32, &mut samples);
+                        buf_stream_handle
+                            .control::<oddio::Stream<_>, _>()
+                            .write(&samples);
+                    },
+                    AudioOutputSink::Multi(out_buffer) => {
+                        if let Ok(mut outbuf) = out_buffer.lock() {
+                            // Apply the same Reinhard tonemapping as the stereo stream
+                            outbuf.extend(
+                                audio_context.output.buffer.iter()
+                                    .flatten()
+                                    .map(|s| s / (1.0 + s.abs()))
+                            );
+
+                            let max_len = AUDIO_STREAM_SIZE * channels;
+                            if outbuf.len() > max_len {
+                                let excess = outbuf.len() - max_len;
+                                outbuf.drain(..excess);
+                            }
+                        } else {
+                            error!("Rack dropped audio output");
+                        }
+                    },
+                }
 
                 audio_context.output.buffer = Vec::with_capacity(AUDIO_BUFFER_SIZE);
             }
 
-            // Consume captured audio
+            // Consume captured audio, which is replaced by the recorded audio
+            // while replaying inputs
             if let Some(input) = &mut audio_context.input {
                 if let Ok(inbuf) = &mut input.buffer.lock() {
-                    let buf = inbuf.drain(..).collect::<Vec<f32>>();
-                    for m in &mut self.modules {
-                        m.1.extend_audio_buffer(&buf);
+                    let mut buf = vec![0.0; input.delay * input.channels];
+                    input.delay = 0;
+                    buf.extend(inbuf.drain(..));
+                    if !replay::is_replaying() {
+                        replay::record_audio(&buf, input.channels);
+                        for m in &mut self.modules {
+                            m.1.extend_audio_buffer(&buf, input.channels);
+                        }
                     }
                 }
             }
+        } else if !self.keep_audio {
+            // Discard generated audio so that it doesn't build up in the
+            // module buffers while there's no output device
+            for m in self.modules.values_mut() {
+                m.drain_audio_buffer();
+                m.drain_multi_audio_buffer();
+            }
+        }
+        if let Some((buf, channels)) = replay::take_audio() {
+            for m in &mut self.modules {
+                m.1.extend_audio_buffer(&buf, channels);
+            }
         }
 
         // Apply feedback patches to knobs
@@ -313,7 +1281,7 @@ impl Rack {
                     match p.1.iok {
                         ModuleIOK::Knob(i) => {
                             if !o.1.is_nan() {
-                                m.set_knob(i, *o.1)
+                                set_module_knob(m, &self.ranges, &mut self.bypassed, k.id, i, *o.1);
                             }
                         },
                         ModuleIOK::Input(_) => {}, // Input feedback patches are handled above
@@ -324,6 +1292,26 @@ impl Rack {
             }
         }
 
+        // Track the range of each patched output over each frame
+        for o in self.patches.outputs() {
+            let history = self.signal_history.entry(*o).or_default();
+            if st == StepType::Key {
+                if let Some(range) = history.current.take() {
+                    history.frames.push_back(range);
+                    // Keep one second of history
+                    if history.frames.len() > usize::from(config::get().frame_rate) {
+                        history.frames.pop_front();
+                    }
+                }
+            }
+            if let Some(v) = self.outs.get(o).filter(|v| !v.is_nan()) {
+                history.current = Some(match history.current {
+                    Some((lo, hi)) => (lo.min(*v), hi.max(*v)),
+                    None => (*v, *v),
+                });
+            }
+        }
+
         // Remove NANs from output map
         self.outs.extract_if(|_, v| v.is_nan()).last();
     }
@@ -332,13 +1320,22 @@ impl Rack {
             m.render(images, meshes, q_text, q_image, q_mesh);
         }
     }
-    pub fn exit(&mut self) {
+    pub fn exit(&mut self, streams: &mut AudioStreams) {
         for m in self.modules.values_mut() {
             m.exit();
         }
 
+        streams.stop();
         self.audio_context = None;
         self.outs.clear();
+        self.oversamplers.clear();
+        self.signal_history.clear();
+        self.bypassed.clear();
+        self.times = None;
+
+        for (id, count) in self.scrubbed.drain() {
+            info!("M{id} had {count} infinite or denormal output values scrubbed");
+        }
     }
 }
 #[derive(Resource, Debug, Clone)]
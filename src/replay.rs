@@ -0,0 +1,312 @@
+/*!
+Recording and replaying the external inputs of a rack, so that bugs can be
+reproduced and a take can be re-performed while tweaking the patch.
+
+Set the `VINCE_RECORD_INPUTS` environment variable to a file path to record
+the inputs, or `VINCE_REPLAY_INPUTS` to the path of a recording to replay it.
+The recording is a TOML file with a `[[frames]]` table for each frame which
+contains:
+
+ * the keys and mouse buttons that were pressed and released, and the cursor
+   position
+ * the MIDI messages received by each `MidiIn` module
+ * the audio captured for `AudioIn` modules
+ * the rack's seed and start time whenever a rack is loaded
+
+The inputs of `SerialIn`, `NetReceive`, `MotionIn`, `PoseIn`, and `VideoIn`
+modules, `AudioIn` modules with their own `device`, and touches aren't
+recorded, so a warning is logged for each of these modules when a rack is
+loaded while replaying since they'll read from their live sources instead.
+
+While replaying, the recorded keys, mouse buttons, and cursor position are
+applied on top of the live input, while the recorded MIDI and audio replace the
+live devices. Inputs are replayed at the start of the same frame that they were
+recorded in, so they may be a few samples earlier or later than they were
+originally.
+
+*/
+
+use std::{collections::VecDeque, fs::{File, OpenOptions}, io::{LineWriter, Write}, sync::Mutex};
+
+use bevy::{prelude::*, utils::HashMap, reflect::{DynamicEnum, DynamicVariant, Enum}, window::PrimaryWindow};
+
+use serde::{Deserialize, Serialize};
+
+use crate::rack::Rack;
+
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+static REPLAYER: Mutex<Option<Replayer>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct MidiRecord {
+    module: usize,
+    bytes: Vec<u8>,
+}
+
+/// The inputs received during a single frame
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+struct InputFrame {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    keys_pressed: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    keys_released: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    buttons_pressed: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    buttons_released: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<[f32; 2]>,
+
+    /// Seeds are stored as strings since TOML integers are signed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<f64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audio_channels: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    audio: Vec<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    midi: Vec<MidiRecord>,
+}
+#[derive(Serialize)]
+struct RecordedFrame<'a> {
+    frames: [&'a InputFrame; 1],
+}
+#[derive(Deserialize)]
+struct Recording {
+    #[serde(default)]
+    frames: VecDeque<InputFrame>,
+}
+
+struct Recorder {
+    file: LineWriter<File>,
+    frame: InputFrame,
+}
+struct Replayer {
+    frames: VecDeque<InputFrame>,
+    seeds: VecDeque<u64>,
+    times: VecDeque<f64>,
+
+    midi: HashMap<usize, VecDeque<Vec<u8>>>,
+    audio: Vec<f32>,
+    audio_channels: usize,
+}
+
+fn key_name(key: KeyCode) -> String {
+    key.variant_name().to_string()
+}
+fn parse_key(name: &str) -> Option<KeyCode> {
+    KeyCode::from_reflect(&DynamicEnum::new(name.to_string(), DynamicVariant::Unit))
+}
+fn button_name(button: MouseButton) -> String {
+    match button {
+        MouseButton::Other(b) => format!("Other{b}"),
+        b => format!("{b:?}"),
+    }
+}
+fn parse_button(name: &str) -> Option<MouseButton> {
+    match name {
+        "Left" => Some(MouseButton::Left),
+        "Right" => Some(MouseButton::Right),
+        "Middle" => Some(MouseButton::Middle),
+        b => b.strip_prefix("Other")
+            .and_then(|b| b.parse().ok())
+            .map(MouseButton::Other),
+    }
+}
+
+/// Start recording or replaying if the environment variables are set
+pub fn init() {
+    if let Some(path) = std::env::var_os("VINCE_REPLAY_INPUTS") {
+        let recording = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|r| toml::from_str::<Recording>(&r).map_err(|e| e.to_string()));
+        match recording {
+            Ok(recording) => {
+                info!("Replaying {} frames of inputs from {}", recording.frames.len(), path.to_string_lossy());
+                *REPLAYER.lock().unwrap() = Some(Replayer {
+                    seeds: recording.frames.iter()
+                        .filter_map(|f| f.seed.as_ref()?.parse().ok())
+                        .collect(),
+                    times: recording.frames.iter()
+                        .filter_map(|f| f.time)
+                        .collect(),
+                    frames: recording.frames,
+
+                    midi: HashMap::default(),
+                    audio: vec![],
+                    audio_channels: 1,
+                });
+            },
+            Err(e) => error!("Failed to read input recording {}: {e}", path.to_string_lossy()),
+        }
+    }
+
+    if let Some(path) = std::env::var_os("VINCE_RECORD_INPUTS") {
+        match OpenOptions::new().create(true).write(true).truncate(true).open(&path) {
+            Ok(file) => {
+                info!("Recording inputs to {}", path.to_string_lossy());
+                *RECORDER.lock().unwrap() = Some(Recorder {
+                    file: LineWriter::new(file),
+                    frame: InputFrame::default(),
+                });
+            },
+            Err(e) => error!("Failed to open input recording {}: {e}", path.to_string_lossy()),
+        }
+    }
+}
+/// Whether a recording is being replayed
+pub fn is_replaying() -> bool {
+    REPLAYER.lock().is_ok_and(|r| r.is_some())
+}
+
+/// Warn about each module in the rack whose external inputs aren't recorded,
+/// since it reads from its live source while replaying
+pub fn warn_unreplayable(rack: &Rack) {
+    if !is_replaying() {
+        return;
+    }
+    let mut ids: Vec<usize> = rack.modules.iter()
+        .filter(|(_, m)| !m.is_replayable())
+        .map(|(k, _)| k.id)
+        .collect();
+    ids.sort();
+    for id in ids {
+        warn!("M{id} reads from a live input which isn't recorded, so it won't be replayed");
+    }
+}
+/// Record the given rack seed, or get the next recorded seed instead if
+/// replaying
+pub fn sync_seed(seed: u64) -> u64 {
+    let seed = REPLAYER.lock()
+        .ok()
+        .and_then(|mut r| r.as_mut()?.seeds.pop_front())
+        .unwrap_or(seed);
+    if let Ok(Some(recorder)) = RECORDER.lock().as_deref_mut() {
+        recorder.frame.seed = Some(seed.to_string());
+    }
+    seed
+}
+/// Record the given rack start time, or get the next recorded start time
+/// instead if replaying
+pub fn sync_time(time: f64) -> f64 {
+    let time = REPLAYER.lock()
+        .ok()
+        .and_then(|mut r| r.as_mut()?.times.pop_front())
+        .unwrap_or(time);
+    if let Ok(Some(recorder)) = RECORDER.lock().as_deref_mut() {
+        recorder.frame.time = Some(time);
+    }
+    time
+}
+
+/// Record a MIDI message received by the given module
+pub fn record_midi(module: usize, bytes: &[u8]) {
+    if let Ok(Some(recorder)) = RECORDER.lock().as_deref_mut() {
+        recorder.frame.midi.push(MidiRecord {
+            module,
+            bytes: bytes.to_vec(),
+        });
+    }
+}
+/// Take the replayed MIDI messages for the given module
+pub fn take_midi(module: usize) -> Vec<Vec<u8>> {
+    REPLAYER.lock()
+        .ok()
+        .and_then(|mut r| r.as_mut()?.midi.remove(&module))
+        .map(Vec::from)
+        .unwrap_or_default()
+}
+
+/// Record the given interleaved audio input
+pub fn record_audio(buf: &[f32], channels: usize) {
+    if buf.is_empty() {
+        return;
+    }
+    if let Ok(Some(recorder)) = RECORDER.lock().as_deref_mut() {
+        recorder.frame.audio_channels = Some(channels);
+        recorder.frame.audio.extend(buf);
+    }
+}
+/// Take the replayed interleaved audio input and its number of channels, if
+/// replaying
+pub fn take_audio() -> Option<(Vec<f32>, usize)> {
+    let mut replayer = REPLAYER.lock().ok()?;
+    let replayer = replayer.as_mut()?;
+    if replayer.audio.is_empty() {
+        return None;
+    }
+    Some((std::mem::take(&mut replayer.audio), replayer.audio_channels))
+}
+
+/// Record the keyboard and mouse inputs of this frame and write the frame
+pub fn record_inputs(keys: Res<Input<KeyCode>>, buttons: Res<Input<MouseButton>>, q_window: Query<&Window, With<PrimaryWindow>>) {
+    let Ok(mut recorder) = RECORDER.lock() else {
+        return;
+    };
+    let Some(rec) = recorder.as_mut() else {
+        return;
+    };
+
+    let mut frame = std::mem::take(&mut rec.frame);
+    frame.keys_pressed = keys.get_just_pressed().copied().map(key_name).collect();
+    frame.keys_released = keys.get_just_released().copied().map(key_name).collect();
+    frame.buttons_pressed = buttons.get_just_pressed().copied().map(button_name).collect();
+    frame.buttons_released = buttons.get_just_released().copied().map(button_name).collect();
+    frame.cursor = q_window.get_single()
+        .ok()
+        .and_then(Window::cursor_position)
+        .map(|p| p.to_array());
+
+    let written = toml::to_string(&RecordedFrame {
+        frames: [&frame],
+    }).map_err(|e| e.to_string())
+        .and_then(|f| rec.file.write_all(f.as_bytes()).map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        error!("Failed to write input recording, stopping recording: {e}");
+        *recorder = None;
+    }
+}
+/// Apply the recorded inputs of the next frame
+pub fn replay_inputs(mut keys: ResMut<Input<KeyCode>>, mut buttons: ResMut<Input<MouseButton>>, mut q_window: Query<&mut Window, With<PrimaryWindow>>) {
+    let Ok(mut replayer) = REPLAYER.lock() else {
+        return;
+    };
+    let Some(rep) = replayer.as_mut() else {
+        return;
+    };
+    let Some(frame) = rep.frames.pop_front() else {
+        info!("Finished replaying inputs");
+        *replayer = None;
+        return;
+    };
+
+    for key in frame.keys_pressed.iter().filter_map(|k| parse_key(k)) {
+        keys.press(key);
+    }
+    for key in frame.keys_released.iter().filter_map(|k| parse_key(k)) {
+        keys.release(key);
+    }
+    for button in frame.buttons_pressed.iter().filter_map(|b| parse_button(b)) {
+        buttons.press(button);
+    }
+    for button in frame.buttons_released.iter().filter_map(|b| parse_button(b)) {
+        buttons.release(button);
+    }
+    if let (Some(cursor), Ok(mut window)) = (frame.cursor, q_window.get_single_mut()) {
+        window.set_cursor_position(Some(Vec2::from_array(cursor)));
+    }
+
+    for midi in frame.midi {
+        rep.midi.entry(midi.module)
+            .or_default()
+            .push_back(midi.bytes);
+    }
+    if let Some(channels) = frame.audio_channels {
+        rep.audio_channels = channels;
+    }
+    rep.audio.extend(frame.audio);
+}
@@ -0,0 +1,118 @@
+/*!
+An optional session recorder which logs the events of a performance to a JSONL
+file so that it can be reconstructed or debugged afterwards.
+
+The recorder is enabled by setting the `VINCE_SESSION_LOG` environment variable
+to the path of the log file, which is appended to. Each line is a JSON object
+with the `time` in seconds since the session started, the `event` type, and the
+event's fields:
+
+ * `start` - the session started at `unix_time` in seconds
+ * `rack` - the rack at `path` was loaded, and whether it was a hot-`reload`
+ * `knob` - the `knob` of the `module` changed to `value`, recorded at most once
+   per frame
+ * `midi` - the MIDI message `bytes` were received from `port`
+ * `error` - an error occurred with `message`, and the `module` it occurred in
+   if any
+
+*/
+
+use std::{fs::{File, OpenOptions}, io::{LineWriter, Write}, sync::Mutex, time::{Instant, SystemTime, UNIX_EPOCH}};
+
+use bevy::prelude::*;
+
+static SESSION_LOG: Mutex<Option<SessionLog>> = Mutex::new(None);
+
+struct SessionLog {
+    file: LineWriter<File>,
+    start: Instant,
+}
+
+/// The value of an event field
+pub enum Field<'a> {
+    Num(f64),
+    Str(&'a str),
+    Bool(bool),
+    Bytes(&'a [u8]),
+}
+impl Field<'_> {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Field::Num(n) if n.is_finite() => out.push_str(&n.to_string()),
+            Field::Num(_) => out.push_str("null"),
+            Field::Str(s) => {
+                out.push('"');
+                for c in s.chars() {
+                    match c {
+                        '"' => out.push_str("\\\""),
+                        '\\' => out.push_str("\\\\"),
+                        '\n' => out.push_str("\\n"),
+                        '\r' => out.push_str("\\r"),
+                        '\t' => out.push_str("\\t"),
+                        c if c.is_control() => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+                        c => out.push(c),
+                    }
+                }
+                out.push('"');
+            },
+            Field::Bool(b) => out.push_str(&b.to_string()),
+            Field::Bytes(bytes) => {
+                let bytes: Vec<String> = bytes.iter()
+                    .map(u8::to_string)
+                    .collect();
+                out.push_str(&format!("[{}]", bytes.join(",")));
+            },
+        }
+    }
+}
+
+/// Start recording if the `VINCE_SESSION_LOG` environment variable is set
+pub fn init() {
+    let Some(path) = std::env::var_os("VINCE_SESSION_LOG") else {
+        return;
+    };
+
+    match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(file) => {
+            info!("Recording session log to {}", path.to_string_lossy());
+            *SESSION_LOG.lock().unwrap() = Some(SessionLog {
+                file: LineWriter::new(file),
+                start: Instant::now(),
+            });
+
+            let unix_time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0.0, |d| d.as_secs_f64());
+            record("start", &[("unix_time", Field::Num(unix_time))]);
+        },
+        Err(e) => error!("Failed to open session log {}: {e}", path.to_string_lossy()),
+    }
+}
+/// Whether a session log is being recorded
+pub fn is_enabled() -> bool {
+    SESSION_LOG.lock().is_ok_and(|log| log.is_some())
+}
+/// Record an event with the given fields if a session log is being recorded
+pub fn record(event: &str, fields: &[(&str, Field<'_>)]) {
+    let Ok(mut guard) = SESSION_LOG.lock() else {
+        return;
+    };
+    let Some(log) = guard.as_mut() else {
+        return;
+    };
+
+    let mut line = format!("{{\"time\":{},\"event\":", log.start.elapsed().as_secs_f64());
+    Field::Str(event).write_json(&mut line);
+    for (name, field) in fields {
+        line.push(',');
+        Field::Str(name).write_json(&mut line);
+        line.push(':');
+        field.write_json(&mut line);
+    }
+    line.push_str("}\n");
+
+    if let Err(e) = log.file.write_all(line.as_bytes()) {
+        error!("Failed to write to session log, stopping recording: {e}");
+        *guard = None;
+    }
+}
@@ -0,0 +1,376 @@
+/*!
+A best-effort importer for VCV Rack patches, which maps the common modules of
+VCV's Fundamental plugin onto their closest Vince equivalents so that existing
+patches can be migrated.
+
+With the `vcv` feature, `.vcv` files in the asset directory are loaded as racks
+directly, or they can be converted to a rack's TOML to be edited further:
+
+```text
+$ cargo run --release --features vcv --bin vcv2vince -- patch.vcv > assets/racks/patch.toml
+```
+
+The following modules are mapped, where VCV modules with several outputs of
+different kinds are split into a Vince module for each patched output:
+
+ * `VCO` and `LFO` - an `Oscillator` for each waveform
+ * `VCF` - an `Equalizer` for each of the `LPF` and `HPF` outputs
+ * `VCA`, `VCA-1` - a `Multiplier` for each channel with a patched CV, or a
+   `Mixer` with the channel's level otherwise
+ * `VCMixer` and `Mixer` - a `Mixer`
+ * `Noise` - a `Noise` for each noise color
+ * `Scope` - an `Oscilloscope`
+ * Core's `AudioInterface` - an `AudioOut`
+
+A warning is emitted for each module which isn't mapped and for each cable
+which can't be patched, e.g. because it connects an unmapped port.
+
+##### Note
+VCV Rack 2 saves patches as a compressed archive, so the `patch.json` must be
+extracted from it first, e.g. with `tar --zstd -xf patch.vcv patch.json`, while
+VCV Rack 1 patches can be imported directly.
+
+Cables are patched as is, so voltages aren't rescaled from VCV's ±5 V audio and
+0-10 V control signals, and V/oct pitch inputs are patched into knobs which
+expect a frequency in Hz. Imported racks will usually need some attenuators or
+knob ranges to sound like the original.
+
+*/
+
+use std::collections::BTreeMap;
+
+use bevy::{prelude::*, asset::{AssetLoader, LoadContext, LoadedAsset}, utils::BoxedFuture};
+
+use serde::Deserialize;
+
+use crate::rack::Rack;
+
+/// The frequency of C4 which VCV's pitches are relative to
+const FREQ_C4: f32 = 261.625_57;
+
+#[derive(Deserialize)]
+struct VcvPatch {
+    modules: Vec<VcvModule>,
+    /// VCV Rack 1 calls cables wires
+    #[serde(default, alias = "wires")]
+    cables: Vec<VcvCable>,
+}
+#[derive(Deserialize)]
+struct VcvModule {
+    /// Modules were identified by their index before VCV Rack 1
+    #[serde(default)]
+    id: Option<i64>,
+    plugin: String,
+    model: String,
+    #[serde(default)]
+    params: Vec<VcvParam>,
+}
+#[derive(Deserialize)]
+struct VcvParam {
+    #[serde(default, alias = "paramId")]
+    id: Option<usize>,
+    value: f32,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VcvCable {
+    output_module_id: i64,
+    output_id: usize,
+    input_module_id: i64,
+    input_id: usize,
+}
+
+impl VcvModule {
+    /// Get the value of the given param, or the given default if it isn't
+    /// saved in the patch
+    fn param(&self, id: usize, default: f32) -> f32 {
+        self.params.iter()
+            .enumerate()
+            .find(|(i, p)| p.id.unwrap_or(*i) == id)
+            .map_or(default, |(_, p)| p.value)
+    }
+}
+
+/// Where a VCV input is patched to on the Vince modules of a mapping
+#[derive(Debug, Clone, Copy)]
+enum InputMap {
+    Input(usize, usize),
+    Knob(usize, usize),
+}
+/// The Vince modules which a VCV module is mapped to along with how its ports
+/// are mapped
+#[derive(Default)]
+struct Mapping {
+    modules: Vec<toml::Table>,
+    /// Each VCV input's indices in [Mapping::modules] and inputs or knobs,
+    /// where an input can be patched into several modules
+    inputs: BTreeMap<usize, Vec<InputMap>>,
+    /// Each VCV output's index in [Mapping::modules] and output
+    outputs: BTreeMap<usize, (usize, usize)>,
+    /// The VCV inputs which are patched into knobs that expect a different
+    /// unit, e.g. V/oct pitch into a frequency
+    approximate_inputs: Vec<usize>,
+}
+impl Mapping {
+    fn add_module(&mut self, ty: &str, name: String, func: Option<&str>, knobs: &[f32]) -> usize {
+        let mut module = toml::Table::new();
+        module.insert("type".to_string(), toml::Value::String(ty.to_string()));
+        module.insert("name".to_string(), toml::Value::String(name));
+        if let Some(func) = func {
+            module.insert("func".to_string(), toml::Value::String(func.to_string()));
+        }
+        if !knobs.is_empty() {
+            module.insert(
+                "knobs".to_string(),
+                toml::Value::Array(
+                    knobs.iter()
+                        .map(|k| toml::Value::Float(f64::from(*k)))
+                        .collect()
+                ),
+            );
+        }
+        self.modules.push(module);
+        self.modules.len() - 1
+    }
+    fn add_input(&mut self, input: usize, map: InputMap) {
+        self.inputs.entry(input)
+            .or_default()
+            .push(map);
+    }
+    /// Add a VCA channel as a `Multiplier` of its input and CV when the CV is
+    /// patched, otherwise as a `Mixer` with the channel's level
+    fn add_vca(&mut self, name: String, level: f32, input: usize, cv: usize, is_cv_patched: bool) -> usize {
+        if is_cv_patched {
+            let idx = self.add_module("Multiplier", name, None, &[]);
+            self.add_input(input, InputMap::Input(idx, 0));
+            self.add_input(cv, InputMap::Input(idx, 1));
+            idx
+        } else {
+            let mut knobs = [0.0; 8];
+            knobs[0] = level;
+            let idx = self.add_module("Mixer", name, None, &knobs);
+            self.add_input(input, InputMap::Input(idx, 0));
+            idx
+        }
+    }
+}
+
+/// Map a VCV module onto Vince modules, or [None] if it isn't supported
+fn map_module(m: &VcvModule, patched_inputs: &[usize], patched_outputs: &[usize]) -> Option<Mapping> {
+    let mut mapping = Mapping::default();
+    let name = &m.model;
+    match (m.plugin.as_str(), m.model.as_str()) {
+        ("Fundamental", "VCO" | "VCO-1" | "VCO2" | "VCO-2") => {
+            let freq = FREQ_C4 * 2.0f32.powf(m.param(2, 0.0) / 12.0);
+            for (i, func) in ["Sine", "Triangle", "Saw", "Square"].into_iter().enumerate() {
+                if patched_outputs.contains(&i) || (patched_outputs.is_empty() && i == 0) {
+                    let idx = mapping.add_module("Oscillator", format!("{name} {func}"), Some(func), &[0.0, freq, 1.0, 0.0]);
+                    mapping.outputs.insert(i, (idx, 0));
+                    mapping.add_input(0, InputMap::Knob(idx, 1));
+                    mapping.add_input(3, InputMap::Input(idx, 2));
+                }
+            }
+            mapping.approximate_inputs.push(0);
+        },
+        ("Fundamental", "LFO" | "LFO-1" | "LFO2" | "LFO-2") => {
+            let freq = 2.0f32.powf(m.param(2, 1.0));
+            let (shift, depth) = if m.param(0, 1.0) > 0.5 {
+                (0.0, 1.0)
+            } else {
+                (0.5, 0.5)
+            };
+            for (i, func) in ["Sine", "Triangle", "Saw", "Square"].into_iter().enumerate() {
+                if patched_outputs.contains(&i) || (patched_outputs.is_empty() && i == 0) {
+                    let idx = mapping.add_module("Oscillator", format!("{name} {func}"), Some(func), &[shift, freq, depth, 0.0]);
+                    mapping.outputs.insert(i, (idx, 0));
+                    mapping.add_input(0, InputMap::Knob(idx, 1));
+                    mapping.add_input(3, InputMap::Input(idx, 2));
+                }
+            }
+            mapping.approximate_inputs.push(0);
+        },
+        ("Fundamental", "VCF") => {
+            let cutoff = FREQ_C4 * 2.0f32.powf(m.param(0, 0.5) * 10.0 - 5.0);
+            let q = std::f32::consts::FRAC_1_SQRT_2 + m.param(2, 0.0) * 10.0;
+            for (i, func) in ["LPF", "HPF"].into_iter().enumerate() {
+                if patched_outputs.contains(&i) || (patched_outputs.is_empty() && i == 0) {
+                    let idx = mapping.add_module("Equalizer", format!("{name} {func}"), Some(func), &[cutoff, q, 1.0]);
+                    mapping.outputs.insert(i, (idx, 0));
+                    mapping.add_input(3, InputMap::Input(idx, 0));
+                    mapping.add_input(0, InputMap::Knob(idx, 0));
+                }
+            }
+            mapping.approximate_inputs.push(0);
+        },
+        ("Fundamental", "VCA-1") => {
+            mapping.add_vca(name.clone(), m.param(0, 1.0), 1, 0, patched_inputs.contains(&0));
+            mapping.outputs.insert(0, (0, 0));
+        },
+        ("Fundamental", "VCA") => {
+            for ch in 0..2 {
+                let cv = ch * 3 + 1;
+                let idx = mapping.add_vca(format!("{name} {}", ch + 1), m.param(ch, 1.0), ch * 3 + 2, cv, patched_inputs.contains(&cv));
+                mapping.outputs.insert(ch, (idx, 0));
+            }
+        },
+        ("Fundamental", "VCMixer") => {
+            let mix = m.param(0, 1.0);
+            let mut knobs = [0.0; 8];
+            for (i, k) in knobs.iter_mut().take(4).enumerate() {
+                *k = m.param(i + 1, 1.0) * mix;
+            }
+            let idx = mapping.add_module("Mixer", name.clone(), None, &knobs);
+            for ch in 0..4 {
+                mapping.add_input(ch + 1, InputMap::Input(idx, ch));
+            }
+            mapping.outputs.insert(0, (idx, 0));
+        },
+        ("Fundamental", "Mixer") => {
+            let level = m.param(0, 1.0);
+            let mut knobs = [0.0; 8];
+            knobs[..6].fill(level);
+            let idx = mapping.add_module("Mixer", name.clone(), None, &knobs);
+            for ch in 0..6 {
+                mapping.add_input(ch, InputMap::Input(idx, ch));
+            }
+            mapping.outputs.insert(0, (idx, 0));
+        },
+        ("Fundamental", "Noise") => {
+            for (i, func) in [(0, "White"), (1, "Pink"), (2, "Brown"), (4, "Blue")] {
+                if patched_outputs.contains(&i) || (patched_outputs.is_empty() && i == 0) {
+                    let idx = mapping.add_module("Noise", format!("{name} {func}"), Some(func), &[1.0, 0.0]);
+                    mapping.outputs.insert(i, (idx, 0));
+                }
+            }
+        },
+        ("Fundamental", "Scope") => {
+            let idx = mapping.add_module("Oscilloscope", name.clone(), None, &[]);
+            mapping.add_input(0, InputMap::Input(idx, 0));
+            mapping.add_input(1, InputMap::Input(idx, 1));
+        },
+        ("Core", "AudioInterface" | "AudioInterface2" | "AudioInterface16") => {
+            let idx = mapping.add_module("AudioOut", name.clone(), None, &[1.0]);
+            mapping.add_input(0, InputMap::Input(idx, 0));
+            mapping.add_input(1, InputMap::Input(idx, 1));
+        },
+        _ => return None,
+    }
+    Some(mapping)
+}
+/// The result of importing a VCV Rack patch
+pub struct VcvImport {
+    /// The rack's TOML representation
+    pub rack: toml::Table,
+    /// Any modules and cables which couldn't be imported
+    pub warnings: Vec<String>,
+}
+impl VcvImport {
+    /// Convert the imported rack to TOML
+    pub fn to_toml(&self) -> String {
+        toml::to_string(&self.rack)
+            .unwrap_or_default()
+    }
+}
+
+/// Import a VCV Rack patch from its JSON
+pub fn import(json: &str) -> Result<VcvImport, String> {
+    let patch: VcvPatch = serde_json::from_str(json)
+        .map_err(|e| format!("Invalid VCV Rack patch: {e}"))?;
+    let mut warnings = vec![];
+
+    // Map each module and number the Vince modules in order
+    let mut modules = toml::Table::new();
+    let mut mappings = BTreeMap::new();
+    let mut next_id = 0;
+    for (i, m) in patch.modules.iter().enumerate() {
+        let vcv_id = m.id.unwrap_or(i as i64);
+        let patched_outputs: Vec<usize> = patch.cables.iter()
+            .filter(|c| c.output_module_id == vcv_id)
+            .map(|c| c.output_id)
+            .collect();
+        let patched_inputs: Vec<usize> = patch.cables.iter()
+            .filter(|c| c.input_module_id == vcv_id)
+            .map(|c| c.input_id)
+            .collect();
+        let Some(mapping) = map_module(m, &patched_inputs, &patched_outputs) else {
+            warnings.push(format!("Unsupported module {} {}", m.plugin, m.model));
+            continue;
+        };
+
+        let ids: Vec<usize> = (next_id..next_id + mapping.modules.len()).collect();
+        next_id += mapping.modules.len();
+        for (id, module) in ids.iter().zip(mapping.modules.iter().cloned()) {
+            modules.insert(format!("{id}M"), toml::Value::Table(module));
+        }
+        mappings.insert(vcv_id, (ids, mapping));
+    }
+
+    // Patch every cable whose ports are mapped
+    let mut patches = toml::Table::new();
+    for c in &patch.cables {
+        let output = mappings.get(&c.output_module_id)
+            .and_then(|(ids, mapping)| {
+                let (idx, o) = mapping.outputs.get(&c.output_id)?;
+                Some(format!("{}M{o}O", ids[*idx]))
+            });
+        let inputs = mappings.get(&c.input_module_id)
+            .and_then(|(ids, mapping)| {
+                if mapping.approximate_inputs.contains(&c.input_id) {
+                    warnings.push(format!("Cable into input {} of module {} is patched into a knob which expects a frequency", c.input_id, c.input_module_id));
+                }
+                let inputs = mapping.inputs.get(&c.input_id)?
+                    .iter()
+                    .map(|input| match input {
+                        InputMap::Input(idx, i) => format!("{}M{i}I", ids[*idx]),
+                        InputMap::Knob(idx, k) => format!("{}M{k}K", ids[*idx]),
+                    }).map(toml::Value::String);
+                Some(inputs.collect::<Vec<toml::Value>>())
+            });
+
+        match (output, inputs) {
+            (Some(output), Some(inputs)) if !inputs.is_empty() => {
+                if let toml::Value::Array(patched) = patches.entry(output).or_insert_with(|| toml::Value::Array(vec![])) {
+                    patched.extend(inputs);
+                }
+            },
+            _ => warnings.push(format!(
+                "Unsupported cable from output {} of module {} to input {} of module {}",
+                c.output_id, c.output_module_id, c.input_id, c.input_module_id,
+            )),
+        }
+    }
+
+    let mut rack = toml::Table::new();
+    rack.insert("modules".to_string(), toml::Value::Table(modules));
+    rack.insert("patches".to_string(), toml::Value::Table(patches));
+    Ok(VcvImport {
+        rack,
+        warnings,
+    })
+}
+
+/// Loads `.vcv` files as racks, logging any import warnings
+#[derive(Default)]
+pub struct VcvLoader;
+impl AssetLoader for VcvLoader {
+    fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let path = load_context.path().display().to_string();
+            let import = import(std::str::from_utf8(bytes)?)
+                .map_err(|e| bevy::asset::Error::msg(format!("{path}: {e}")))?;
+            for w in &import.warnings {
+                warn!("{path}: {w}");
+            }
+
+            let rack: Rack = toml::Value::Table(import.rack)
+                .try_into()
+                .map_err(|e| bevy::asset::Error::msg(format!("{path}: {e}")))?;
+            load_context.set_default_asset(LoadedAsset::new(rack));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["vcv"]
+    }
+}
@@ -0,0 +1,52 @@
+/*!
+Running racks in the browser when built for `wasm32-unknown-unknown`, where the
+main window is drawn into the page's `#vince` canvas and audio is played with
+WebAudio.
+
+Racks are loaded over HTTP from the asset directory next to the page, so they
+can be shared as links:
+
+ * `?rack=racks/rack1.toml` - load the given rack file, like the positional
+   argument of the binary
+ * `#rack=<rack>` - load the given URL-encoded rack TOML, so that a patch can
+   be shared without hosting its file
+
+Every other setting uses its default since there are no command line flags or
+`vince.toml` in the browser.
+
+##### Note
+Browsers only allow audio to start after the user interacts with the page, so
+the page should wait for a click before starting the app, as in
+`web/index.html`. Racks can't be loaded from directories since their contents
+can't be listed over HTTP, and modules which use threads, the file system, or
+the network will fail to init.
+
+*/
+
+/// Get the given URL-decoded parameter from the given `?` or `#` part of the
+/// page's URL
+fn param(part: &str, name: &str) -> Option<String> {
+    part.trim_start_matches(['?', '#'])
+        .split('&')
+        .filter_map(|p| p.split_once('='))
+        .find(|(k, _)| *k == name)
+        .and_then(|(_, v)| js_sys::decode_uri_component(v).ok())
+        .map(String::from)
+}
+
+/// Get the rack path given by the page's `?rack=` query parameter
+pub fn rack_path() -> Option<String> {
+    let search = web_sys::window()?
+        .location()
+        .search()
+        .ok()?;
+    param(&search, "rack")
+}
+/// Get the rack TOML given by the page's `#rack=` fragment
+pub fn shared_rack() -> Option<String> {
+    let hash = web_sys::window()?
+        .location()
+        .hash()
+        .ok()?;
+    param(&hash, "rack")
+}